@@ -1,10 +1,34 @@
+use crate::middleware::auth::Scope;
 use config::{Config, ConfigError, Environment, File};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub server: ServerConfig,
     pub email: EmailConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub mfa: MfaConfig,
+    #[serde(default)]
+    pub totp: TotpConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub trash: TrashConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub smtp: SmtpConfig,
+    #[serde(default)]
+    pub subscriptions: SubscriptionsConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -19,11 +43,388 @@ pub struct EmailConfig {
     pub app_password: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EventsConfig {
+    /// Poll the inbox in the background and publish new mail events
+    pub watcher_enabled: bool,
+    pub poll_interval_secs: u64,
+    /// Most new emails fetched on each poll
+    pub poll_limit: u32,
+    /// Number of events kept for `Last-Event-ID` resume
+    pub log_capacity: usize,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            watcher_enabled: true,
+            poll_interval_secs: 30,
+            poll_limit: 20,
+            log_capacity: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Directory for persisted state (webhook subscriptions, delivery log, ...)
+    pub data_dir: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: "data".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled on every following attempt
+    pub initial_backoff_ms: u64,
+    pub timeout_secs: u64,
+    /// Number of delivery records kept in the delivery log
+    pub delivery_log_capacity: usize,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 1000,
+            timeout_secs: 10,
+            delivery_log_capacity: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MfaConfig {
+    /// Registry of code patterns, keywords and sender -> service mappings;
+    /// the built-in registry is used when the file does not exist
+    pub registry_path: String,
+    pub cleanup: MfaCleanupConfig,
+}
+
+impl Default for MfaConfig {
+    fn default() -> Self {
+        Self {
+            registry_path: "config/mfa_registry.toml".to_string(),
+            cleanup: MfaCleanupConfig::default(),
+        }
+    }
+}
+
+/// What the cleanup job does with a processed verification email
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupAction {
+    MarkRead,
+    Move,
+    /// Move to the trash
+    Delete,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MfaCleanupConfig {
+    /// Run the cleanup job in the background
+    pub enabled: bool,
+    /// Only record what would be done in the audit log
+    pub dry_run: bool,
+    pub interval_secs: u64,
+    /// Clean up verification emails older than this
+    pub retention_minutes: u64,
+    /// Clean up verification emails as soon as their code is consumed
+    pub on_consumed: bool,
+    pub action: CleanupAction,
+    /// Destination folder (Gmail label) for the `move` action
+    pub target_folder: String,
+    /// Number of recent emails inspected on each run
+    pub scan_limit: u32,
+    /// Number of records kept in the audit log
+    pub audit_log_capacity: usize,
+}
+
+impl Default for MfaCleanupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dry_run: false,
+            interval_secs: 300,
+            retention_minutes: 60,
+            on_consumed: true,
+            action: CleanupAction::MarkRead,
+            target_folder: "MFA".to_string(),
+            scan_limit: 50,
+            audit_log_capacity: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TotpConfig {
+    /// Passphrase the vault encryption key is derived from; when empty a
+    /// random key is generated in the data directory
+    pub vault_key: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Which bearer credentials are accepted
+    pub mode: AuthMode,
+    /// API tokens accepted in the `Authorization: Bearer` header
+    pub tokens: Vec<TokenConfig>,
+    /// Start without any configured token, accepting `dev-token` with every
+    /// scope. Never enable this outside local development.
+    pub insecure_dev: bool,
+    pub jwt: JwtConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// Static and minted API tokens
+    #[default]
+    Tokens,
+    /// JWTs from an identity provider only
+    Jwt,
+    /// API tokens and JWTs
+    Both,
+}
+
+impl AuthMode {
+    pub fn accepts_tokens(&self) -> bool {
+        matches!(self, AuthMode::Tokens | AuthMode::Both)
+    }
+
+    pub fn accepts_jwt(&self) -> bool {
+        matches!(self, AuthMode::Jwt | AuthMode::Both)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// JWKS file with the identity provider's public keys
+    pub jwks_path: Option<String>,
+    /// PEM public keys (RSA, EC or Ed25519), used besides the JWKS
+    pub public_key_paths: Vec<String>,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required `aud` claim; JWT authentication refuses to start without it
+    pub audience: Option<String>,
+    /// Claim holding the scopes, either a space-separated string or an array
+    pub scopes_claim: String,
+    /// Claim listing the mailboxes the subject may access. When the claim is
+    /// present it must include the configured email address.
+    pub accounts_claim: String,
+    /// Clock skew tolerated on `exp` and `nbf`
+    pub leeway_secs: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            jwks_path: None,
+            public_key_paths: Vec::new(),
+            issuer: None,
+            audience: None,
+            scopes_claim: "scope".to_string(),
+            accounts_claim: "accounts".to_string(),
+            leeway_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenConfig {
+    pub name: String,
+    /// Hex-encoded SHA-256 hash of the token (`echo -n "$TOKEN" | sha256sum`)
+    pub sha256: String,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Quota of every token and client address without an override
+    pub default_quota: RateLimitQuota,
+    /// Quotas of specific tokens, by token name (or JWT subject)
+    pub token_quotas: HashMap<String, RateLimitQuota>,
+    /// Cost of matching routes; other routes cost 1
+    pub route_costs: Vec<RouteCost>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let cost = |method: &str, path: &str, cost| RouteCost {
+            method: method.to_string(),
+            path: path.to_string(),
+            cost,
+        };
+
+        Self {
+            enabled: true,
+            default_quota: RateLimitQuota::default(),
+            token_quotas: HashMap::new(),
+            route_costs: vec![
+                cost("POST", "/emails/bulk", 20),
+                cost("POST", "/emails/bulk-delete", 20),
+                cost("POST", "/emails/bulk-mark-read", 10),
+                cost("POST", "/emails/search", 5),
+                cost("DELETE", "/emails/{id}", 2),
+                cost("POST", "/admin/mfa/cleanup/run", 10),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitQuota {
+    /// Bucket size: cost that can be spent in a burst
+    pub burst: u32,
+    /// Cost refilled per minute
+    pub per_minute: u32,
+}
+
+impl Default for RateLimitQuota {
+    fn default() -> Self {
+        Self {
+            burst: 60,
+            per_minute: 120,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteCost {
+    /// HTTP method, or `*` for any
+    pub method: String,
+    /// Route pattern, e.g. `/emails/{id}`
+    pub path: String,
+    pub cost: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// Folder deleted messages are moved to; empty to use the folder the
+    /// server flags `\Trash`, falling back to `[Gmail]/Trash`
+    pub folder: String,
+    /// Seconds during which a delete can be undone
+    pub undo_window_secs: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            folder: String::new(),
+            undo_window_secs: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// Messages changed per UID command
+    pub batch_size: usize,
+    /// Finished jobs kept for `GET /jobs`
+    pub history_limit: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 500,
+            history_limit: 100,
+        }
+    }
+}
+
+/// Outgoing mail server; authenticates with the email address and app password
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    /// Implicit TLS (port 465). Disable only for a local relay.
+    pub tls: bool,
+    pub timeout_secs: u64,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            host: "smtp.gmail.com".to_string(),
+            port: 465,
+            tls: true,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SubscriptionsConfig {
+    /// Number of recent emails aggregated into subscriptions
+    pub scan_limit: u32,
+    /// Timeout of one-click unsubscribe requests
+    pub timeout_secs: u64,
+    /// Accept plain `http` one-click URLs (RFC 8058 requires https)
+    pub allow_http: bool,
+    /// Let one-click URLs reach loopback, private and link-local addresses
+    pub allow_private_hosts: bool,
+}
+
+impl Default for SubscriptionsConfig {
+    fn default() -> Self {
+        Self {
+            scan_limit: 200,
+            timeout_secs: 10,
+            allow_http: false,
+            allow_private_hosts: false,
+        }
+    }
+}
+
 impl Settings {
+    /// `config/default.toml` overridden by `APP_` environment variables, with
+    /// `__` between nested keys (`APP_MFA__CLEANUP__ENABLED=true`). The email
+    /// account defaults to `GMAIL_EMAIL` and `GMAIL_APP_PASSWORD`.
     pub fn from_env() -> Result<Self, ConfigError> {
+        let env_or =
+            |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+
         let config = Config::builder()
+            .set_default("server.host", "127.0.0.1")?
+            .set_default("server.port", 8080)?
+            .set_default(
+                "email.email_address",
+                env_or("GMAIL_EMAIL", "your-email@gmail.com"),
+            )?
+            .set_default(
+                "email.app_password",
+                env_or("GMAIL_APP_PASSWORD", "your-app-password"),
+            )?
             .add_source(File::with_name("config/default").required(false))
-            .add_source(Environment::with_prefix("APP").try_parsing(true))
+            .add_source(
+                Environment::with_prefix("APP")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .try_parsing(true),
+            )
             .build()?;
 
         config.try_deserialize()
@@ -6,6 +6,9 @@ pub enum ApiError {
     #[error("Authentication failed: {0}")]
     AuthenticationError(String),
 
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     #[error("Gmail API error: {0}")]
     GmailApiError(String),
 
@@ -23,6 +26,9 @@ pub enum ApiError {
 
     #[error("Connection error: {0}")]
     ConnectionError(String),
+
+    #[error("Request timed out: {0}")]
+    Timeout(String),
 }
 
 impl ResponseError for ApiError {
@@ -48,12 +54,24 @@ impl ResponseError for ApiError {
                     }
                 }))
             }
+            ApiError::Forbidden(_) => HttpResponse::Forbidden().json(serde_json::json!({
+                "error": {
+                    "code": "FORBIDDEN",
+                    "message": self.to_string()
+                }
+            })),
             ApiError::RateLimitError => HttpResponse::TooManyRequests().json(serde_json::json!({
                 "error": {
                     "code": "RATE_LIMIT_ERROR",
                     "message": self.to_string()
                 }
             })),
+            ApiError::Timeout(_) => HttpResponse::RequestTimeout().json(serde_json::json!({
+                "error": {
+                    "code": "TIMEOUT",
+                    "message": self.to_string()
+                }
+            })),
             _ => HttpResponse::InternalServerError().json(serde_json::json!({
                 "error": {
                     "code": "INTERNAL_ERROR",
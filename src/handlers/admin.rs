@@ -0,0 +1,163 @@
+use crate::errors::ApiError;
+use crate::handlers::emails::parse_since;
+use crate::services::audit_log::{AuditAction, AuditActor, AuditLog, AuditQuery};
+use crate::services::html_text;
+use crate::services::mfa_cleanup::MfaCleanup;
+use crate::services::mfa_extractor::{CodeType, MfaExtractor};
+use crate::services::mfa_registry::{CodePattern, MfaRegistry, PatternDefinition};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub type SharedMfaCleanup = Arc<MfaCleanup>;
+pub type SharedAuditLog = Arc<AuditLog>;
+
+#[derive(Debug, Deserialize)]
+pub struct TestPatternRequest {
+    /// Regex to test; a code is made of all its capture groups
+    pub pattern: String,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    pub code_type: Option<CodeType>,
+    pub subject: Option<String>,
+    pub sender: Option<String>,
+    #[serde(default)]
+    pub body: String,
+    pub html: Option<String>,
+}
+
+/// Test a code pattern against a sample email, alongside what the loaded
+/// registry extracts from the same email
+pub async fn test_mfa_pattern(
+    request: web::Json<TestPatternRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = request.into_inner();
+    let pattern = CodePattern::compile(&PatternDefinition {
+        name: "test".to_string(),
+        regex: request.pattern.clone(),
+        code_type: request.code_type.unwrap_or(CodeType::Numeric),
+        case_sensitive: request.case_sensitive,
+        confidence: 1.0,
+        language: None,
+    })?;
+
+    let mut text = request.body.clone();
+    if let Some(html) = &request.html {
+        text.push('\n');
+        text.push_str(&html_text::html_to_text(html).text);
+    }
+
+    let matches: Vec<String> = pattern
+        .regex()
+        .captures_iter(&text)
+        .map(|captures| {
+            captures
+                .iter()
+                .skip(1)
+                .flatten()
+                .map(|m| m.as_str())
+                .collect()
+        })
+        .collect();
+
+    let registry = MfaRegistry::global();
+    let service = registry.detect_service(request.sender.as_deref(), request.subject.as_deref());
+    let extracted = MfaExtractor::extract_codes_with_html(
+        "test",
+        request.subject.as_deref(),
+        request.sender.as_deref(),
+        Some(&request.body),
+        request.html.as_deref(),
+        Utc::now(),
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "pattern": request.pattern,
+        "matched": !matches.is_empty(),
+        "code": pattern.find(&text).map(|m| m.code),
+        "matches": matches,
+        "service": service,
+        "extracted": extracted
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CleanupRunParams {
+    /// Defaults to the configured `dry_run`
+    pub dry_run: Option<bool>,
+}
+
+/// Run the MFA email cleanup now instead of waiting for the background job
+pub async fn run_mfa_cleanup(
+    req: HttpRequest,
+    cleanup: web::Data<SharedMfaCleanup>,
+    query: web::Query<CleanupRunParams>,
+) -> Result<HttpResponse, ApiError> {
+    let dry_run = query.dry_run.unwrap_or(cleanup.config().dry_run);
+    let records = cleanup
+        .run_once(dry_run, AuditActor::from_request(&req))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "dry_run": dry_run,
+        "action": cleanup.config().action,
+        "records": records,
+        "count": records.len()
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQueryParams {
+    #[serde(default = "default_audit_limit")]
+    pub limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+pub async fn get_mfa_cleanup_audit(
+    cleanup: web::Data<SharedMfaCleanup>,
+    query: web::Query<AuditQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let records = cleanup.audit_log(query.limit).await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "records": records,
+        "count": records.len()
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQueryParams {
+    action: Option<AuditAction>,
+    email_id: Option<String>,
+    token: Option<String>,
+    /// RFC 3339 or Unix seconds
+    since: Option<String>,
+    #[serde(default = "default_audit_limit")]
+    limit: usize,
+}
+
+/// Audit records of mutating operations, newest first
+pub async fn get_audit_log(
+    audit_log: web::Data<SharedAuditLog>,
+    query: web::Query<AuditLogQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let records = audit_log
+        .query(&AuditQuery {
+            action: query.action,
+            email_id: query.email_id,
+            token: query.token,
+            since: query.since.as_deref().map(parse_since).transpose()?,
+            limit: query.limit,
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "records": records,
+        "count": records.len()
+    })))
+}
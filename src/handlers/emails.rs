@@ -1,14 +1,31 @@
 use crate::errors::ApiError;
-use crate::models::{BulkDeleteRequest, SearchQuery};
-use crate::services::imap_service::ImapService;
-use crate::services::mfa_extractor::MfaExtractor;
-use actix_web::{web, HttpResponse};
-use chrono::Utc;
+use crate::handlers::admin::SharedAuditLog;
+use crate::handlers::events::SharedEventBus;
+use crate::handlers::jobs::SharedJobQueue;
+use crate::middleware::auth::{require_scope, Scope};
+use crate::models::{
+    BulkAction, BulkDeleteRequest, BulkRequest, BulkSelector, EmailSummary, FlagsRequest,
+    LabelsRequest, MailboxTarget, SearchQuery,
+};
+use crate::services::audit_log::{AuditAction, AuditActor, AuditMessage, AuditRecord};
+use crate::services::event_bus::EventPayload;
+use crate::services::imap_service::{BulkOperation, ImapService};
+use crate::services::job_queue::{self, MAX_JOB_MESSAGES};
+use crate::services::mfa_extractor::{MfaCode, MfaExtractor};
+use crate::services::mfa_store::{MfaStore, StoredMfaCode};
+use crate::services::undo_store::{UndoEntry, UndoStore};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
 
 pub type SharedEmailService = Arc<Mutex<ImapService>>;
+pub type SharedMfaStore = Arc<MfaStore>;
+pub type SharedUndoStore = Arc<UndoStore>;
 
 pub async fn get_recent_emails(
     email_service: web::Data<SharedEmailService>,
@@ -119,11 +136,22 @@ pub async fn search_emails(
 }
 
 pub async fn mark_as_read(
+    req: HttpRequest,
     email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
     email_id: web::Path<String>,
 ) -> Result<HttpResponse, ApiError> {
     let service = email_service.lock().await;
-    service.mark_as_read(&email_id).await?;
+    let snapshot = service.cached_email(&email_id).await;
+    let result = service.mark_as_read(&email_id).await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::MarkRead)
+                .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                .with_result(&result),
+        )
+        .await;
+    result?;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Email marked as read",
@@ -132,11 +160,22 @@ pub async fn mark_as_read(
 }
 
 pub async fn mark_as_unread(
+    req: HttpRequest,
     email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
     email_id: web::Path<String>,
 ) -> Result<HttpResponse, ApiError> {
     let service = email_service.lock().await;
-    service.mark_as_unread(&email_id).await?;
+    let snapshot = service.cached_email(&email_id).await;
+    let result = service.mark_as_unread(&email_id).await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::MarkUnread)
+                .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                .with_result(&result),
+        )
+        .await;
+    result?;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": "Email marked as unread",
@@ -144,21 +183,261 @@ pub async fn mark_as_unread(
     })))
 }
 
+/// Move an email to another mailbox, e.g. to archive it
+pub async fn move_email(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
+    email_id: web::Path<String>,
+    request: web::Json<MailboxTarget>,
+) -> Result<HttpResponse, ApiError> {
+    let service = email_service.lock().await;
+    let snapshot = service.cached_email(&email_id).await;
+    let result = service
+        .move_email(&email_id, &request.mailbox, request.create)
+        .await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::Move)
+                .with_target(request.mailbox.clone())
+                .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Email moved",
+        "email_id": email_id.into_inner(),
+        "mailbox": request.mailbox
+    })))
+}
+
+/// Copy an email to another mailbox, keeping it in the inbox
+pub async fn copy_email(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
+    email_id: web::Path<String>,
+    request: web::Json<MailboxTarget>,
+) -> Result<HttpResponse, ApiError> {
+    let service = email_service.lock().await;
+    let snapshot = service.cached_email(&email_id).await;
+    let result = service
+        .copy_email(&email_id, &request.mailbox, request.create)
+        .await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::Copy)
+                .with_target(request.mailbox.clone())
+                .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Email copied",
+        "email_id": email_id.into_inner(),
+        "mailbox": request.mailbox
+    })))
+}
+
+/// Add or remove Gmail labels
+pub async fn modify_labels(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
+    email_id: web::Path<String>,
+    request: web::Json<LabelsRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let service = email_service.lock().await;
+    let snapshot = service.cached_email(&email_id).await;
+    let result = service
+        .modify_labels(&email_id, &request.add, &request.remove)
+        .await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::Labels)
+                .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "email_id": email_id.into_inner(),
+        "added": request.add,
+        "removed": request.remove
+    })))
+}
+
+/// Add or remove IMAP flags and keywords, e.g. `\Flagged` or `$Todo`
+pub async fn modify_flags(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
+    email_id: web::Path<String>,
+    request: web::Json<FlagsRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let service = email_service.lock().await;
+    let snapshot = service.cached_email(&email_id).await;
+    let result = service
+        .modify_flags(&email_id, &request.add, &request.remove)
+        .await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::Flags)
+                .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "email_id": email_id.into_inner(),
+        "added": request.add,
+        "removed": request.remove
+    })))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteParams {
+    /// Expunge instead of moving to the trash; requires the admin scope
+    #[serde(default)]
+    permanent: bool,
+}
+
+impl DeleteParams {
+    fn check_scope(&self, req: &HttpRequest) -> Result<(), ApiError> {
+        if self.permanent {
+            require_scope(req, Scope::Admin)?;
+        }
+        Ok(())
+    }
+
+    fn action(&self, bulk: bool) -> AuditAction {
+        match (self.permanent, bulk) {
+            (true, _) => AuditAction::PermanentDelete,
+            (false, true) => AuditAction::BulkDelete,
+            (false, false) => AuditAction::Delete,
+        }
+    }
+}
+
+fn undo_json(entry: &UndoEntry) -> serde_json::Value {
+    serde_json::json!({
+        "undo_token": entry.token,
+        "undo_expires_at": entry.expires_at,
+        "trash_folder": entry.emails.first().map(|email| &email.trash_folder)
+    })
+}
+
+fn json_merge(target: &mut serde_json::Value, source: serde_json::Value) {
+    if let (Some(target), serde_json::Value::Object(source)) = (target.as_object_mut(), source) {
+        target.extend(source);
+    }
+}
+
+/// Move an email to the trash, returning an undo token. With
+/// `?permanent=true` it is expunged instead, which needs the admin scope.
 pub async fn delete_email(
+    req: HttpRequest,
     email_service: web::Data<SharedEmailService>,
+    undo_store: web::Data<SharedUndoStore>,
+    audit_log: web::Data<SharedAuditLog>,
     email_id: web::Path<String>,
+    params: web::Query<DeleteParams>,
+) -> Result<HttpResponse, ApiError> {
+    params.check_scope(&req)?;
+
+    let service = email_service.lock().await;
+    // Deletions are looked up on the server when not cached, so the audit log
+    // can tell what was deleted
+    let snapshot = service.get_email_by_id(&email_id).await.ok();
+    let result = if params.permanent {
+        service.delete_email(&email_id).await.map(|_| None)
+    } else {
+        service.trash_email(&email_id).await.map(Some)
+    };
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), params.action(false))
+                .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                .with_result(&result),
+        )
+        .await;
+
+    let mut response = serde_json::json!({
+        "message": if params.permanent { "Email permanently deleted" } else { "Email moved to trash" },
+        "email_id": email_id.into_inner(),
+        "permanent": params.permanent
+    });
+    if let Some(trashed) = result? {
+        let entry = undo_store.register(vec![trashed]).await?;
+        json_merge(&mut response, undo_json(&entry));
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Move the emails of a delete back out of the trash
+pub async fn undo_delete(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    undo_store: web::Data<SharedUndoStore>,
+    audit_log: web::Data<SharedAuditLog>,
+    token: web::Path<String>,
 ) -> Result<HttpResponse, ApiError> {
+    let entry = undo_store.take(&token).await?;
+
     let service = email_service.lock().await;
-    service.delete_email(&email_id).await?;
+    let mut messages = Vec::new();
+    let mut failed = Vec::new();
+    for email in &entry.emails {
+        let restored = match service.restore_email(email).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("Failed to restore email {}: {}", email.email_id, e);
+                failed.push(email.clone());
+                false
+            }
+        };
+        messages.push(AuditMessage::new(&email.email_id, None, restored));
+    }
+    drop(service);
+
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::Restore)
+                .with_target(entry.token.clone())
+                .with_messages(messages),
+        )
+        .await;
+
+    let restored = entry.emails.len() - failed.len();
+    let failed_ids: Vec<String> = failed.iter().map(|e| e.email_id.clone()).collect();
+    if !failed.is_empty() {
+        // Keep the token usable for the messages that are still in the trash
+        undo_store
+            .reinstate(UndoEntry {
+                emails: failed,
+                ..entry
+            })
+            .await?;
+    }
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "Email deleted",
-        "email_id": email_id.into_inner()
+        "restored": restored,
+        "failed": failed_ids.len(),
+        "failed_ids": failed_ids
     })))
 }
 
 pub async fn bulk_mark_as_read(
+    req: HttpRequest,
     email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse, ApiError> {
     // Get count from query params (default to 50)
@@ -171,7 +450,21 @@ pub async fn bulk_mark_as_read(
     let count = count.min(500);
 
     let service = email_service.lock().await;
-    let marked_count = service.mark_multiple_as_read(count).await?;
+    let result = service.mark_multiple_as_read(count).await;
+
+    let mut messages = Vec::new();
+    for email_id in result.as_deref().unwrap_or_default() {
+        let snapshot = service.cached_email(email_id).await;
+        messages.push(AuditMessage::new(email_id, snapshot.as_ref(), true));
+    }
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::BulkMarkRead)
+                .with_messages(messages)
+                .with_result(&result),
+        )
+        .await;
+    let marked_count = result?.len();
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
@@ -182,31 +475,145 @@ pub async fn bulk_mark_as_read(
 }
 
 pub async fn bulk_delete(
+    req: HttpRequest,
     email_service: web::Data<SharedEmailService>,
+    undo_store: web::Data<SharedUndoStore>,
+    audit_log: web::Data<SharedAuditLog>,
     request: web::Json<BulkDeleteRequest>,
+    params: web::Query<DeleteParams>,
 ) -> Result<HttpResponse, ApiError> {
     if request.ids.is_empty() {
         return Err(ApiError::ValidationError(
             "No email IDs provided for deletion".to_string(),
         ));
     }
+    params.check_scope(&req)?;
+
+    // Ids are sequence numbers, which shift as soon as a message is removed,
+    // so resolve them all to UIDs and remove the messages in one command
+    let (valid_ids, mut failed_ids): (Vec<String>, Vec<String>) = request
+        .ids
+        .iter()
+        .cloned()
+        .partition(|id| id.parse::<u32>().is_ok());
 
     let service = email_service.lock().await;
-    let mut deleted_count = 0;
-    let mut failed_ids = Vec::new();
+    let selected = if valid_ids.is_empty() {
+        Vec::new()
+    } else {
+        service
+            .select_messages(&BulkSelector::Ids(valid_ids.clone()), valid_ids.len())
+            .await?
+    };
+
+    let mut snapshots = HashMap::new();
+    for message in &selected {
+        if let Ok(email) = service.get_email_by_id(&message.id()).await {
+            snapshots.insert(message.id(), email);
+        }
+    }
+
+    let operation = if params.permanent {
+        BulkOperation::Expunge
+    } else {
+        BulkOperation::Trash
+    };
+    let (deleted_ids, trashed) = match service.apply_bulk(&selected, &operation).await {
+        Ok(trashed) => (selected.iter().map(|m| m.id()).collect(), trashed),
+        Err(e) => {
+            tracing::warn!("Bulk delete failed: {}", e);
+            (HashSet::new(), Vec::new())
+        }
+    };
+    drop(service);
 
+    let mut messages = Vec::new();
     for email_id in &request.ids {
-        match service.delete_email(email_id).await {
-            Ok(_) => deleted_count += 1,
-            Err(_) => failed_ids.push(email_id.clone()),
+        let deleted = deleted_ids.contains(email_id);
+        if !deleted && !failed_ids.contains(email_id) {
+            failed_ids.push(email_id.clone());
         }
+        messages.push(AuditMessage::new(
+            email_id,
+            snapshots.get(email_id),
+            deleted,
+        ));
     }
+    let deleted_count = deleted_ids.len();
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), params.action(true))
+                .with_messages(messages),
+        )
+        .await;
+
+    let mut response = serde_json::json!({
         "deleted": deleted_count,
         "failed": failed_ids.len(),
-        "failed_ids": failed_ids
-    })))
+        "failed_ids": failed_ids,
+        "permanent": params.permanent
+    });
+    if !trashed.is_empty() {
+        let entry = undo_store.register(trashed).await?;
+        json_merge(&mut response, undo_json(&entry));
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Dry runs return summaries of at most this many messages
+const BULK_PREVIEW_LIMIT: usize = 100;
+
+/// Apply an action to the messages picked by an id list, a search query or a
+/// date range. Dry runs are answered directly; other requests are queued as
+/// a job processed in UID batches.
+pub async fn bulk_operation(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    job_queue: web::Data<SharedJobQueue>,
+    request: web::Json<BulkRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = request.into_inner();
+    job_queue::bulk_operation(&request)?;
+    if matches!(&request.selector, BulkSelector::Ids(ids) if ids.is_empty()) {
+        return Err(ApiError::ValidationError(
+            "No email IDs provided".to_string(),
+        ));
+    }
+
+    if request.dry_run {
+        // The route only requires `emails:read`, which is enough for a dry run
+        let limit = request.limit.clamp(1, MAX_JOB_MESSAGES);
+        let service = email_service.lock().await;
+        let selected = service.select_messages(&request.selector, limit).await?;
+        let preview_count = selected.len().min(BULK_PREVIEW_LIMIT);
+        let emails = service.preview_messages(&selected[..preview_count]).await?;
+        let email_ids: Vec<String> = selected.iter().map(|message| message.id()).collect();
+
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "dry_run": true,
+            "action": request.action,
+            "count": selected.len(),
+            "email_ids": email_ids,
+            "emails": emails
+        })));
+    }
+
+    match request.action {
+        BulkAction::Delete => require_scope(&req, Scope::EmailsDelete)?,
+        _ => require_scope(&req, Scope::EmailsWrite)?,
+    }
+    if request.permanent {
+        require_scope(&req, Scope::Admin)?;
+    }
+
+    let job = job_queue
+        .enqueue(request, AuditActor::from_request(&req))
+        .await?;
+    Ok(HttpResponse::Accepted()
+        .insert_header(("Location", format!("/jobs/{}", job.id)))
+        .json(job))
 }
 
 #[derive(Deserialize)]
@@ -216,6 +623,15 @@ pub struct MfaQueryParams {
     #[serde(default = "default_minutes")]
     minutes: u32,
     service: Option<String>,
+    /// Skip codes (and alternatives) below this confidence, from 0 to 1
+    #[serde(default)]
+    min_confidence: f32,
+    /// `/mfa/latest` skips expired codes unless this is set
+    #[serde(default)]
+    include_expired: bool,
+    /// `/mfa/latest` skips consumed codes unless this is set
+    #[serde(default)]
+    include_consumed: bool,
 }
 
 fn default_limit() -> u32 {
@@ -228,6 +644,7 @@ fn default_minutes() -> u32 {
 
 pub async fn get_mfa_codes(
     email_service: web::Data<SharedEmailService>,
+    mfa_store: web::Data<SharedMfaStore>,
     query: web::Query<MfaQueryParams>,
 ) -> Result<HttpResponse, ApiError> {
     let service = email_service.lock().await;
@@ -269,29 +686,9 @@ pub async fn get_mfa_codes(
     let mut all_codes = Vec::new();
 
     for email in recent_emails {
-        // Use full body if available, otherwise fall back to snippet
-        let text_to_search = email.body.as_deref().unwrap_or(&email.snippet);
-
-        // First try to extract from body
-        let mut codes = MfaExtractor::extract_codes(
-            &email.id,
-            Some(&email.subject),
-            Some(&email.sender_email),
-            Some(text_to_search),
-            email.date,
-        );
-
-        // If no codes found in body, try extracting from subject
-        if codes.is_empty() && email.subject.chars().any(|c| c.is_ascii_digit()) {
-            tracing::debug!("No code in body, trying subject: {}", email.subject);
-            codes = MfaExtractor::extract_codes(
-                &email.id,
-                Some(&email.subject),
-                Some(&email.sender_email),
-                Some(&email.subject), // Use subject as body
-                email.date,
-            );
-        }
+        let codes = MfaExtractor::extract_from_email(&email)
+            .into_iter()
+            .filter_map(|code| code.with_min_confidence(query.min_confidence));
 
         // If we found codes and there's a service filter, apply it
         if let Some(ref filter_service) = query.service {
@@ -309,21 +706,26 @@ pub async fn get_mfa_codes(
     }
 
     // Sort by date (newest first)
-    all_codes.sort_by(|a, b| b.email_date.cmp(&a.email_date));
+    all_codes.sort_by_key(|code| std::cmp::Reverse(code.email_date));
 
     // Limit the number of codes returned
     all_codes.truncate(query.limit as usize);
 
+    // Persist the codes and report their expiry and consumed state
+    let all_codes = mfa_store.record(all_codes).await?;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "codes": all_codes,
         "count": all_codes.len(),
         "search_window_minutes": query.minutes,
-        "service_filter": query.service
+        "service_filter": query.service,
+        "min_confidence": query.min_confidence
     })))
 }
 
 pub async fn get_latest_mfa_code(
     email_service: web::Data<SharedEmailService>,
+    mfa_store: web::Data<SharedMfaStore>,
     query: web::Query<MfaQueryParams>,
 ) -> Result<HttpResponse, ApiError> {
     let service = email_service.lock().await;
@@ -345,51 +747,29 @@ pub async fn get_latest_mfa_code(
             break; // Stop if we've gone past the time window
         }
 
-        // Use full body if available, otherwise fall back to snippet
-        let text_to_search = email.body.as_deref().unwrap_or(&email.snippet);
-
-        // First try to extract from body
-        let mut codes = MfaExtractor::extract_codes(
-            &email.id,
-            Some(&email.subject),
-            Some(&email.sender_email),
-            Some(text_to_search),
-            email.date,
-        );
+        let codes: Vec<_> = MfaExtractor::extract_from_email(&email)
+            .into_iter()
+            .filter_map(|code| code.with_min_confidence(query.min_confidence))
+            .filter(|code| code_matches_service(code, query.service.as_deref()))
+            .collect();
 
-        // If no codes found in body, try extracting from subject
-        if codes.is_empty() && email.subject.chars().any(|c| c.is_ascii_digit()) {
-            tracing::debug!("No code in body, trying subject: {}", email.subject);
-            codes = MfaExtractor::extract_codes(
-                &email.id,
-                Some(&email.subject),
-                Some(&email.sender_email),
-                Some(&email.subject), // Use subject as body
-                email.date,
-            );
+        if codes.is_empty() {
+            continue;
         }
 
-        if !codes.is_empty() {
-            // If there's a service filter, check if it matches
-            if let Some(ref filter_service) = query.service {
-                let filter_lower = filter_service.to_lowercase();
-                for code in codes {
-                    if let Some(ref service) = code.service {
-                        if service.to_lowercase().contains(&filter_lower) {
-                            return Ok(HttpResponse::Ok().json(code));
-                        }
-                    }
-                }
-            } else {
-                // Return the first code found
-                return Ok(HttpResponse::Ok().json(&codes[0]));
-            }
+        // Return the first code that is still usable, unless asked otherwise
+        let usable = mfa_store.record(codes).await?.into_iter().find(|stored| {
+            (query.include_expired || !stored.expired)
+                && (query.include_consumed || !stored.is_consumed())
+        });
+        if let Some(stored) = usable {
+            return Ok(HttpResponse::Ok().json(stored));
         }
     }
 
     // No MFA code found
     Err(ApiError::NotFound(format!(
-        "No MFA code found in emails from the last {} minutes{}",
+        "No unused MFA code found in emails from the last {} minutes{}",
         query.minutes,
         if let Some(ref s) = query.service {
             format!(" for service: {}", s)
@@ -398,3 +778,306 @@ pub async fn get_latest_mfa_code(
         }
     )))
 }
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ConsumeCodeRequest {
+    /// Only consume this code; defaults to every code of the email
+    pub code: Option<String>,
+    /// Also set the email's \Seen flag
+    pub mark_read: bool,
+    /// Also move the email to the trash
+    pub delete: bool,
+}
+
+/// Mark the codes of an email as used so `/mfa/latest` stops returning them
+pub async fn consume_mfa_code(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    mfa_store: web::Data<SharedMfaStore>,
+    undo_store: web::Data<SharedUndoStore>,
+    audit_log: web::Data<SharedAuditLog>,
+    path: web::Path<String>,
+    request: Option<web::Json<ConsumeCodeRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let email_id = path.into_inner();
+    let request = request.map(|r| r.into_inner()).unwrap_or_default();
+
+    // The route only requires `mfa:read`; changing the email needs more
+    if request.delete {
+        require_scope(&req, Scope::EmailsDelete)?;
+    } else if request.mark_read {
+        require_scope(&req, Scope::EmailsWrite)?;
+    }
+
+    // Codes the API has not seen yet (e.g. with the mailbox watcher disabled)
+    // are extracted now
+    if mfa_store.get(&email_id).await.is_empty() {
+        let email = email_service
+            .lock()
+            .await
+            .get_email_by_id(&email_id)
+            .await?;
+        mfa_store
+            .record(MfaExtractor::extract_from_email(&email))
+            .await?;
+    }
+
+    let consumed = mfa_store
+        .consume(&email_id, request.code.as_deref())
+        .await?;
+
+    let service = email_service.lock().await;
+    let mut undo = None;
+    if request.delete {
+        let snapshot = service.cached_email(&email_id).await;
+        let result = service.trash_email(&email_id).await;
+        audit_log
+            .record(
+                AuditRecord::new(AuditActor::from_request(&req), AuditAction::Delete)
+                    .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                    .with_result(&result),
+            )
+            .await;
+        undo = Some(undo_store.register(vec![result?]).await?);
+    } else if request.mark_read {
+        let snapshot = service.cached_email(&email_id).await;
+        let result = service.mark_as_read(&email_id).await;
+        audit_log
+            .record(
+                AuditRecord::new(AuditActor::from_request(&req), AuditAction::MarkRead)
+                    .with_messages(vec![AuditMessage::new(&email_id, snapshot.as_ref(), true)])
+                    .with_result(&result),
+            )
+            .await;
+        result?;
+    }
+
+    let mut response = serde_json::json!({
+        "email_id": email_id,
+        "consumed": consumed,
+        "marked_read": request.mark_read && !request.delete,
+        "deleted": request.delete
+    });
+    if let Some(entry) = &undo {
+        json_merge(&mut response, undo_json(entry));
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[derive(Deserialize)]
+pub struct MfaWaitParams {
+    service: Option<String>,
+    /// Seconds to hold the request open (default: 60, max: 300)
+    #[serde(default = "default_wait_timeout")]
+    timeout: u64,
+    /// Only accept codes from emails newer than this (RFC 3339 or Unix seconds).
+    /// Defaults to the time of the request.
+    since: Option<String>,
+    #[serde(default)]
+    min_confidence: f32,
+}
+
+fn default_wait_timeout() -> u64 {
+    60
+}
+
+const MAX_WAIT_TIMEOUT_SECS: u64 = 300;
+const WAIT_SEARCH_LIMIT: u32 = 20;
+
+/// Fetches the emails `/mfa/wait` looks through for codes delivered before
+/// the request. Defaults to the newest emails in the inbox; tests install one
+/// that stays off the network.
+pub type MfaWaitPoll =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Vec<EmailSummary>, ApiError>> + Send + Sync>;
+
+/// Fetch the newest emails with a clone of the service, so the shared lock
+/// is only held to take the clone
+fn inbox_poll(email_service: SharedEmailService) -> MfaWaitPoll {
+    Arc::new(move || {
+        let email_service = email_service.clone();
+        Box::pin(async move {
+            let service = email_service.lock().await.clone();
+            service.get_recent_emails_fresh(WAIT_SEARCH_LIMIT).await
+        })
+    })
+}
+
+pub(crate) fn parse_since(since: &str) -> Result<DateTime<Utc>, ApiError> {
+    if let Ok(seconds) = since.parse::<i64>() {
+        return DateTime::from_timestamp(seconds, 0)
+            .ok_or_else(|| ApiError::ValidationError("Invalid 'since' timestamp".to_string()));
+    }
+
+    DateTime::parse_from_rfc3339(since)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|_| {
+            ApiError::ValidationError("Invalid 'since'. Use RFC 3339 or Unix seconds".to_string())
+        })
+}
+
+fn code_matches(
+    code: &MfaCode,
+    since: DateTime<Utc>,
+    service_filter: Option<&str>,
+    min_confidence: f32,
+) -> bool {
+    code.email_date > since
+        && code.confidence >= min_confidence
+        && code_matches_service(code, service_filter)
+}
+
+fn code_matches_service(code: &MfaCode, service_filter: Option<&str>) -> bool {
+    match service_filter {
+        Some(filter_service) => code
+            .service
+            .as_ref()
+            .map(|service| {
+                service
+                    .to_lowercase()
+                    .contains(&filter_service.to_lowercase())
+            })
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+/// Look through the newest emails for a matching code
+async fn find_waited_code(
+    poll: &MfaWaitPoll,
+    mfa_store: &MfaStore,
+    since: DateTime<Utc>,
+    service_filter: Option<&str>,
+    min_confidence: f32,
+) -> Result<Option<StoredMfaCode>, ApiError> {
+    let emails = poll().await?;
+
+    let codes: Vec<_> = emails
+        .iter()
+        .filter(|email| email.date > since)
+        .flat_map(MfaExtractor::extract_from_email)
+        .filter(|code| code_matches(code, since, service_filter, min_confidence))
+        .filter_map(|code| code.with_min_confidence(min_confidence))
+        .collect();
+
+    usable_code(mfa_store, codes).await
+}
+
+/// Record the codes and return the first one that is neither expired nor consumed
+async fn usable_code(
+    mfa_store: &MfaStore,
+    codes: Vec<MfaCode>,
+) -> Result<Option<StoredMfaCode>, ApiError> {
+    Ok(mfa_store
+        .record(codes)
+        .await?
+        .into_iter()
+        .find(|stored| !stored.expired && !stored.is_consumed()))
+}
+
+/// Hold the request until a code newer than `since` arrives or the timeout expires.
+///
+/// New codes come from the mailbox watcher's `mfa_code_detected` events. When
+/// `since` is before the request, codes already recorded and, failing that,
+/// the newest emails in the inbox are checked once first.
+pub async fn wait_for_mfa_code(
+    email_service: web::Data<SharedEmailService>,
+    mfa_store: web::Data<SharedMfaStore>,
+    event_bus: web::Data<SharedEventBus>,
+    wait_poll: Option<web::Data<MfaWaitPoll>>,
+    query: web::Query<MfaWaitParams>,
+) -> Result<HttpResponse, ApiError> {
+    let requested_at = Utc::now();
+    let since = match query.since {
+        Some(ref since) => parse_since(since)?,
+        None => requested_at,
+    };
+    let timeout = Duration::from_secs(query.timeout.clamp(1, MAX_WAIT_TIMEOUT_SECS));
+    let service_filter = query.service.as_deref();
+    let min_confidence = query.min_confidence;
+
+    tracing::info!(
+        "Waiting up to {:?} for MFA code newer than {} (service: {:?})",
+        timeout,
+        since,
+        service_filter
+    );
+
+    // Subscribe before looking at earlier mail so no event is missed in between
+    let mut receiver = event_bus.subscribe();
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    if since < requested_at {
+        let recorded = mfa_store
+            .since(since)
+            .await
+            .into_iter()
+            .filter(|stored| code_matches(&stored.code, since, service_filter, min_confidence))
+            .find(|stored| !stored.expired && !stored.is_consumed());
+        if let Some(stored) = recorded {
+            return Ok(HttpResponse::Ok().json(stored));
+        }
+
+        // A slow fetch must not hold the request past its deadline; on
+        // errors the wait carries on with events alone
+        let wait_poll = match wait_poll {
+            Some(wait_poll) => wait_poll.get_ref().clone(),
+            None => inbox_poll(email_service.get_ref().clone()),
+        };
+        let found = find_waited_code(
+            &wait_poll,
+            &mfa_store,
+            since,
+            service_filter,
+            min_confidence,
+        );
+        match tokio::time::timeout_at(deadline, found).await {
+            Err(_) => return Err(wait_timeout(timeout, service_filter)),
+            Ok(Ok(Some(code))) => return Ok(HttpResponse::Ok().json(code)),
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => tracing::warn!("MFA wait inbox check failed: {}", e),
+        }
+    }
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => break,
+            result = receiver.recv() => match result {
+                Ok(event) => {
+                    if let EventPayload::MfaCodeDetected(code) = event.payload {
+                        let codes: Vec<_> = code
+                            .with_min_confidence(min_confidence)
+                            .filter(|code| code_matches(code, since, service_filter, min_confidence))
+                            .into_iter()
+                            .collect();
+                        if let Some(stored) = usable_code(&mfa_store, codes).await? {
+                            return Ok(HttpResponse::Ok().json(stored));
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("MFA wait lagged behind event bus, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    tokio::time::sleep_until(deadline).await;
+                    break;
+                }
+            },
+        }
+    }
+
+    Err(wait_timeout(timeout, service_filter))
+}
+
+fn wait_timeout(timeout: Duration, service_filter: Option<&str>) -> ApiError {
+    ApiError::Timeout(format!(
+        "No MFA code received within {} seconds{}",
+        timeout.as_secs(),
+        if let Some(s) = service_filter {
+            format!(" for service: {}", s)
+        } else {
+            String::new()
+        }
+    ))
+}
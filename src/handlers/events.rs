@@ -0,0 +1,101 @@
+use crate::services::event_bus::{EventBus, EventFilter, MailboxEvent};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use futures::{stream, Stream, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub type SharedEventBus = Arc<EventBus>;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Stream mailbox events as Server-Sent Events.
+///
+/// Clients reconnecting with `Last-Event-ID` first receive the events they
+/// missed that are still in the bus log, then the live stream.
+pub async fn stream_events(
+    req: HttpRequest,
+    event_bus: web::Data<SharedEventBus>,
+    filter: web::Query<EventFilter>,
+) -> HttpResponse {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|id| id.trim().parse::<u64>().ok());
+
+    // Subscribe before reading the log so nothing published in between is lost
+    let receiver = event_bus.subscribe();
+    let backlog = last_event_id
+        .map(|id| event_bus.events_since(id))
+        .unwrap_or_default();
+
+    tracing::info!(
+        "SSE client connected (last_event_id: {:?}, replaying {} events)",
+        last_event_id,
+        backlog.len()
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(event_stream(receiver, backlog, filter.into_inner()))
+}
+
+fn event_stream(
+    receiver: broadcast::Receiver<MailboxEvent>,
+    backlog: Vec<MailboxEvent>,
+    filter: EventFilter,
+) -> impl Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    let replayed_up_to = backlog.last().map(|event| event.id).unwrap_or(0);
+
+    let replay_filter = filter.clone();
+    let replay = stream::iter(
+        backlog
+            .into_iter()
+            .filter(move |event| replay_filter.matches(event))
+            .map(|event| Ok(format_event(&event))),
+    );
+
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+    keep_alive.reset();
+
+    let live = stream::unfold(
+        (receiver, keep_alive, filter),
+        move |(mut receiver, mut keep_alive, filter)| async move {
+            loop {
+                tokio::select! {
+                    result = receiver.recv() => match result {
+                        Ok(event) => {
+                            if event.id <= replayed_up_to || !filter.matches(&event) {
+                                continue;
+                            }
+                            let bytes = format_event(&event);
+                            return Some((Ok(bytes), (receiver, keep_alive, filter)));
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            tracing::warn!("SSE client lagged behind, skipped {} events", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    },
+                    _ = keep_alive.tick() => {
+                        let bytes = web::Bytes::from_static(b": keep-alive\n\n");
+                        return Some((Ok(bytes), (receiver, keep_alive, filter)));
+                    }
+                }
+            }
+        },
+    );
+
+    replay.chain(live)
+}
+
+fn format_event(event: &MailboxEvent) -> web::Bytes {
+    let data = serde_json::to_string(event).unwrap_or_else(|_| "{}".to_string());
+    web::Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.id,
+        event.payload.event_type().as_str(),
+        data
+    ))
+}
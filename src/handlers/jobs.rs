@@ -0,0 +1,30 @@
+use crate::errors::ApiError;
+use crate::services::job_queue::JobQueue;
+use actix_web::{web, HttpResponse};
+use std::sync::Arc;
+
+pub type SharedJobQueue = Arc<JobQueue>;
+
+pub async fn list_jobs(job_queue: web::Data<SharedJobQueue>) -> Result<HttpResponse, ApiError> {
+    let jobs = job_queue.list().await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "jobs": jobs,
+        "count": jobs.len()
+    })))
+}
+
+pub async fn get_job(
+    job_queue: web::Data<SharedJobQueue>,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(job_queue.get(&job_id).await?))
+}
+
+/// Stop a job before its next batch
+pub async fn cancel_job(
+    job_queue: web::Data<SharedJobQueue>,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(job_queue.cancel(&job_id).await?))
+}
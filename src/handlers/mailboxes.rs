@@ -0,0 +1,94 @@
+use crate::errors::ApiError;
+use crate::handlers::admin::SharedAuditLog;
+use crate::handlers::emails::SharedEmailService;
+use crate::models::MailboxRequest;
+use crate::services::audit_log::{AuditAction, AuditActor, AuditRecord};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+pub async fn list_mailboxes(
+    email_service: web::Data<SharedEmailService>,
+) -> Result<HttpResponse, ApiError> {
+    let mailboxes = email_service.lock().await.list_mailboxes().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "mailboxes": mailboxes,
+        "count": mailboxes.len()
+    })))
+}
+
+pub async fn create_mailbox(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
+    request: web::Json<MailboxRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let result = email_service
+        .lock()
+        .await
+        .create_mailbox(&request.name)
+        .await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::MailboxCreate)
+                .with_target(request.name.clone())
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "message": "Mailbox created",
+        "name": request.name
+    })))
+}
+
+/// Rename the mailbox in the path to the name in the body
+pub async fn rename_mailbox(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
+    name: web::Path<String>,
+    request: web::Json<MailboxRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let result = email_service
+        .lock()
+        .await
+        .rename_mailbox(&name, &request.name)
+        .await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::MailboxRename)
+                .with_target(format!("{} -> {}", name, request.name))
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Mailbox renamed",
+        "from": name.into_inner(),
+        "name": request.name
+    })))
+}
+
+pub async fn delete_mailbox(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    audit_log: web::Data<SharedAuditLog>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let result = email_service.lock().await.delete_mailbox(&name).await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::MailboxDelete)
+                .with_target(name.as_str())
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Mailbox deleted",
+        "name": name.into_inner()
+    })))
+}
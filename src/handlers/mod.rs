@@ -1,4 +1,13 @@
+pub mod admin;
 pub mod emails;
+pub mod events;
+pub mod jobs;
+pub mod mailboxes;
+pub mod rules;
+pub mod subscriptions;
+pub mod tokens;
+pub mod totp;
+pub mod webhooks;
 
 use actix_web::HttpResponse;
 
@@ -0,0 +1,192 @@
+use crate::errors::ApiError;
+use crate::handlers::admin::SharedAuditLog;
+use crate::handlers::emails::SharedEmailService;
+use crate::services::audit_log::{AuditAction, AuditActor, AuditRecord};
+use crate::services::rules::{RuleRequest, RulesEngine, SampleMessage};
+use crate::services::sieve;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub type SharedRulesEngine = Arc<RulesEngine>;
+
+pub async fn list_rules(rules: web::Data<SharedRulesEngine>) -> Result<HttpResponse, ApiError> {
+    let rules = rules.list().await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "rules": rules,
+        "count": rules.len()
+    })))
+}
+
+pub async fn get_rule(
+    rules: web::Data<SharedRulesEngine>,
+    rule_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(rules.get(&rule_id).await?))
+}
+
+pub async fn create_rule(
+    req: HttpRequest,
+    rules: web::Data<SharedRulesEngine>,
+    audit_log: web::Data<SharedAuditLog>,
+    request: web::Json<RuleRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let rule = rules.create(request.into_inner()).await?;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::RuleCreate)
+                .with_target(&rule.id),
+        )
+        .await;
+
+    Ok(HttpResponse::Created().json(rule))
+}
+
+/// Replace a rule's name, conditions and actions
+pub async fn update_rule(
+    req: HttpRequest,
+    rules: web::Data<SharedRulesEngine>,
+    audit_log: web::Data<SharedAuditLog>,
+    rule_id: web::Path<String>,
+    request: web::Json<RuleRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let result = rules.update(&rule_id, request.into_inner()).await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::RuleUpdate)
+                .with_target(rule_id.as_str())
+                .with_result(&result),
+        )
+        .await;
+
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+pub async fn delete_rule(
+    req: HttpRequest,
+    rules: web::Data<SharedRulesEngine>,
+    audit_log: web::Data<SharedAuditLog>,
+    rule_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let result = rules.delete(&rule_id).await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::RuleDelete)
+                .with_target(rule_id.as_str())
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Rule deleted",
+        "rule_id": rule_id.into_inner()
+    })))
+}
+
+/// A stored message (`email_id`) or one given inline (`message`)
+#[derive(Debug, Deserialize)]
+pub struct TestRulesRequest {
+    pub email_id: Option<String>,
+    pub message: Option<SampleMessage>,
+}
+
+/// Which rules would apply to a message, without changing anything
+pub async fn test_rules(
+    rules: web::Data<SharedRulesEngine>,
+    email_service: web::Data<SharedEmailService>,
+    request: web::Json<TestRulesRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let email = match request.into_inner() {
+        TestRulesRequest {
+            email_id: Some(email_id),
+            message: None,
+        } => {
+            let service = email_service.lock().await;
+            service.get_email_by_id(&email_id).await?
+        }
+        TestRulesRequest {
+            email_id: None,
+            message: Some(message),
+        } => message.into_email(),
+        _ => {
+            return Err(ApiError::ValidationError(
+                "Give either 'email_id' or 'message'".to_string(),
+            ))
+        }
+    };
+
+    let matched: Vec<_> = rules
+        .matching(&email)
+        .await
+        .into_iter()
+        .map(|rule| {
+            serde_json::json!({
+                "id": rule.id,
+                "name": rule.name,
+                "actions": rule.actions,
+                "stop": rule.stop
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "email_id": email.id,
+        "matches": matched,
+        "count": matched.len()
+    })))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportParams {
+    /// Only return the translated rules
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Add the rules of a Sieve script (the request body) after the existing ones
+pub async fn import_sieve(
+    req: HttpRequest,
+    rules: web::Data<SharedRulesEngine>,
+    audit_log: web::Data<SharedAuditLog>,
+    query: web::Query<ImportParams>,
+    script: String,
+) -> Result<HttpResponse, ApiError> {
+    let import = sieve::import(&script)?;
+
+    if query.dry_run {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "rules": import.rules,
+            "count": import.rules.len(),
+            "unsupported": import.unsupported,
+            "dry_run": true
+        })));
+    }
+
+    let mut created = Vec::new();
+    for request in import.rules {
+        let rule = rules.create(request).await?;
+        audit_log
+            .record(
+                AuditRecord::new(AuditActor::from_request(&req), AuditAction::RuleCreate)
+                    .with_target(&rule.id),
+            )
+            .await;
+        created.push(rule);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "rules": created,
+        "count": created.len(),
+        "unsupported": import.unsupported,
+        "dry_run": false
+    })))
+}
+
+/// The rules as a Sieve script
+pub async fn export_sieve(rules: web::Data<SharedRulesEngine>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/sieve; charset=utf-8")
+        .body(sieve::export(&rules.list().await)))
+}
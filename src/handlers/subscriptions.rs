@@ -0,0 +1,54 @@
+use crate::errors::ApiError;
+use crate::handlers::admin::SharedAuditLog;
+use crate::middleware::auth::{require_scope, Scope};
+use crate::services::audit_log::{AuditAction, AuditActor, AuditRecord};
+use crate::services::subscriptions::{SubscriptionManager, UnsubscribeMethod, UnsubscribeStatus};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::sync::Arc;
+
+pub type SharedSubscriptionManager = Arc<SubscriptionManager>;
+
+/// Newsletter and mailing-list senders among the recent emails
+pub async fn list_subscriptions(
+    subscriptions: web::Data<SharedSubscriptionManager>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriptions = subscriptions.list().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "subscriptions": subscriptions,
+        "count": subscriptions.len()
+    })))
+}
+
+/// Unsubscribe with one-click or, with the `send` scope, by mail
+pub async fn unsubscribe(
+    req: HttpRequest,
+    subscriptions: web::Data<SharedSubscriptionManager>,
+    audit_log: web::Data<SharedAuditLog>,
+    subscription_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    if let UnsubscribeMethod::Mailto { .. } = subscriptions.method(&subscription_id).await? {
+        require_scope(&req, Scope::Send)?;
+    }
+
+    // Attempts are recorded whether or not the sender accepts them
+    let record = subscriptions.unsubscribe(&subscription_id).await?;
+    let outcome = match record.status {
+        UnsubscribeStatus::Succeeded => Ok(()),
+        UnsubscribeStatus::Failed => Err(ApiError::ConnectionError(
+            record.error.clone().unwrap_or_default(),
+        )),
+    };
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::Unsubscribe)
+                .with_target(subscription_id.as_str())
+                .with_result(&outcome),
+        )
+        .await;
+
+    Ok(match outcome {
+        Ok(()) => HttpResponse::Ok().json(record),
+        Err(_) => HttpResponse::BadGateway().json(record),
+    })
+}
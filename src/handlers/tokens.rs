@@ -0,0 +1,59 @@
+use crate::errors::ApiError;
+use crate::handlers::admin::SharedAuditLog;
+use crate::services::audit_log::{AuditAction, AuditActor, AuditRecord};
+use crate::services::token_store::{CreateTokenRequest, TokenStore};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::sync::Arc;
+
+pub type SharedTokenStore = Arc<TokenStore>;
+
+pub async fn create_token(
+    req: HttpRequest,
+    token_store: web::Data<SharedTokenStore>,
+    audit_log: web::Data<SharedAuditLog>,
+    request: web::Json<CreateTokenRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let created = token_store.create(request.into_inner()).await?;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::TokenCreate)
+                .with_target(&created.info.id),
+        )
+        .await;
+
+    // The token is only ever returned here
+    Ok(HttpResponse::Created().json(created))
+}
+
+pub async fn list_tokens(
+    token_store: web::Data<SharedTokenStore>,
+) -> Result<HttpResponse, ApiError> {
+    let tokens = token_store.list().await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "tokens": tokens,
+        "count": tokens.len()
+    })))
+}
+
+pub async fn revoke_token(
+    req: HttpRequest,
+    token_store: web::Data<SharedTokenStore>,
+    audit_log: web::Data<SharedAuditLog>,
+    token_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let result = token_store.revoke(&token_id).await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::TokenRevoke)
+                .with_target(token_id.as_str())
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Token revoked",
+        "token_id": token_id.into_inner()
+    })))
+}
@@ -0,0 +1,97 @@
+use crate::errors::ApiError;
+use crate::handlers::admin::SharedAuditLog;
+use crate::handlers::emails::SharedEmailService;
+use crate::services::audit_log::{AuditAction, AuditActor, AuditMessage, AuditRecord};
+use crate::services::totp_vault::TotpVault;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub type SharedTotpVault = Arc<TotpVault>;
+
+#[derive(Debug, Deserialize)]
+pub struct EnrolParams {
+    /// Replace a different secret already stored for the same service and
+    /// account
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpQueryParams {
+    /// Pick the secret of this account when the service has several
+    pub account: Option<String>,
+}
+
+/// Stored TOTP secrets (services and accounts only)
+pub async fn list_totp_secrets(
+    totp_vault: web::Data<SharedTotpVault>,
+) -> Result<HttpResponse, ApiError> {
+    let secrets = totp_vault.list().await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "secrets": secrets,
+        "count": secrets.len()
+    })))
+}
+
+/// Current TOTP code of a service
+pub async fn get_totp_code(
+    totp_vault: web::Data<SharedTotpVault>,
+    service: web::Path<String>,
+    query: web::Query<TotpQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let code = totp_vault
+        .code(&service, query.account.as_deref(), Utc::now())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(code))
+}
+
+/// Store the TOTP secrets of an enrolment email. Secrets are only ever
+/// stored through this endpoint, never automatically from incoming mail.
+pub async fn enrol_from_email(
+    req: HttpRequest,
+    email_service: web::Data<SharedEmailService>,
+    totp_vault: web::Data<SharedTotpVault>,
+    audit_log: web::Data<SharedAuditLog>,
+    email_id: web::Path<String>,
+    params: web::Query<EnrolParams>,
+) -> Result<HttpResponse, ApiError> {
+    let email = {
+        let service = email_service.lock().await;
+        service.get_email_by_id(&email_id).await?
+    };
+
+    if email.totp_secrets.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "No TOTP secret found in email {}",
+            email.id
+        )));
+    }
+
+    let added = totp_vault
+        .add(&email.totp_secrets, Some(&email.id), params.overwrite)
+        .await;
+    let services: Vec<&str> = email
+        .totp_secrets
+        .iter()
+        .map(|secret| secret.service.as_str())
+        .collect();
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::TotpEnrol)
+                .with_target(services.join(", "))
+                .with_messages(vec![AuditMessage::new(&email.id, Some(&email), true)])
+                .with_result(&added),
+        )
+        .await;
+    let added = added?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "email_id": email.id,
+        "services": services,
+        "added": added
+    })))
+}
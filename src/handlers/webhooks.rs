@@ -0,0 +1,94 @@
+use crate::errors::ApiError;
+use crate::handlers::admin::SharedAuditLog;
+use crate::services::audit_log::{AuditAction, AuditActor, AuditRecord};
+use crate::services::webhooks::{CreateWebhookRequest, WebhookManager};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub type SharedWebhookManager = Arc<WebhookManager>;
+
+pub async fn create_webhook(
+    req: HttpRequest,
+    webhooks: web::Data<SharedWebhookManager>,
+    audit_log: web::Data<SharedAuditLog>,
+    request: web::Json<CreateWebhookRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let subscription = webhooks.create_subscription(request.into_inner()).await?;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::WebhookCreate)
+                .with_target(&subscription.id),
+        )
+        .await;
+
+    // The secret is only ever returned here
+    Ok(HttpResponse::Created().json(subscription))
+}
+
+pub async fn list_webhooks(
+    webhooks: web::Data<SharedWebhookManager>,
+) -> Result<HttpResponse, ApiError> {
+    let subscriptions = webhooks.list_subscriptions().await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "webhooks": subscriptions,
+        "count": subscriptions.len()
+    })))
+}
+
+pub async fn delete_webhook(
+    req: HttpRequest,
+    webhooks: web::Data<SharedWebhookManager>,
+    audit_log: web::Data<SharedAuditLog>,
+    webhook_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let result = webhooks.delete_subscription(&webhook_id).await;
+    audit_log
+        .record(
+            AuditRecord::new(AuditActor::from_request(&req), AuditAction::WebhookDelete)
+                .with_target(webhook_id.as_str())
+                .with_result(&result),
+        )
+        .await;
+    result?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "Webhook deleted",
+        "webhook_id": webhook_id.into_inner()
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryQueryParams {
+    subscription_id: Option<String>,
+    #[serde(default = "default_delivery_limit")]
+    limit: usize,
+}
+
+fn default_delivery_limit() -> usize {
+    50
+}
+
+pub async fn list_deliveries(
+    webhooks: web::Data<SharedWebhookManager>,
+    query: web::Query<DeliveryQueryParams>,
+) -> Result<HttpResponse, ApiError> {
+    let deliveries = webhooks
+        .list_deliveries(query.subscription_id.as_deref(), query.limit)
+        .await;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "deliveries": deliveries,
+        "count": deliveries.len()
+    })))
+}
+
+pub async fn replay_delivery(
+    webhooks: web::Data<SharedWebhookManager>,
+    delivery_id: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let delivery = webhooks.get_ref().replay_delivery(&delivery_id).await?;
+
+    Ok(HttpResponse::Accepted().json(delivery))
+}
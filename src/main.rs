@@ -1,14 +1,41 @@
 use actix_web::{middleware as actix_middleware, web, App, HttpServer};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use email_manager::config::Settings;
 use email_manager::handlers;
+use email_manager::handlers::admin as admin_handlers;
 use email_manager::handlers::emails as email_handlers;
-use email_manager::middleware::auth::ApiTokenAuth;
+use email_manager::handlers::events as event_handlers;
+use email_manager::handlers::jobs as job_handlers;
+use email_manager::handlers::mailboxes as mailbox_handlers;
+use email_manager::handlers::rules as rule_handlers;
+use email_manager::handlers::subscriptions as subscription_handlers;
+use email_manager::handlers::tokens as token_handlers;
+use email_manager::handlers::totp as totp_handlers;
+use email_manager::handlers::webhooks as webhook_handlers;
+use email_manager::middleware::auth::{configured_tokens, ApiTokenAuth, RequireScope, Scope};
+use email_manager::middleware::jwt::JwtValidator;
+use email_manager::middleware::rate_limit::{RateLimit, RateLimiter};
+use email_manager::services::audit_log::AuditLog;
+use email_manager::services::event_bus::EventBus;
 use email_manager::services::imap_service::ImapService;
+use email_manager::services::job_queue::JobQueue;
+use email_manager::services::mailbox_watcher::MailboxWatcher;
+use email_manager::services::mfa_cleanup::MfaCleanup;
+use email_manager::services::mfa_registry::MfaRegistry;
+use email_manager::services::mfa_store::MfaStore;
+use email_manager::services::rules::RulesEngine;
+use email_manager::services::smtp::SmtpClient;
+use email_manager::services::subscriptions::SubscriptionManager;
+use email_manager::services::token_store::TokenStore;
+use email_manager::services::totp_vault::TotpVault;
+use email_manager::services::undo_store::UndoStore;
+use email_manager::services::webhooks::WebhookManager;
 use std::env;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tracing::{error, info};
+use tracing::{info, warn};
 
 #[actix_web::main]
 async fn main() -> Result<()> {
@@ -17,25 +44,9 @@ async fn main() -> Result<()> {
 
     info!("Starting Gmail Manager API");
 
-    // Load API token from environment variable
-    let api_token = env::var("API_TOKEN").unwrap_or_else(|_| {
-        error!("API_TOKEN environment variable not set. Using default token 'dev-token'");
-        "dev-token".to_string()
-    });
-
     // Load configuration
-    let settings = Settings::from_env().unwrap_or_else(|_| Settings {
-        server: email_manager::config::ServerConfig {
-            host: "127.0.0.1".to_string(),
-            port: 8080,
-        },
-        email: email_manager::config::EmailConfig {
-            email_address: std::env::var("GMAIL_EMAIL")
-                .unwrap_or_else(|_| "your-email@gmail.com".to_string()),
-            app_password: std::env::var("GMAIL_APP_PASSWORD")
-                .unwrap_or_else(|_| "your-app-password".to_string()),
-        },
-    });
+    // Auth, JWT and rate limits come from the config, so an invalid one is fatal
+    let settings = Settings::from_env().context("Invalid configuration")?;
 
     info!(
         "Configuration loaded: {}:{}",
@@ -46,77 +57,485 @@ async fn main() -> Result<()> {
         settings.email.email_address
     );
 
+    MfaRegistry::install(MfaRegistry::load(Path::new(&settings.mfa.registry_path))?);
+    info!("MFA registry loaded");
+
     // Initialize IMAP service
     info!("Initializing IMAP service...");
     info!("Connecting to Gmail IMAP server (imap.gmail.com:993)");
 
-    let email_service = Arc::new(Mutex::new(ImapService::new(
-        settings.email.email_address.clone(),
-        settings.email.app_password.clone(),
-    )));
+    let event_bus = Arc::new(EventBus::new(settings.events.log_capacity));
+
+    let email_service = Arc::new(Mutex::new(
+        ImapService::new(
+            settings.email.email_address.clone(),
+            settings.email.app_password.clone(),
+        )
+        .with_event_bus(event_bus.clone())
+        .with_trash_folder(settings.trash.folder.clone()),
+    ));
 
     info!("IMAP service initialized successfully");
     info!("Note: Make sure you're using an App Password, not your regular Gmail password");
     info!("Create one at: https://myaccount.google.com/apppasswords");
 
+    let data_dir = Path::new(&settings.storage.data_dir);
+    let webhook_manager = Arc::new(WebhookManager::new(settings.webhooks.clone(), data_dir)?);
+    webhook_manager.clone().spawn(&event_bus);
+    info!("Webhook dispatcher started");
+
+    let token_store = Arc::new(TokenStore::new(data_dir)?);
+    let audit_log = Arc::new(AuditLog::new(data_dir)?);
+    let undo_store = Arc::new(UndoStore::new(data_dir, settings.trash.undo_window_secs)?);
+
     info!("API authentication enabled - use 'Authorization: Bearer <token>' header");
+    let mut api_auth = if settings.auth.mode.accepts_tokens() {
+        // Named tokens from the config, plus the API_TOKEN environment variable
+        let api_tokens = configured_tokens(&settings.auth, env::var("API_TOKEN").ok().as_deref())?;
+        for token in &api_tokens {
+            let scopes: Vec<&str> = token.scopes.iter().map(|s| s.as_str()).collect();
+            info!("API token '{}' loaded ({})", token.name, scopes.join(", "));
+        }
+        ApiTokenAuth::with_tokens(api_tokens).with_token_store(token_store.clone())
+    } else {
+        ApiTokenAuth::with_tokens(Vec::new())
+    };
+    if settings.auth.mode.accepts_jwt() {
+        let jwt = JwtValidator::new(&settings.auth.jwt, &settings.email.email_address)?;
+        info!("JWT authentication enabled");
+        api_auth = api_auth.with_jwt(Arc::new(jwt));
+    }
     info!("Token authentication required for all endpoints except /health");
 
+    let rate_limiter = Arc::new(RateLimiter::new(settings.rate_limit.clone())?);
+    if settings.rate_limit.enabled {
+        info!(
+            "Rate limiting enabled ({} burst, {} per minute per token and address)",
+            settings.rate_limit.default_quota.burst, settings.rate_limit.default_quota.per_minute
+        );
+    }
+
+    let mfa_store = Arc::new(MfaStore::new(data_dir)?);
+    mfa_store.clone().spawn(&event_bus);
+
+    let totp_vault = Arc::new(TotpVault::new(data_dir, &settings.totp.vault_key)?);
+
+    let mfa_cleanup = Arc::new(MfaCleanup::new(
+        email_service.clone(),
+        mfa_store.clone(),
+        audit_log.clone(),
+        settings.mfa.cleanup.clone(),
+        data_dir,
+    )?);
+    if settings.mfa.cleanup.enabled {
+        info!(
+            "Starting MFA email cleanup ({:?} after {} minutes{})",
+            settings.mfa.cleanup.action,
+            settings.mfa.cleanup.retention_minutes,
+            if settings.mfa.cleanup.dry_run {
+                ", dry run"
+            } else {
+                ""
+            }
+        );
+        mfa_cleanup.clone().spawn();
+    }
+
+    let job_queue = Arc::new(JobQueue::new(
+        email_service.clone(),
+        undo_store.clone(),
+        audit_log.clone(),
+        settings.jobs.clone(),
+        data_dir,
+    )?);
+    job_queue.clone().spawn();
+    info!("Job worker started");
+
+    let rules_engine = Arc::new(RulesEngine::new(
+        email_service.clone(),
+        audit_log.clone(),
+        data_dir,
+    )?);
+    rules_engine.clone().spawn(&event_bus);
+    info!(
+        "Filtering rules loaded ({})",
+        rules_engine.list().await.len()
+    );
+
+    let subscription_manager = Arc::new(SubscriptionManager::new(
+        email_service.clone(),
+        SmtpClient::new(
+            settings.smtp.clone(),
+            settings.email.email_address.clone(),
+            settings.email.app_password.clone(),
+        ),
+        settings.subscriptions.clone(),
+        data_dir,
+    )?);
+
     let server_host = settings.server.host.clone();
     let server_port = settings.server.port;
 
     info!("Email service ready");
 
+    if settings.events.watcher_enabled {
+        info!(
+            "Starting mailbox watcher (polling every {}s)",
+            settings.events.poll_interval_secs
+        );
+        MailboxWatcher::new(
+            email_service.clone(),
+            event_bus.clone(),
+            Duration::from_secs(settings.events.poll_interval_secs),
+            settings.events.poll_limit,
+        )
+        .spawn();
+    } else {
+        warn!("Mailbox watcher disabled: /mfa/wait will not see new codes");
+    }
+
     // Create and run HTTP server
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(email_service.clone()))
-            .wrap(ApiTokenAuth::new(api_token.clone()))
+            .app_data(web::Data::new(event_bus.clone()))
+            .app_data(web::Data::new(webhook_manager.clone()))
+            .app_data(web::Data::new(mfa_store.clone()))
+            .app_data(web::Data::new(mfa_cleanup.clone()))
+            .app_data(web::Data::new(totp_vault.clone()))
+            .app_data(web::Data::new(token_store.clone()))
+            .app_data(web::Data::new(audit_log.clone()))
+            .app_data(web::Data::new(undo_store.clone()))
+            .app_data(web::Data::new(job_queue.clone()))
+            .app_data(web::Data::new(rules_engine.clone()))
+            .app_data(web::Data::new(subscription_manager.clone()))
+            // Runs after authentication, which it needs to key buckets by token
+            .wrap(RateLimit::new(rate_limiter.clone()))
+            .wrap(api_auth.clone())
             .wrap(actix_middleware::Logger::default())
             // Health endpoint
             .route("/health", web::get().to(handlers::health))
             // Email endpoints
             .route(
                 "/emails/recent",
-                web::get().to(email_handlers::get_recent_emails),
+                web::get()
+                    .to(email_handlers::get_recent_emails)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
             )
             .route(
                 "/emails/today",
-                web::get().to(email_handlers::get_today_emails),
+                web::get()
+                    .to(email_handlers::get_today_emails)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
             )
             .route(
                 "/emails/by-date/{date}",
-                web::get().to(email_handlers::get_emails_by_date),
+                web::get()
+                    .to(email_handlers::get_emails_by_date)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
             )
             .route(
                 "/emails/search",
-                web::post().to(email_handlers::search_emails),
+                web::post()
+                    .to(email_handlers::search_emails)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
             )
             .route(
                 "/emails/{id}/read",
-                web::post().to(email_handlers::mark_as_read),
+                web::post()
+                    .to(email_handlers::mark_as_read)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
             )
             .route(
                 "/emails/{id}/unread",
-                web::post().to(email_handlers::mark_as_unread),
+                web::post()
+                    .to(email_handlers::mark_as_unread)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            .route(
+                "/emails/{id}/move",
+                web::post()
+                    .to(email_handlers::move_email)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            .route(
+                "/emails/{id}/copy",
+                web::post()
+                    .to(email_handlers::copy_email)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            .route(
+                "/emails/{id}/labels",
+                web::post()
+                    .to(email_handlers::modify_labels)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            .route(
+                "/emails/{id}/flags",
+                web::post()
+                    .to(email_handlers::modify_flags)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
             )
             .route(
                 "/emails/{id}",
-                web::delete().to(email_handlers::delete_email),
+                web::delete()
+                    .to(email_handlers::delete_email)
+                    .wrap(RequireScope::new(Scope::EmailsDelete)),
             )
             .route(
                 "/emails/bulk-delete",
-                web::post().to(email_handlers::bulk_delete),
+                web::post()
+                    .to(email_handlers::bulk_delete)
+                    .wrap(RequireScope::new(Scope::EmailsDelete)),
+            )
+            .route(
+                "/emails/bulk",
+                web::post()
+                    .to(email_handlers::bulk_operation)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/emails/undo/{token}",
+                web::post()
+                    .to(email_handlers::undo_delete)
+                    .wrap(RequireScope::new(Scope::EmailsDelete)),
             )
             .route(
                 "/emails/bulk-mark-read",
-                web::post().to(email_handlers::bulk_mark_as_read),
+                web::post()
+                    .to(email_handlers::bulk_mark_as_read)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            // Mailbox endpoints; names may contain the hierarchy delimiter
+            .route(
+                "/mailboxes",
+                web::get()
+                    .to(mailbox_handlers::list_mailboxes)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/mailboxes",
+                web::post()
+                    .to(mailbox_handlers::create_mailbox)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            .route(
+                "/mailboxes/{name:.*}",
+                web::patch()
+                    .to(mailbox_handlers::rename_mailbox)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            .route(
+                "/mailboxes/{name:.*}",
+                web::delete()
+                    .to(mailbox_handlers::delete_mailbox)
+                    .wrap(RequireScope::new(Scope::EmailsDelete)),
+            )
+            // Background job endpoints
+            .route(
+                "/jobs",
+                web::get()
+                    .to(job_handlers::list_jobs)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/jobs/{id}",
+                web::get()
+                    .to(job_handlers::get_job)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/jobs/{id}",
+                web::delete()
+                    .to(job_handlers::cancel_job)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            )
+            // Filtering rule endpoints
+            .route(
+                "/rules",
+                web::get()
+                    .to(rule_handlers::list_rules)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/rules",
+                web::post()
+                    .to(rule_handlers::create_rule)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/rules/import",
+                web::post()
+                    .to(rule_handlers::import_sieve)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/rules/export",
+                web::get()
+                    .to(rule_handlers::export_sieve)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/rules/test",
+                web::post()
+                    .to(rule_handlers::test_rules)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/rules/{id}",
+                web::get()
+                    .to(rule_handlers::get_rule)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/rules/{id}",
+                web::put()
+                    .to(rule_handlers::update_rule)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/rules/{id}",
+                web::delete()
+                    .to(rule_handlers::delete_rule)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            // Newsletter subscription endpoints
+            .route(
+                "/subscriptions",
+                web::get()
+                    .to(subscription_handlers::list_subscriptions)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/subscriptions/{id}/unsubscribe",
+                web::post()
+                    .to(subscription_handlers::unsubscribe)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
             )
             // MFA code extraction endpoints
-            .route("/mfa/codes", web::get().to(email_handlers::get_mfa_codes))
+            .route(
+                "/mfa/codes",
+                web::get()
+                    .to(email_handlers::get_mfa_codes)
+                    .wrap(RequireScope::new(Scope::MfaRead)),
+            )
+            .route(
+                "/mfa/codes/{email_id}/consume",
+                web::post()
+                    .to(email_handlers::consume_mfa_code)
+                    .wrap(RequireScope::new(Scope::MfaRead)),
+            )
             .route(
                 "/mfa/latest",
-                web::get().to(email_handlers::get_latest_mfa_code),
+                web::get()
+                    .to(email_handlers::get_latest_mfa_code)
+                    .wrap(RequireScope::new(Scope::MfaRead)),
+            )
+            .route(
+                "/mfa/wait",
+                web::get()
+                    .to(email_handlers::wait_for_mfa_code)
+                    .wrap(RequireScope::new(Scope::MfaRead)),
+            )
+            // TOTP endpoints
+            .route(
+                "/totp",
+                web::get()
+                    .to(totp_handlers::list_totp_secrets)
+                    .wrap(RequireScope::new(Scope::MfaRead)),
+            )
+            .route(
+                "/totp/enrol/{email_id}",
+                web::post()
+                    .to(totp_handlers::enrol_from_email)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/totp/{service}",
+                web::get()
+                    .to(totp_handlers::get_totp_code)
+                    .wrap(RequireScope::new(Scope::MfaRead)),
+            )
+            // Admin endpoints
+            .route(
+                "/admin/mfa/test-pattern",
+                web::post()
+                    .to(admin_handlers::test_mfa_pattern)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/mfa/cleanup/run",
+                web::post()
+                    .to(admin_handlers::run_mfa_cleanup)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/mfa/cleanup/audit",
+                web::get()
+                    .to(admin_handlers::get_mfa_cleanup_audit)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/audit",
+                web::get()
+                    .to(admin_handlers::get_audit_log)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/tokens",
+                web::post()
+                    .to(token_handlers::create_token)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/tokens",
+                web::get()
+                    .to(token_handlers::list_tokens)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/tokens/{id}",
+                web::delete()
+                    .to(token_handlers::revoke_token)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            // Mailbox event stream
+            .route(
+                "/events",
+                web::get()
+                    .to(event_handlers::stream_events)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            // Webhook endpoints
+            .route(
+                "/webhooks",
+                web::post()
+                    .to(webhook_handlers::create_webhook)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/webhooks",
+                web::get()
+                    .to(webhook_handlers::list_webhooks)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/webhooks/deliveries",
+                web::get()
+                    .to(webhook_handlers::list_deliveries)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/webhooks/deliveries/{id}/replay",
+                web::post()
+                    .to(webhook_handlers::replay_delivery)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/webhooks/{id}",
+                web::delete()
+                    .to(webhook_handlers::delete_webhook)
+                    .wrap(RequireScope::new(Scope::Admin)),
             )
     })
     .bind((&server_host[..], server_port))?
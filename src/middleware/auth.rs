@@ -1,25 +1,226 @@
+use crate::config::AuthConfig;
+use crate::errors::ApiError;
+use crate::middleware::jwt::JwtValidator;
+use crate::services::token_store::TokenStore;
 use actix_web::{
     body::EitherBody,
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpResponse,
+    Error, HttpMessage, HttpRequest, HttpResponse, ResponseError,
 };
 use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
 use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+/// Token accepted when `auth.insecure_dev` is set and no token is configured
+pub const INSECURE_DEV_TOKEN: &str = "dev-token";
+
+/// Permissions an API token can be granted. `admin` grants every scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "emails:read")]
+    EmailsRead,
+    #[serde(rename = "emails:write")]
+    EmailsWrite,
+    #[serde(rename = "emails:delete")]
+    EmailsDelete,
+    #[serde(rename = "mfa:read")]
+    MfaRead,
+    #[serde(rename = "send")]
+    Send,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::EmailsRead => "emails:read",
+            Scope::EmailsWrite => "emails:write",
+            Scope::EmailsDelete => "emails:delete",
+            Scope::MfaRead => "mfa:read",
+            Scope::Send => "send",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A token accepted by `ApiTokenAuth`. Only the SHA-256 hash of the token
+/// is kept.
+#[derive(Debug, Clone)]
+pub struct ApiToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    hash: [u8; 32],
+}
+
+impl ApiToken {
+    pub fn new(name: impl Into<String>, token: &str, scopes: Vec<Scope>) -> Self {
+        Self {
+            name: name.into(),
+            scopes,
+            hash: hash_token(token),
+        }
+    }
+
+    /// A token from its hex-encoded SHA-256 hash
+    pub fn from_hash(
+        name: impl Into<String>,
+        sha256: &str,
+        scopes: Vec<Scope>,
+    ) -> Result<Self, ApiError> {
+        let name = name.into();
+        let hash = hex::decode(sha256.trim())
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .ok_or_else(|| {
+                ApiError::ValidationError(format!(
+                    "Token '{}' must have a hex-encoded SHA-256 hash",
+                    name
+                ))
+            })?;
+
+        Ok(Self { name, scopes, hash })
+    }
+}
+
+pub fn hash_token(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Tokens from `auth.tokens`, plus the legacy `API_TOKEN` environment
+/// variable as a token with every scope. Fails when there are none, unless
+/// `auth.insecure_dev` is set.
+pub fn configured_tokens(
+    config: &AuthConfig,
+    legacy_token: Option<&str>,
+) -> Result<Vec<ApiToken>, ApiError> {
+    let mut tokens = config
+        .tokens
+        .iter()
+        .map(|token| ApiToken::from_hash(&token.name, &token.sha256, token.scopes.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(token) = legacy_token.filter(|t| !t.is_empty()) {
+        tokens.push(ApiToken::new("API_TOKEN", token, vec![Scope::Admin]));
+    }
+
+    if tokens.is_empty() {
+        if !config.insecure_dev {
+            return Err(ApiError::ValidationError(
+                "No API token configured: add auth.tokens, set API_TOKEN, or set \
+                 APP_AUTH__INSECURE_DEV=true for local development"
+                    .to_string(),
+            ));
+        }
+        tracing::warn!(
+            "No API token configured, accepting '{}' with every scope (insecure dev mode)",
+            INSECURE_DEV_TOKEN
+        );
+        tokens.push(ApiToken::new(
+            "insecure-dev",
+            INSECURE_DEV_TOKEN,
+            vec![Scope::Admin],
+        ));
+    }
+
+    Ok(tokens)
+}
+
+/// The token a request was authenticated with, stored in the request
+/// extensions by `ApiTokenAuth`
+#[derive(Debug, Clone)]
+pub struct AuthContext {
+    pub token_name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl AuthContext {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+}
+
+/// Check that the request's token has `scope`, for handlers where the scope
+/// needed depends on the request body
+pub fn require_scope(req: &HttpRequest, scope: Scope) -> Result<(), ApiError> {
+    check_scope(req.extensions().get::<AuthContext>(), scope)
+}
+
+fn check_scope(context: Option<&AuthContext>, scope: Scope) -> Result<(), ApiError> {
+    match context {
+        Some(context) if context.has_scope(scope) => Ok(()),
+        Some(context) => Err(ApiError::Forbidden(format!(
+            "Token '{}' lacks the '{}' scope",
+            context.token_name, scope
+        ))),
+        None => Err(ApiError::Forbidden(format!(
+            "The '{}' scope is required",
+            scope
+        ))),
+    }
+}
 
 /// Middleware to validate API token
+#[derive(Clone)]
 pub struct ApiTokenAuth {
-    token: String,
+    tokens: Arc<Vec<ApiToken>>,
+    token_store: Option<Arc<TokenStore>>,
+    jwt: Option<Arc<JwtValidator>>,
 }
 
 impl ApiTokenAuth {
+    /// Accept a single token with every scope
     pub fn new(token: String) -> Self {
-        Self { token }
+        Self::with_tokens(vec![ApiToken::new("default", &token, vec![Scope::Admin])])
     }
+
+    pub fn with_tokens(tokens: Vec<ApiToken>) -> Self {
+        Self {
+            tokens: Arc::new(tokens),
+            token_store: None,
+            jwt: None,
+        }
+    }
+
+    /// Also accept JWTs signed by the identity provider
+    pub fn with_jwt(mut self, jwt: Arc<JwtValidator>) -> Self {
+        self.jwt = Some(jwt);
+        self
+    }
+
+    /// Also accept the tokens minted through `/admin/tokens`
+    pub fn with_token_store(mut self, token_store: Arc<TokenStore>) -> Self {
+        self.token_store = Some(token_store);
+        self
+    }
+}
+
+/// The configured token with this hash. Every token is compared, in
+/// constant time, so the time taken does not reveal which one matched.
+fn find_token<'a>(tokens: &'a [ApiToken], hash: &[u8; 32]) -> Option<&'a ApiToken> {
+    let mut found = None;
+    for token in tokens {
+        if bool::from(token.hash.ct_eq(hash)) {
+            found = Some(token);
+        }
+    }
+    found
 }
 
 impl<S, B> Transform<S, ServiceRequest> for ApiTokenAuth
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -31,20 +232,24 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(ApiTokenAuthMiddleware {
-            service,
-            token: self.token.clone(),
+            service: Rc::new(service),
+            tokens: self.tokens.clone(),
+            token_store: self.token_store.clone(),
+            jwt: self.jwt.clone(),
         }))
     }
 }
 
 pub struct ApiTokenAuthMiddleware<S> {
-    service: S,
-    token: String,
+    service: Rc<S>,
+    tokens: Arc<Vec<ApiToken>>,
+    token_store: Option<Arc<TokenStore>>,
+    jwt: Option<Arc<JwtValidator>>,
 }
 
 impl<S, B> Service<ServiceRequest> for ApiTokenAuthMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -64,29 +269,134 @@ where
             });
         }
 
-        // Get Authorization header
-        let auth_header = req
+        // Get the bearer token from the Authorization header
+        let bearer = req
             .headers()
             .get("Authorization")
-            .and_then(|h| h.to_str().ok());
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "));
+
+        let jwt = bearer.and_then(|token| {
+            let jwt = self.jwt.as_ref()?;
+            JwtValidator::looks_like_jwt(token).then(|| jwt.validate(token))
+        });
+        let (context, presented) = match jwt {
+            Some(Ok(context)) => (Some(context), None),
+            Some(Err(e)) => {
+                tracing::debug!("Rejected bearer token: {}", e);
+                (None, None)
+            }
+            None => {
+                let presented = bearer.map(hash_token);
+                let context = presented
+                    .as_ref()
+                    .and_then(|hash| find_token(&self.tokens, hash))
+                    .map(|token| AuthContext {
+                        token_name: token.name.clone(),
+                        scopes: token.scopes.clone(),
+                    });
+                (context, presented)
+            }
+        };
+        let service = self.service.clone();
+        let token_store = self.token_store.clone();
+
+        Box::pin(async move {
+            // Minted tokens are checked when no configured token matched
+            let context = match (context, presented, token_store) {
+                (Some(context), _, _) => Some(context),
+                (None, Some(hash), Some(token_store)) => {
+                    let ip = req.peer_addr().map(|addr| addr.ip());
+                    token_store.authenticate(&hash, ip).await
+                }
+                _ => None,
+            };
+
+            match context {
+                Some(context) => {
+                    // Token is valid, proceed with request
+                    req.extensions_mut().insert(context);
+                    let res = service.call(req).await?;
+                    Ok(res.map_into_left_body())
+                }
+                None => {
+                    // Token is missing, invalid, expired or not allowed from
+                    // this address
+                    let (req, _) = req.into_parts();
+                    let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                        "error": "Invalid or missing API token"
+                    }));
+
+                    Ok(ServiceResponse::new(req, response).map_into_right_body())
+                }
+            }
+        })
+    }
+}
+
+/// Route middleware rejecting requests whose token lacks a scope, e.g.
+/// `web::get().to(handler).wrap(RequireScope::new(Scope::EmailsRead))`
+pub struct RequireScope {
+    scope: Scope,
+}
+
+impl RequireScope {
+    pub fn new(scope: Scope) -> Self {
+        Self { scope }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireScopeMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireScopeMiddleware {
+            service,
+            scope: self.scope,
+        }))
+    }
+}
 
-        let expected_token = format!("Bearer {}", self.token);
+pub struct RequireScopeMiddleware<S> {
+    service: S,
+    scope: Scope,
+}
 
-        match auth_header {
-            Some(token) if token == expected_token => {
-                // Token is valid, proceed with request
+impl<S, B> Service<ServiceRequest> for RequireScopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let allowed = check_scope(req.extensions().get::<AuthContext>(), self.scope);
+
+        match allowed {
+            Ok(()) => {
                 let fut = self.service.call(req);
                 Box::pin(async move {
                     let res = fut.await?;
                     Ok(res.map_into_left_body())
                 })
             }
-            _ => {
-                // Token is missing or invalid
+            Err(e) => {
                 let (req, _) = req.into_parts();
-                let response = HttpResponse::Unauthorized().json(serde_json::json!({
-                    "error": "Invalid or missing API token"
-                }));
+                let response = e.error_response();
 
                 Box::pin(
                     async move { Ok(ServiceResponse::new(req, response).map_into_right_body()) },
@@ -99,6 +409,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::TokenConfig;
     use actix_web::{test, web, App, HttpResponse};
 
     async fn test_handler() -> HttpResponse {
@@ -170,4 +481,89 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert_eq!(resp.status(), 200);
     }
+
+    fn scoped_app_tokens() -> ApiTokenAuth {
+        ApiTokenAuth::with_tokens(vec![
+            ApiToken::new("reader", "read-token", vec![Scope::EmailsRead]),
+            ApiToken::new("admin", "admin-token", vec![Scope::Admin]),
+        ])
+    }
+
+    #[actix_web::test]
+    async fn test_route_scopes() {
+        let app = test::init_service(
+            App::new().wrap(scoped_app_tokens()).route(
+                "/test",
+                web::delete()
+                    .to(test_handler)
+                    .wrap(RequireScope::new(Scope::EmailsDelete)),
+            ),
+        )
+        .await;
+
+        let req = test::TestRequest::delete()
+            .uri("/test")
+            .insert_header(("Authorization", "Bearer read-token"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 403);
+
+        // admin grants every scope
+        let req = test::TestRequest::delete()
+            .uri("/test")
+            .insert_header(("Authorization", "Bearer admin-token"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_hashed_token_from_config() {
+        let config = AuthConfig {
+            tokens: vec![TokenConfig {
+                name: "ci".to_string(),
+                sha256: hex::encode(hash_token("ci-token")),
+                scopes: vec![Scope::MfaRead],
+            }],
+            ..AuthConfig::default()
+        };
+        let tokens = configured_tokens(&config, None).unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap(ApiTokenAuth::with_tokens(tokens))
+                .route("/test", web::get().to(test_handler)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/test")
+            .insert_header(("Authorization", "Bearer ci-token"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_startup_requires_a_token() {
+        let config = AuthConfig::default();
+        assert!(configured_tokens(&config, None).is_err());
+        assert_eq!(configured_tokens(&config, Some("secret")).unwrap().len(), 1);
+
+        let insecure = AuthConfig {
+            insecure_dev: true,
+            ..AuthConfig::default()
+        };
+        let tokens = configured_tokens(&insecure, None).unwrap();
+        assert_eq!(tokens[0].hash, hash_token(INSECURE_DEV_TOKEN));
+
+        let invalid = AuthConfig {
+            tokens: vec![TokenConfig {
+                name: "bad".to_string(),
+                sha256: "not-a-hash".to_string(),
+                scopes: vec![],
+            }],
+            ..AuthConfig::default()
+        };
+        assert!(configured_tokens(&invalid, None).is_err());
+    }
 }
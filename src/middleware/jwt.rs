@@ -0,0 +1,191 @@
+use crate::config::JwtConfig;
+use crate::errors::ApiError;
+use crate::middleware::auth::{AuthContext, Scope};
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::fs;
+
+const RSA_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+];
+const EC_ALGORITHMS: &[Algorithm] = &[Algorithm::ES256, Algorithm::ES384];
+const ED_ALGORITHMS: &[Algorithm] = &[Algorithm::EdDSA];
+
+/// A public key and the signature algorithms it may be used with
+struct VerificationKey {
+    kid: Option<String>,
+    key: DecodingKey,
+    algorithms: &'static [Algorithm],
+}
+
+/// Validates JWTs from an identity provider offline, against public keys from
+/// a JWKS file or PEM files. Symmetric (HS*) tokens are never accepted.
+pub struct JwtValidator {
+    keys: Vec<VerificationKey>,
+    config: JwtConfig,
+    /// Mailbox served by this instance, checked against the accounts claim
+    account: String,
+}
+
+impl JwtValidator {
+    pub fn new(config: &JwtConfig, account: &str) -> Result<Self, ApiError> {
+        let mut keys = Vec::new();
+
+        if let Some(path) = &config.jwks_path {
+            let jwks: JwkSet = serde_json::from_str(&read_key_file(path)?)
+                .map_err(|e| ApiError::ValidationError(format!("Invalid JWKS {}: {}", path, e)))?;
+            for jwk in &jwks.keys {
+                let algorithms = match &jwk.algorithm {
+                    AlgorithmParameters::RSA(_) => RSA_ALGORITHMS,
+                    AlgorithmParameters::EllipticCurve(_) => EC_ALGORITHMS,
+                    AlgorithmParameters::OctetKeyPair(_) => ED_ALGORITHMS,
+                    AlgorithmParameters::OctetKey(_) => {
+                        tracing::warn!("Ignoring symmetric key in JWKS {}", path);
+                        continue;
+                    }
+                };
+                let key = DecodingKey::from_jwk(jwk).map_err(|e| {
+                    ApiError::ValidationError(format!("Invalid key in JWKS {}: {}", path, e))
+                })?;
+                keys.push(VerificationKey {
+                    kid: jwk.common.key_id.clone(),
+                    key,
+                    algorithms,
+                });
+            }
+        }
+
+        for path in &config.public_key_paths {
+            let pem = read_key_file(path)?;
+            let (key, algorithms) = DecodingKey::from_rsa_pem(pem.as_bytes())
+                .map(|key| (key, RSA_ALGORITHMS))
+                .or_else(|_| {
+                    DecodingKey::from_ec_pem(pem.as_bytes()).map(|key| (key, EC_ALGORITHMS))
+                })
+                .or_else(|_| {
+                    DecodingKey::from_ed_pem(pem.as_bytes()).map(|key| (key, ED_ALGORITHMS))
+                })
+                .map_err(|_| {
+                    ApiError::ValidationError(format!(
+                        "{} is not an RSA, EC or Ed25519 public key",
+                        path
+                    ))
+                })?;
+            keys.push(VerificationKey {
+                kid: None,
+                key,
+                algorithms,
+            });
+        }
+
+        if keys.is_empty() {
+            return Err(ApiError::ValidationError(
+                "JWT authentication needs auth.jwt.jwks_path or auth.jwt.public_key_paths"
+                    .to_string(),
+            ));
+        }
+        // Without an audience, any token the provider issued for another
+        // application would be accepted here
+        if config
+            .audience
+            .as_deref()
+            .is_none_or(|audience| audience.trim().is_empty())
+        {
+            return Err(ApiError::ValidationError(
+                "JWT authentication needs auth.jwt.audience".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            keys,
+            config: config.clone(),
+            account: account.to_string(),
+        })
+    }
+
+    /// Whether a bearer token is shaped like a JWT (three base64url parts)
+    pub fn looks_like_jwt(token: &str) -> bool {
+        token.split('.').count() == 3
+    }
+
+    /// Validate a JWT and map its claims to the permissions of the request
+    pub fn validate(&self, token: &str) -> Result<AuthContext, ApiError> {
+        let unauthorized =
+            |reason: String| ApiError::AuthenticationError(format!("Invalid JWT: {}", reason));
+
+        let header = decode_header(token).map_err(|e| unauthorized(e.to_string()))?;
+        let candidates = self.keys.iter().filter(|key| {
+            key.algorithms.contains(&header.alg)
+                && (header.kid.is_none() || key.kid.is_none() || key.kid == header.kid)
+        });
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway_secs;
+        validation.set_audience(&[self.config.audience.as_deref().unwrap_or_default()]);
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        let mut last_error = "no key for this token".to_string();
+        for key in candidates {
+            match decode::<Value>(token, &key.key, &validation) {
+                Ok(data) => return self.context(&data.claims).map_err(unauthorized),
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+        Err(unauthorized(last_error))
+    }
+
+    fn context(&self, claims: &Value) -> Result<AuthContext, String> {
+        if let Some(accounts) = claims.get(&self.config.accounts_claim) {
+            let allowed = claim_values(accounts)
+                .iter()
+                .any(|account| account.eq_ignore_ascii_case(&self.account) || account == "*");
+            if !allowed {
+                return Err(format!("not allowed to access {}", self.account));
+            }
+        }
+
+        // Scopes this API does not know about (e.g. "openid") are ignored
+        let scopes = claims
+            .get(&self.config.scopes_claim)
+            .map(claim_values)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|scope| serde_json::from_value::<Scope>(Value::String(scope)).ok())
+            .collect();
+
+        Ok(AuthContext {
+            token_name: claims
+                .get("sub")
+                .and_then(Value::as_str)
+                .unwrap_or("jwt")
+                .to_string(),
+            scopes,
+        })
+    }
+}
+
+/// A claim holding a space-separated string or an array of strings
+fn claim_values(claim: &Value) -> Vec<String> {
+    match claim {
+        Value::String(values) => values.split_whitespace().map(String::from).collect(),
+        Value::Array(values) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn read_key_file(path: &str) -> Result<String, ApiError> {
+    fs::read_to_string(path)
+        .map_err(|e| ApiError::ValidationError(format!("Failed to read {}: {}", path, e)))
+}
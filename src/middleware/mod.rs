@@ -1 +1,3 @@
 pub mod auth;
+pub mod jwt;
+pub mod rate_limit;
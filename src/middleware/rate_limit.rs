@@ -0,0 +1,391 @@
+use crate::config::{RateLimitConfig, RateLimitQuota};
+use crate::errors::ApiError;
+use crate::middleware::auth::AuthContext;
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+    http::Method,
+    Error, HttpMessage, ResponseError,
+};
+use futures::future::LocalBoxFuture;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub const RATE_LIMIT_LIMIT: &str = "ratelimit-limit";
+pub const RATE_LIMIT_REMAINING: &str = "ratelimit-remaining";
+pub const RATE_LIMIT_RESET: &str = "ratelimit-reset";
+
+/// Most buckets kept; full ones are dropped first, then the least recently
+/// used
+const MAX_BUCKETS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// The quota of the bucket's token, so it can be judged on its own
+    capacity: f64,
+    per_sec: f64,
+}
+
+impl Bucket {
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens + now.duration_since(self.updated).as_secs_f64() * self.per_sec >= self.capacity
+    }
+}
+
+struct CompiledRouteCost {
+    method: Option<Method>,
+    path: ResourceDef,
+    cost: u32,
+}
+
+/// Outcome of charging a request to its bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// Seconds until the request could be retried, when it was rejected
+    pub retry_after_secs: u64,
+}
+
+/// Token buckets keyed by API token and client address
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    route_costs: Vec<CompiledRouteCost>,
+    buckets: Mutex<HashMap<(String, Option<IpAddr>), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Result<Self, ApiError> {
+        let route_costs = config
+            .route_costs
+            .iter()
+            .map(|route| {
+                let method = match route.method.as_str() {
+                    "*" => None,
+                    method => Some(Method::from_bytes(method.as_bytes()).map_err(|_| {
+                        ApiError::ValidationError(format!(
+                            "Invalid rate limit method '{}'",
+                            route.method
+                        ))
+                    })?),
+                };
+                Ok(CompiledRouteCost {
+                    method,
+                    path: ResourceDef::new(route.path.as_str()),
+                    cost: route.cost,
+                })
+            })
+            .collect::<Result<Vec<_>, ApiError>>()?;
+
+        Ok(Self {
+            config,
+            route_costs,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Cost of a request: the first matching route cost, or 1
+    pub fn cost(&self, method: &Method, path: &str) -> u32 {
+        self.route_costs
+            .iter()
+            .find(|route| {
+                route.method.as_ref().is_none_or(|m| m == method) && route.path.is_match(path)
+            })
+            .map(|route| route.cost)
+            .unwrap_or(1)
+    }
+
+    fn quota(&self, token_name: &str) -> &RateLimitQuota {
+        self.config
+            .token_quotas
+            .get(token_name)
+            .unwrap_or(&self.config.default_quota)
+    }
+
+    /// Take `cost` from the bucket of a token and client address
+    pub fn check(&self, token_name: &str, ip: Option<IpAddr>, cost: u32) -> RateLimitDecision {
+        self.check_at(token_name, ip, cost, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        token_name: &str,
+        ip: Option<IpAddr>,
+        cost: u32,
+        now: Instant,
+    ) -> RateLimitDecision {
+        let quota = self.quota(token_name);
+        let capacity = quota.burst.max(1) as f64;
+        let per_sec = quota.per_minute.max(1) as f64 / 60.0;
+        // A request costing more than the bucket holds could never pass
+        let cost = (cost as f64).min(capacity);
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (token_name.to_string(), ip);
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            prune(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            capacity,
+            per_sec,
+        });
+        // The quota may have changed since the bucket was created
+        bucket.capacity = capacity;
+        bucket.per_sec = per_sec;
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_sec).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= cost;
+        if allowed {
+            bucket.tokens -= cost;
+        }
+
+        RateLimitDecision {
+            allowed,
+            limit: quota.burst,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: ((capacity - bucket.tokens) / per_sec).ceil() as u64,
+            retry_after_secs: if allowed {
+                0
+            } else {
+                ((cost - bucket.tokens) / per_sec).ceil().max(1.0) as u64
+            },
+        }
+    }
+}
+
+/// Make room for a new bucket. Full buckets hold no state worth keeping;
+/// when that is not enough, the least recently used tenth is dropped.
+fn prune(buckets: &mut HashMap<(String, Option<IpAddr>), Bucket>, now: Instant) {
+    buckets.retain(|_, bucket| !bucket.is_full(now));
+    if buckets.len() < MAX_BUCKETS {
+        return;
+    }
+
+    let mut updated: Vec<Instant> = buckets.values().map(|bucket| bucket.updated).collect();
+    let evicted = buckets.len() + 1 - MAX_BUCKETS * 9 / 10;
+    let (_, cutoff, _) = updated.select_nth_unstable(evicted - 1);
+    let cutoff = *cutoff;
+    buckets.retain(|_, bucket| bucket.updated > cutoff);
+}
+
+fn insert_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    let mut insert = |name: &'static str, value: u64| {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    };
+    insert(RATE_LIMIT_LIMIT, decision.limit as u64);
+    insert(RATE_LIMIT_REMAINING, decision.remaining as u64);
+    insert(RATE_LIMIT_RESET, decision.reset_secs);
+    if !decision.allowed {
+        headers.insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+    }
+}
+
+/// Middleware charging each authenticated request to the bucket of its token
+/// and client address. It must run after `ApiTokenAuth`, i.e. be wrapped
+/// before it: `.wrap(RateLimit::new(limiter)).wrap(ApiTokenAuth::new(..))`.
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimit {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service,
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Unauthenticated requests (health checks) are not limited
+        let token_name = req
+            .extensions()
+            .get::<AuthContext>()
+            .map(|context| context.token_name.clone());
+        let decision = token_name
+            .filter(|_| self.limiter.config.enabled)
+            .map(|name| {
+                let cost = self.limiter.cost(req.method(), req.path());
+                let ip = req.peer_addr().map(|addr| addr.ip());
+                self.limiter.check(&name, ip, cost)
+            });
+
+        match decision {
+            Some(decision) if !decision.allowed => {
+                tracing::warn!(
+                    "Rate limited {} {} (retry after {}s)",
+                    req.method(),
+                    req.path(),
+                    decision.retry_after_secs
+                );
+                let (req, _) = req.into_parts();
+                let mut response = ApiError::RateLimitError.error_response();
+                insert_headers(response.headers_mut(), &decision);
+
+                Box::pin(
+                    async move { Ok(ServiceResponse::new(req, response).map_into_right_body()) },
+                )
+            }
+            _ => {
+                let fut = self.service.call(req);
+                Box::pin(async move {
+                    let mut res = fut.await?;
+                    if let Some(decision) = decision {
+                        insert_headers(res.headers_mut(), &decision);
+                    }
+                    Ok(res.map_into_left_body())
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limiter(burst: u32, per_minute: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            default_quota: RateLimitQuota { burst, per_minute },
+            ..RateLimitConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = limiter(2, 60);
+        let start = Instant::now();
+
+        assert!(limiter.check_at("a", None, 1, start).allowed);
+        assert!(limiter.check_at("a", None, 1, start).allowed);
+        let rejected = limiter.check_at("a", None, 1, start);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.retry_after_secs, 1);
+        assert_eq!(rejected.reset_secs, 2);
+
+        // Other tokens and addresses have their own buckets
+        assert!(limiter.check_at("b", None, 1, start).allowed);
+        let ip = Some("10.0.0.1".parse().unwrap());
+        assert!(limiter.check_at("a", ip, 1, start).allowed);
+
+        assert!(
+            limiter
+                .check_at("a", None, 1, start + Duration::from_secs(1))
+                .allowed
+        );
+    }
+
+    #[test]
+    fn test_route_costs() {
+        let limiter = limiter(60, 60);
+
+        assert_eq!(limiter.cost(&Method::POST, "/emails/bulk-delete"), 20);
+        assert_eq!(limiter.cost(&Method::DELETE, "/emails/42"), 2);
+        assert_eq!(limiter.cost(&Method::GET, "/emails/recent"), 1);
+
+        // A cost above the burst size is capped so the request can pass
+        assert!(limiter.check("a", None, 1000).allowed);
+    }
+
+    fn client(n: usize) -> Option<IpAddr> {
+        Some(IpAddr::from((n as u32).to_be_bytes()))
+    }
+
+    #[test]
+    fn test_pruning_judges_buckets_by_their_own_quota() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            default_quota: RateLimitQuota {
+                burst: 1000,
+                per_minute: 60_000,
+            },
+            token_quotas: HashMap::from([(
+                "slow".to_string(),
+                RateLimitQuota {
+                    burst: 2,
+                    per_minute: 1,
+                },
+            )]),
+            ..RateLimitConfig::default()
+        })
+        .unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check_at("slow", None, 2, start).allowed);
+        for n in 0..MAX_BUCKETS - 1 {
+            limiter.check_at("fast", client(n), 1, start);
+        }
+
+        // The fast buckets have refilled and make room; the slow one has not
+        let later = start + Duration::from_secs(1);
+        assert!(
+            limiter
+                .check_at("fast", client(MAX_BUCKETS), 1, later)
+                .allowed
+        );
+        assert!(!limiter.check_at("slow", None, 1, later).allowed);
+    }
+
+    #[test]
+    fn test_bucket_count_is_bounded() {
+        let limiter = limiter(10, 1);
+        let start = Instant::now();
+
+        for n in 0..MAX_BUCKETS + 100 {
+            limiter.check_at("a", client(n), 1, start + Duration::from_millis(n as u64));
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+        }
+        // The most recently used buckets are kept
+        let last = start + Duration::from_millis((MAX_BUCKETS + 100) as u64);
+        let decision = limiter.check_at("a", client(MAX_BUCKETS + 99), 1, last);
+        assert_eq!(decision.remaining, 8);
+    }
+}
@@ -1,9 +1,14 @@
-use chrono::{DateTime, Utc};
+use crate::services::totp::TotpSecret;
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmailSummary {
+    /// Sequence number, which shifts when earlier messages are removed
     pub id: String,
+    /// IMAP UID, stable while the mailbox's UIDVALIDITY is unchanged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
     pub subject: String,
     pub sender: String,
     pub sender_email: String,
@@ -11,9 +16,34 @@ pub struct EmailSummary {
     pub snippet: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<String>, // Full body text for MFA extraction
+    /// HTML part, used for verification link extraction (not serialized)
+    #[serde(skip)]
+    pub html_body: Option<String>,
     pub is_read: bool,
+    /// IMAP flags and keywords, e.g. `\Flagged` or `$Todo`
+    #[serde(default)]
+    pub flags: Vec<String>,
     pub labels: Vec<String>,
     pub importance_score: u8,
+    #[serde(default)]
+    pub category: EmailCategory,
+    /// TOTP secrets from an authenticator enrolment email (never serialized)
+    #[serde(skip)]
+    pub totp_secrets: Vec<TotpSecret>,
+    /// Message headers as (name, value), for filtering rules (not serialized)
+    #[serde(skip)]
+    pub headers: Vec<(String, String)>,
+}
+
+/// Coarse classification used to filter event streams and notifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailCategory {
+    #[default]
+    Personal,
+    Notification,
+    Newsletter,
+    Verification,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -38,3 +68,92 @@ fn default_min_score() -> u8 {
 pub struct BulkDeleteRequest {
     pub ids: Vec<String>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailboxInfo {
+    pub name: String,
+    /// Hierarchy delimiter, e.g. `/`
+    pub delimiter: Option<String>,
+    /// Name attributes, e.g. `\Noselect` or special-use `\Trash`
+    pub attributes: Vec<String>,
+}
+
+/// Target of a move or copy
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailboxTarget {
+    pub mailbox: String,
+    /// Create the mailbox if it does not exist
+    #[serde(default)]
+    pub create: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LabelsRequest {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlagsRequest {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailboxRequest {
+    pub name: String,
+}
+
+/// What `POST /emails/bulk` does with the selected messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    MarkRead,
+    MarkUnread,
+    Flag,
+    Unflag,
+    /// Move to `folder`
+    Move,
+    /// Copy to `folder`, keeping the message in the inbox (a label on Gmail)
+    Label,
+    /// Move to the trash, or expunge with `permanent`
+    Delete,
+}
+
+/// Which messages a bulk operation applies to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkSelector {
+    Ids(Vec<String>),
+    /// Same syntax as `POST /emails/search`
+    Query(String),
+    DateRange {
+        /// First day included
+        since: Option<NaiveDate>,
+        /// First day excluded
+        before: Option<NaiveDate>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkRequest {
+    pub action: BulkAction,
+    pub selector: BulkSelector,
+    /// Target folder of `move` and `label`
+    pub folder: Option<String>,
+    /// Only return the matched messages
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Expunge instead of moving to the trash (`delete` only)
+    #[serde(default)]
+    pub permanent: bool,
+    /// Most recent messages to act on (default: 500, max: 5000)
+    #[serde(default = "default_bulk_limit")]
+    pub limit: usize,
+}
+
+fn default_bulk_limit() -> usize {
+    500
+}
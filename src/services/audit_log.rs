@@ -0,0 +1,265 @@
+use crate::errors::ApiError;
+use crate::middleware::auth::AuthContext;
+use crate::models::EmailSummary;
+use actix_web::{HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    MarkRead,
+    MarkUnread,
+    /// Moved to the trash
+    Delete,
+    /// Expunged without going through the trash
+    PermanentDelete,
+    /// Moved back out of the trash
+    Restore,
+    Move,
+    Copy,
+    /// Gmail labels added or removed
+    Labels,
+    /// IMAP flags or keywords added or removed
+    Flags,
+    BulkMarkRead,
+    BulkMarkUnread,
+    BulkFlag,
+    BulkUnflag,
+    BulkMove,
+    BulkLabel,
+    BulkDelete,
+    TokenCreate,
+    TokenRevoke,
+    WebhookCreate,
+    WebhookDelete,
+    MailboxCreate,
+    MailboxRename,
+    MailboxDelete,
+    RuleCreate,
+    RuleUpdate,
+    RuleDelete,
+    /// A filtering rule acted on incoming mail
+    RuleApplied,
+    /// Unsubscribed from a mailing list
+    Unsubscribe,
+    /// The MFA email cleanup acted on verification emails
+    MfaCleanupMarkRead,
+    MfaCleanupMove,
+    MfaCleanupDelete,
+    /// TOTP secrets of an enrolment email stored in the vault
+    TotpEnrol,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Succeeded,
+    /// Some of the messages of a bulk operation failed
+    PartiallyFailed,
+    Failed,
+}
+
+/// Who made a request: the token (or JWT subject) and client address
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditActor {
+    pub token: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+impl AuditActor {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self {
+            token: req
+                .extensions()
+                .get::<AuthContext>()
+                .map(|context| context.token_name.clone()),
+            client_ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        }
+    }
+}
+
+/// A message an operation touched, with a snapshot taken before the change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditMessage {
+    pub email_id: String,
+    pub subject: Option<String>,
+    pub sender_email: Option<String>,
+    pub succeeded: bool,
+}
+
+impl AuditMessage {
+    pub fn new(email_id: &str, snapshot: Option<&EmailSummary>, succeeded: bool) -> Self {
+        Self {
+            email_id: email_id.to_string(),
+            subject: snapshot.map(|email| email.subject.clone()),
+            sender_email: snapshot.map(|email| email.sender_email.clone()),
+            succeeded,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub actor: AuditActor,
+    pub action: AuditAction,
+    /// Token, webhook or mailbox the operation was about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub messages: Vec<AuditMessage>,
+    pub outcome: AuditOutcome,
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(actor: AuditActor, action: AuditAction) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: Utc::now(),
+            actor,
+            action,
+            target: None,
+            messages: Vec::new(),
+            outcome: AuditOutcome::Succeeded,
+            error: None,
+        }
+    }
+
+    pub fn with_target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Record the messages touched, deriving the outcome from their results
+    pub fn with_messages(mut self, messages: Vec<AuditMessage>) -> Self {
+        let failed = messages.iter().filter(|m| !m.succeeded).count();
+        self.outcome = match failed {
+            0 => AuditOutcome::Succeeded,
+            n if n == messages.len() => AuditOutcome::Failed,
+            _ => AuditOutcome::PartiallyFailed,
+        };
+        self.messages = messages;
+        self
+    }
+
+    /// Record the result of the operation; an error marks it failed
+    pub fn with_result<T>(mut self, result: &Result<T, ApiError>) -> Self {
+        if let Err(e) = result {
+            self.outcome = AuditOutcome::Failed;
+            self.error = Some(e.to_string());
+            for message in &mut self.messages {
+                message.succeeded = false;
+            }
+        }
+        self
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub action: Option<AuditAction>,
+    /// Only records touching this email
+    pub email_id: Option<String>,
+    /// Only records made with this token
+    pub token: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+/// Append-only audit log of mutating operations, one JSON record per line
+pub struct AuditLog {
+    path: PathBuf,
+    /// Serializes appends
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(data_dir: &Path) -> Result<Self, ApiError> {
+        fs::create_dir_all(data_dir).map_err(|e| {
+            ApiError::InternalError(format!(
+                "Failed to create directory {}: {}",
+                data_dir.display(),
+                e
+            ))
+        })?;
+
+        Ok(Self {
+            path: data_dir.join("audit.jsonl"),
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn append(&self, record: &AuditRecord) -> Result<(), ApiError> {
+        let mut line = serde_json::to_string(record)
+            .map_err(|e| ApiError::InternalError(format!("Failed to serialize: {}", e)))?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| {
+                ApiError::InternalError(format!("Failed to write {}: {}", self.path.display(), e))
+            })
+    }
+
+    /// Append a record, logging instead of failing the operation it describes
+    pub async fn record(&self, record: AuditRecord) {
+        if let Err(e) = self.append(&record).await {
+            tracing::error!("Failed to write audit record {:?}: {}", record.action, e);
+        }
+    }
+
+    /// Matching records, newest first
+    pub async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>, ApiError> {
+        let _guard = self.lock.lock().await;
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = fs::File::open(&self.path).map_err(|e| {
+            ApiError::InternalError(format!("Failed to read {}: {}", self.path.display(), e))
+        })?;
+
+        let mut records: Vec<AuditRecord> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(&line) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    tracing::warn!("Skipping unreadable audit record: {}", e);
+                    None
+                }
+            })
+            .filter(|record: &AuditRecord| {
+                query.action.is_none_or(|action| record.action == action)
+                    && query.since.is_none_or(|since| record.timestamp >= since)
+                    && query
+                        .token
+                        .as_ref()
+                        .is_none_or(|token| record.actor.token.as_ref() == Some(token))
+                    && query.email_id.as_ref().is_none_or(|email_id| {
+                        record.messages.iter().any(|m| &m.email_id == email_id)
+                    })
+            })
+            .collect();
+
+        records.reverse();
+        records.truncate(query.limit);
+        Ok(records)
+    }
+}
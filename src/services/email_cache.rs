@@ -69,7 +69,7 @@ impl EmailCache {
             .collect();
 
         // Sort by date, most recent first
-        valid_emails.sort_by(|a, b| b.date.cmp(&a.date));
+        valid_emails.sort_by_key(|email| std::cmp::Reverse(email.date));
         valid_emails.truncate(limit);
 
         valid_emails
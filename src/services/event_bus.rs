@@ -0,0 +1,194 @@
+use crate::models::{EmailCategory, EmailSummary};
+use crate::services::mfa_extractor::MfaCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Kinds of mailbox events published on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    NewEmail,
+    FlagsChanged,
+    Deleted,
+    MfaCodeDetected,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::NewEmail => "new_email",
+            EventType::FlagsChanged => "flags_changed",
+            EventType::Deleted => "deleted",
+            EventType::MfaCodeDetected => "mfa_code_detected",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum EventPayload {
+    NewEmail(EmailSummary),
+    FlagsChanged {
+        email_id: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    Deleted {
+        email_id: String,
+    },
+    MfaCodeDetected(MfaCode),
+}
+
+impl EventPayload {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            EventPayload::NewEmail(_) => EventType::NewEmail,
+            EventPayload::FlagsChanged { .. } => EventType::FlagsChanged,
+            EventPayload::Deleted { .. } => EventType::Deleted,
+            EventPayload::MfaCodeDetected(_) => EventType::MfaCodeDetected,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailboxEvent {
+    /// Monotonic id, used as the SSE `id:` field for `Last-Event-ID` resume
+    pub id: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub payload: EventPayload,
+}
+
+/// Subscriber-side filter. Events that carry no email data (flag changes and
+/// deletions) always pass.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventFilter {
+    pub min_score: Option<u8>,
+    pub category: Option<EmailCategory>,
+    pub service: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &MailboxEvent) -> bool {
+        match &event.payload {
+            EventPayload::NewEmail(email) => {
+                if let Some(min_score) = self.min_score {
+                    if email.importance_score < min_score {
+                        return false;
+                    }
+                }
+                if let Some(category) = self.category {
+                    if email.category != category {
+                        return false;
+                    }
+                }
+                if let Some(ref service) = self.service {
+                    let service_lower = service.to_lowercase();
+                    if !email.sender_email.to_lowercase().contains(&service_lower)
+                        && !email.sender.to_lowercase().contains(&service_lower)
+                    {
+                        return false;
+                    }
+                }
+                true
+            }
+            EventPayload::MfaCodeDetected(code) => {
+                if let Some(category) = self.category {
+                    if category != EmailCategory::Verification {
+                        return false;
+                    }
+                }
+                match self.service {
+                    Some(ref filter_service) => code
+                        .service
+                        .as_ref()
+                        .map(|s| s.to_lowercase().contains(&filter_service.to_lowercase()))
+                        .unwrap_or(false),
+                    None => true,
+                }
+            }
+            EventPayload::FlagsChanged { .. } | EventPayload::Deleted { .. } => true,
+        }
+    }
+}
+
+/// Broadcast bus for mailbox events with a bounded replay log
+pub struct EventBus {
+    sender: broadcast::Sender<MailboxEvent>,
+    log: Mutex<EventLog>,
+}
+
+struct EventLog {
+    events: VecDeque<MailboxEvent>,
+    next_id: u64,
+    capacity: usize,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+
+        Self {
+            sender,
+            log: Mutex::new(EventLog {
+                events: VecDeque::with_capacity(capacity),
+                next_id: 1,
+                capacity,
+            }),
+        }
+    }
+
+    /// Assign an id to the payload, record it and notify live subscribers
+    pub fn publish(&self, payload: EventPayload) -> MailboxEvent {
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+
+        let event = MailboxEvent {
+            id: log.next_id,
+            timestamp: Utc::now(),
+            payload,
+        };
+        log.next_id += 1;
+
+        if log.events.len() >= log.capacity {
+            log.events.pop_front();
+        }
+        log.events.push_back(event.clone());
+
+        // Send while holding the log lock so ids reach subscribers in order.
+        // An error only means nobody is listening right now.
+        let _ = self.sender.send(event.clone());
+
+        tracing::debug!(
+            "Published {} event #{}",
+            event.payload.event_type().as_str(),
+            event.id
+        );
+
+        event
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<MailboxEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Events recorded after `last_id`. If `last_id` has already been evicted,
+    /// everything still retained is returned.
+    pub fn events_since(&self, last_id: u64) -> Vec<MailboxEvent> {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        log.events
+            .iter()
+            .filter(|event| event.id > last_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Id of the most recently published event (0 if none)
+    pub fn latest_id(&self) -> u64 {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        log.next_id - 1
+    }
+}
@@ -0,0 +1,331 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Plain-text rendering of an HTML body
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HtmlText {
+    pub text: String,
+    /// Text of elements that visually stand out: `<strong>`/`<b>`, headings,
+    /// large-font or letter-spaced elements, and cells holding a single token.
+    /// Verification emails put the code in one of these.
+    pub emphasized: Vec<String>,
+}
+
+/// Elements whose content is never rendered
+const SKIPPED_ELEMENTS: &[&str] = &["style", "script", "head", "title", "noscript", "template"];
+
+const VOID_ELEMENTS: &[&str] = &[
+    "br", "img", "meta", "link", "hr", "input", "col", "area", "base", "wbr", "source",
+];
+
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p",
+    "div",
+    "br",
+    "tr",
+    "li",
+    "ul",
+    "ol",
+    "table",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "section",
+    "article",
+    "header",
+    "footer",
+    "center",
+    "hr",
+];
+
+const EMPHASIS_ELEMENTS: &[&str] = &["strong", "b", "h1", "h2", "h3"];
+
+/// Containers whose whole content may be a code ("dedicated" cells)
+const CODE_CONTAINERS: &[&str] = &["td", "th", "div", "p", "span", "code", "pre"];
+
+/// Font sizes at or above this (in px) count as emphasized
+const LARGE_FONT_PX: f32 = 20.0;
+
+struct OpenElement {
+    name: String,
+    hidden: bool,
+    emphasized: bool,
+    /// Byte offset into the output where this element's text starts
+    text_start: usize,
+}
+
+fn tag_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"(?s)<!--.*?-->|<!\[CDATA\[.*?\]\]>|<!.*?>|<(/?)([a-zA-Z][a-zA-Z0-9]*)([^>]*?)(/?)>",
+        )
+        .unwrap()
+    })
+}
+
+fn attribute_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r#"([^\s"'=/>]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+)))?"#).unwrap()
+    })
+}
+
+fn font_size_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"font-size\s*:\s*([0-9.]+)\s*(px|pt|em|rem)?").unwrap())
+}
+
+fn code_token_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9 -]{2,14}[A-Za-z0-9]$").unwrap())
+}
+
+/// Render HTML to text, dropping `<style>`, `<script>` and hidden elements
+pub fn html_to_text(html: &str) -> HtmlText {
+    let mut output = String::new();
+    let mut emphasized = Vec::new();
+    let mut stack: Vec<OpenElement> = Vec::new();
+    let mut last_end = 0;
+
+    let hidden = |stack: &[OpenElement]| stack.iter().any(|e| e.hidden);
+
+    for captures in tag_regex().captures_iter(html) {
+        let whole = captures.get(0).unwrap();
+
+        if !hidden(&stack) {
+            push_text(&mut output, &html[last_end..whole.start()]);
+        }
+        last_end = whole.end();
+
+        // Comments, doctype and CDATA
+        let Some(name) = captures.get(2) else {
+            continue;
+        };
+        let name = name.as_str().to_lowercase();
+        let is_closing = !captures[1].is_empty();
+        let attributes = captures.get(3).map(|m| m.as_str()).unwrap_or_default();
+        let self_closing = !captures[4].is_empty() || VOID_ELEMENTS.contains(&name.as_str());
+
+        if is_closing {
+            // Pop up to and including the matching element; unmatched closing
+            // tags are ignored
+            if let Some(position) = stack.iter().rposition(|e| e.name == name) {
+                while stack.len() > position {
+                    let element = stack.pop().unwrap();
+                    close_element(&element, &output, &mut emphasized);
+                }
+            }
+            if BLOCK_ELEMENTS.contains(&name.as_str()) || name == "td" || name == "th" {
+                push_break(&mut output, &name);
+            }
+            continue;
+        }
+
+        if BLOCK_ELEMENTS.contains(&name.as_str()) && !hidden(&stack) {
+            push_break(&mut output, &name);
+        }
+
+        if self_closing {
+            continue;
+        }
+
+        stack.push(OpenElement {
+            hidden: SKIPPED_ELEMENTS.contains(&name.as_str()) || is_hidden(attributes),
+            emphasized: EMPHASIS_ELEMENTS.contains(&name.as_str()) || is_emphasized(attributes),
+            text_start: output.len(),
+            name,
+        });
+    }
+
+    if !hidden(&stack) {
+        push_text(&mut output, &html[last_end..]);
+    }
+    while let Some(element) = stack.pop() {
+        close_element(&element, &output, &mut emphasized);
+    }
+
+    let text = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    HtmlText { text, emphasized }
+}
+
+fn close_element(element: &OpenElement, output: &str, emphasized: &mut Vec<String>) {
+    if element.hidden || element.text_start > output.len() {
+        return;
+    }
+
+    let content = output[element.text_start..]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if content.is_empty() {
+        return;
+    }
+
+    let dedicated_code = CODE_CONTAINERS.contains(&element.name.as_str())
+        && code_token_regex().is_match(&content)
+        && content.chars().any(|c| c.is_ascii_digit());
+
+    if (element.emphasized || dedicated_code) && !emphasized.contains(&content) {
+        emphasized.push(content);
+    }
+}
+
+fn push_text(output: &mut String, raw: &str) {
+    let decoded = decode_entities(raw);
+    let collapsed = decoded.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.is_empty() {
+        return;
+    }
+
+    if !output.is_empty() && !output.ends_with(['\n', ' ']) {
+        output.push(' ');
+    }
+    output.push_str(&collapsed);
+}
+
+fn push_break(output: &mut String, name: &str) {
+    let separator = if name == "td" || name == "th" {
+        ' '
+    } else {
+        '\n'
+    };
+    if !output.is_empty() && !output.ends_with(separator) {
+        output.push(separator);
+    }
+}
+
+/// The value of the attribute called `name` (case-insensitive); `Some("")`
+/// for a bare attribute such as `hidden`
+fn attribute_value(attributes: &str, name: &str) -> Option<String> {
+    attribute_regex()
+        .captures_iter(attributes)
+        .find(|c| c[1].eq_ignore_ascii_case(name))
+        .map(|c| {
+            (2..=4)
+                .find_map(|group| c.get(group))
+                .map(|value| value.as_str().to_string())
+                .unwrap_or_default()
+        })
+}
+
+fn style_value(attributes: &str) -> String {
+    attribute_value(attributes, "style")
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+fn is_hidden(attributes: &str) -> bool {
+    let style: String = style_value(attributes)
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+
+    attribute_value(attributes, "hidden").is_some()
+        || attribute_value(attributes, "aria-hidden")
+            .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+        || style.contains("display:none")
+        || style.contains("visibility:hidden")
+        || ["max-height", "font-size", "opacity"]
+            .iter()
+            .any(|property| is_zero(&style, property))
+}
+
+/// Whether a compacted style sets `property` to zero (`0`, `0px`, ...)
+fn is_zero(style: &str, property: &str) -> bool {
+    style.split(';').any(|declaration| {
+        declaration
+            .strip_prefix(property)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .map(|value| {
+                value
+                    .trim_end_matches("!important")
+                    .trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%')
+                    .parse::<f32>()
+                    .map(|v| v == 0.0)
+                    .unwrap_or(false)
+            })
+            .unwrap_or(false)
+    })
+}
+
+fn is_emphasized(attributes: &str) -> bool {
+    let style = style_value(attributes);
+    let compact: String = style.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if compact.contains("letter-spacing")
+        || ["bold", "700", "800", "900"]
+            .iter()
+            .any(|weight| compact.contains(&format!("font-weight:{}", weight)))
+    {
+        return true;
+    }
+
+    font_size_regex()
+        .captures(&style)
+        .and_then(|c| {
+            let size: f32 = c[1].parse().ok()?;
+            let px = match c.get(2).map(|u| u.as_str()) {
+                Some("pt") => size * 4.0 / 3.0,
+                Some("em") | Some("rem") => size * 16.0,
+                _ => size,
+            };
+            Some(px >= LARGE_FONT_PX)
+        })
+        .unwrap_or(false)
+}
+
+fn entity_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+);").unwrap())
+}
+
+/// Decode character references in one pass, so `&amp;lt;` stays `&lt;`;
+/// unknown named entities are left as they are
+pub(crate) fn decode_entities(text: &str) -> String {
+    entity_regex()
+        .replace_all(text, |c: &regex::Captures| {
+            let value = &c[1];
+            if let Some(number) = value.strip_prefix('#') {
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                };
+                return code
+                    .and_then(char::from_u32)
+                    .map(|ch| if ch == '\u{a0}' { ' ' } else { ch })
+                    .map(String::from)
+                    .unwrap_or_default();
+            }
+
+            let decoded = match value {
+                "nbsp" => " ",
+                "lt" => "<",
+                "gt" => ">",
+                "quot" => "\"",
+                "apos" => "'",
+                "amp" => "&",
+                "ccedil" => "ç",
+                "atilde" => "ã",
+                "otilde" => "õ",
+                "eacute" => "é",
+                "aacute" => "á",
+                "iacute" => "í",
+                "oacute" => "ó",
+                "uacute" => "ú",
+                _ => return c[0].to_string(),
+            };
+            decoded.to_string()
+        })
+        .into_owned()
+}
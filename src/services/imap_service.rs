@@ -1,20 +1,81 @@
 use crate::errors::ApiError;
-use crate::models::EmailSummary;
+use crate::models::{BulkSelector, EmailCategory, EmailSummary, MailboxInfo};
 use crate::services::connection_pool::ImapConnectionPool;
 use crate::services::email_cache::EmailCache;
+use crate::services::event_bus::{EventBus, EventPayload};
+use crate::services::html_text;
+use crate::services::mfa_extractor::MfaExtractor;
 use crate::services::scoring::EmailScorer;
+use crate::services::totp;
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use imap::types::{Fetch, Flag};
 use imap::Session;
+use mailparse::ParsedMail;
 use native_tls::TlsStream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::TcpStream;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+/// Trash folder used when the server does not flag one with `\Trash`
+pub const DEFAULT_TRASH_FOLDER: &str = "[Gmail]/Trash";
+
+/// A message moved to the trash, with what is needed to move it back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedEmail {
+    pub email_id: String,
+    /// `Message-ID` header, used to find the message in the trash
+    pub message_id: Option<String>,
+    pub source_folder: String,
+    pub trash_folder: String,
+}
+
+/// A message picked by a bulk selector: its id (sequence number) and UID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectedMessage {
+    pub seq: u32,
+    pub uid: u32,
+}
+
+impl SelectedMessage {
+    pub fn id(&self) -> String {
+        self.seq.to_string()
+    }
+}
+
+/// A change applied to a set of messages with a single UID command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BulkOperation {
+    AddFlags(Vec<String>),
+    RemoveFlags(Vec<String>),
+    /// Gmail labels, added through `X-GM-LABELS`
+    AddLabels(Vec<String>),
+    Move(String),
+    Copy(String),
+    Trash,
+    Expunge,
+}
+
+/// Where the mailbox watcher left off: the inbox's UIDVALIDITY and the
+/// highest UID it has seen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxCursor {
+    pub uid_validity: u32,
+    pub last_uid: u32,
+}
+
+/// Clones share the connection pool, cache and scorer, so a clone can talk to
+/// the server without holding the lock the service is shared behind
+#[derive(Clone)]
 pub struct ImapService {
     pool: Arc<ImapConnectionPool>,
     cache: Arc<EmailCache>,
     scorer: Arc<Mutex<EmailScorer>>,
+    event_bus: Option<Arc<EventBus>>,
+    /// Configured trash folder; detected from the server when unset
+    trash_folder: Option<String>,
 }
 
 impl ImapService {
@@ -26,9 +87,38 @@ impl ImapService {
             pool,
             cache,
             scorer: Arc::new(Mutex::new(EmailScorer::new())),
+            event_bus: None,
+            trash_folder: None,
+        }
+    }
+
+    /// Move deleted messages to this folder instead of the server's `\Trash`
+    pub fn with_trash_folder(mut self, folder: impl Into<String>) -> Self {
+        let folder = folder.into();
+        self.trash_folder = Some(folder).filter(|f| !f.is_empty());
+        self
+    }
+
+    /// Publish flag changes and deletions made through this service
+    pub fn with_event_bus(mut self, event_bus: Arc<EventBus>) -> Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    fn publish(&self, payload: EventPayload) {
+        if let Some(ref event_bus) = self.event_bus {
+            event_bus.publish(payload);
         }
     }
 
+    fn publish_flags_changed(&self, email_id: String, added: &[&str], removed: &[&str]) {
+        self.publish(EventPayload::FlagsChanged {
+            email_id,
+            added: added.iter().map(|f| f.to_string()).collect(),
+            removed: removed.iter().map(|f| f.to_string()).collect(),
+        });
+    }
+
     pub async fn get_recent_emails(&self, limit: u32) -> Result<Vec<EmailSummary>, ApiError> {
         // Check if we should use cache or fetch new emails
         // Only use cache if we have very recent data (less than 30 seconds old)
@@ -91,7 +181,7 @@ impl ImapService {
         }
 
         // Sort emails by date, most recent first
-        emails.sort_by(|a, b| b.date.cmp(&a.date));
+        emails.sort_by_key(|email| std::cmp::Reverse(email.date));
 
         // Take only the requested limit after sorting
         emails.truncate(limit as usize);
@@ -127,7 +217,7 @@ impl ImapService {
         }
 
         // Sort emails by date, most recent first
-        emails.sort_by(|a, b| b.date.cmp(&a.date));
+        emails.sort_by_key(|email| std::cmp::Reverse(email.date));
 
         // Cache the fetched emails
         self.cache.put_many(emails.clone()).await;
@@ -141,15 +231,7 @@ impl ImapService {
     pub async fn search_emails(&self, query: &str) -> Result<Vec<EmailSummary>, ApiError> {
         let mut session = self.pool.get().await?;
 
-        // Convert Gmail-style query to IMAP
-        // Simple conversion - in production you'd want more sophisticated parsing
-        let imap_query = if query.starts_with("from:") {
-            format!("FROM \"{}\"", query.trim_start_matches("from:"))
-        } else if query.starts_with("subject:") {
-            format!("SUBJECT \"{}\"", query.trim_start_matches("subject:"))
-        } else {
-            format!("TEXT \"{}\"", query)
-        };
+        let imap_query = imap_search_query(query);
 
         let messages = session
             .search(&imap_query)
@@ -169,7 +251,7 @@ impl ImapService {
         }
 
         // Sort emails by date, most recent first
-        emails.sort_by(|a, b| b.date.cmp(&a.date));
+        emails.sort_by_key(|email| std::cmp::Reverse(email.date));
 
         // Return up to 50 most recent emails
         emails.truncate(50);
@@ -195,6 +277,7 @@ impl ImapService {
             .map_err(|e| ApiError::InternalError(format!("Failed to mark as read: {}", e)))?;
 
         self.pool.return_connection(session).await;
+        self.publish_flags_changed(uid.to_string(), &["\\Seen"], &[]);
         Ok(())
     }
 
@@ -210,31 +293,311 @@ impl ImapService {
             .map_err(|e| ApiError::InternalError(format!("Failed to mark as unread: {}", e)))?;
 
         self.pool.return_connection(session).await;
+        self.publish_flags_changed(uid.to_string(), &[], &["\\Seen"]);
         Ok(())
     }
 
-    pub async fn delete_email(&self, message_id: &str) -> Result<(), ApiError> {
+    /// Move a message out of the inbox into `mailbox` (a label on Gmail),
+    /// creating the mailbox first when `create` is set
+    pub async fn move_email(
+        &self,
+        message_id: &str,
+        mailbox: &str,
+        create: bool,
+    ) -> Result<(), ApiError> {
+        validate_mailbox_name(mailbox)?;
         let mut session = self.pool.get().await?;
 
-        let uid: u32 = message_id
-            .parse()
-            .map_err(|_| ApiError::ValidationError("Invalid message ID".to_string()))?;
+        let result = (|| {
+            let (uid, _) = resolve_message(&mut session, message_id)?;
+            if create {
+                ensure_folder(&mut session, mailbox);
+            }
+            move_uids(&mut session, &uid.to_string(), mailbox)
+        })();
 
-        // Mark as deleted
-        session
-            .store(format!("{}", uid), "+FLAGS (\\Deleted)")
-            .map_err(|e| ApiError::InternalError(format!("Failed to delete: {}", e)))?;
+        self.pool.return_connection(session).await;
+        result
+    }
 
-        // Expunge to actually delete
-        session
-            .expunge()
-            .map_err(|e| ApiError::InternalError(format!("Failed to expunge: {}", e)))?;
+    /// Copy a message into `mailbox`, keeping it in the inbox
+    pub async fn copy_email(
+        &self,
+        message_id: &str,
+        mailbox: &str,
+        create: bool,
+    ) -> Result<(), ApiError> {
+        validate_mailbox_name(mailbox)?;
+        let mut session = self.pool.get().await?;
+
+        let result = (|| {
+            let (uid, _) = resolve_message(&mut session, message_id)?;
+            if create {
+                ensure_folder(&mut session, mailbox);
+            }
+            session
+                .uid_copy(uid.to_string(), mailbox)
+                .map_err(|e| mailbox_error("copy", mailbox, e))
+        })();
+
+        self.pool.return_connection(session).await;
+        result
+    }
+
+    /// Add and remove Gmail labels (`X-GM-LABELS`)
+    pub async fn modify_labels(
+        &self,
+        message_id: &str,
+        add: &[String],
+        remove: &[String],
+    ) -> Result<(), ApiError> {
+        if add.is_empty() && remove.is_empty() {
+            return Err(ApiError::ValidationError(
+                "No labels to add or remove".to_string(),
+            ));
+        }
+        let add = gmail_labels(add)?;
+        let remove = gmail_labels(remove)?;
+        let mut session = self.pool.get().await?;
+
+        let result = (|| {
+            let (uid, _) = resolve_message(&mut session, message_id)?;
+            for (sign, labels) in [('+', &add), ('-', &remove)] {
+                if labels.is_empty() {
+                    continue;
+                }
+                session
+                    .uid_store(uid.to_string(), format!("{}X-GM-LABELS ({})", sign, labels))
+                    .map_err(|e| {
+                        ApiError::InternalError(format!("Failed to store labels: {}", e))
+                    })?;
+            }
+            Ok(())
+        })();
+
+        self.pool.return_connection(session).await;
+        result
+    }
+
+    /// Add and remove IMAP flags and keywords. Flags the mailbox does not
+    /// keep (`PERMANENTFLAGS`) are rejected before anything is stored.
+    pub async fn modify_flags(
+        &self,
+        message_id: &str,
+        add: &[String],
+        remove: &[String],
+    ) -> Result<(), ApiError> {
+        if add.is_empty() && remove.is_empty() {
+            return Err(ApiError::ValidationError(
+                "No flags to add or remove".to_string(),
+            ));
+        }
+        let add = normalize_flags(add)?;
+        let remove = normalize_flags(remove)?;
+        let mut session = self.pool.get().await?;
+
+        let result = (|| {
+            // Re-selecting the inbox is how the server reports PERMANENTFLAGS
+            let mailbox = session
+                .select("INBOX")
+                .map_err(|e| ApiError::InternalError(format!("Failed to select INBOX: {}", e)))?;
+            let permanent: Vec<String> = mailbox
+                .permanent_flags
+                .iter()
+                .map(|f| f.to_string())
+                .collect();
+            let rejected = unsupported_flags(&add, &permanent);
+            if !rejected.is_empty() {
+                return Err(ApiError::ValidationError(format!(
+                    "Flags not supported by the server: {}",
+                    rejected.join(", ")
+                )));
+            }
+
+            let (uid, _) = resolve_message(&mut session, message_id)?;
+            for (sign, flags) in [('+', &add), ('-', &remove)] {
+                if flags.is_empty() {
+                    continue;
+                }
+                session
+                    .uid_store(
+                        uid.to_string(),
+                        format!("{}FLAGS ({})", sign, flags.join(" ")),
+                    )
+                    .map_err(|e| {
+                        ApiError::InternalError(format!("Failed to store flags: {}", e))
+                    })?;
+            }
+            Ok(())
+        })();
+
+        self.pool.return_connection(session).await;
+        if result.is_ok() {
+            let add: Vec<&str> = add.iter().map(String::as_str).collect();
+            let remove: Vec<&str> = remove.iter().map(String::as_str).collect();
+            self.publish_flags_changed(message_id.to_string(), &add, &remove);
+        }
+        result
+    }
+
+    pub async fn list_mailboxes(&self) -> Result<Vec<MailboxInfo>, ApiError> {
+        let mut session = self.pool.get().await?;
+
+        let result = session
+            .list(Some(""), Some("*"))
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| MailboxInfo {
+                        name: name.name().to_string(),
+                        delimiter: name.delimiter().map(str::to_string),
+                        attributes: name
+                            .attributes()
+                            .iter()
+                            .map(|attribute| match attribute {
+                                imap::types::NameAttribute::NoInferiors => {
+                                    "\\Noinferiors".to_string()
+                                }
+                                imap::types::NameAttribute::NoSelect => "\\Noselect".to_string(),
+                                imap::types::NameAttribute::Marked => "\\Marked".to_string(),
+                                imap::types::NameAttribute::Unmarked => "\\Unmarked".to_string(),
+                                imap::types::NameAttribute::Custom(a) => a.to_string(),
+                            })
+                            .collect(),
+                    })
+                    .collect()
+            })
+            .map_err(|e| ApiError::InternalError(format!("Failed to list mailboxes: {}", e)));
+
+        self.pool.return_connection(session).await;
+        result
+    }
+
+    pub async fn create_mailbox(&self, name: &str) -> Result<(), ApiError> {
+        validate_mailbox_name(name)?;
+        let mut session = self.pool.get().await?;
+
+        let result = session
+            .create(name)
+            .map_err(|e| ApiError::InternalError(format!("Failed to create {}: {}", name, e)));
 
         self.pool.return_connection(session).await;
+        result
+    }
+
+    pub async fn rename_mailbox(&self, from: &str, to: &str) -> Result<(), ApiError> {
+        validate_mailbox_name(to)?;
+        validate_user_mailbox(from)?;
+        let mut session = self.pool.get().await?;
+
+        let result = session
+            .rename(from, to)
+            .map_err(|e| ApiError::InternalError(format!("Failed to rename {}: {}", from, e)));
+
+        self.pool.return_connection(session).await;
+        result
+    }
+
+    /// Delete a mailbox. Servers delete the messages only found in it.
+    pub async fn delete_mailbox(&self, name: &str) -> Result<(), ApiError> {
+        validate_user_mailbox(name)?;
+        let mut session = self.pool.get().await?;
+
+        let result = session
+            .delete(name)
+            .map_err(|e| ApiError::InternalError(format!("Failed to delete {}: {}", name, e)));
+
+        self.pool.return_connection(session).await;
+        result
+    }
+
+    /// Delete a message permanently. Only this message is expunged.
+    pub async fn delete_email(&self, message_id: &str) -> Result<(), ApiError> {
+        let mut session = self.pool.get().await?;
+
+        let result = (|| {
+            let (uid, _) = resolve_message(&mut session, message_id)?;
+            expunge_uids(&mut session, &uid.to_string())
+        })();
+
+        self.pool.return_connection(session).await;
+        result?;
+        self.publish(EventPayload::Deleted {
+            email_id: message_id.to_string(),
+        });
         Ok(())
     }
 
-    pub async fn mark_multiple_as_read(&self, count: u32) -> Result<usize, ApiError> {
+    fn trash_folder(&self, session: &mut Session<TlsStream<TcpStream>>) -> String {
+        match &self.trash_folder {
+            Some(folder) => folder.clone(),
+            None => find_trash_folder(session),
+        }
+    }
+
+    /// Move a message to the trash, from where `restore_email` can bring it
+    /// back. Servers without MOVE get a COPY plus UID EXPUNGE.
+    pub async fn trash_email(&self, message_id: &str) -> Result<TrashedEmail, ApiError> {
+        let mut session = self.pool.get().await?;
+
+        let result = (|| {
+            let (uid, header_id) = resolve_message(&mut session, message_id)?;
+            let trash_folder = self.trash_folder(&mut session);
+            move_uids(&mut session, &uid.to_string(), &trash_folder)?;
+
+            Ok(TrashedEmail {
+                email_id: message_id.to_string(),
+                message_id: header_id,
+                source_folder: "INBOX".to_string(),
+                trash_folder,
+            })
+        })();
+
+        self.pool.return_connection(session).await;
+        let trashed = result?;
+        self.publish(EventPayload::Deleted {
+            email_id: message_id.to_string(),
+        });
+        Ok(trashed)
+    }
+
+    /// Move a trashed message back to the folder it was deleted from
+    pub async fn restore_email(&self, trashed: &TrashedEmail) -> Result<(), ApiError> {
+        let header_id = trashed.message_id.as_deref().ok_or_else(|| {
+            ApiError::ValidationError(format!(
+                "Email {} has no Message-ID and cannot be found in the trash",
+                trashed.email_id
+            ))
+        })?;
+        let mut session = self.pool.get().await?;
+
+        let result = (|| {
+            session.select(&trashed.trash_folder).map_err(|e| {
+                ApiError::InternalError(format!("Failed to open {}: {}", trashed.trash_folder, e))
+            })?;
+            let uids = session
+                .uid_search(format!("HEADER Message-ID {}", quoted(header_id)))
+                .map_err(|e| ApiError::InternalError(format!("Search failed: {}", e)))?;
+            let uid = uids.into_iter().max().ok_or_else(|| {
+                ApiError::NotFound(format!(
+                    "{} is no longer in {}",
+                    trashed.email_id, trashed.trash_folder
+                ))
+            })?;
+
+            move_uids(&mut session, &uid.to_string(), &trashed.source_folder)
+        })();
+
+        // Pooled sessions are expected to have the inbox selected
+        let reselected = session.select("INBOX");
+        if reselected.is_ok() {
+            self.pool.return_connection(session).await;
+        }
+        result
+    }
+
+    /// Mark the most recent unread messages as read, returning their ids
+    pub async fn mark_multiple_as_read(&self, count: u32) -> Result<Vec<String>, ApiError> {
         let mut session = self.pool.get().await?;
 
         // Get the most recent unread messages
@@ -248,54 +611,224 @@ impl ImapService {
         messages_vec.sort_by(|a, b| b.cmp(a));
         let messages_to_mark: Vec<_> = messages_vec.into_iter().take(count as usize).collect();
 
-        let total_marked = messages_to_mark.len();
-
         // Mark each message as read
+        let mut marked = Vec::new();
         for uid in &messages_to_mark {
-            let _ = session.store(format!("{}", uid), "+FLAGS (\\Seen)");
+            if session.store(format!("{}", uid), "+FLAGS (\\Seen)").is_ok() {
+                marked.push(*uid);
+            }
         }
 
         self.pool.return_connection(session).await;
-        Ok(total_marked)
+        let marked: Vec<String> = marked.iter().map(|uid| uid.to_string()).collect();
+        for uid in &marked {
+            self.publish_flags_changed(uid.clone(), &["\\Seen"], &[]);
+        }
+        Ok(marked)
     }
 
-    pub async fn delete_multiple(&self, ids: Vec<String>) -> Result<usize, ApiError> {
+    /// Messages matching a bulk selector, most recent first, at most `limit`
+    pub async fn select_messages(
+        &self,
+        selector: &BulkSelector,
+        limit: usize,
+    ) -> Result<Vec<SelectedMessage>, ApiError> {
+        let (mut ids, criteria) = match selector {
+            BulkSelector::Ids(ids) => (
+                ids.iter()
+                    .map(|id| {
+                        id.parse::<u32>().map_err(|_| {
+                            ApiError::ValidationError(format!("Invalid message ID '{}'", id))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                None,
+            ),
+            BulkSelector::Query(query) => (Vec::new(), Some(imap_search_query(query))),
+            BulkSelector::DateRange { since, before } => {
+                (Vec::new(), Some(date_range_query(*since, *before)?))
+            }
+        };
+
         let mut session = self.pool.get().await?;
-        let mut deleted = 0;
-
-        for id in ids {
-            if let Ok(uid) = id.parse::<u32>() {
-                if session
-                    .store(format!("{}", uid), "+FLAGS (\\Deleted)")
-                    .is_ok()
-                {
-                    deleted += 1;
-                }
+        let result = (|| {
+            if let Some(criteria) = &criteria {
+                ids = session
+                    .search(criteria)
+                    .map_err(|e| ApiError::InternalError(format!("Search failed: {}", e)))?
+                    .into_iter()
+                    .collect();
+            }
+            // Higher sequence numbers are more recent
+            ids.sort_unstable_by(|a, b| b.cmp(a));
+            ids.dedup();
+            ids.truncate(limit);
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let fetched = session
+                .fetch(sequence_set(&ids), "UID")
+                .map_err(|e| ApiError::InternalError(format!("Fetch failed: {}", e)))?;
+            let mut selected: Vec<SelectedMessage> = fetched
+                .iter()
+                .filter_map(|message| {
+                    message.uid.map(|uid| SelectedMessage {
+                        seq: message.message,
+                        uid,
+                    })
+                })
+                .collect();
+            selected.sort_unstable_by_key(|message| std::cmp::Reverse(message.seq));
+            Ok(selected)
+        })();
+
+        self.pool.return_connection(session).await;
+        result
+    }
+
+    /// Summaries of selected messages, for a dry run
+    pub async fn preview_messages(
+        &self,
+        messages: &[SelectedMessage],
+    ) -> Result<Vec<EmailSummary>, ApiError> {
+        let mut session = self.pool.get().await?;
+
+        let mut emails = Vec::new();
+        for message in messages {
+            if let Ok(email) = self.fetch_email(&mut session, message.seq).await {
+                emails.push(email);
             }
         }
+        self.cache.put_many(emails.clone()).await;
 
-        session
-            .expunge()
-            .map_err(|e| ApiError::InternalError(format!("Failed to expunge: {}", e)))?;
+        self.pool.return_connection(session).await;
+        Ok(emails)
+    }
+
+    /// Apply an operation to all the messages at once, by UID. Returns the
+    /// trashed messages for `BulkOperation::Trash`.
+    pub async fn apply_bulk(
+        &self,
+        messages: &[SelectedMessage],
+        operation: &BulkOperation,
+    ) -> Result<Vec<TrashedEmail>, ApiError> {
+        if messages.is_empty() {
+            return Ok(Vec::new());
+        }
+        let uids: Vec<u32> = messages.iter().map(|message| message.uid).collect();
+        let uid_set = sequence_set(&uids);
+        let mut session = self.pool.get().await?;
+
+        let result = (|| {
+            match operation {
+                BulkOperation::AddFlags(flags) | BulkOperation::RemoveFlags(flags) => {
+                    let sign = if matches!(operation, BulkOperation::AddFlags(_)) {
+                        '+'
+                    } else {
+                        '-'
+                    };
+                    session
+                        .uid_store(&uid_set, format!("{}FLAGS ({})", sign, flags.join(" ")))
+                        .map_err(|e| {
+                            ApiError::InternalError(format!("Failed to store flags: {}", e))
+                        })?;
+                }
+                BulkOperation::AddLabels(labels) => {
+                    session
+                        .uid_store(
+                            &uid_set,
+                            format!("+X-GM-LABELS ({})", gmail_labels(labels)?),
+                        )
+                        .map_err(|e| {
+                            ApiError::InternalError(format!("Failed to store labels: {}", e))
+                        })?;
+                }
+                BulkOperation::Move(folder) => {
+                    ensure_folder(&mut session, folder);
+                    move_uids(&mut session, &uid_set, folder)?;
+                }
+                BulkOperation::Copy(folder) => {
+                    ensure_folder(&mut session, folder);
+                    session
+                        .uid_copy(&uid_set, folder)
+                        .map_err(|e| ApiError::InternalError(format!("Failed to copy: {}", e)))?;
+                }
+                BulkOperation::Expunge => expunge_uids(&mut session, &uid_set)?,
+                BulkOperation::Trash => {
+                    let trash_folder = self.trash_folder(&mut session);
+                    let header_ids = header_message_ids(&mut session, &uid_set)?;
+                    move_uids(&mut session, &uid_set, &trash_folder)?;
+                    return Ok(messages
+                        .iter()
+                        .map(|message| TrashedEmail {
+                            email_id: message.id(),
+                            message_id: header_ids.get(&message.uid).cloned(),
+                            source_folder: "INBOX".to_string(),
+                            trash_folder: trash_folder.clone(),
+                        })
+                        .collect());
+                }
+            }
+            Ok(Vec::new())
+        })();
 
         self.pool.return_connection(session).await;
-        Ok(deleted)
+        let trashed = result?;
+        for message in messages {
+            match operation {
+                BulkOperation::AddFlags(flags) => {
+                    let flags: Vec<&str> = flags.iter().map(String::as_str).collect();
+                    self.publish_flags_changed(message.id(), &flags, &[])
+                }
+                BulkOperation::RemoveFlags(flags) => {
+                    let flags: Vec<&str> = flags.iter().map(String::as_str).collect();
+                    self.publish_flags_changed(message.id(), &[], &flags)
+                }
+                BulkOperation::Trash | BulkOperation::Expunge => {
+                    self.publish(EventPayload::Deleted {
+                        email_id: message.id(),
+                    })
+                }
+                BulkOperation::AddLabels(_) | BulkOperation::Move(_) | BulkOperation::Copy(_) => {}
+            }
+        }
+        Ok(trashed)
     }
 
     async fn fetch_email(
+        &self,
+        session: &mut Session<TlsStream<TcpStream>>,
+        seq: u32,
+    ) -> Result<EmailSummary, ApiError> {
+        let messages = session
+            .fetch(format!("{}", seq), "(UID FLAGS BODY.PEEK[])")
+            .map_err(|e| ApiError::InternalError(format!("Fetch failed: {}", e)))?;
+
+        let message = messages
+            .iter()
+            .next()
+            .ok_or_else(|| ApiError::InternalError("No message found".to_string()))?;
+        self.summarize(message).await
+    }
+
+    async fn fetch_email_by_uid(
         &self,
         session: &mut Session<TlsStream<TcpStream>>,
         uid: u32,
     ) -> Result<EmailSummary, ApiError> {
         let messages = session
-            .fetch(format!("{}", uid), "RFC822")
+            .uid_fetch(format!("{}", uid), "(UID FLAGS BODY.PEEK[])")
             .map_err(|e| ApiError::InternalError(format!("Fetch failed: {}", e)))?;
 
         let message = messages
             .iter()
             .next()
             .ok_or_else(|| ApiError::InternalError("No message found".to_string()))?;
+        self.summarize(message).await
+    }
 
+    async fn summarize(&self, message: &Fetch) -> Result<EmailSummary, ApiError> {
         let body = message
             .body()
             .ok_or_else(|| ApiError::InternalError("No message body".to_string()))?;
@@ -344,8 +877,10 @@ impl ImapService {
             Utc::now()
         };
 
-        // Extract body text
-        let body_text = parsed.get_body().unwrap_or_else(|_| String::new());
+        let MessageBodies {
+            text: body_text,
+            html: html_part,
+        } = message_bodies(&parsed);
         let snippet = body_text.chars().take(200).collect::<String>();
 
         // Keep full body for MFA extraction (limit to 5000 chars to avoid huge emails)
@@ -355,6 +890,18 @@ impl ImapService {
             None
         };
 
+        // HTML part, if any, for emphasized codes and verification links
+        let html_body = html_part.map(|html| html.chars().take(50000).collect::<String>());
+
+        // Authenticator setup keys, otpauth:// URIs and QR codes
+        let totp_secrets = totp::detect_secrets(
+            &subject,
+            &from,
+            &body_text,
+            html_body.as_deref(),
+            &image_parts(&parsed),
+        );
+
         // Parse sender email
         let sender_email = if from.contains('<') && from.contains('>') {
             from.split('<')
@@ -373,9 +920,14 @@ impl ImapService {
             from.clone()
         };
 
-        // Check if read - for simplicity, we'll assume all messages in INBOX are unread unless explicitly marked
-        // In production, you'd properly handle the flag lifetime issue
-        let is_read = false; // Default to unread for now
+        // \Recent is session state, not something clients can set or search on
+        let flags: Vec<String> = message
+            .flags()
+            .iter()
+            .filter(|flag| **flag != Flag::Recent)
+            .map(|flag| flag.to_string())
+            .collect();
+        let is_read = message.flags().contains(&Flag::Seen);
 
         // Get labels (folders)
         let labels = vec!["INBOX".to_string()];
@@ -384,19 +936,39 @@ impl ImapService {
         // Score the email
         let scorer = self.scorer.lock().await;
         let importance_score = scorer.calculate_score(&sender_email, &subject, &label_refs);
+        let category = scorer.categorize(&sender_email, &subject);
+        drop(scorer);
 
-        Ok(EmailSummary {
-            id: uid.to_string(),
+        let headers = parsed
+            .headers
+            .iter()
+            .map(|h| (h.get_key(), h.get_value()))
+            .collect();
+
+        let mut email = EmailSummary {
+            id: message.message.to_string(),
+            uid: message.uid,
             sender,
             sender_email,
             subject,
             snippet,
             body,
+            html_body,
             date,
             is_read,
+            flags,
             labels,
             importance_score,
-        })
+            category,
+            totp_secrets,
+            headers,
+        };
+
+        if !MfaExtractor::extract_from_email(&email).is_empty() {
+            email.category = EmailCategory::Verification;
+        }
+
+        Ok(email)
     }
 
     pub async fn get_today_emails(&self) -> Result<Vec<EmailSummary>, ApiError> {
@@ -440,7 +1012,7 @@ impl ImapService {
         }
 
         // Sort emails by date, most recent first
-        emails.sort_by(|a, b| b.date.cmp(&a.date));
+        emails.sort_by_key(|email| std::cmp::Reverse(email.date));
 
         // Update cache with fresh data
         self.cache.put_many(emails.clone()).await;
@@ -451,6 +1023,85 @@ impl ImapService {
         Ok(emails)
     }
 
+    /// Messages that arrived in the inbox after `cursor`, oldest first (at
+    /// most the `limit` most recent), and the cursor for the next call.
+    /// Without a cursor, or when UIDVALIDITY changed, only the cursor is
+    /// returned so mail already in the inbox is not reported as new.
+    pub async fn new_emails_since(
+        &self,
+        cursor: Option<MailboxCursor>,
+        limit: u32,
+    ) -> Result<(MailboxCursor, Vec<EmailSummary>), ApiError> {
+        let mut session = self.pool.get().await?;
+        let result = self.poll_inbox(&mut session, cursor, limit).await;
+        self.pool.return_connection(session).await;
+
+        if let Ok((_, emails)) = &result {
+            self.cache.put_many(emails.clone()).await;
+        }
+        result
+    }
+
+    async fn poll_inbox(
+        &self,
+        session: &mut Session<TlsStream<TcpStream>>,
+        cursor: Option<MailboxCursor>,
+        limit: u32,
+    ) -> Result<(MailboxCursor, Vec<EmailSummary>), ApiError> {
+        // Selecting again refreshes UIDVALIDITY and UIDNEXT
+        let mailbox = session
+            .select("INBOX")
+            .map_err(|e| ApiError::InternalError(format!("Failed to open INBOX: {}", e)))?;
+        let uid_validity = mailbox.uid_validity.unwrap_or_default();
+
+        let Some(cursor) = cursor.filter(|cursor| cursor.uid_validity == uid_validity) else {
+            let last_uid = match mailbox.uid_next {
+                Some(uid_next) => uid_next.saturating_sub(1),
+                None => session
+                    .uid_search("ALL")
+                    .map_err(|e| ApiError::InternalError(format!("Search failed: {}", e)))?
+                    .into_iter()
+                    .max()
+                    .unwrap_or_default(),
+            };
+            return Ok((
+                MailboxCursor {
+                    uid_validity,
+                    last_uid,
+                },
+                Vec::new(),
+            ));
+        };
+
+        // `n:*` always matches the last message, even below `n`
+        let mut uids: Vec<u32> = session
+            .uid_search(format!("UID {}:*", cursor.last_uid + 1))
+            .map_err(|e| ApiError::InternalError(format!("Search failed: {}", e)))?
+            .into_iter()
+            .filter(|uid| *uid > cursor.last_uid)
+            .collect();
+        uids.sort_unstable();
+        let next = MailboxCursor {
+            uid_validity,
+            last_uid: uids.last().copied().unwrap_or(cursor.last_uid),
+        };
+
+        let skipped = uids.len().saturating_sub(limit as usize);
+        let mut emails = Vec::new();
+        for uid in &uids[skipped..] {
+            match self.fetch_email_by_uid(session, *uid).await {
+                Ok(email) => emails.push(email),
+                Err(e) => tracing::warn!("Failed to fetch new message {}: {}", uid, e),
+            }
+        }
+        Ok((next, emails))
+    }
+
+    /// An email from the cache, without going to the server
+    pub async fn cached_email(&self, id: &str) -> Option<EmailSummary> {
+        self.cache.get(id).await
+    }
+
     pub async fn get_email_by_id(&self, id: &str) -> Result<EmailSummary, ApiError> {
         // First check cache
         if let Some(cached) = self.cache.get(id).await {
@@ -472,3 +1123,397 @@ impl ImapService {
         Ok(email)
     }
 }
+
+/// Gmail-style query (`from:`, `subject:` or free text) as IMAP search
+/// criteria. `is:flagged`, `is:unflagged`, `is:read`, `is:unread`,
+/// `keyword:$Todo` and `-keyword:$Todo` words filter on flags.
+pub fn imap_search_query(query: &str) -> String {
+    let mut flag_criteria = Vec::new();
+    let mut words = Vec::new();
+    for word in query.split_whitespace() {
+        match flag_criterion(word) {
+            Some(criterion) => flag_criteria.push(criterion),
+            None => words.push(word),
+        }
+    }
+    if flag_criteria.is_empty() {
+        return text_criterion(query);
+    }
+
+    let mut criteria = Vec::new();
+    if !words.is_empty() {
+        criteria.push(text_criterion(&words.join(" ")));
+    }
+    criteria.extend(flag_criteria);
+    criteria.join(" ")
+}
+
+fn text_criterion(query: &str) -> String {
+    // Simple conversion - in production you'd want more sophisticated parsing
+    if query.starts_with("from:") {
+        format!("FROM \"{}\"", query.trim_start_matches("from:"))
+    } else if query.starts_with("subject:") {
+        format!("SUBJECT \"{}\"", query.trim_start_matches("subject:"))
+    } else {
+        format!("TEXT \"{}\"", query)
+    }
+}
+
+fn flag_criterion(word: &str) -> Option<String> {
+    let criterion = match word.to_ascii_lowercase().as_str() {
+        "is:flagged" | "is:starred" => "FLAGGED",
+        "is:unflagged" | "is:unstarred" => "UNFLAGGED",
+        "is:read" => "SEEN",
+        "is:unread" => "UNSEEN",
+        _ => {
+            if let Some(keyword) = word.strip_prefix("-keyword:") {
+                return is_keyword(keyword).then(|| format!("UNKEYWORD {}", keyword));
+            }
+            let keyword = word.strip_prefix("keyword:")?;
+            return is_keyword(keyword).then(|| format!("KEYWORD {}", keyword));
+        }
+    };
+    Some(criterion.to_string())
+}
+
+/// System flags that can be set through `POST /emails/{id}/flags`.
+/// `\Deleted` is left to the delete endpoints and `\Recent` cannot be set.
+const SETTABLE_SYSTEM_FLAGS: [&str; 4] = ["\\Seen", "\\Answered", "\\Flagged", "\\Draft"];
+
+/// A keyword is an IMAP atom that is not a system flag, e.g. `$Todo`
+fn is_keyword(flag: &str) -> bool {
+    !flag.is_empty()
+        && flag.chars().all(|c| {
+            c.is_ascii_graphic() && !matches!(c, '(' | ')' | '{' | '%' | '*' | '"' | '\\' | ']')
+        })
+}
+
+/// Flags checked for syntax, with system flags in their canonical case
+pub fn normalize_flags(flags: &[String]) -> Result<Vec<String>, ApiError> {
+    flags
+        .iter()
+        .map(|flag| {
+            if let Some(system) = SETTABLE_SYSTEM_FLAGS
+                .iter()
+                .find(|system| system.eq_ignore_ascii_case(flag))
+            {
+                Ok(system.to_string())
+            } else if is_keyword(flag) {
+                Ok(flag.clone())
+            } else {
+                Err(ApiError::ValidationError(format!(
+                    "Invalid flag '{}'",
+                    flag
+                )))
+            }
+        })
+        .collect()
+}
+
+/// Flags the mailbox would not keep, given its `PERMANENTFLAGS`. `\*` in
+/// that list allows new keywords; an empty list means every flag is kept.
+pub fn unsupported_flags<'a>(flags: &'a [String], permanent_flags: &[String]) -> Vec<&'a str> {
+    if permanent_flags.is_empty() {
+        return Vec::new();
+    }
+    let may_create = permanent_flags.iter().any(|f| f == "\\*");
+    flags
+        .iter()
+        .filter(|flag| {
+            let new_keyword_allowed = may_create && !flag.starts_with('\\');
+            !new_keyword_allowed && !permanent_flags.iter().any(|f| f.eq_ignore_ascii_case(flag))
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+/// IMAP search criteria for messages from `since` (included) to `before`
+/// (excluded)
+pub fn date_range_query(
+    since: Option<NaiveDate>,
+    before: Option<NaiveDate>,
+) -> Result<String, ApiError> {
+    if let (Some(since), Some(before)) = (since, before) {
+        if since >= before {
+            return Err(ApiError::ValidationError(
+                "Date range 'since' must be before 'before'".to_string(),
+            ));
+        }
+    }
+
+    let criteria: Vec<String> = [("SINCE", since), ("BEFORE", before)]
+        .into_iter()
+        .filter_map(|(key, date)| date.map(|d| format!("{} {}", key, d.format("%d-%b-%Y"))))
+        .collect();
+    if criteria.is_empty() {
+        return Err(ApiError::ValidationError(
+            "Date range needs 'since' or 'before'".to_string(),
+        ));
+    }
+    Ok(criteria.join(" "))
+}
+
+/// Compact IMAP sequence set, e.g. `1:3,7,9:10`
+pub fn sequence_set(ids: &[u32]) -> String {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let mut ranges: Vec<String> = Vec::new();
+    let mut iter = ids.into_iter().peekable();
+    while let Some(start) = iter.next() {
+        let mut end = start;
+        while iter.peek() == Some(&(end + 1)) {
+            end = iter.next().unwrap_or(end);
+        }
+        ranges.push(if start == end {
+            start.to_string()
+        } else {
+            format!("{}:{}", start, end)
+        });
+    }
+    ranges.join(",")
+}
+
+/// Create a folder, unless it already exists
+fn ensure_folder(session: &mut Session<TlsStream<TcpStream>>, folder: &str) {
+    if let Err(e) = session.create(folder) {
+        tracing::debug!("Not creating folder {}: {}", folder, e);
+    }
+}
+
+/// Flag messages `\Deleted` and expunge only them, not every message flagged
+/// `\Deleted` in the mailbox
+fn expunge_uids(
+    session: &mut Session<TlsStream<TcpStream>>,
+    uid_set: &str,
+) -> Result<(), ApiError> {
+    session
+        .uid_store(uid_set, "+FLAGS (\\Deleted)")
+        .map_err(|e| ApiError::InternalError(format!("Failed to delete: {}", e)))?;
+    session
+        .uid_expunge(uid_set)
+        .map_err(|e| ApiError::InternalError(format!("Failed to expunge: {}", e)))?;
+    Ok(())
+}
+
+/// Whether the server supports the MOVE extension (RFC 6851)
+fn supports_move(session: &mut Session<TlsStream<TcpStream>>) -> bool {
+    session
+        .capabilities()
+        .map(|capabilities| capabilities.has_str("MOVE"))
+        .unwrap_or(false)
+}
+
+/// Servers answer `NO [TRYCREATE]` when the target mailbox does not exist
+fn mailbox_error(operation: &str, mailbox: &str, e: imap::error::Error) -> ApiError {
+    if e.to_string().contains("TRYCREATE") {
+        ApiError::NotFound(format!("Mailbox {}", mailbox))
+    } else {
+        ApiError::InternalError(format!("Failed to {} to {}: {}", operation, mailbox, e))
+    }
+}
+
+/// MOVE messages, or COPY and expunge them on servers without MOVE
+fn move_uids(
+    session: &mut Session<TlsStream<TcpStream>>,
+    uid_set: &str,
+    folder: &str,
+) -> Result<(), ApiError> {
+    if supports_move(session) {
+        return session
+            .uid_mv(uid_set, folder)
+            .map_err(|e| mailbox_error("move", folder, e));
+    }
+
+    session
+        .uid_copy(uid_set, folder)
+        .map_err(|e| mailbox_error("copy", folder, e))?;
+    expunge_uids(session, uid_set)
+}
+
+/// An IMAP quoted string. Line breaks cannot be quoted and are dropped.
+pub fn quoted(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace(['\r', '\n'], "")
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+    )
+}
+
+pub fn validate_mailbox_name(name: &str) -> Result<(), ApiError> {
+    if name.trim().is_empty() || name.contains(['\r', '\n']) {
+        return Err(ApiError::ValidationError(format!(
+            "Invalid mailbox name '{}'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Mailboxes that can be renamed or deleted: anything but the inbox
+fn validate_user_mailbox(name: &str) -> Result<(), ApiError> {
+    validate_mailbox_name(name)?;
+    if name.eq_ignore_ascii_case("INBOX") {
+        return Err(ApiError::ValidationError(
+            "The inbox cannot be renamed or deleted".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Labels as an `X-GM-LABELS` list: system labels (`\Important`) as atoms,
+/// others as quoted strings
+pub fn gmail_labels(labels: &[String]) -> Result<String, ApiError> {
+    labels
+        .iter()
+        .map(|label| {
+            if label.trim().is_empty() || label.contains(['\r', '\n']) {
+                return Err(ApiError::ValidationError(format!(
+                    "Invalid label '{}'",
+                    label
+                )));
+            }
+            let system = label
+                .strip_prefix('\\')
+                .is_some_and(|name| !name.is_empty() && name.chars().all(char::is_alphanumeric));
+            Ok(if system { label.clone() } else { quoted(label) })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|labels| labels.join(" "))
+}
+
+/// `Message-ID` headers of messages, by UID
+fn header_message_ids(
+    session: &mut Session<TlsStream<TcpStream>>,
+    uid_set: &str,
+) -> Result<HashMap<u32, String>, ApiError> {
+    let messages = session
+        .uid_fetch(uid_set, "(UID ENVELOPE)")
+        .map_err(|e| ApiError::InternalError(format!("Fetch failed: {}", e)))?;
+
+    Ok(messages
+        .iter()
+        .filter_map(|message| {
+            let id = message.envelope()?.message_id?;
+            Some((message.uid?, String::from_utf8_lossy(id).trim().to_string()))
+        })
+        .collect())
+}
+
+/// UID and `Message-ID` header of a message
+fn resolve_message(
+    session: &mut Session<TlsStream<TcpStream>>,
+    message_id: &str,
+) -> Result<(u32, Option<String>), ApiError> {
+    let seq: u32 = message_id
+        .parse()
+        .map_err(|_| ApiError::ValidationError("Invalid message ID".to_string()))?;
+
+    let messages = session
+        .fetch(seq.to_string(), "(UID ENVELOPE)")
+        .map_err(|e| ApiError::InternalError(format!("Fetch failed: {}", e)))?;
+    let message = messages
+        .iter()
+        .next()
+        .ok_or_else(|| ApiError::NotFound(format!("Email {}", message_id)))?;
+    let uid = message
+        .uid
+        .ok_or_else(|| ApiError::InternalError("Server did not return a UID".to_string()))?;
+    let header_id = message
+        .envelope()
+        .and_then(|envelope| envelope.message_id)
+        .map(|id| String::from_utf8_lossy(id).trim().to_string());
+
+    Ok((uid, header_id))
+}
+
+/// The mailbox the server flags as `\Trash` (RFC 6154), or the Gmail trash
+fn find_trash_folder(session: &mut Session<TlsStream<TcpStream>>) -> String {
+    let names = match session.list(Some(""), Some("*")) {
+        Ok(names) => names,
+        Err(e) => {
+            tracing::debug!("Failed to list mailboxes: {}", e);
+            return DEFAULT_TRASH_FOLDER.to_string();
+        }
+    };
+
+    names
+        .iter()
+        .find(|name| {
+            name.attributes().iter().any(|attribute| {
+                matches!(attribute, imap::types::NameAttribute::Custom(a) if a.eq_ignore_ascii_case("\\Trash"))
+            })
+        })
+        .map(|name| name.name().to_string())
+        .unwrap_or_else(|| DEFAULT_TRASH_FOLDER.to_string())
+}
+
+/// Text and HTML bodies of a parsed message
+#[derive(Debug, Clone, Default)]
+pub struct MessageBodies {
+    /// The text/plain parts, or a text rendering of the HTML part for
+    /// HTML-only emails, so callers never see raw markup
+    pub text: String,
+    pub html: Option<String>,
+}
+
+pub fn message_bodies(parsed: &ParsedMail) -> MessageBodies {
+    let html = find_part_body(parsed, "text/html");
+    let plain_parts = collect_part_bodies(parsed, "text/plain");
+
+    let text = if !plain_parts.is_empty() {
+        plain_parts.join("\n\n")
+    } else if let Some(ref html) = html {
+        html_text::html_to_text(html).text
+    } else {
+        parsed.get_body().unwrap_or_else(|_| String::new())
+    };
+
+    MessageBodies { text, html }
+}
+
+/// Raw contents of every image part, inline or attached
+fn image_parts(part: &ParsedMail) -> Vec<Vec<u8>> {
+    if part.subparts.is_empty() {
+        if part
+            .ctype
+            .mimetype
+            .to_ascii_lowercase()
+            .starts_with("image/")
+        {
+            if let Ok(body) = part.get_body_raw() {
+                return vec![body];
+            }
+        }
+        return Vec::new();
+    }
+
+    part.subparts.iter().flat_map(image_parts).collect()
+}
+
+/// Body of the first part (depth-first) with the given MIME type
+fn find_part_body(part: &ParsedMail, mimetype: &str) -> Option<String> {
+    collect_part_bodies(part, mimetype).into_iter().next()
+}
+
+/// Bodies of every inline (non-attachment) part with the given MIME type
+fn collect_part_bodies(part: &ParsedMail, mimetype: &str) -> Vec<String> {
+    if part.subparts.is_empty() {
+        let is_attachment =
+            part.get_content_disposition().disposition == mailparse::DispositionType::Attachment;
+        if !is_attachment && part.ctype.mimetype.eq_ignore_ascii_case(mimetype) {
+            if let Ok(body) = part.get_body() {
+                return vec![body];
+            }
+        }
+        return Vec::new();
+    }
+
+    part.subparts
+        .iter()
+        .flat_map(|subpart| collect_part_bodies(subpart, mimetype))
+        .collect()
+}
@@ -0,0 +1,455 @@
+use crate::config::JobsConfig;
+use crate::errors::ApiError;
+use crate::models::{BulkAction, BulkRequest};
+use crate::services::audit_log::{AuditAction, AuditActor, AuditLog, AuditMessage, AuditRecord};
+use crate::services::imap_service::{BulkOperation, ImapService, SelectedMessage, TrashedEmail};
+use crate::services::json_store::JsonStore;
+use crate::services::undo_store::UndoStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::task::JoinHandle;
+
+/// Most messages a single bulk operation can select
+pub const MAX_JOB_MESSAGES: usize = 50_000;
+
+/// Errors kept per job
+const MAX_JOB_ERRORS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    /// Some batches failed
+    PartiallyFailed,
+    Failed,
+    Cancelled,
+}
+
+/// Progress of a bulk operation running in the background
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub action: BulkAction,
+    pub status: JobStatus,
+    /// Messages selected, known once the job started
+    pub total: Option<usize>,
+    pub processed: usize,
+    pub failed: usize,
+    pub errors: Vec<String>,
+    /// Restores the messages a `delete` job moved to the trash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undo_token: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredJob {
+    #[serde(flatten)]
+    info: JobInfo,
+    request: BulkRequest,
+    actor: AuditActor,
+    /// Messages still to process, selected when the job starts
+    remaining: Option<Vec<SelectedMessage>>,
+    /// Messages processed so far, for the audit record
+    #[serde(default)]
+    messages: Vec<AuditMessage>,
+    #[serde(default)]
+    trashed: Vec<TrashedEmail>,
+}
+
+impl StoredJob {
+    fn push_error(&mut self, error: String) {
+        if self.info.errors.len() < MAX_JOB_ERRORS {
+            self.info.errors.push(error);
+        }
+    }
+}
+
+/// What a bulk request does to each batch of messages
+pub fn bulk_operation(request: &BulkRequest) -> Result<BulkOperation, ApiError> {
+    if request.permanent && request.action != BulkAction::Delete {
+        return Err(ApiError::ValidationError(
+            "'permanent' only applies to the delete action".to_string(),
+        ));
+    }
+    let folder = || {
+        request
+            .folder
+            .clone()
+            .filter(|folder| !folder.trim().is_empty())
+            .ok_or_else(|| {
+                ApiError::ValidationError("Move and label actions need a folder".to_string())
+            })
+    };
+
+    Ok(match request.action {
+        BulkAction::MarkRead => BulkOperation::AddFlags(vec!["\\Seen".to_string()]),
+        BulkAction::MarkUnread => BulkOperation::RemoveFlags(vec!["\\Seen".to_string()]),
+        BulkAction::Flag => BulkOperation::AddFlags(vec!["\\Flagged".to_string()]),
+        BulkAction::Unflag => BulkOperation::RemoveFlags(vec!["\\Flagged".to_string()]),
+        BulkAction::Move => BulkOperation::Move(folder()?),
+        BulkAction::Label => BulkOperation::Copy(folder()?),
+        BulkAction::Delete if request.permanent => BulkOperation::Expunge,
+        BulkAction::Delete => BulkOperation::Trash,
+    })
+}
+
+fn audit_action(request: &BulkRequest) -> AuditAction {
+    match request.action {
+        BulkAction::MarkRead => AuditAction::BulkMarkRead,
+        BulkAction::MarkUnread => AuditAction::BulkMarkUnread,
+        BulkAction::Flag => AuditAction::BulkFlag,
+        BulkAction::Unflag => AuditAction::BulkUnflag,
+        BulkAction::Move => AuditAction::BulkMove,
+        BulkAction::Label => AuditAction::BulkLabel,
+        BulkAction::Delete if request.permanent => AuditAction::PermanentDelete,
+        BulkAction::Delete => AuditAction::BulkDelete,
+    }
+}
+
+/// Bulk operations processed in the background in UID batches, persisted in
+/// the data directory so they resume after a restart
+pub struct JobQueue {
+    email_service: Arc<Mutex<ImapService>>,
+    undo_store: Arc<UndoStore>,
+    audit_log: Arc<AuditLog>,
+    config: JobsConfig,
+    jobs: RwLock<Vec<StoredJob>>,
+    store: JsonStore<Vec<StoredJob>>,
+    wake: Notify,
+}
+
+impl JobQueue {
+    pub fn new(
+        email_service: Arc<Mutex<ImapService>>,
+        undo_store: Arc<UndoStore>,
+        audit_log: Arc<AuditLog>,
+        config: JobsConfig,
+        data_dir: &Path,
+    ) -> Result<Self, ApiError> {
+        let store = JsonStore::new(data_dir.join("jobs.json"));
+
+        Ok(Self {
+            email_service,
+            undo_store,
+            audit_log,
+            config,
+            jobs: RwLock::new(store.load()?),
+            store,
+            wake: Notify::new(),
+        })
+    }
+
+    /// Queue a bulk operation made by `actor`
+    pub async fn enqueue(
+        &self,
+        request: BulkRequest,
+        actor: AuditActor,
+    ) -> Result<JobInfo, ApiError> {
+        bulk_operation(&request)?;
+        let job = StoredJob {
+            info: JobInfo {
+                id: uuid::Uuid::new_v4().to_string(),
+                action: request.action,
+                status: JobStatus::Queued,
+                total: None,
+                processed: 0,
+                failed: 0,
+                errors: Vec::new(),
+                undo_token: None,
+                created_at: Utc::now(),
+                started_at: None,
+                finished_at: None,
+            },
+            request,
+            actor,
+            remaining: None,
+            messages: Vec::new(),
+            trashed: Vec::new(),
+        };
+        let info = job.info.clone();
+
+        let mut jobs = self.jobs.write().await;
+        jobs.push(job);
+        self.store.save(&jobs)?;
+        drop(jobs);
+
+        self.wake.notify_one();
+        Ok(info)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<JobInfo, ApiError> {
+        self.jobs
+            .read()
+            .await
+            .iter()
+            .find(|job| job.info.id == id)
+            .map(|job| job.info.clone())
+            .ok_or_else(|| ApiError::NotFound(format!("Job {}", id)))
+    }
+
+    /// Jobs, newest first
+    pub async fn list(&self) -> Vec<JobInfo> {
+        self.jobs
+            .read()
+            .await
+            .iter()
+            .rev()
+            .map(|job| job.info.clone())
+            .collect()
+    }
+
+    /// Cancel a job. A running job stops before its next batch; the batches
+    /// already processed are not reverted.
+    pub async fn cancel(&self, id: &str) -> Result<JobInfo, ApiError> {
+        let mut jobs = self.jobs.write().await;
+        let job = jobs
+            .iter_mut()
+            .find(|job| job.info.id == id)
+            .ok_or_else(|| ApiError::NotFound(format!("Job {}", id)))?;
+
+        match job.info.status {
+            JobStatus::Queued => {
+                job.info.status = JobStatus::Cancelled;
+                job.info.finished_at = Some(Utc::now());
+            }
+            JobStatus::Running => job.info.status = JobStatus::Cancelled,
+            _ => {
+                return Err(ApiError::ValidationError(format!(
+                    "Job {} has already finished",
+                    id
+                )))
+            }
+        }
+        let info = job.info.clone();
+        self.store.save(&jobs)?;
+        Ok(info)
+    }
+
+    /// Process queued jobs one at a time, starting with any job a restart
+    /// interrupted
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.next_job().await {
+                    Some(id) => self.run(&id).await,
+                    None => self.wake.notified().await,
+                }
+            }
+        })
+    }
+
+    async fn next_job(&self) -> Option<String> {
+        let jobs = self.jobs.read().await;
+        jobs.iter()
+            .find(|job| job.info.status != JobStatus::Queued && job.info.finished_at.is_none())
+            .or_else(|| jobs.iter().find(|job| job.info.status == JobStatus::Queued))
+            .map(|job| job.info.id.clone())
+    }
+
+    /// Apply `f` to a job and persist the change
+    async fn update<R>(&self, id: &str, f: impl FnOnce(&mut StoredJob) -> R) -> Option<R> {
+        let mut jobs = self.jobs.write().await;
+        let result = jobs.iter_mut().find(|job| job.info.id == id).map(f);
+        if let Err(e) = self.store.save(&jobs) {
+            tracing::error!("Failed to persist job {}: {}", id, e);
+        }
+        result
+    }
+
+    async fn run(&self, id: &str) {
+        let started = self
+            .update(id, |job| {
+                // Cancelled before it started
+                if job.info.finished_at.is_some() {
+                    return None;
+                }
+                if job.info.status == JobStatus::Queued {
+                    job.info.status = JobStatus::Running;
+                    job.info.started_at = Some(Utc::now());
+                }
+                Some((job.request.clone(), job.remaining.is_some()))
+            })
+            .await
+            .flatten();
+        let Some((request, selected)) = started else {
+            return;
+        };
+
+        let operation = match bulk_operation(&request) {
+            Ok(operation) => operation,
+            Err(e) => {
+                self.update(id, |job| job.push_error(e.to_string())).await;
+                return self.finish(id).await;
+            }
+        };
+
+        if !selected {
+            tracing::info!("Job {} selecting messages", id);
+            let limit = request.limit.clamp(1, MAX_JOB_MESSAGES);
+            let result = self
+                .email_service
+                .lock()
+                .await
+                .select_messages(&request.selector, limit)
+                .await;
+            match result {
+                Ok(messages) => {
+                    self.update(id, |job| {
+                        job.info.total = Some(messages.len());
+                        job.remaining = Some(messages);
+                    })
+                    .await;
+                }
+                Err(e) => {
+                    self.update(id, |job| job.push_error(e.to_string())).await;
+                    return self.finish(id).await;
+                }
+            }
+        }
+
+        let batch_size = self.config.batch_size.max(1);
+        loop {
+            // The next batch, unless the job was cancelled meanwhile
+            let batch = self
+                .jobs
+                .read()
+                .await
+                .iter()
+                .find(|job| job.info.id == id && job.info.status == JobStatus::Running)
+                .and_then(|job| job.remaining.as_ref())
+                .map(|remaining| remaining[..remaining.len().min(batch_size)].to_vec())
+                .filter(|batch| !batch.is_empty());
+            let Some(batch) = batch else {
+                break;
+            };
+
+            // The service is only locked per batch, so requests are served
+            // while a job runs
+            let result = self
+                .email_service
+                .lock()
+                .await
+                .apply_bulk(&batch, &operation)
+                .await;
+            self.update(id, |job| {
+                if let Some(remaining) = job.remaining.as_mut() {
+                    remaining.drain(..batch.len());
+                }
+                let succeeded = result.is_ok();
+                match result {
+                    Ok(trashed) => {
+                        job.info.processed += batch.len();
+                        job.trashed.extend(trashed);
+                    }
+                    Err(e) => {
+                        job.info.failed += batch.len();
+                        job.push_error(e.to_string());
+                    }
+                }
+                job.messages.extend(
+                    batch
+                        .iter()
+                        .map(|message| AuditMessage::new(&message.id(), None, succeeded)),
+                );
+            })
+            .await;
+        }
+
+        self.finish(id).await;
+    }
+
+    /// Record the outcome of a job: its status, undo token and audit record
+    async fn finish(&self, id: &str) {
+        let finished = self
+            .update(id, |job| {
+                (
+                    job.request.clone(),
+                    job.actor.clone(),
+                    std::mem::take(&mut job.messages),
+                    std::mem::take(&mut job.trashed),
+                    job.info.clone(),
+                )
+            })
+            .await;
+        let Some((request, actor, messages, trashed, info)) = finished else {
+            return;
+        };
+
+        let undo_token = if trashed.is_empty() {
+            None
+        } else {
+            match self.undo_store.register(trashed).await {
+                Ok(entry) => Some(entry.token),
+                Err(e) => {
+                    tracing::error!("Failed to register undo for job {}: {}", id, e);
+                    None
+                }
+            }
+        };
+
+        let result = match info.errors.first() {
+            Some(error) if info.processed == 0 && messages.is_empty() => {
+                Err(ApiError::InternalError(error.clone()))
+            }
+            _ => Ok(()),
+        };
+        let mut record = AuditRecord::new(actor, audit_action(&request))
+            .with_messages(messages)
+            .with_result(&result);
+        if let Some(folder) = &request.folder {
+            record = record.with_target(folder.clone());
+        }
+        self.audit_log.record(record).await;
+
+        let history_limit = self.config.history_limit;
+        let mut jobs = self.jobs.write().await;
+        if let Some(job) = jobs.iter_mut().find(|job| job.info.id == id) {
+            if job.info.status != JobStatus::Cancelled {
+                job.info.status = if job.info.failed == 0 && job.info.errors.is_empty() {
+                    JobStatus::Succeeded
+                } else if job.info.processed == 0 {
+                    JobStatus::Failed
+                } else {
+                    JobStatus::PartiallyFailed
+                };
+            }
+            job.info.undo_token = undo_token;
+            job.info.finished_at = Some(Utc::now());
+            job.remaining = None;
+            tracing::info!(
+                "Job {} finished ({:?}, {} processed, {} failed)",
+                id,
+                job.info.status,
+                job.info.processed,
+                job.info.failed
+            );
+        }
+
+        // Keep unfinished jobs, and the most recent finished ones
+        let finished_count = jobs
+            .iter()
+            .filter(|job| job.info.finished_at.is_some())
+            .count();
+        let mut excess = finished_count.saturating_sub(history_limit);
+        jobs.retain(|job| {
+            if excess > 0 && job.info.finished_at.is_some() {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+
+        if let Err(e) = self.store.save(&jobs) {
+            tracing::error!("Failed to persist job {}: {}", id, e);
+        }
+    }
+}
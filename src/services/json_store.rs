@@ -0,0 +1,66 @@
+use crate::errors::ApiError;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// A JSON document persisted to a single file in the data directory
+pub struct JsonStore<T> {
+    path: PathBuf,
+    _marker: PhantomData<T>,
+}
+
+impl<T> JsonStore<T>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load the document, returning the default value if the file does not exist yet
+    pub fn load(&self) -> Result<T, ApiError> {
+        if !self.path.exists() {
+            return Ok(T::default());
+        }
+
+        let contents = fs::read_to_string(&self.path).map_err(|e| {
+            ApiError::InternalError(format!("Failed to read {}: {}", self.path.display(), e))
+        })?;
+
+        serde_json::from_str(&contents).map_err(|e| {
+            ApiError::InternalError(format!("Failed to parse {}: {}", self.path.display(), e))
+        })
+    }
+
+    /// Write the document atomically (temp file + rename)
+    pub fn save(&self, value: &T) -> Result<(), ApiError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                ApiError::InternalError(format!(
+                    "Failed to create directory {}: {}",
+                    parent.display(),
+                    e
+                ))
+            })?;
+        }
+
+        let contents = serde_json::to_string_pretty(value)
+            .map_err(|e| ApiError::InternalError(format!("Failed to serialize: {}", e)))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, contents).map_err(|e| {
+            ApiError::InternalError(format!("Failed to write {}: {}", tmp_path.display(), e))
+        })?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| {
+            ApiError::InternalError(format!("Failed to write {}: {}", self.path.display(), e))
+        })
+    }
+}
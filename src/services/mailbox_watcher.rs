@@ -0,0 +1,77 @@
+use crate::models::EmailSummary;
+use crate::services::event_bus::{EventBus, EventPayload};
+use crate::services::imap_service::{ImapService, MailboxCursor};
+use crate::services::mfa_extractor::MfaExtractor;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Background task that polls the inbox and publishes new mail to the event bus
+pub struct MailboxWatcher {
+    email_service: Arc<Mutex<ImapService>>,
+    event_bus: Arc<EventBus>,
+    poll_interval: Duration,
+    fetch_limit: u32,
+}
+
+impl MailboxWatcher {
+    pub fn new(
+        email_service: Arc<Mutex<ImapService>>,
+        event_bus: Arc<EventBus>,
+        poll_interval: Duration,
+        fetch_limit: u32,
+    ) -> Self {
+        Self {
+            email_service,
+            event_bus,
+            poll_interval,
+            fetch_limit,
+        }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    async fn run(self) {
+        let mut interval = tokio::time::interval(self.poll_interval);
+        // Sequence numbers shift when mail is removed, so new mail is found by
+        // UID. The first poll only places the cursor, so existing mail is not
+        // reported as new on startup.
+        let mut cursor: Option<MailboxCursor> = None;
+
+        loop {
+            interval.tick().await;
+
+            // Only hold the shared service while cloning it, not while fetching
+            let service = self.email_service.lock().await.clone();
+            let emails = match service.new_emails_since(cursor, self.fetch_limit).await {
+                Ok((next, emails)) => {
+                    cursor = Some(next);
+                    emails
+                }
+                Err(e) => {
+                    tracing::warn!("Mailbox watcher poll failed: {}", e);
+                    continue;
+                }
+            };
+
+            for email in &emails {
+                self.publish_new_email(email);
+            }
+        }
+    }
+
+    /// Publish a `new_email` event plus one `mfa_code_detected` event per code
+    pub fn publish_new_email(&self, email: &EmailSummary) {
+        let codes = MfaExtractor::extract_from_email(email);
+
+        self.event_bus
+            .publish(EventPayload::NewEmail(email.clone()));
+
+        for code in codes {
+            self.event_bus.publish(EventPayload::MfaCodeDetected(code));
+        }
+    }
+}
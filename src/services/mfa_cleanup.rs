@@ -0,0 +1,331 @@
+use crate::config::{CleanupAction, MfaCleanupConfig};
+use crate::errors::ApiError;
+use crate::models::{EmailCategory, EmailSummary};
+use crate::services::audit_log::{AuditAction, AuditActor, AuditLog, AuditMessage, AuditRecord};
+use crate::services::imap_service::{BulkOperation, ImapService, SelectedMessage};
+use crate::services::json_store::JsonStore;
+use crate::services::mfa_extractor::{CodeType, MfaExtractor};
+use crate::services::mfa_store::MfaStore;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+/// Why an email was cleaned up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupReason {
+    /// Older than the retention period
+    Retention,
+    /// Its code was consumed
+    Consumed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupOutcome {
+    /// Dry-run: nothing was changed
+    Skipped,
+    Done,
+    Failed,
+}
+
+/// An audit log entry for one email the cleanup job touched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CleanupRecord {
+    pub id: String,
+    pub email_id: String,
+    /// UID the action was applied to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    pub subject: String,
+    pub sender_email: String,
+    pub email_date: DateTime<Utc>,
+    pub reason: CleanupReason,
+    pub action: CleanupAction,
+    /// Destination folder of a `move`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_folder: Option<String>,
+    pub dry_run: bool,
+    pub outcome: CleanupOutcome,
+    pub error: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// An email the policy selected for cleanup
+#[derive(Debug, Clone)]
+pub struct PlannedCleanup {
+    pub email: EmailSummary,
+    pub reason: CleanupReason,
+}
+
+/// Background job applying the retention policy to verification emails
+pub struct MfaCleanup {
+    email_service: Arc<Mutex<ImapService>>,
+    mfa_store: Arc<MfaStore>,
+    /// Shared log of mutating operations; `audit_log` keeps the job's own
+    /// per-email records
+    operations_log: Arc<AuditLog>,
+    config: MfaCleanupConfig,
+    audit_log: RwLock<VecDeque<CleanupRecord>>,
+    audit_store: JsonStore<VecDeque<CleanupRecord>>,
+}
+
+impl MfaCleanup {
+    pub fn new(
+        email_service: Arc<Mutex<ImapService>>,
+        mfa_store: Arc<MfaStore>,
+        operations_log: Arc<AuditLog>,
+        config: MfaCleanupConfig,
+        data_dir: &Path,
+    ) -> Result<Self, ApiError> {
+        let audit_store = JsonStore::new(data_dir.join("mfa_cleanup_audit.json"));
+
+        Ok(Self {
+            email_service,
+            mfa_store,
+            operations_log,
+            config,
+            audit_log: RwLock::new(audit_store.load()?),
+            audit_store,
+        })
+    }
+
+    pub fn config(&self) -> &MfaCleanupConfig {
+        &self.config
+    }
+
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(self.config.interval_secs.max(1)));
+
+            loop {
+                interval.tick().await;
+                match self
+                    .run_once(self.config.dry_run, AuditActor::default())
+                    .await
+                {
+                    Ok(records) if !records.is_empty() => {
+                        tracing::info!("MFA cleanup processed {} emails", records.len())
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("MFA cleanup run failed: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Apply the policy to the most recent emails. `actor` is recorded in the
+    /// audit log when emails are changed.
+    pub async fn run_once(
+        &self,
+        dry_run: bool,
+        actor: AuditActor,
+    ) -> Result<Vec<CleanupRecord>, ApiError> {
+        let emails = {
+            let service = self.email_service.lock().await;
+            service
+                .get_recent_emails_fresh(self.config.scan_limit)
+                .await?
+        };
+
+        let planned = self.plan(&emails, Utc::now()).await;
+        self.execute(planned, dry_run, actor).await
+    }
+
+    /// Pick the verification emails that are due for cleanup
+    pub async fn plan(&self, emails: &[EmailSummary], now: DateTime<Utc>) -> Vec<PlannedCleanup> {
+        let retention = chrono::Duration::minutes(self.config.retention_minutes as i64);
+        let mut planned = Vec::new();
+
+        for email in emails {
+            if email.category != EmailCategory::Verification {
+                continue;
+            }
+            // Marking read is the only action that leaves the email in the inbox
+            if self.config.action == CleanupAction::MarkRead && email.is_read {
+                continue;
+            }
+
+            // Only emails carrying a code; link-only sign-in and reset emails
+            // are left alone
+            let codes: Vec<String> = MfaExtractor::extract_from_email(email)
+                .into_iter()
+                .filter(|c| c.code_type != CodeType::Url)
+                .map(|c| c.code)
+                .collect();
+            if codes.is_empty() {
+                continue;
+            }
+
+            // Sequence numbers are reused, so a stored code only counts when it
+            // is for the same message (same date, sender and code)
+            let consumed = self.config.on_consumed
+                && self.mfa_store.get(&email.id).await.iter().any(|stored| {
+                    stored.is_consumed()
+                        && stored.code.email_date == email.date
+                        && stored.code.email_sender.as_deref() == Some(email.sender_email.as_str())
+                        && codes.contains(&stored.code.code)
+                });
+
+            let reason = if consumed {
+                CleanupReason::Consumed
+            } else if email.date + retention <= now {
+                CleanupReason::Retention
+            } else {
+                continue;
+            };
+
+            planned.push(PlannedCleanup {
+                email: email.clone(),
+                reason,
+            });
+        }
+
+        planned
+    }
+
+    /// Apply the configured action to the planned emails and record each one
+    /// in the audit log. Emails already handled by an earlier run with the
+    /// same action are skipped. The action is applied by UID, so removing one
+    /// message does not retarget the ones planned after it.
+    pub async fn execute(
+        &self,
+        planned: Vec<PlannedCleanup>,
+        dry_run: bool,
+        actor: AuditActor,
+    ) -> Result<Vec<CleanupRecord>, ApiError> {
+        let mut records = Vec::new();
+        let mut messages = Vec::new();
+        for PlannedCleanup { email, reason } in planned {
+            if self.already_handled(&email, dry_run).await {
+                continue;
+            }
+
+            let (outcome, error) = if dry_run {
+                (CleanupOutcome::Skipped, None)
+            } else {
+                match self.apply(&email).await {
+                    Ok(()) => (CleanupOutcome::Done, None),
+                    Err(e) => {
+                        tracing::warn!("MFA cleanup of email {} failed: {}", email.id, e);
+                        (CleanupOutcome::Failed, Some(e.to_string()))
+                    }
+                }
+            };
+
+            messages.push(AuditMessage::new(
+                &email.id,
+                Some(&email),
+                outcome != CleanupOutcome::Failed,
+            ));
+            tracing::info!(
+                "MFA cleanup{}: {:?} email {} ({:?}) from {}",
+                if dry_run { " (dry run)" } else { "" },
+                self.config.action,
+                email.id,
+                reason,
+                email.sender_email
+            );
+
+            records.push(CleanupRecord {
+                id: uuid::Uuid::new_v4().to_string(),
+                email_id: email.id,
+                uid: email.uid,
+                subject: email.subject,
+                sender_email: email.sender_email,
+                email_date: email.date,
+                reason,
+                action: self.config.action,
+                target_folder: (self.config.action == CleanupAction::Move)
+                    .then(|| self.config.target_folder.clone()),
+                dry_run,
+                outcome,
+                error,
+                timestamp: Utc::now(),
+            });
+        }
+
+        if !records.is_empty() {
+            self.append_audit(&records).await?;
+        }
+        if !dry_run && !messages.is_empty() {
+            let action = match self.config.action {
+                CleanupAction::MarkRead => AuditAction::MfaCleanupMarkRead,
+                CleanupAction::Move => AuditAction::MfaCleanupMove,
+                CleanupAction::Delete => AuditAction::MfaCleanupDelete,
+            };
+            let mut record = AuditRecord::new(actor, action).with_messages(messages);
+            if self.config.action == CleanupAction::Move {
+                record = record.with_target(self.config.target_folder.clone());
+            }
+            self.operations_log.record(record).await;
+        }
+        Ok(records)
+    }
+
+    async fn apply(&self, email: &EmailSummary) -> Result<(), ApiError> {
+        let uid = email.uid.ok_or_else(|| {
+            ApiError::ValidationError(format!("Email {} has no UID to act on", email.id))
+        })?;
+        let message = SelectedMessage {
+            seq: email.id.parse().unwrap_or(0),
+            uid,
+        };
+        let operation = match self.config.action {
+            CleanupAction::MarkRead => BulkOperation::AddFlags(vec!["\\Seen".to_string()]),
+            CleanupAction::Move => BulkOperation::Move(self.config.target_folder.clone()),
+            CleanupAction::Delete => BulkOperation::Trash,
+        };
+        let service = self.email_service.lock().await.clone();
+        service
+            .apply_bulk(std::slice::from_ref(&message), &operation)
+            .await
+            .map(|_| ())
+    }
+
+    /// Sequence numbers are reused, so an earlier record only counts when it
+    /// is for the same message (same UID when both are known, date and sender)
+    async fn already_handled(&self, email: &EmailSummary, dry_run: bool) -> bool {
+        self.audit_log.read().await.iter().any(|record| {
+            let same_message = match (record.uid, email.uid) {
+                (Some(recorded), Some(uid)) => recorded == uid,
+                _ => record.email_id == email.id,
+            };
+            same_message
+                && record.email_date == email.date
+                && record.sender_email == email.sender_email
+                && record.action == self.config.action
+                && record.dry_run == dry_run
+                && record.outcome != CleanupOutcome::Failed
+        })
+    }
+
+    async fn append_audit(&self, records: &[CleanupRecord]) -> Result<(), ApiError> {
+        let mut audit_log = self.audit_log.write().await;
+        for record in records {
+            while audit_log.len() >= self.config.audit_log_capacity.max(1) {
+                audit_log.pop_front();
+            }
+            audit_log.push_back(record.clone());
+        }
+        self.audit_store.save(&audit_log)
+    }
+
+    /// Most recent audit records first
+    pub async fn audit_log(&self, limit: usize) -> Vec<CleanupRecord> {
+        self.audit_log
+            .read()
+            .await
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
@@ -1,4 +1,10 @@
+use crate::models::EmailSummary;
+use crate::services::html_text;
+use crate::services::mfa_registry::MfaRegistry;
+use crate::services::verification_links;
 use regex::Regex;
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct MfaCode {
@@ -9,9 +15,61 @@ pub struct MfaCode {
     pub email_sender: Option<String>,
     pub email_date: chrono::DateTime<chrono::Utc>,
     pub code_type: CodeType,
+    /// How likely this is the code the email was sent for, from 0 to 1
+    #[serde(default)]
+    pub confidence: f32,
+    /// Other codes found in the same email, best first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<CodeCandidate>,
+    /// When the code stops working: the validity stated in the email
+    /// ("expires in 10 minutes"), or the service's default TTL
+    #[serde(default)]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Language detected for the email ("en", "pt", ...), if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// SMS / voicemail forwarder the code came through ("Google Voice"), in
+    /// which case `service` is the one named in the forwarded message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarder: Option<String>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+impl MfaCode {
+    pub fn is_expired(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        self.expires_at.map(|at| at <= now).unwrap_or(false)
+    }
+
+    /// Drop the code if it is below `min_confidence`, and drop any
+    /// alternatives that are
+    pub fn with_min_confidence(mut self, min_confidence: f32) -> Option<Self> {
+        if self.confidence < min_confidence {
+            return None;
+        }
+        self.alternatives
+            .retain(|candidate| candidate.confidence >= min_confidence);
+        Some(self)
+    }
+}
+
+/// A possible code found in an email
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CodeCandidate {
+    pub code: String,
+    pub code_type: CodeType,
+    pub confidence: f32,
+    /// Registry pattern that found the code (`html_emphasis` for codes
+    /// highlighted in the HTML body)
+    pub pattern: String,
+    /// Byte offset of the code in the searched text (the rendered HTML for
+    /// `html_emphasis`), when it could be located there
+    pub position: Option<usize>,
+    /// Bytes between the code and the closest verification keyword
+    pub keyword_distance: Option<usize>,
+    /// Whether the code also appears in the subject
+    pub in_subject: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum CodeType {
     Numeric,
@@ -19,9 +77,82 @@ pub enum CodeType {
     Url,
 }
 
+fn digits_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\b\d{4,8}\b").unwrap())
+}
+
+fn alphanumeric_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\b[A-Z0-9]{5,8}\b").unwrap())
+}
+
+/// Pattern name of codes taken from emphasized HTML elements
+const HTML_EMPHASIS_PATTERN: &str = "html_emphasis";
+const HTML_EMPHASIS_CONFIDENCE: f32 = 0.85;
+
+/// Confidence adjustments applied on top of a pattern's base confidence
+const SERVICE_PATTERN_BONUS: f32 = 0.1;
+const NEAR_KEYWORD_BONUS: f32 = 0.15;
+const CLOSE_KEYWORD_BONUS: f32 = 0.05;
+const IN_SUBJECT_BONUS: f32 = 0.1;
+const LOOKALIKE_PENALTY: f32 = 0.3;
+const ORDER_NUMBER_PENALTY: f32 = 0.4;
+
+/// Keyword distances (in bytes) that count as near / close to the code
+const NEAR_KEYWORD_DISTANCE: usize = 30;
+const CLOSE_KEYWORD_DISTANCE: usize = 80;
+
+/// Words right before a number that make it a phone number or address part
+const PHONE_KEYWORDS: &[&str] = &["call", "phone", "tel", "telefone", "whatsapp", "sms to"];
+const ADDRESS_KEYWORDS: &[&str] = &[
+    "zip", "cep", "postal", "suite", "street", "avenue", "rua ", "avenida",
+];
+
+fn expiry_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        // "expires in 10 minutes", "valid for the next 15 minutes",
+        // "válido por 5 minutos", "expira en 10 minutos"
+        Regex::new(
+            r"(?i)(?:expir\w*|v[aá]lid[oa]?s?|vence\w*)\s+(?:\w+\s+){0,3}?(\d{1,4})\s*(seconds?|secs?|segundos?|minutes?|mins?|minutos?|hours?|hrs?|horas?|days?|d[ií]as?)\b",
+        )
+        .unwrap()
+    })
+}
+
 pub struct MfaExtractor;
 
 impl MfaExtractor {
+    /// Extract codes from a fetched email, falling back to the subject when
+    /// the body does not contain a code.
+    pub fn extract_from_email(email: &EmailSummary) -> Vec<MfaCode> {
+        // Use full body if available, otherwise fall back to snippet
+        let text_to_search = email.body.as_deref().unwrap_or(&email.snippet);
+
+        let codes = Self::extract_codes_with_html(
+            &email.id,
+            Some(&email.subject),
+            Some(&email.sender_email),
+            Some(text_to_search),
+            email.html_body.as_deref(),
+            email.date,
+        );
+
+        if codes.is_empty() && email.subject.chars().any(|c| c.is_ascii_digit()) {
+            tracing::debug!("No code in body, trying subject: {}", email.subject);
+            return Self::extract_codes(
+                &email.id,
+                Some(&email.subject),
+                Some(&email.sender_email),
+                Some(&email.subject), // Use subject as body
+                email.date,
+            );
+        }
+
+        codes
+    }
+
     pub fn extract_codes(
         email_id: &str,
         subject: Option<&str>,
@@ -29,9 +160,21 @@ impl MfaExtractor {
         body: Option<&str>,
         date: chrono::DateTime<chrono::Utc>,
     ) -> Vec<MfaCode> {
-        let mut codes = Vec::new();
+        Self::extract_codes_with_html(email_id, subject, sender, body, None, date)
+    }
 
-        // Log email details for debugging
+    /// Extract codes from the text body and sign-in / verification links from
+    /// both the text and HTML bodies. Every candidate code is scored; the best
+    /// one is returned with the others as `alternatives`. A link is returned as
+    /// a `CodeType::Url` code after any numeric or alphanumeric code.
+    pub fn extract_codes_with_html(
+        email_id: &str,
+        subject: Option<&str>,
+        sender: Option<&str>,
+        body: Option<&str>,
+        html: Option<&str>,
+        date: chrono::DateTime<chrono::Utc>,
+    ) -> Vec<MfaCode> {
         tracing::debug!(
             "Extracting MFA codes from email: id={}, subject={:?}, sender={:?}, body_length={}",
             email_id,
@@ -40,298 +183,431 @@ impl MfaExtractor {
             body.map(|b| b.len()).unwrap_or(0)
         );
 
-        if let Some(body_text) = body {
-            // Check if this looks like a verification email (supports multiple languages)
-            let verification_keywords = [
-                // English
-                "code",
-                "verification",
-                "verify",
-                "OTP",
-                "2FA",
-                "MFA",
-                "authentication",
-                "passcode",
-                "PIN",
-                // Portuguese
-                "código",
-                "codigo",
-                "validação",
-                "validacao",
-                "autenticação",
-                "autenticacao",
-                "verificação",
-                "verificacao",
-                "procedimento",
-                "segurança",
-                "seguranca",
-                // Spanish
-                "código",
-                "verificación",
-                "autenticación",
-                // Common patterns
-                "token",
-                "confirm",
-                "validate",
-            ];
-            let body_lower = body_text.to_lowercase();
-            let has_verification_context = verification_keywords
-                .iter()
-                .any(|keyword| body_lower.contains(&keyword.to_lowercase()));
-
-            // Also check subject for verification context
-            let subject_has_context = if let Some(subj) = subject {
-                let subj_lower = subj.to_lowercase();
-                verification_keywords
-                    .iter()
-                    .any(|keyword| subj_lower.contains(&keyword.to_lowercase()))
-            } else {
-                false
-            };
+        let registry = MfaRegistry::global();
+        let forwarder = registry.detect_forwarder(sender, subject).map(String::from);
+        let mut rendered = html.map(html_text::html_to_text);
 
-            // Check if this looks like an order/shipping/receipt email (should be excluded)
-            let exclusion_keywords = [
-                "order", "invoice", "receipt", "shipping", "package", "tracking",
-            ];
-            let is_excluded = exclusion_keywords.iter().any(|keyword| {
-                body_lower.contains(keyword)
-                    || subject
-                        .map(|s| s.to_lowercase().contains(keyword))
-                        .unwrap_or(false)
-            });
+        let email_subject = subject.map(String::from);
 
-            // Also check if there's a pattern that looks like a code even without keywords
-            // But not if it's an order number (preceded by #)
-            let has_code_pattern = !body_text.contains("#")
-                && (body_text.contains(": ") || body_text.contains("is "))
-                && (Regex::new(r"\b\d{4,8}\b").unwrap().is_match(body_text)
-                    || Regex::new(r"\b[A-Z0-9]{5,8}\b")
-                        .unwrap()
-                        .is_match(body_text));
-
-            if is_excluded
-                || (!has_verification_context && !subject_has_context && !has_code_pattern)
-            {
-                tracing::debug!(
-                    "Skipping email - no verification context found. Subject: {:?}, Body preview: {:?}",
-                    subject,
-                    &body_text.chars().take(100).collect::<String>()
-                );
-                return codes;
+        // Full-width digits and colons ("１２３４５６", "：") become ASCII and
+        // spoken digits ("one two three four") become numbers. Only the
+        // message itself is kept from a forwarded SMS or voicemail.
+        let prepare = |text: &str| {
+            let text = normalize(text);
+            let text = match forwarder.as_deref() {
+                Some(forwarder) => registry.forwarded_text(forwarder, &text),
+                None => &text,
+            };
+            registry.replace_spoken_digits(text).into_owned()
+        };
+        let subject_text = subject.map(normalize);
+        let body_text = body.map(prepare);
+        if let Some(r) = rendered.as_mut() {
+            r.text = prepare(&r.text);
+            for emphasized in r.emphasized.iter_mut() {
+                *emphasized = normalize(emphasized);
             }
+        }
+        let subject = subject_text.as_deref();
+        let body = body_text.as_deref();
 
-            let service = Self::detect_service(sender, subject);
-
-            // Try to extract codes with context (like "código: 123456" or "code is 123456")
-            let patterns = vec![
-                // Portuguese patterns with "é"
-                r"(\d{4,8})\s+(?:é|e)\s+o\s+(?:código|codigo)",
-                // Brazilian government pattern: "código de validação: 275992"
-                r"(?:código|codigo)\s+de\s+(?:validação|validacao):\s*(\d{4,8})",
-                // General Portuguese patterns
-                r"(?:código|codigo)(?:\s+de\s+validação)?(?:\s+é)?:\s*(\d{4,8})",
-                r"(?:utilize|usar|use)\s+o\s+(?:código|codigo)\s+de\s+(?:validação|validacao):\s*(\d{4,8})",
-                // English patterns - MFA specific
-                r"(?:your\s+)?(?:mfa|MFA)\s+code\s+is:?\s*(\d{4,8})",
-                // English patterns - general
-                r"(?:code|token|pin)(?:\s+is)?:\s*(\d{4,8})",
-                r"(?:verification|validation)\s+code:\s*(\d{4,8})",
-                r"(?:use|enter)\s+(?:code|this):\s*(\d{4,8})",
-                r"your\s+(?:verification\s+)?code\s+is:?\s*(\d{4,8})",
-                // Hyphenated codes (like "123-456")
-                r"(?:code|código)\s+is:?\s*(\d{3})-(\d{3})",
-                r"(?:verification|validation)\s+code\s+is:?\s*(\d{3})-(\d{3})",
-                // Generic patterns
-                r"(?:código|code|token|pin)(?:\s+de\s+validação)?(?:\s+is)?:\s*(\d{4,8})",
-                r"(?:verification|validação|validation)\s+(?:code|código):\s*(\d{4,8})",
-                r"(?:use|utilize|usar)\s+(?:o\s+)?(?:código|code)(?:\s+de\s+validação)?:\s*(\d{4,8})",
-                // Standalone 6-digit number (fallback)
-                r"\b([0-9]{6})\b",
-            ];
-
-            for pattern in patterns {
-                if let Ok(re) = regex::RegexBuilder::new(pattern)
-                    .case_insensitive(true)
-                    .build()
-                {
-                    if let Some(captures) = re.captures(body_text) {
-                        // Handle hyphenated codes with two capture groups
-                        let extracted_code = if captures.get(2).is_some() {
-                            // Combine two parts for hyphenated codes
-                            format!(
-                                "{}{}",
-                                captures.get(1).map(|m| m.as_str()).unwrap_or(""),
-                                captures.get(2).map(|m| m.as_str()).unwrap_or("")
-                            )
-                        } else if let Some(code_match) = captures.get(1) {
-                            code_match.as_str().to_string()
-                        } else {
-                            continue;
-                        };
-
-                        if !extracted_code.is_empty() {
-                            tracing::info!(
-                                "Found MFA code '{}' using pattern '{}' in email from {:?}",
-                                extracted_code,
-                                pattern,
-                                sender
-                            );
-                            codes.push(MfaCode {
-                                code: extracted_code,
-                                service: service.clone(),
-                                email_id: email_id.to_string(),
-                                email_subject: subject.map(String::from),
-                                email_sender: sender.map(String::from),
-                                email_date: date,
-                                code_type: CodeType::Numeric,
-                            });
-                            return codes;
-                        }
-                    }
-                }
-            }
+        let message = body
+            .or(rendered.as_ref().map(|r| r.text.as_str()))
+            .unwrap_or_default();
+        let language = registry
+            .detect_language(&format!("{}\n{}", subject.unwrap_or_default(), message))
+            .map(String::from);
 
-            // Try to extract 4-digit codes
-            if codes.is_empty() {
-                if let Ok(re) = Regex::new(r"\b([0-9]{4})\b") {
-                    if let Some(captures) = re.captures(body_text) {
-                        if let Some(code_match) = captures.get(1) {
-                            codes.push(MfaCode {
-                                code: code_match.as_str().to_string(),
-                                service: service.clone(),
-                                email_id: email_id.to_string(),
-                                email_subject: subject.map(String::from),
-                                email_sender: sender.map(String::from),
-                                email_date: date,
-                                code_type: CodeType::Numeric,
-                            });
-                        }
-                    }
+        // A forwarder's sender is the gateway, so the service of a forwarded
+        // message is the one it mentions
+        let service = match forwarder {
+            Some(_) => registry.detect_service_in_text(message).map(String::from),
+            None => Self::detect_service(sender, subject),
+        };
+
+        let validity = body
+            .and_then(Self::parse_expiry)
+            .or_else(|| rendered.as_ref().and_then(|r| Self::parse_expiry(&r.text)))
+            .unwrap_or_else(|| registry.ttl(service.as_deref()));
+        let expires_at = Some(date + validity);
+
+        // Codes in <strong>, large-font or dedicated cells are the strongest candidates
+        let mut candidates: Vec<CodeCandidate> = rendered
+            .as_ref()
+            .and_then(|r| {
+                let context = body.unwrap_or(&r.text);
+                let (code, code_type, emphasized) =
+                    Self::emphasized_code(subject, language.as_deref(), context, &r.emphasized)?;
+                Some((code, code_type, r.text.find(emphasized)))
+            })
+            .map(|(code, code_type, position)| {
+                let in_subject = Self::in_subject(subject, &code);
+                CodeCandidate {
+                    confidence: HTML_EMPHASIS_CONFIDENCE
+                        + if in_subject { IN_SUBJECT_BONUS } else { 0.0 },
+                    code,
+                    code_type,
+                    pattern: HTML_EMPHASIS_PATTERN.to_string(),
+                    position,
+                    keyword_distance: None,
+                    in_subject,
                 }
+            })
+            .into_iter()
+            .collect();
+
+        // Then the text/plain body, or the HTML rendering when the body has no
+        // candidates. Links often contain digits, so they are removed first.
+        let texts = [body, rendered.as_ref().map(|r| r.text.as_str())];
+        for text in texts.into_iter().flatten() {
+            let text_without_urls = verification_links::strip_urls(text);
+            let found = Self::text_candidates(
+                subject,
+                service.as_deref(),
+                language.as_deref(),
+                &text_without_urls,
+            );
+            if !found.is_empty() {
+                candidates.extend(found);
+                break;
             }
+        }
 
-            // Try to extract alphanumeric codes
-            if codes.is_empty() {
-                if let Ok(re) = Regex::new(r"\b([A-Z0-9]{5,8})\b") {
-                    if let Some(captures) = re.captures(body_text) {
-                        if let Some(code_match) = captures.get(1) {
-                            codes.push(MfaCode {
-                                code: code_match.as_str().to_string(),
-                                service,
-                                email_id: email_id.to_string(),
-                                email_subject: subject.map(String::from),
-                                email_sender: sender.map(String::from),
-                                email_date: date,
-                                code_type: CodeType::Alphanumeric,
-                            });
-                        }
-                    }
+        let mut codes: Vec<MfaCode> = Self::rank_candidates(candidates)
+            .map(|(best, alternatives)| {
+                tracing::info!(
+                    "Found MFA code '{}' (pattern '{}', confidence {:.2}, {} alternatives) in email from {:?}",
+                    best.code,
+                    best.pattern,
+                    best.confidence,
+                    alternatives.len(),
+                    sender
+                );
+                MfaCode {
+                    code: best.code,
+                    service: service.clone(),
+                    email_id: email_id.to_string(),
+                    email_subject: email_subject.clone(),
+                    email_sender: sender.map(String::from),
+                    email_date: date,
+                    code_type: best.code_type,
+                    confidence: best.confidence,
+                    alternatives,
+                    expires_at,
+                    language: language.clone(),
+                    forwarder: forwarder.clone(),
                 }
-            }
+            })
+            .into_iter()
+            .collect();
+
+        let rendered_text = rendered.as_ref().map(|r| r.text.as_str());
+        if let Some(link) =
+            Self::extract_link(subject, language.as_deref(), body, html, rendered_text)
+        {
+            tracing::info!(
+                "Found verification link '{}' (label: {:?}) in email from {:?}",
+                link.url,
+                link.label,
+                sender
+            );
+            codes.push(MfaCode {
+                code: link.url,
+                service,
+                email_id: email_id.to_string(),
+                email_subject: email_subject.clone(),
+                email_sender: sender.map(String::from),
+                email_date: date,
+                code_type: CodeType::Url,
+                confidence: (0.5 + 0.1 * link.score as f32).min(0.95),
+                alternatives: Vec::new(),
+                expires_at,
+                language,
+                forwarder,
+            });
         }
 
         codes
     }
 
-    fn detect_service(sender: Option<&str>, subject: Option<&str>) -> Option<String> {
-        if let Some(sender_email) = sender {
-            let sender_lower = sender_email.to_lowercase();
-
-            // Check for government services (Brazil)
-            if sender_lower.contains(".gov.br") || sender_lower.contains("celepar") {
-                // Check subject for more specific service
-                if let Some(subj) = subject {
-                    let subj_lower = subj.to_lowercase();
-                    if subj_lower.contains("central de seguranca")
-                        || subj_lower.contains("segurança")
-                    {
-                        return Some(String::from("Central de Segurança"));
-                    }
-                }
-                return Some(String::from("Brazilian Gov"));
+    /// How long a code stays valid, as stated in the email text
+    pub fn parse_expiry(text: &str) -> Option<chrono::Duration> {
+        let captures = expiry_regex().captures(text)?;
+        let amount: i64 = captures[1].parse().ok()?;
+        let unit = captures[2].to_lowercase();
+
+        let duration = if unit.starts_with("sec") || unit.starts_with("seg") {
+            chrono::Duration::seconds(amount)
+        } else if unit.starts_with("min") {
+            chrono::Duration::minutes(amount)
+        } else if unit.starts_with('h') {
+            chrono::Duration::hours(amount)
+        } else {
+            chrono::Duration::days(amount)
+        };
+        Some(duration)
+    }
+
+    /// Sort candidates by confidence (pattern priority breaks ties) and merge
+    /// duplicates, returning the best one and the rest
+    fn rank_candidates(
+        mut candidates: Vec<CodeCandidate>,
+    ) -> Option<(CodeCandidate, Vec<CodeCandidate>)> {
+        // Ties go to the code that comes first
+        candidates.sort_by(|a, b| {
+            b.confidence.total_cmp(&a.confidence).then_with(|| {
+                a.position
+                    .unwrap_or(usize::MAX)
+                    .cmp(&b.position.unwrap_or(usize::MAX))
+            })
+        });
+
+        let mut ranked: Vec<CodeCandidate> = Vec::new();
+        for candidate in candidates {
+            if !ranked.iter().any(|r| r.code == candidate.code) {
+                ranked.push(candidate);
             }
+        }
+
+        if ranked.is_empty() {
+            return None;
+        }
+        let best = ranked.remove(0);
+        Some((best, ranked))
+    }
+
+    fn in_subject(subject: Option<&str>, code: &str) -> bool {
+        subject.map(|s| s.contains(code)).unwrap_or(false)
+    }
 
-            // Check subject for service hints even without government domain
-            if let Some(subj) = subject {
-                let subj_lower = subj.to_lowercase();
-                if subj_lower.contains("central de seguranca") || subj_lower.contains("segurança")
-                {
-                    return Some(String::from("Central de Segurança"));
+    fn extract_link(
+        subject: Option<&str>,
+        language: Option<&str>,
+        body: Option<&str>,
+        html: Option<&str>,
+        rendered_text: Option<&str>,
+    ) -> Option<verification_links::VerificationLink> {
+        let link = verification_links::find_verification_link(body, html)?;
+
+        // The visible text only: markup and attributes say nothing about intent
+        let text = format!(
+            "{}\n{}",
+            body.unwrap_or_default(),
+            rendered_text.unwrap_or_default()
+        );
+        if Self::is_excluded(subject, language, &text) {
+            return None;
+        }
+
+        // Newsletters and notifications have "Log in" buttons too, so even a
+        // well-labelled link needs verification wording in the email
+        let registry = MfaRegistry::global();
+        let has_context = registry.has_verification_context(&text, language)
+            || subject
+                .map(|s| registry.has_verification_context(s, language))
+                .unwrap_or(false);
+
+        has_context.then_some(link)
+    }
+
+    /// Pick a code-like token out of the emphasized HTML elements, if the
+    /// email reads like a verification email. The element's text is returned
+    /// too, to locate the code in the rendered HTML.
+    fn emphasized_code<'a>(
+        subject: Option<&str>,
+        language: Option<&str>,
+        text: &str,
+        emphasized: &'a [String],
+    ) -> Option<(String, CodeType, &'a str)> {
+        if Self::is_excluded(subject, language, text) {
+            return None;
+        }
+        let registry = MfaRegistry::global();
+        if !registry.has_verification_context(text, language)
+            && !registry.has_verification_context(subject.unwrap_or_default(), language)
+        {
+            return None;
+        }
+
+        emphasized.iter().find_map(|candidate| {
+            // "123 456" and "123-456" are rendered for readability
+            let compact: String = candidate
+                .chars()
+                .filter(|c| !c.is_whitespace() && *c != '-')
+                .collect();
+            let length_ok = (4..=8).contains(&compact.len());
+
+            if length_ok && compact.chars().all(|c| c.is_ascii_digit()) {
+                // A lone year in bold is more likely a date than a code
+                let looks_like_year = compact.len() == 4
+                    && compact
+                        .parse::<u32>()
+                        .map(|year| (1900..=2099).contains(&year))
+                        .unwrap_or(false);
+                if looks_like_year {
+                    return None;
                 }
+                return Some((compact, CodeType::Numeric, candidate.as_str()));
             }
 
-            // Match common service domains
-            if sender_lower.contains("google") || sender_lower.contains("gmail") {
-                return Some(String::from("Google"));
-            }
-            if sender_lower.contains("microsoft")
-                || sender_lower.contains("outlook")
-                || sender_lower.contains("hotmail")
-            {
-                return Some(String::from("Microsoft"));
-            }
-            if sender_lower.contains("facebook") || sender_lower.contains("meta") {
-                return Some(String::from("Facebook"));
-            }
-            if sender_lower.contains("twitter") || sender_lower.contains("@x.com") {
-                return Some(String::from("Twitter"));
-            }
-            if sender_lower.contains("github") {
-                return Some(String::from("GitHub"));
-            }
-            if sender_lower.contains("aws") {
-                return Some(String::from("AWS"));
-            }
-            if sender_lower.contains("amazon") {
-                return Some(String::from("Amazon"));
-            }
-            if sender_lower.contains("apple") || sender_lower.contains("icloud") {
-                return Some(String::from("Apple"));
-            }
-            if sender_lower.contains("linkedin") {
-                return Some(String::from("LinkedIn"));
-            }
-            if sender_lower.contains("paypal") {
-                return Some(String::from("PayPal"));
-            }
-            if sender_lower.contains("discord") {
-                return Some(String::from("Discord"));
-            }
-            if sender_lower.contains("slack") {
-                return Some(String::from("Slack"));
-            }
-            if sender_lower.contains("dropbox") {
-                return Some(String::from("Dropbox"));
-            }
-            if sender_lower.contains("stripe") {
-                return Some(String::from("Stripe"));
-            }
-            if sender_lower.contains("coinbase") {
-                return Some(String::from("Coinbase"));
-            }
-            if sender_lower.contains("binance") {
-                return Some(String::from("Binance"));
-            }
-            if sender_lower.contains("steam") {
-                return Some(String::from("Steam"));
-            }
-            if sender_lower.contains("epic") {
-                return Some(String::from("Epic"));
-            }
-            if sender_lower.contains("netflix") {
-                return Some(String::from("Netflix"));
-            }
-            if sender_lower.contains("spotify") {
-                return Some(String::from("Spotify"));
-            }
-            if sender_lower.contains("uber") {
-                return Some(String::from("Uber"));
-            }
-            if sender_lower.contains("zoom") {
-                return Some(String::from("Zoom"));
+            let alphanumeric = compact.len() >= 5
+                && compact
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                && compact.chars().any(|c| c.is_ascii_digit());
+            if length_ok && alphanumeric {
+                return Some((compact, CodeType::Alphanumeric, candidate.as_str()));
             }
+
+            None
+        })
+    }
+
+    fn is_excluded(subject: Option<&str>, language: Option<&str>, text: &str) -> bool {
+        let registry = MfaRegistry::global();
+        registry.is_excluded(text, language)
+            || subject
+                .map(|s| registry.is_excluded(s, language))
+                .unwrap_or(false)
+    }
+
+    /// Every pattern match in `text`, scored, if the text reads like a
+    /// verification email
+    fn text_candidates(
+        subject: Option<&str>,
+        service: Option<&str>,
+        language: Option<&str>,
+        text: &str,
+    ) -> Vec<CodeCandidate> {
+        let registry = MfaRegistry::global();
+        let has_verification_context = registry.has_verification_context(text, language);
+
+        // Also check subject for verification context
+        let subject_has_context = subject
+            .map(|subj| registry.has_verification_context(subj, language))
+            .unwrap_or(false);
+
+        // Check if this looks like an order/shipping/receipt email (should be excluded)
+        let is_excluded = Self::is_excluded(subject, language, text);
+
+        // Also check if there's a pattern that looks like a code even without keywords
+        // But not if it's an order number (preceded by #)
+        let has_code_pattern = !text.contains("#")
+            && (text.contains(": ") || text.contains("is "))
+            && (digits_regex().is_match(text) || alphanumeric_regex().is_match(text));
+
+        if is_excluded || (!has_verification_context && !subject_has_context && !has_code_pattern) {
+            tracing::debug!(
+                "Skipping email - no verification context found. Subject: {:?}, Body preview: {:?}",
+                subject,
+                &text.chars().take(100).collect::<String>()
+            );
+            return Vec::new();
+        }
+
+        // The service's own patterns first, then the generic ones (like
+        // "código: 123456" or "code is 123456"), then standalone fallbacks
+        let keywords = registry.keyword_positions(text, language);
+        registry
+            .find_candidates(service, language, text)
+            .into_iter()
+            .map(|found| {
+                let keyword_distance = keywords
+                    .iter()
+                    .map(|&(start, end)| {
+                        if end <= found.start {
+                            found.start - end
+                        } else {
+                            start.saturating_sub(found.end)
+                        }
+                    })
+                    .min();
+                let in_subject = Self::in_subject(subject, &found.code);
+
+                let mut confidence = found.confidence;
+                if found.service_pattern {
+                    confidence += SERVICE_PATTERN_BONUS;
+                }
+                match keyword_distance {
+                    Some(d) if d <= NEAR_KEYWORD_DISTANCE => confidence += NEAR_KEYWORD_BONUS,
+                    Some(d) if d <= CLOSE_KEYWORD_DISTANCE => confidence += CLOSE_KEYWORD_BONUS,
+                    _ => {}
+                }
+                if in_subject {
+                    confidence += IN_SUBJECT_BONUS;
+                }
+                confidence -= Self::lookalike_penalty(text, &found.code, found.start, found.end);
+
+                CodeCandidate {
+                    code: found.code,
+                    code_type: found.code_type,
+                    confidence: confidence.clamp(0.0, 1.0),
+                    pattern: found.pattern,
+                    position: Some(found.start),
+                    keyword_distance,
+                    in_subject,
+                }
+            })
+            .collect()
+    }
+
+    /// Penalty for numbers that look like years, order numbers, phone
+    /// numbers or parts of an address
+    fn lookalike_penalty(text: &str, code: &str, start: usize, end: usize) -> f32 {
+        let before = &text[..start];
+        let after = &text[end..];
+
+        if before.trim_end().ends_with('#') {
+            return ORDER_NUMBER_PENALTY;
         }
 
-        None
+        let looks_like_year = code.len() == 4
+            && code
+                .parse::<u32>()
+                .map(|year| (1900..=2099).contains(&year))
+                .unwrap_or(false);
+
+        // Part of a longer number such as "555-123-4567" or "+55 (41) 3333-4444"
+        let joined_before = before
+            .strip_suffix(['-', '.', '/', ')', '('])
+            .unwrap_or(before)
+            .trim_end_matches(' ')
+            .ends_with(|c: char| c.is_ascii_digit() || c == ')' || c == '+');
+        let joined_after = after
+            .strip_prefix(['-', '.', '/', '('])
+            .map(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+            .unwrap_or(false);
+
+        let preceding: String = before
+            .chars()
+            .rev()
+            .take(25)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect::<String>()
+            .to_lowercase();
+        let phone_or_address = PHONE_KEYWORDS
+            .iter()
+            .chain(ADDRESS_KEYWORDS)
+            .any(|keyword| preceding.contains(keyword));
+
+        if looks_like_year || joined_before || joined_after || phone_or_address {
+            LOOKALIKE_PENALTY
+        } else {
+            0.0
+        }
+    }
+
+    fn detect_service(sender: Option<&str>, subject: Option<&str>) -> Option<String> {
+        MfaRegistry::global()
+            .detect_service(sender, subject)
+            .map(String::from)
     }
 }
+
+/// NFKC-normalize text so full-width and other compatibility forms of digits
+/// and punctuation match the registry patterns
+fn normalize(text: &str) -> String {
+    text.nfkc().collect()
+}
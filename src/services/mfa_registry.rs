@@ -0,0 +1,744 @@
+use crate::errors::ApiError;
+use crate::services::mfa_extractor::CodeType;
+use config::{Config, File, FileFormat};
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Registry shipped with the binary, used when no registry file is present
+pub const BUILTIN_REGISTRY: &str = include_str!("../../config/mfa_registry.toml");
+
+/// Marker words an email needs before it is assigned a language
+const MIN_LANGUAGE_MARKERS: usize = 2;
+
+/// A code pattern as written in the registry file
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatternDefinition {
+    pub name: String,
+    pub regex: String,
+    #[serde(default = "default_code_type")]
+    pub code_type: CodeType,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// Confidence of a code found by this pattern before context is taken
+    /// into account
+    #[serde(default = "default_confidence")]
+    pub confidence: f32,
+    /// Only try the pattern on emails in this language
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+fn default_code_type() -> CodeType {
+    CodeType::Numeric
+}
+
+fn default_confidence() -> f32 {
+    0.6
+}
+
+/// A sender -> service mapping with the service's own code patterns
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceDefinition {
+    pub name: String,
+    /// Matches when the sender contains any of these (any sender when empty)
+    #[serde(default)]
+    pub senders: Vec<String>,
+    /// Matches when the subject contains any of these (any subject when empty)
+    #[serde(default)]
+    pub subjects: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<PatternDefinition>,
+    /// How long the service's codes stay valid when the email does not say
+    pub ttl_secs: Option<u64>,
+}
+
+/// Words used to recognise a language and its verification emails
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LanguageDefinition {
+    /// Common words of the language
+    pub markers: Vec<String>,
+    /// Regex matching characters only this language uses (like kana for
+    /// Japanese); a match decides the language on its own
+    pub script: Option<String>,
+    pub verification_keywords: Vec<String>,
+    pub exclusion_keywords: Vec<String>,
+    /// Spoken words for each digit, from 0 to 9
+    pub digits: Vec<Vec<String>>,
+}
+
+/// A sender that forwards SMS or voicemail transcripts by email
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ForwarderDefinition {
+    pub name: String,
+    /// Matches when the sender contains any of these (any sender when empty)
+    pub senders: Vec<String>,
+    /// Matches when the subject contains any of these (any subject when empty)
+    pub subjects: Vec<String>,
+    /// The forwarded message ends at the first of these (the forwarder's footer)
+    pub cut_after: Vec<String>,
+}
+
+/// Contents of a registry file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RegistryDefinition {
+    /// Keywords used whatever the language
+    pub verification_keywords: Vec<String>,
+    pub exclusion_keywords: Vec<String>,
+    /// Languages by code ("en", "pt", ...)
+    pub languages: BTreeMap<String, LanguageDefinition>,
+    /// How long codes stay valid when neither the email nor the service says
+    pub default_ttl_secs: u64,
+    pub patterns: Vec<PatternDefinition>,
+    pub services: Vec<ServiceDefinition>,
+    pub forwarders: Vec<ForwarderDefinition>,
+}
+
+impl Default for RegistryDefinition {
+    fn default() -> Self {
+        Self {
+            verification_keywords: Vec::new(),
+            exclusion_keywords: Vec::new(),
+            languages: BTreeMap::new(),
+            default_ttl_secs: 600,
+            patterns: Vec::new(),
+            services: Vec::new(),
+            forwarders: Vec::new(),
+        }
+    }
+}
+
+/// A compiled code pattern
+#[derive(Debug, Clone)]
+pub struct CodePattern {
+    pub name: String,
+    pub code_type: CodeType,
+    pub confidence: f32,
+    pub language: Option<String>,
+    regex: Regex,
+}
+
+/// A code found by a registry pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternMatch {
+    pub code: String,
+    pub code_type: CodeType,
+    /// Name of the pattern that found the code
+    pub pattern: String,
+    /// Base confidence of the pattern
+    pub confidence: f32,
+    /// Whether the pattern belongs to the detected service
+    pub service_pattern: bool,
+    /// Byte range of the code (first to last capture group) in the text
+    pub start: usize,
+    pub end: usize,
+}
+
+impl CodePattern {
+    pub fn compile(definition: &PatternDefinition) -> Result<Self, ApiError> {
+        let regex = RegexBuilder::new(&definition.regex)
+            .case_insensitive(!definition.case_sensitive)
+            .build()
+            .map_err(|e| {
+                ApiError::ValidationError(format!("Invalid pattern '{}': {}", definition.name, e))
+            })?;
+
+        Ok(Self {
+            name: definition.name.clone(),
+            code_type: definition.code_type,
+            confidence: definition.confidence.clamp(0.0, 1.0),
+            language: definition.language.clone(),
+            regex,
+        })
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// The first match in `text`, with all capture groups joined together
+    /// ("123-456" captured as two groups becomes "123456")
+    pub fn find(&self, text: &str) -> Option<PatternMatch> {
+        self.regex
+            .captures(text)
+            .and_then(|captures| self.to_match(&captures))
+    }
+
+    /// Every match in `text`, in order of position
+    pub fn find_all(&self, text: &str) -> Vec<PatternMatch> {
+        self.regex
+            .captures_iter(text)
+            .filter_map(|captures| self.to_match(&captures))
+            .collect()
+    }
+
+    fn applies_to(&self, language: Option<&str>) -> bool {
+        match (self.language.as_deref(), language) {
+            (Some(pattern_language), Some(language)) => pattern_language == language,
+            _ => true,
+        }
+    }
+
+    fn to_match(&self, captures: &regex::Captures) -> Option<PatternMatch> {
+        let groups: Vec<_> = captures.iter().skip(1).flatten().collect();
+        let code: String = groups.iter().map(|m| m.as_str()).collect();
+        if code.is_empty() {
+            return None;
+        }
+
+        Some(PatternMatch {
+            code,
+            code_type: self.code_type,
+            pattern: self.name.clone(),
+            confidence: self.confidence,
+            service_pattern: false,
+            start: groups.first()?.start(),
+            end: groups.last()?.end(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ServiceRule {
+    name: String,
+    /// Folded name, to find the service mentioned in forwarded text
+    name_folded: String,
+    senders: Vec<String>,
+    subjects: Vec<String>,
+    patterns: Vec<CodePattern>,
+    ttl_secs: Option<u64>,
+}
+
+impl ServiceRule {
+    fn matches(&self, sender_lower: &str, subject_lower: &str) -> bool {
+        matches_sender_and_subject(&self.senders, &self.subjects, sender_lower, subject_lower)
+    }
+}
+
+/// Runs of four or more spoken digits, with the digit of each (folded) word
+#[derive(Debug)]
+struct SpokenDigits {
+    regex: Regex,
+    digits: HashMap<String, char>,
+}
+
+#[derive(Debug)]
+struct ForwarderRule {
+    name: String,
+    senders: Vec<String>,
+    subjects: Vec<String>,
+    cut_after: Vec<String>,
+}
+
+fn matches_sender_and_subject(
+    senders: &[String],
+    subjects: &[String],
+    sender_lower: &str,
+    subject_lower: &str,
+) -> bool {
+    (senders.is_empty() || senders.iter().any(|s| sender_lower.contains(s)))
+        && (subjects.is_empty() || subjects.iter().any(|s| subject_lower.contains(s)))
+}
+
+#[derive(Debug)]
+struct LanguageRule {
+    code: String,
+    markers: HashSet<String>,
+    script: Option<Regex>,
+    verification_keywords: Vec<String>,
+    exclusion_keywords: Vec<String>,
+}
+
+/// Compiled MFA patterns, keywords and sender -> service mappings
+#[derive(Debug)]
+pub struct MfaRegistry {
+    verification_keywords: Vec<String>,
+    exclusion_keywords: Vec<String>,
+    languages: Vec<LanguageRule>,
+    patterns: Vec<CodePattern>,
+    /// All generic patterns, to find the candidates for a text in one pass
+    pattern_set: RegexSet,
+    services: Vec<ServiceRule>,
+    forwarders: Vec<ForwarderRule>,
+    spoken_digits: Option<SpokenDigits>,
+    default_ttl_secs: u64,
+}
+
+fn global_registry() -> &'static RwLock<Arc<MfaRegistry>> {
+    static REGISTRY: OnceLock<RwLock<Arc<MfaRegistry>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(Arc::new(MfaRegistry::builtin())))
+}
+
+impl MfaRegistry {
+    /// The registry compiled into the binary
+    pub fn builtin() -> Self {
+        Self::from_toml(BUILTIN_REGISTRY).expect("built-in MFA registry is valid")
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, ApiError> {
+        let definition: RegistryDefinition = Config::builder()
+            .add_source(File::from_str(contents, FileFormat::Toml))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .map_err(|e| ApiError::ValidationError(format!("Invalid MFA registry: {}", e)))?;
+
+        Self::from_definition(&definition)
+    }
+
+    /// Load a registry file, falling back to the built-in registry when the
+    /// file does not exist
+    pub fn load(path: &Path) -> Result<Self, ApiError> {
+        if !path.exists() {
+            tracing::info!(
+                "MFA registry {} not found, using built-in registry",
+                path.display()
+            );
+            return Ok(Self::builtin());
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            ApiError::InternalError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        Self::from_toml(&contents)
+    }
+
+    pub fn from_definition(definition: &RegistryDefinition) -> Result<Self, ApiError> {
+        let patterns = definition
+            .patterns
+            .iter()
+            .map(CodePattern::compile)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pattern_set = RegexSetBuilder::new(definition.patterns.iter().map(|p| {
+            if p.case_sensitive {
+                p.regex.clone()
+            } else {
+                format!("(?i:{})", p.regex)
+            }
+        }))
+        .build()
+        .map_err(|e| ApiError::ValidationError(format!("Invalid MFA patterns: {}", e)))?;
+
+        let services = definition
+            .services
+            .iter()
+            .map(|service| {
+                Ok(ServiceRule {
+                    name: service.name.clone(),
+                    name_folded: fold(&service.name),
+                    senders: lowercase_all(&service.senders),
+                    subjects: lowercase_all(&service.subjects),
+                    patterns: service
+                        .patterns
+                        .iter()
+                        .map(CodePattern::compile)
+                        .collect::<Result<Vec<_>, _>>()?,
+                    ttl_secs: service.ttl_secs,
+                })
+            })
+            .collect::<Result<Vec<_>, ApiError>>()?;
+
+        let languages = definition
+            .languages
+            .iter()
+            .map(|(code, language)| {
+                let script = language
+                    .script
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| {
+                        ApiError::ValidationError(format!(
+                            "Invalid script of language '{}': {}",
+                            code, e
+                        ))
+                    })?;
+
+                Ok(LanguageRule {
+                    code: code.clone(),
+                    markers: fold_all(&language.markers).into_iter().collect(),
+                    script,
+                    verification_keywords: fold_all(&language.verification_keywords),
+                    exclusion_keywords: fold_all(&language.exclusion_keywords),
+                })
+            })
+            .collect::<Result<Vec<_>, ApiError>>()?;
+
+        let forwarders = definition
+            .forwarders
+            .iter()
+            .map(|forwarder| ForwarderRule {
+                name: forwarder.name.clone(),
+                senders: lowercase_all(&forwarder.senders),
+                subjects: lowercase_all(&forwarder.subjects),
+                cut_after: fold_all(&forwarder.cut_after),
+            })
+            .collect();
+
+        Ok(Self {
+            spoken_digits: spoken_digits(definition.languages.values())?,
+            verification_keywords: fold_all(&definition.verification_keywords),
+            exclusion_keywords: fold_all(&definition.exclusion_keywords),
+            languages,
+            patterns,
+            pattern_set,
+            services,
+            forwarders,
+            default_ttl_secs: definition.default_ttl_secs,
+        })
+    }
+
+    /// The registry used by `MfaExtractor`
+    pub fn global() -> Arc<MfaRegistry> {
+        global_registry()
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Replace the registry used by `MfaExtractor`
+    pub fn install(registry: MfaRegistry) {
+        *global_registry().write().unwrap_or_else(|e| e.into_inner()) = Arc::new(registry);
+    }
+
+    /// Language of `text`: the language whose script it uses, otherwise the
+    /// one with the most marker words (at least two, and more than any other
+    /// language). `None` when no language stands out.
+    pub fn detect_language(&self, text: &str) -> Option<&str> {
+        if let Some(language) = self.languages.iter().find(|l| {
+            l.script
+                .as_ref()
+                .is_some_and(|script| script.is_match(text))
+        }) {
+            return Some(&language.code);
+        }
+
+        let folded = fold(text);
+        let words: HashSet<&str> = folded
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect();
+
+        let mut scores: Vec<(usize, &str)> = self
+            .languages
+            .iter()
+            .map(|l| {
+                (
+                    l.markers
+                        .iter()
+                        .filter(|m| words.contains(m.as_str()))
+                        .count(),
+                    l.code.as_str(),
+                )
+            })
+            .collect();
+        scores.sort_by_key(|(count, _)| std::cmp::Reverse(*count));
+
+        match scores.as_slice() {
+            [(best, code), rest @ ..]
+                if *best >= MIN_LANGUAGE_MARKERS
+                    && rest
+                        .first()
+                        .map(|(runner_up, _)| runner_up < best)
+                        .unwrap_or(true) =>
+            {
+                Some(code)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether text contains a verification keyword of `language` (of any
+    /// language when `None`), ignoring case and accents
+    pub fn has_verification_context(&self, text: &str, language: Option<&str>) -> bool {
+        let folded = fold(text);
+        self.keywords(
+            language,
+            |l| &l.verification_keywords,
+            &self.verification_keywords,
+        )
+        .any(|keyword| folded.contains(keyword.as_str()))
+    }
+
+    /// Whether text contains an exclusion keyword of `language` (of any
+    /// language when `None`), ignoring case and accents
+    pub fn is_excluded(&self, text: &str, language: Option<&str>) -> bool {
+        let folded = fold(text);
+        self.keywords(
+            language,
+            |l| &l.exclusion_keywords,
+            &self.exclusion_keywords,
+        )
+        .any(|keyword| folded.contains(keyword.as_str()))
+    }
+
+    /// Language-neutral keywords followed by those of `language`, or of every
+    /// language when `None`
+    fn keywords<'a>(
+        &'a self,
+        language: Option<&'a str>,
+        select: fn(&LanguageRule) -> &Vec<String>,
+        neutral: &'a [String],
+    ) -> impl Iterator<Item = &'a String> {
+        neutral.iter().chain(
+            self.languages
+                .iter()
+                .filter(move |l| language.map(|code| l.code == code).unwrap_or(true))
+                .flat_map(move |l| select(l).iter()),
+        )
+    }
+
+    /// Name of the first service whose mapping matches the sender and subject
+    pub fn detect_service(&self, sender: Option<&str>, subject: Option<&str>) -> Option<&str> {
+        let sender_lower = sender?.to_lowercase();
+        let subject_lower = subject.map(str::to_lowercase).unwrap_or_default();
+
+        self.services
+            .iter()
+            .find(|service| service.matches(&sender_lower, &subject_lower))
+            .map(|service| service.name.as_str())
+    }
+
+    /// Name of the SMS / voicemail forwarder that sent the email, if any
+    pub fn detect_forwarder(&self, sender: Option<&str>, subject: Option<&str>) -> Option<&str> {
+        let sender_lower = sender?.to_lowercase();
+        let subject_lower = subject.map(str::to_lowercase).unwrap_or_default();
+
+        self.forwarders
+            .iter()
+            .find(|f| {
+                matches_sender_and_subject(&f.senders, &f.subjects, &sender_lower, &subject_lower)
+            })
+            .map(|f| f.name.as_str())
+    }
+
+    /// The forwarded message in the email of `forwarder`, without its footer
+    pub fn forwarded_text<'a>(&self, forwarder: &str, text: &'a str) -> &'a str {
+        let (folded, offsets) = fold_with_offsets(text);
+        let end = self
+            .forwarders
+            .iter()
+            .filter(|f| f.name == forwarder)
+            .flat_map(|f| &f.cut_after)
+            .filter_map(|marker| folded.find(marker.as_str()))
+            .min()
+            .map(|i| offsets[i])
+            .unwrap_or(text.len());
+        &text[..end]
+    }
+
+    /// The service a forwarded SMS or transcript is from: the first service
+    /// whose own pattern matches, otherwise the service named first in the text
+    pub fn detect_service_in_text(&self, text: &str) -> Option<&str> {
+        if let Some(service) = self
+            .services
+            .iter()
+            .find(|s| s.patterns.iter().any(|p| p.regex().is_match(text)))
+        {
+            return Some(&service.name);
+        }
+
+        let folded = fold(text);
+        self.services
+            .iter()
+            .filter_map(|service| {
+                folded
+                    .match_indices(service.name_folded.as_str())
+                    .find(|&(start, name)| is_whole_word(&folded, start, start + name.len()))
+                    .map(|(start, _)| (start, service.name.as_str()))
+            })
+            .min_by_key(|&(start, _)| start)
+            .map(|(_, name)| name)
+    }
+
+    /// Replace runs of four or more spoken digits ("one two three four",
+    /// "un, deux, trois, quatre") with the number they spell
+    pub fn replace_spoken_digits<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let Some(SpokenDigits { regex, digits }) = &self.spoken_digits else {
+            return Cow::Borrowed(text);
+        };
+
+        regex.replace_all(text, |captures: &regex::Captures| {
+            captures[0]
+                .split(|c: char| c.is_whitespace() || matches!(c, ',' | '.' | '-'))
+                .filter(|word| !word.is_empty())
+                .filter_map(|word| {
+                    word.chars()
+                        .next()
+                        .filter(char::is_ascii_digit)
+                        .or_else(|| digits.get(&fold(word)).copied())
+                })
+                .collect::<String>()
+        })
+    }
+
+    /// Find a code in `text`, trying the patterns of `service` before the
+    /// generic patterns
+    pub fn find_code(&self, service: Option<&str>, text: &str) -> Option<PatternMatch> {
+        let service_match = service.and_then(|name| {
+            self.services
+                .iter()
+                .filter(|s| s.name == name)
+                .flat_map(|s| &s.patterns)
+                .find_map(|pattern| pattern.find(text))
+                .map(|m| PatternMatch {
+                    service_pattern: true,
+                    ..m
+                })
+        });
+        if service_match.is_some() {
+            return service_match;
+        }
+
+        // Set matches are reported in pattern order
+        self.pattern_set
+            .matches(text)
+            .into_iter()
+            .find_map(|index| self.patterns[index].find(text))
+    }
+
+    /// How long codes from `service` stay valid when the email does not say
+    pub fn ttl(&self, service: Option<&str>) -> chrono::Duration {
+        let secs = self
+            .services
+            .iter()
+            .filter(|s| Some(s.name.as_str()) == service)
+            .find_map(|s| s.ttl_secs)
+            .unwrap_or(self.default_ttl_secs);
+        chrono::Duration::seconds(secs as i64)
+    }
+
+    /// Every match of the patterns of `service` and of the generic patterns
+    /// for `language` (all of them when `None`), in priority order
+    pub fn find_candidates(
+        &self,
+        service: Option<&str>,
+        language: Option<&str>,
+        text: &str,
+    ) -> Vec<PatternMatch> {
+        let service_matches = self
+            .services
+            .iter()
+            .filter(|s| Some(s.name.as_str()) == service)
+            .flat_map(|s| &s.patterns)
+            .flat_map(|pattern| pattern.find_all(text))
+            .map(|m| PatternMatch {
+                service_pattern: true,
+                ..m
+            });
+
+        let generic_matches = self
+            .pattern_set
+            .matches(text)
+            .into_iter()
+            .map(|index| &self.patterns[index])
+            .filter(|pattern| pattern.applies_to(language))
+            .flat_map(|pattern| pattern.find_all(text));
+
+        service_matches.chain(generic_matches).collect()
+    }
+
+    /// Byte ranges of the verification keywords of `language` in `text`,
+    /// ignoring case and accents
+    pub fn keyword_positions(&self, text: &str, language: Option<&str>) -> Vec<(usize, usize)> {
+        let (folded, offsets) = fold_with_offsets(text);
+        let original_offset = |i: usize| offsets.get(i).copied().unwrap_or(text.len());
+
+        self.keywords(
+            language,
+            |l| &l.verification_keywords,
+            &self.verification_keywords,
+        )
+        .flat_map(|keyword| {
+            folded
+                .match_indices(keyword.as_str())
+                .map(|(start, k)| (original_offset(start), original_offset(start + k.len())))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+    }
+
+    /// Generic patterns in priority order
+    pub fn patterns(&self) -> &[CodePattern] {
+        &self.patterns
+    }
+}
+
+fn lowercase_all(values: &[String]) -> Vec<String> {
+    values.iter().map(|v| v.to_lowercase()).collect()
+}
+
+fn is_whole_word(text: &str, start: usize, end: usize) -> bool {
+    !text[..start].ends_with(char::is_alphanumeric)
+        && !text[end..].starts_with(char::is_alphanumeric)
+}
+
+/// Spoken digits of every language
+fn spoken_digits<'a>(
+    languages: impl Iterator<Item = &'a LanguageDefinition>,
+) -> Result<Option<SpokenDigits>, ApiError> {
+    let mut digits = HashMap::new();
+    let mut words = Vec::new();
+    for language in languages {
+        for (digit, spoken) in language.digits.iter().take(10).enumerate() {
+            let digit = char::from_digit(digit as u32, 10).unwrap_or('0');
+            for word in spoken {
+                digits.insert(fold(word), digit);
+                words.push(regex::escape(word));
+                words.push(regex::escape(&fold(word)));
+            }
+        }
+    }
+    if words.is_empty() {
+        return Ok(None);
+    }
+
+    // Longest first, so "seis" is not read as "sei"
+    words.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    words.dedup();
+    let token = format!(r"(?:\b(?:{})\b|\b\d\b)", words.join("|"));
+    let regex = RegexBuilder::new(&format!(r"{token}(?:[\s,.-]+{token}){{3,}}"))
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| ApiError::ValidationError(format!("Invalid spoken digits: {}", e)))?;
+
+    Ok(Some(SpokenDigits { regex, digits }))
+}
+
+fn fold_all(values: &[String]) -> Vec<String> {
+    values.iter().map(|v| fold(v)).collect()
+}
+
+/// Lowercase `text` and strip its accents ("Código" -> "codigo")
+pub fn fold(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// `fold`, with the byte offset in `text` of every byte of the result
+fn fold_with_offsets(text: &str) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len());
+
+    for (offset, c) in text.char_indices() {
+        let before = folded.len();
+        folded.extend(
+            std::iter::once(c)
+                .nfkd()
+                .filter(|c| !is_combining_mark(*c))
+                .flat_map(char::to_lowercase),
+        );
+        offsets.resize(offsets.len() + folded.len() - before, offset);
+    }
+    (folded, offsets)
+}
@@ -0,0 +1,180 @@
+use crate::errors::ApiError;
+use crate::services::event_bus::{EventBus, EventPayload};
+use crate::services::json_store::JsonStore;
+use crate::services::mfa_extractor::MfaCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+
+/// How long codes are kept after they expire
+const RETENTION_AFTER_EXPIRY_HOURS: i64 = 24;
+
+/// An extracted code and whether it has been used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMfaCode {
+    #[serde(flatten)]
+    pub code: MfaCode,
+    pub first_seen_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+    /// Computed whenever the code is read
+    #[serde(default, skip_deserializing)]
+    pub expired: bool,
+}
+
+impl StoredMfaCode {
+    pub fn is_consumed(&self) -> bool {
+        self.consumed_at.is_some()
+    }
+
+    fn with_status(mut self, now: DateTime<Utc>) -> Self {
+        self.expired = self.code.is_expired(now);
+        self
+    }
+}
+
+/// Extracted MFA codes with their expiry and consumed state
+pub struct MfaStore {
+    codes: RwLock<Vec<StoredMfaCode>>,
+    store: JsonStore<Vec<StoredMfaCode>>,
+}
+
+impl MfaStore {
+    pub fn new(data_dir: &Path) -> Result<Self, ApiError> {
+        let store = JsonStore::new(data_dir.join("mfa_codes.json"));
+
+        Ok(Self {
+            codes: RwLock::new(store.load()?),
+            store,
+        })
+    }
+
+    /// Remember newly extracted codes and return their stored state, in the
+    /// same order. Codes seen before keep their consumed state.
+    pub async fn record(&self, codes: Vec<MfaCode>) -> Result<Vec<StoredMfaCode>, ApiError> {
+        let now = Utc::now();
+        let mut stored = self.codes.write().await;
+        let mut changed = false;
+
+        let retention_cutoff = now - chrono::Duration::hours(RETENTION_AFTER_EXPIRY_HOURS);
+        let before = stored.len();
+        stored.retain(|s| !s.code.is_expired(retention_cutoff));
+        changed |= stored.len() != before;
+
+        let mut recorded = Vec::with_capacity(codes.len());
+        for code in codes {
+            let existing = stored
+                .iter_mut()
+                .find(|s| s.code.email_id == code.email_id && s.code.code == code.code);
+
+            let entry = match existing {
+                Some(entry) => {
+                    // Keep the expiry first computed for the code
+                    let expires_at = entry.code.expires_at.or(code.expires_at);
+                    entry.code = MfaCode { expires_at, ..code };
+                    entry.clone()
+                }
+                None => {
+                    let entry = StoredMfaCode {
+                        code,
+                        first_seen_at: now,
+                        consumed_at: None,
+                        expired: false,
+                    };
+                    stored.push(entry.clone());
+                    changed = true;
+                    entry
+                }
+            };
+            recorded.push(entry.with_status(now));
+        }
+
+        if changed {
+            self.store.save(&stored)?;
+        }
+        Ok(recorded)
+    }
+
+    /// Stored codes of an email
+    pub async fn get(&self, email_id: &str) -> Vec<StoredMfaCode> {
+        let now = Utc::now();
+        self.codes
+            .read()
+            .await
+            .iter()
+            .filter(|s| s.code.email_id == email_id)
+            .map(|s| s.clone().with_status(now))
+            .collect()
+    }
+
+    /// Stored codes from emails newer than `since`, newest first
+    pub async fn since(&self, since: DateTime<Utc>) -> Vec<StoredMfaCode> {
+        let now = Utc::now();
+        let mut codes: Vec<_> = self
+            .codes
+            .read()
+            .await
+            .iter()
+            .filter(|s| s.code.email_date > since)
+            .map(|s| s.clone().with_status(now))
+            .collect();
+        codes.sort_by_key(|s| std::cmp::Reverse(s.code.email_date));
+        codes
+    }
+
+    /// Mark the codes of an email (or only `code`) as used
+    pub async fn consume(
+        &self,
+        email_id: &str,
+        code: Option<&str>,
+    ) -> Result<Vec<StoredMfaCode>, ApiError> {
+        let now = Utc::now();
+        let mut stored = self.codes.write().await;
+
+        let mut consumed = Vec::new();
+        for entry in stored.iter_mut().filter(|s| {
+            s.code.email_id == email_id && code.map(|c| s.code.code == c).unwrap_or(true)
+        }) {
+            entry.consumed_at.get_or_insert(now);
+            consumed.push(entry.clone().with_status(now));
+        }
+
+        if consumed.is_empty() {
+            return Err(ApiError::NotFound(format!(
+                "No MFA code recorded for email {}",
+                email_id
+            )));
+        }
+
+        self.store.save(&stored)?;
+        Ok(consumed)
+    }
+
+    /// Record every code published on the bus, so codes picked up by the
+    /// mailbox watcher get an expiry and can be consumed
+    pub fn spawn(self: Arc<Self>, event_bus: &EventBus) {
+        let mut receiver = event_bus.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if let EventPayload::MfaCodeDetected(code) = event.payload {
+                            if let Err(e) = self.record(vec![code]).await {
+                                tracing::error!("Failed to record MFA code: {}", e);
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!(
+                            "MFA store lagged behind event bus, skipped {} events",
+                            skipped
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
@@ -1,5 +1,24 @@
+pub mod audit_log;
 pub mod connection_pool;
 pub mod email_cache;
+pub mod event_bus;
+pub mod html_text;
 pub mod imap_service;
+pub mod job_queue;
+pub mod json_store;
+pub mod mailbox_watcher;
+pub mod mfa_cleanup;
 pub mod mfa_extractor;
+pub mod mfa_registry;
+pub mod mfa_store;
+pub mod rules;
 pub mod scoring;
+pub mod sieve;
+pub mod smtp;
+pub mod subscriptions;
+pub mod token_store;
+pub mod totp;
+pub mod totp_vault;
+pub mod undo_store;
+pub mod verification_links;
+pub mod webhooks;
@@ -0,0 +1,592 @@
+use crate::errors::ApiError;
+use crate::models::{EmailCategory, EmailSummary, ImportanceScore};
+use crate::services::audit_log::{AuditAction, AuditActor, AuditLog, AuditMessage, AuditRecord};
+use crate::services::event_bus::{EventBus, EventPayload};
+use crate::services::imap_service::{
+    gmail_labels, normalize_flags, validate_mailbox_name, BulkOperation, ImapService,
+    SelectedMessage,
+};
+use crate::services::json_store::JsonStore;
+use crate::services::mfa_extractor::MfaExtractor;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+/// How a text condition compares; all comparisons ignore case
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextMatch {
+    Is(String),
+    Contains(String),
+    /// `*` matches any run of characters, `?` a single one
+    Matches(String),
+    Regex(RegexPattern),
+}
+
+impl TextMatch {
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            TextMatch::Is(value) => text.eq_ignore_ascii_case(value),
+            TextMatch::Contains(value) => text.to_lowercase().contains(&value.to_lowercase()),
+            TextMatch::Matches(pattern) => glob_matches(pattern, text),
+            TextMatch::Regex(pattern) => pattern.regex().is_ok_and(|re| re.is_match(text)),
+        }
+    }
+}
+
+/// A regular expression, compiled the first time it is validated or used and
+/// reused afterwards. Serialized as the pattern string.
+#[derive(Debug, Clone)]
+pub struct RegexPattern {
+    pattern: String,
+    compiled: OnceLock<Result<Regex, regex::Error>>,
+}
+
+impl RegexPattern {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            compiled: OnceLock::new(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn regex(&self) -> Result<&Regex, &regex::Error> {
+        self.compiled
+            .get_or_init(|| Regex::new(&self.pattern))
+            .as_ref()
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for RegexPattern {}
+
+impl Serialize for RegexPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl<'de> Deserialize<'de> for RegexPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(RegexPattern::new)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreMatch {
+    Eq(u8),
+    AtLeast(u8),
+    AtMost(u8),
+}
+
+/// Deepest nesting of `all_of`, `any_of` and `not`; far below the recursion
+/// limit that would stop `rules.json` from loading again
+pub const MAX_CONDITION_DEPTH: usize = 32;
+
+/// A test on an incoming message. A rule's conditions must all hold; use
+/// `any_of` for alternatives.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleCondition {
+    /// Sender address
+    From(TextMatch),
+    Subject(TextMatch),
+    /// Plain-text body
+    Body(TextMatch),
+    /// Holds when any header with this name matches
+    Header {
+        name: String,
+        #[serde(flatten)]
+        value: TextMatch,
+    },
+    Score(ScoreMatch),
+    Category(EmailCategory),
+    /// Whether a verification code was detected
+    HasMfaCode(bool),
+    AllOf(Vec<RuleCondition>),
+    AnyOf(Vec<RuleCondition>),
+    Not(Box<RuleCondition>),
+}
+
+impl RuleCondition {
+    pub fn matches(&self, email: &EmailSummary) -> bool {
+        match self {
+            RuleCondition::From(value) => value.matches(&email.sender_email),
+            RuleCondition::Subject(value) => value.matches(&email.subject),
+            RuleCondition::Body(value) => value.matches(email.body.as_deref().unwrap_or("")),
+            RuleCondition::Header { name, value } => email
+                .headers
+                .iter()
+                .any(|(key, text)| key.eq_ignore_ascii_case(name) && value.matches(text)),
+            RuleCondition::Score(ScoreMatch::Eq(score)) => email.importance_score == *score,
+            RuleCondition::Score(ScoreMatch::AtLeast(score)) => email.importance_score >= *score,
+            RuleCondition::Score(ScoreMatch::AtMost(score)) => email.importance_score <= *score,
+            RuleCondition::Category(category) => email.category == *category,
+            RuleCondition::HasMfaCode(expected) => {
+                let detected = !MfaExtractor::extract_from_email(email).is_empty();
+                detected == *expected
+            }
+            RuleCondition::AllOf(conditions) => conditions.iter().all(|c| c.matches(email)),
+            RuleCondition::AnyOf(conditions) => conditions.iter().any(|c| c.matches(email)),
+            RuleCondition::Not(condition) => !condition.matches(email),
+        }
+    }
+
+    /// `depth` is the nesting level of this condition, starting at 1
+    fn validate(&self, depth: usize) -> Result<(), ApiError> {
+        if depth > MAX_CONDITION_DEPTH {
+            return Err(ApiError::ValidationError(format!(
+                "Conditions cannot be nested more than {} levels deep",
+                MAX_CONDITION_DEPTH
+            )));
+        }
+        match self {
+            RuleCondition::From(value)
+            | RuleCondition::Subject(value)
+            | RuleCondition::Body(value) => validate_text_match(value),
+            RuleCondition::Header { name, value } => {
+                if name.trim().is_empty() || name.contains(':') {
+                    return Err(ApiError::ValidationError(format!(
+                        "Invalid header name '{}'",
+                        name
+                    )));
+                }
+                validate_text_match(value)
+            }
+            RuleCondition::AllOf(conditions) | RuleCondition::AnyOf(conditions) => {
+                conditions.iter().try_for_each(|c| c.validate(depth + 1))
+            }
+            RuleCondition::Not(condition) => condition.validate(depth + 1),
+            RuleCondition::Score(_) | RuleCondition::Category(_) | RuleCondition::HasMfaCode(_) => {
+                Ok(())
+            }
+        }
+    }
+}
+
+fn validate_text_match(value: &TextMatch) -> Result<(), ApiError> {
+    if let TextMatch::Regex(pattern) = value {
+        pattern.regex().map_err(|e| {
+            ApiError::ValidationError(format!("Invalid regex '{}': {}", pattern.as_str(), e))
+        })?;
+    }
+    Ok(())
+}
+
+/// Wildcard match ignoring case: `*` matches any run of characters, `?` one
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            // Let the `*` absorb one more character
+            p = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// What a matching rule does with the message, in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    MarkRead,
+    MarkUnread,
+    AddFlags(Vec<String>),
+    RemoveFlags(Vec<String>),
+    /// Add a Gmail label, keeping the message in the inbox
+    Label(String),
+    /// Copy into a mailbox, created if missing
+    Copy(String),
+    /// Move out of the inbox, creating the mailbox if missing
+    Move(String),
+    /// Move to the trash
+    Delete,
+}
+
+impl RuleAction {
+    /// The message is no longer in the inbox afterwards
+    pub fn leaves_inbox(&self) -> bool {
+        matches!(self, RuleAction::Move(_) | RuleAction::Delete)
+    }
+
+    fn validate(&self) -> Result<(), ApiError> {
+        match self {
+            RuleAction::AddFlags(flags) | RuleAction::RemoveFlags(flags) => {
+                if flags.is_empty() {
+                    return Err(ApiError::ValidationError(
+                        "Flag actions need at least one flag".to_string(),
+                    ));
+                }
+                normalize_flags(flags).map(|_| ())
+            }
+            RuleAction::Label(label) => gmail_labels(std::slice::from_ref(label)).map(|_| ()),
+            RuleAction::Copy(mailbox) | RuleAction::Move(mailbox) => validate_mailbox_name(mailbox),
+            RuleAction::MarkRead | RuleAction::MarkUnread | RuleAction::Delete => Ok(()),
+        }
+    }
+
+    /// The UID command that carries the action out
+    fn operation(&self) -> Result<BulkOperation, ApiError> {
+        Ok(match self {
+            RuleAction::MarkRead => BulkOperation::AddFlags(vec!["\\Seen".to_string()]),
+            RuleAction::MarkUnread => BulkOperation::RemoveFlags(vec!["\\Seen".to_string()]),
+            RuleAction::AddFlags(flags) => BulkOperation::AddFlags(normalize_flags(flags)?),
+            RuleAction::RemoveFlags(flags) => BulkOperation::RemoveFlags(normalize_flags(flags)?),
+            RuleAction::Label(label) => BulkOperation::AddLabels(vec![label.clone()]),
+            RuleAction::Copy(mailbox) => BulkOperation::Copy(mailbox.clone()),
+            RuleAction::Move(mailbox) => BulkOperation::Move(mailbox.clone()),
+            RuleAction::Delete => BulkOperation::Trash,
+        })
+    }
+
+    async fn apply(
+        &self,
+        service: &ImapService,
+        message: &SelectedMessage,
+    ) -> Result<(), ApiError> {
+        service
+            .apply_bulk(std::slice::from_ref(message), &self.operation()?)
+            .await
+            .map(|_| ())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    pub conditions: Vec<RuleCondition>,
+    pub actions: Vec<RuleAction>,
+    /// Skip the rules after this one when it matches
+    pub stop: bool,
+    /// Times the rule was applied to incoming mail
+    pub hits: u64,
+    pub last_hit_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Rule {
+    /// Whether the rule is enabled and all its conditions hold. A rule
+    /// without conditions matches every message.
+    pub fn matches(&self, email: &EmailSummary) -> bool {
+        self.enabled && self.conditions.iter().all(|c| c.matches(email))
+    }
+}
+
+/// Body of `POST /rules` and `PUT /rules/{id}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleRequest {
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub conditions: Vec<RuleCondition>,
+    #[serde(default)]
+    pub actions: Vec<RuleAction>,
+    #[serde(default)]
+    pub stop: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl RuleRequest {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.name.trim().is_empty() {
+            return Err(ApiError::ValidationError(
+                "Rule name cannot be empty".to_string(),
+            ));
+        }
+        if self.actions.is_empty() && !self.stop {
+            return Err(ApiError::ValidationError(
+                "Rule needs at least one action or 'stop'".to_string(),
+            ));
+        }
+        // Once the message has left the inbox there is nothing to act on
+        if let Some(position) = self.actions.iter().position(RuleAction::leaves_inbox) {
+            if position + 1 != self.actions.len() {
+                return Err(ApiError::ValidationError(
+                    "'move' and 'delete' must be the last action of a rule".to_string(),
+                ));
+            }
+        }
+        self.conditions.iter().try_for_each(|c| c.validate(1))?;
+        self.actions.iter().try_for_each(RuleAction::validate)
+    }
+}
+
+/// A message given inline to `POST /rules/test`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SampleMessage {
+    pub sender: String,
+    pub sender_email: String,
+    pub subject: String,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+    pub importance_score: u8,
+    pub category: EmailCategory,
+}
+
+impl Default for SampleMessage {
+    fn default() -> Self {
+        Self {
+            sender: String::new(),
+            sender_email: String::new(),
+            subject: String::new(),
+            body: String::new(),
+            headers: Vec::new(),
+            importance_score: ImportanceScore::Normal as u8,
+            category: EmailCategory::default(),
+        }
+    }
+}
+
+impl SampleMessage {
+    pub fn into_email(self) -> EmailSummary {
+        EmailSummary {
+            id: "sample".to_string(),
+            uid: None,
+            subject: self.subject,
+            sender: self.sender,
+            sender_email: self.sender_email,
+            date: Utc::now(),
+            snippet: self.body.chars().take(200).collect(),
+            body: Some(self.body),
+            html_body: None,
+            is_read: false,
+            flags: Vec::new(),
+            labels: vec!["INBOX".to_string()],
+            importance_score: self.importance_score,
+            category: self.category,
+            totp_secrets: Vec::new(),
+            headers: self.headers,
+        }
+    }
+}
+
+/// Filtering rules applied, in order, to newly arrived mail
+pub struct RulesEngine {
+    email_service: Arc<Mutex<ImapService>>,
+    audit_log: Arc<AuditLog>,
+    rules: RwLock<Vec<Rule>>,
+    store: JsonStore<Vec<Rule>>,
+}
+
+impl RulesEngine {
+    pub fn new(
+        email_service: Arc<Mutex<ImapService>>,
+        audit_log: Arc<AuditLog>,
+        data_dir: &Path,
+    ) -> Result<Self, ApiError> {
+        let store = JsonStore::new(data_dir.join("rules.json"));
+
+        Ok(Self {
+            email_service,
+            audit_log,
+            rules: RwLock::new(store.load()?),
+            store,
+        })
+    }
+
+    pub async fn list(&self) -> Vec<Rule> {
+        self.rules.read().await.clone()
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Rule, ApiError> {
+        self.rules
+            .read()
+            .await
+            .iter()
+            .find(|rule| rule.id == id)
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound(format!("Rule {}", id)))
+    }
+
+    /// Add a rule after the existing ones
+    pub async fn create(&self, request: RuleRequest) -> Result<Rule, ApiError> {
+        request.validate()?;
+        let now = Utc::now();
+        let rule = Rule {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: request.name,
+            enabled: request.enabled,
+            conditions: request.conditions,
+            actions: request.actions,
+            stop: request.stop,
+            hits: 0,
+            last_hit_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        let mut rules = self.rules.write().await;
+        rules.push(rule.clone());
+        self.store.save(&rules)?;
+        Ok(rule)
+    }
+
+    /// Replace a rule's definition, keeping its position and hit counter
+    pub async fn update(&self, id: &str, request: RuleRequest) -> Result<Rule, ApiError> {
+        request.validate()?;
+        let mut rules = self.rules.write().await;
+        let rule = rules
+            .iter_mut()
+            .find(|rule| rule.id == id)
+            .ok_or_else(|| ApiError::NotFound(format!("Rule {}", id)))?;
+
+        rule.name = request.name;
+        rule.enabled = request.enabled;
+        rule.conditions = request.conditions;
+        rule.actions = request.actions;
+        rule.stop = request.stop;
+        rule.updated_at = Utc::now();
+        let rule = rule.clone();
+
+        self.store.save(&rules)?;
+        Ok(rule)
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<(), ApiError> {
+        let mut rules = self.rules.write().await;
+        let before = rules.len();
+        rules.retain(|rule| rule.id != id);
+
+        if rules.len() == before {
+            return Err(ApiError::NotFound(format!("Rule {}", id)));
+        }
+        self.store.save(&rules)
+    }
+
+    /// Rules that would be applied to a message: every matching rule up to
+    /// one that stops processing or takes the message out of the inbox
+    pub async fn matching(&self, email: &EmailSummary) -> Vec<Rule> {
+        let mut matched = Vec::new();
+        for rule in self.rules.read().await.iter() {
+            if !rule.matches(email) {
+                continue;
+            }
+            matched.push(rule.clone());
+            if rule.stop || rule.actions.iter().any(RuleAction::leaves_inbox) {
+                break;
+            }
+        }
+        matched
+    }
+
+    /// Apply the matching rules to a message, counting a hit for each and
+    /// recording it in the audit log. Stops at the first failing rule.
+    pub async fn apply(&self, email: &EmailSummary) -> Result<usize, ApiError> {
+        let matched = self.matching(email).await;
+        for rule in &matched {
+            let result = match selected_message(email) {
+                Ok(message) => {
+                    let service = self.email_service.lock().await.clone();
+                    let mut result = Ok(());
+                    for action in &rule.actions {
+                        result = action.apply(&service, &message).await;
+                        if result.is_err() {
+                            break;
+                        }
+                    }
+                    result
+                }
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = self.record_hit(&rule.id).await {
+                tracing::warn!("Failed to save hit counter of rule '{}': {}", rule.name, e);
+            }
+            self.audit_log
+                .record(
+                    AuditRecord::new(AuditActor::default(), AuditAction::RuleApplied)
+                        .with_target(&rule.id)
+                        .with_messages(vec![AuditMessage::new(&email.id, Some(email), true)])
+                        .with_result(&result),
+                )
+                .await;
+
+            if let Err(e) = result {
+                tracing::warn!("Rule '{}' failed on email {}: {}", rule.name, email.id, e);
+                return Err(e);
+            }
+            tracing::info!("Rule '{}' applied to email {}", rule.name, email.id);
+        }
+        Ok(matched.len())
+    }
+
+    async fn record_hit(&self, id: &str) -> Result<(), ApiError> {
+        let mut rules = self.rules.write().await;
+        if let Some(rule) = rules.iter_mut().find(|rule| rule.id == id) {
+            rule.hits += 1;
+            rule.last_hit_at = Some(Utc::now());
+        }
+        self.store.save(&rules)
+    }
+
+    /// Apply the rules to every `new_email` event
+    pub fn spawn(self: Arc<Self>, event_bus: &EventBus) -> JoinHandle<()> {
+        let mut receiver = event_bus.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if let EventPayload::NewEmail(email) = event.payload {
+                            // Failures are logged and audited by `apply`
+                            let _ = self.apply(&email).await;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Rules engine lagged, skipped {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+/// Rules act by UID, which stays valid while earlier messages are moved away
+fn selected_message(email: &EmailSummary) -> Result<SelectedMessage, ApiError> {
+    let uid = email.uid.ok_or_else(|| {
+        ApiError::ValidationError(format!("Email {} has no UID to act on", email.id))
+    })?;
+    Ok(SelectedMessage {
+        seq: email.id.parse().unwrap_or(0),
+        uid,
+    })
+}
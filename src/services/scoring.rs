@@ -1,9 +1,11 @@
+use crate::models::EmailCategory;
 use std::collections::HashSet;
 
 pub struct EmailScorer {
     important_domains: HashSet<String>,
     urgent_keywords: Vec<String>,
     spam_indicators: Vec<String>,
+    notification_indicators: Vec<String>,
 }
 
 impl EmailScorer {
@@ -24,6 +26,13 @@ impl EmailScorer {
                 "promo".to_string(),
                 "unsubscribe".to_string(),
             ],
+            notification_indicators: vec![
+                "noreply".to_string(),
+                "no-reply".to_string(),
+                "donotreply".to_string(),
+                "notification".to_string(),
+                "alerts".to_string(),
+            ],
         }
     }
 
@@ -69,6 +78,31 @@ impl EmailScorer {
         // Default to normal priority (Score 2)
         2
     }
+
+    /// Classify an email by its sender and subject. Verification emails are
+    /// detected by the MFA extractor, so they are never returned here.
+    pub fn categorize(&self, sender_email: &str, subject: &str) -> EmailCategory {
+        let sender_lower = sender_email.to_lowercase();
+        let subject_lower = subject.to_lowercase();
+
+        let is_newsletter = ["newsletter", "marketing", "promo", "digest"]
+            .iter()
+            .any(|indicator| sender_lower.contains(indicator))
+            || subject_lower.contains("newsletter");
+        if is_newsletter {
+            return EmailCategory::Newsletter;
+        }
+
+        if self
+            .notification_indicators
+            .iter()
+            .any(|indicator| sender_lower.contains(indicator))
+        {
+            return EmailCategory::Notification;
+        }
+
+        EmailCategory::Personal
+    }
 }
 
 impl Default for EmailScorer {
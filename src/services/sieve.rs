@@ -0,0 +1,1035 @@
+//! Sieve (RFC 5228) import and export of filtering rules, with the
+//! `fileinto`, `copy`, `imap4flags`, `envelope`, `body` and `regex` extensions.
+
+use crate::errors::ApiError;
+use crate::services::rules::{
+    RegexPattern, Rule, RuleAction, RuleCondition, RuleRequest, TextMatch,
+};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Extensions a script may `require`
+pub const SUPPORTED_EXTENSIONS: [&str; 7] = [
+    "fileinto",
+    "copy",
+    "imap4flags",
+    "envelope",
+    "body",
+    "regex",
+    "comparator-i;ascii-casemap",
+];
+
+/// Deepest nesting of blocks and tests a script may use; the parser
+/// recurses once per level
+const MAX_NESTING: usize = 32;
+
+/// A construct that was left out of the import or export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SieveIssue {
+    pub line: usize,
+    pub message: String,
+}
+
+impl SieveIssue {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SieveImport {
+    pub rules: Vec<RuleRequest>,
+    pub unsupported: Vec<SieveIssue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Tag(String),
+    Number(u64),
+    Str(String),
+    Comment(String),
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semicolon,
+}
+
+fn syntax_error(line: usize, message: impl std::fmt::Display) -> ApiError {
+    ApiError::ValidationError(format!("Sieve syntax error on line {}: {}", line, message))
+}
+
+fn tokenize(script: &str) -> Result<Vec<(Token, usize)>, ApiError> {
+    let chars: Vec<char> = script.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+        match c {
+            '\n' => {
+                line += 1;
+                i += 1;
+            }
+            c if c.is_whitespace() => i += 1,
+            '#' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|c| *c == '\n')
+                    .map_or(chars.len(), |n| i + n);
+                let text: String = chars[i + 1..end].iter().collect();
+                tokens.push((Token::Comment(text.trim().to_string()), line));
+                i = end;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let end = (i + 2..chars.len().saturating_sub(1))
+                    .find(|&j| chars[j] == '*' && chars[j + 1] == '/')
+                    .ok_or_else(|| syntax_error(line, "unterminated comment"))?;
+                let text: String = chars[i + 2..end].iter().collect();
+                tokens.push((Token::Comment(text.trim().to_string()), line));
+                line += text.matches('\n').count();
+                i = end + 2;
+            }
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(syntax_error(start_line, "unterminated string")),
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(c) => {
+                            if *c == '\n' {
+                                line += 1;
+                            }
+                            value.push(*c);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                tokens.push((Token::Str(value.replace("\r\n", "\n")), start_line));
+            }
+            '[' | ']' | '(' | ')' | '{' | '}' | ',' | ';' => {
+                let token = match c {
+                    '[' => Token::LBracket,
+                    ']' => Token::RBracket,
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    '{' => Token::LBrace,
+                    '}' => Token::RBrace,
+                    ',' => Token::Comma,
+                    _ => Token::Semicolon,
+                };
+                tokens.push((token, line));
+                i += 1;
+            }
+            ':' => {
+                let end = identifier_end(&chars, i + 1);
+                if end == i + 1 {
+                    return Err(syntax_error(line, "expected a tag name after ':'"));
+                }
+                tokens.push((Token::Tag(chars[i + 1..end].iter().collect()), line));
+                i = end;
+            }
+            c if c.is_ascii_digit() => {
+                let end = (i..chars.len())
+                    .find(|&j| !chars[j].is_ascii_digit())
+                    .unwrap_or(chars.len());
+                let digits: String = chars[i..end].iter().collect();
+                let number: u64 = digits
+                    .parse()
+                    .map_err(|_| syntax_error(line, "number too large"))?;
+                let (multiplier, end) = match chars.get(end).map(|c| c.to_ascii_uppercase()) {
+                    Some('K') => (1 << 10, end + 1),
+                    Some('M') => (1 << 20, end + 1),
+                    Some('G') => (1 << 30, end + 1),
+                    _ => (1, end),
+                };
+                tokens.push((Token::Number(number.saturating_mul(multiplier)), line));
+                i = end;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let end = identifier_end(&chars, i);
+                let word: String = chars[i..end].iter().collect();
+                if word.eq_ignore_ascii_case("text") && chars.get(end) == Some(&':') {
+                    let (value, next, lines) = multiline_string(&chars, end + 1)
+                        .ok_or_else(|| syntax_error(line, "unterminated text: block"))?;
+                    tokens.push((Token::Str(value), line));
+                    line += lines;
+                    i = next;
+                } else {
+                    tokens.push((Token::Identifier(word.to_ascii_lowercase()), line));
+                    i = end;
+                }
+            }
+            c => return Err(syntax_error(line, format!("unexpected character '{}'", c))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn identifier_end(chars: &[char], start: usize) -> usize {
+    (start..chars.len())
+        .find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '_'))
+        .unwrap_or(chars.len())
+}
+
+/// A `text:` string: the lines after the tag up to a line holding a single
+/// `.`, with leading dots unstuffed. Returns the value, the position after
+/// the terminating line and the number of lines consumed.
+fn multiline_string(chars: &[char], start: usize) -> Option<(String, usize, usize)> {
+    let text: String = chars[start..].iter().collect();
+    let mut lines = text.split_inclusive('\n');
+    // The rest of the `text:` line may only hold whitespace or a comment
+    let first = lines.next()?;
+    let mut consumed = first.chars().count();
+    let mut line_count = 1;
+    let mut value = String::new();
+
+    for raw in lines {
+        consumed += raw.chars().count();
+        line_count += 1;
+        let content = raw.trim_end_matches('\n').trim_end_matches('\r');
+        if content == "." {
+            return Some((value, start + consumed, line_count));
+        }
+        value.push_str(content.strip_prefix('.').unwrap_or(content));
+        value.push('\n');
+    }
+    None
+}
+
+#[derive(Debug, Clone)]
+enum Argument {
+    Tag(String),
+    Number(u64),
+    Strings(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Test {
+    name: String,
+    line: usize,
+    args: Vec<Argument>,
+    tests: Vec<Test>,
+}
+
+#[derive(Debug, Clone)]
+struct Command {
+    name: String,
+    line: usize,
+    args: Vec<Argument>,
+    tests: Vec<Test>,
+    block: Option<Vec<Command>>,
+    /// Comment on the lines just before the command
+    comment: Option<String>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    comment: Option<String>,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&mut self) -> Option<&Token> {
+        while let Some((Token::Comment(text), _)) = self.tokens.get(self.position) {
+            self.comment = Some(text.clone());
+            self.position += 1;
+        }
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.peek()?;
+        self.position += 1;
+        self.tokens.get(self.position - 1).map(|(t, _)| t.clone())
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        if self.depth >= MAX_NESTING {
+            return Err(syntax_error(
+                self.line(),
+                format!("nested more than {} levels deep", MAX_NESTING),
+            ));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn commands(&mut self, in_block: bool) -> Result<Vec<Command>, ApiError> {
+        let mut commands = Vec::new();
+        loop {
+            self.comment = None;
+            match self.peek() {
+                None if in_block => return Err(syntax_error(self.line(), "missing '}'")),
+                None => return Ok(commands),
+                Some(Token::RBrace) if in_block => {
+                    self.next();
+                    return Ok(commands);
+                }
+                _ => commands.push(self.command()?),
+            }
+        }
+    }
+
+    fn command(&mut self) -> Result<Command, ApiError> {
+        let comment = self.comment.take();
+        let line = self.line();
+        let name = match self.next() {
+            Some(Token::Identifier(name)) => name,
+            _ => return Err(syntax_error(line, "expected a command")),
+        };
+        let (args, tests) = self.arguments()?;
+
+        let block = match self.next() {
+            Some(Token::Semicolon) => None,
+            Some(Token::LBrace) => Some(self.nested(|parser| parser.commands(true))?),
+            _ => return Err(syntax_error(self.line(), "expected ';' or '{'")),
+        };
+
+        Ok(Command {
+            name,
+            line,
+            args,
+            tests,
+            block,
+            comment,
+        })
+    }
+
+    fn arguments(&mut self) -> Result<(Vec<Argument>, Vec<Test>), ApiError> {
+        let mut args = Vec::new();
+        loop {
+            let argument = match self.peek() {
+                Some(Token::Tag(tag)) => Argument::Tag(tag.to_ascii_lowercase()),
+                Some(Token::Number(number)) => Argument::Number(*number),
+                Some(Token::Str(value)) => Argument::Strings(vec![value.clone()]),
+                Some(Token::LBracket) => {
+                    self.next();
+                    args.push(Argument::Strings(self.string_list()?));
+                    continue;
+                }
+                _ => break,
+            };
+            self.next();
+            args.push(argument);
+        }
+
+        let tests = match self.peek() {
+            Some(Token::LParen) => {
+                self.next();
+                let mut tests = vec![self.test()?];
+                loop {
+                    let line = self.line();
+                    match self.next() {
+                        Some(Token::Comma) => tests.push(self.test()?),
+                        Some(Token::RParen) => break,
+                        _ => return Err(syntax_error(line, "expected ',' or ')'")),
+                    }
+                }
+                tests
+            }
+            Some(Token::Identifier(_)) => vec![self.test()?],
+            _ => Vec::new(),
+        };
+
+        Ok((args, tests))
+    }
+
+    fn string_list(&mut self) -> Result<Vec<String>, ApiError> {
+        let mut values = Vec::new();
+        loop {
+            let line = self.line();
+            match self.next() {
+                Some(Token::Str(value)) => values.push(value),
+                _ => return Err(syntax_error(line, "expected a string")),
+            }
+            let line = self.line();
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::RBracket) => return Ok(values),
+                _ => return Err(syntax_error(line, "expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn test(&mut self) -> Result<Test, ApiError> {
+        let line = self.line();
+        let name = match self.next() {
+            Some(Token::Identifier(name)) => name,
+            _ => return Err(syntax_error(line, "expected a test")),
+        };
+        let (args, tests) = self.nested(Self::arguments)?;
+        Ok(Test {
+            name,
+            line,
+            args,
+            tests,
+        })
+    }
+}
+
+/// Translate a Sieve script into rule definitions. Syntax errors fail the
+/// whole import; constructs without an equivalent are left out and listed
+/// in `unsupported`.
+pub fn import(script: &str) -> Result<SieveImport, ApiError> {
+    let mut parser = Parser {
+        tokens: tokenize(script)?,
+        position: 0,
+        comment: None,
+        depth: 0,
+    };
+    let commands = parser.commands(false)?;
+
+    let mut import = SieveImport::default();
+    let mut pending: Vec<&Command> = Vec::new();
+    let mut index = 0;
+    while index < commands.len() {
+        let command = &commands[index];
+        index += 1;
+        match command.name.as_str() {
+            "require" => {
+                for extension in strings(&command.args) {
+                    if !SUPPORTED_EXTENSIONS.contains(&extension.as_str()) {
+                        import.unsupported.push(SieveIssue::new(
+                            command.line,
+                            format!("extension \"{}\" is not supported", extension),
+                        ));
+                    }
+                }
+            }
+            "if" => {
+                import.flush_actions(&mut pending);
+                let mut chain = vec![command];
+                while let Some(next) = commands.get(index) {
+                    if next.name != "elsif" && next.name != "else" {
+                        break;
+                    }
+                    chain.push(next);
+                    index += 1;
+                    if next.name == "else" {
+                        break;
+                    }
+                }
+                import.add_chain(&chain);
+            }
+            "elsif" | "else" => import.unsupported.push(SieveIssue::new(
+                command.line,
+                format!("'{}' without 'if'", command.name),
+            )),
+            _ => {
+                pending.push(command);
+                if command.name == "stop" {
+                    import.flush_actions(&mut pending);
+                }
+            }
+        }
+    }
+    import.flush_actions(&mut pending);
+
+    Ok(import)
+}
+
+impl SieveImport {
+    /// Top-level actions become a rule without conditions
+    fn flush_actions(&mut self, pending: &mut Vec<&Command>) {
+        if pending.is_empty() {
+            return;
+        }
+        let commands: Vec<Command> = pending.drain(..).cloned().collect();
+        let line = commands[0].line;
+        let name = rule_name(commands[0].comment.as_deref(), line);
+        self.add_rule(name, line, Vec::new(), &commands);
+    }
+
+    /// An `if`/`elsif`/`else` chain: each branch becomes a rule that also
+    /// requires the earlier branches' tests to fail
+    fn add_chain(&mut self, chain: &[&Command]) {
+        let mut previous: Vec<RuleCondition> = Vec::new();
+        for command in chain {
+            let condition = if command.name == "else" {
+                None
+            } else {
+                let test = match command.tests.as_slice() {
+                    [test] => test,
+                    _ => {
+                        self.unsupported.push(SieveIssue::new(
+                            command.line,
+                            format!("'{}' needs exactly one test", command.name),
+                        ));
+                        return;
+                    }
+                };
+                match condition(test) {
+                    Ok(condition) => Some(condition),
+                    Err(issue) => {
+                        // Later branches depend on this test failing
+                        self.unsupported.push(issue);
+                        return;
+                    }
+                }
+            };
+
+            let mut conditions: Vec<RuleCondition> = previous
+                .iter()
+                .map(|c| RuleCondition::Not(Box::new(c.clone())))
+                .collect();
+            match condition.clone() {
+                Some(RuleCondition::AllOf(all)) => conditions.extend(all),
+                Some(condition) => conditions.push(condition),
+                None => {}
+            }
+
+            let mut name = rule_name(chain[0].comment.as_deref(), chain[0].line);
+            if command.name != "if" {
+                name = format!("{} ({} on line {})", name, command.name, command.line);
+            }
+            let block = command.block.as_deref().unwrap_or_default();
+            self.add_rule(name, command.line, conditions, block);
+            previous.extend(condition);
+        }
+    }
+
+    fn add_rule(
+        &mut self,
+        name: String,
+        line: usize,
+        conditions: Vec<RuleCondition>,
+        commands: &[Command],
+    ) {
+        let (actions, stop) = match actions(commands) {
+            Ok(actions) => actions,
+            Err(issues) => {
+                self.unsupported.extend(issues);
+                return;
+            }
+        };
+        // A branch that only keeps the message changes nothing
+        if actions.is_empty() && !stop {
+            return;
+        }
+
+        let request = RuleRequest {
+            name,
+            enabled: true,
+            conditions,
+            actions,
+            stop,
+        };
+        match request.validate() {
+            Ok(()) => self.rules.push(request),
+            Err(e) => self.unsupported.push(SieveIssue::new(line, e.to_string())),
+        }
+    }
+}
+
+/// `# rule:[Name]` (as written by export and common Sieve editors), any
+/// other comment, or the line number
+fn rule_name(comment: Option<&str>, line: usize) -> String {
+    match comment.map(str::trim).filter(|c| !c.is_empty()) {
+        Some(comment) => comment
+            .strip_prefix("rule:[")
+            .and_then(|name| name.strip_suffix(']'))
+            .unwrap_or(comment)
+            .to_string(),
+        None => format!("Sieve rule (line {})", line),
+    }
+}
+
+fn strings(args: &[Argument]) -> Vec<String> {
+    args.iter()
+        .filter_map(|arg| match arg {
+            Argument::Strings(values) => Some(values.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+fn any_of(mut conditions: Vec<RuleCondition>) -> RuleCondition {
+    if conditions.len() == 1 {
+        conditions.remove(0)
+    } else {
+        RuleCondition::AnyOf(conditions)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchType {
+    Is,
+    Contains,
+    Matches,
+    Regex,
+}
+
+impl MatchType {
+    fn text_match(self, key: &str) -> TextMatch {
+        match self {
+            MatchType::Is => TextMatch::Is(key.to_string()),
+            MatchType::Contains => TextMatch::Contains(key.to_string()),
+            MatchType::Matches => TextMatch::Matches(key.to_string()),
+            MatchType::Regex => TextMatch::Regex(RegexPattern::new(key)),
+        }
+    }
+}
+
+/// Options and positional string lists of a test
+struct TestArguments {
+    match_type: MatchType,
+    address_part: String,
+    lists: Vec<Vec<String>>,
+}
+
+fn test_arguments(test: &Test) -> Result<TestArguments, SieveIssue> {
+    let mut parsed = TestArguments {
+        match_type: MatchType::Is,
+        address_part: "all".to_string(),
+        lists: Vec::new(),
+    };
+    let mut args = test.args.iter();
+    while let Some(arg) = args.next() {
+        match arg {
+            Argument::Strings(values) => parsed.lists.push(values.clone()),
+            Argument::Number(number) => {
+                return Err(SieveIssue::new(
+                    test.line,
+                    format!("unexpected number {} in '{}'", number, test.name),
+                ))
+            }
+            Argument::Tag(tag) => match tag.as_str() {
+                "is" => parsed.match_type = MatchType::Is,
+                "contains" => parsed.match_type = MatchType::Contains,
+                "matches" => parsed.match_type = MatchType::Matches,
+                "regex" => parsed.match_type = MatchType::Regex,
+                "all" | "localpart" | "domain" => parsed.address_part = tag.clone(),
+                "text" => {}
+                "comparator" => match args.next() {
+                    Some(Argument::Strings(values))
+                        if values.len() == 1 && values[0] == "i;ascii-casemap" => {}
+                    _ => {
+                        return Err(SieveIssue::new(
+                            test.line,
+                            "only the i;ascii-casemap comparator is supported",
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(SieveIssue::new(
+                        test.line,
+                        format!("':{}' is not supported in '{}'", tag, test.name),
+                    ))
+                }
+            },
+        }
+    }
+    Ok(parsed)
+}
+
+fn condition(test: &Test) -> Result<RuleCondition, SieveIssue> {
+    let arity = |expected: usize, parsed: &TestArguments| {
+        if parsed.lists.len() == expected {
+            Ok(())
+        } else {
+            Err(SieveIssue::new(
+                test.line,
+                format!("'{}' needs {} string arguments", test.name, expected),
+            ))
+        }
+    };
+
+    match test.name.as_str() {
+        "true" => Ok(RuleCondition::AllOf(Vec::new())),
+        "false" => Ok(RuleCondition::AnyOf(Vec::new())),
+        "not" => match test.tests.as_slice() {
+            [inner] => Ok(RuleCondition::Not(Box::new(condition(inner)?))),
+            _ => Err(SieveIssue::new(test.line, "'not' needs one test")),
+        },
+        "allof" | "anyof" => {
+            let conditions = test
+                .tests
+                .iter()
+                .map(condition)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(if test.name == "allof" {
+                RuleCondition::AllOf(conditions)
+            } else {
+                RuleCondition::AnyOf(conditions)
+            })
+        }
+        "header" => {
+            let parsed = test_arguments(test)?;
+            arity(2, &parsed)?;
+            let mut conditions = Vec::new();
+            for name in &parsed.lists[0] {
+                for key in &parsed.lists[1] {
+                    let value = parsed.match_type.text_match(key);
+                    conditions.push(if name.eq_ignore_ascii_case("subject") {
+                        RuleCondition::Subject(value)
+                    } else {
+                        RuleCondition::Header {
+                            name: name.clone(),
+                            value,
+                        }
+                    });
+                }
+            }
+            Ok(any_of(conditions))
+        }
+        // The sender address stands in for the envelope sender
+        "address" | "envelope" => {
+            let parsed = test_arguments(test)?;
+            arity(2, &parsed)?;
+            if let Some(name) = parsed.lists[0]
+                .iter()
+                .find(|name| !name.eq_ignore_ascii_case("from"))
+            {
+                return Err(SieveIssue::new(
+                    test.line,
+                    format!(
+                        "'{}' is only supported for \"from\", not \"{}\"",
+                        test.name, name
+                    ),
+                ));
+            }
+            let conditions = parsed.lists[1]
+                .iter()
+                .map(|key| {
+                    let value = match (parsed.address_part.as_str(), parsed.match_type) {
+                        ("all", match_type) => match_type.text_match(key),
+                        ("domain", MatchType::Is) => TextMatch::Matches(format!("*@{}", key)),
+                        ("localpart", MatchType::Is) => TextMatch::Matches(format!("{}@*", key)),
+                        (part, _) => {
+                            return Err(SieveIssue::new(
+                                test.line,
+                                format!("':{}' is only supported with ':is'", part),
+                            ))
+                        }
+                    };
+                    Ok(RuleCondition::From(value))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(any_of(conditions))
+        }
+        "exists" => {
+            let parsed = test_arguments(test)?;
+            arity(1, &parsed)?;
+            Ok(RuleCondition::AllOf(
+                parsed.lists[0]
+                    .iter()
+                    .map(|name| RuleCondition::Header {
+                        name: name.clone(),
+                        value: TextMatch::Matches("*".to_string()),
+                    })
+                    .collect(),
+            ))
+        }
+        "body" => {
+            let parsed = test_arguments(test)?;
+            arity(1, &parsed)?;
+            Ok(any_of(
+                parsed.lists[0]
+                    .iter()
+                    .map(|key| RuleCondition::Body(parsed.match_type.text_match(key)))
+                    .collect(),
+            ))
+        }
+        name => Err(SieveIssue::new(
+            test.line,
+            format!("test '{}' is not supported", name),
+        )),
+    }
+}
+
+/// Actions of a block and whether it ends with `stop`. Sieve runs actions
+/// after the whole script, so flag changes come first and the message leaves
+/// the inbox last: with the last `fileinto` as a move, or a `discard`
+/// (to the trash) when nothing keeps or files it.
+fn actions(commands: &[Command]) -> Result<(Vec<RuleAction>, bool), Vec<SieveIssue>> {
+    let mut issues = Vec::new();
+    let mut actions = Vec::new();
+    let mut mailboxes: Vec<(String, bool)> = Vec::new();
+    let (mut keep, mut discard, mut stop) = (false, false, false);
+
+    for command in commands {
+        let tags: Vec<&str> = command
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                Argument::Tag(tag) => Some(tag.as_str()),
+                _ => None,
+            })
+            .collect();
+        let lists: Vec<&Vec<String>> = command
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                Argument::Strings(values) => Some(values),
+                _ => None,
+            })
+            .collect();
+
+        match (command.name.as_str(), tags.as_slice(), lists.as_slice()) {
+            ("keep", [], []) => keep = true,
+            ("discard", [], []) => discard = true,
+            ("stop", [], []) => stop = true,
+            ("fileinto", [], [mailbox]) if mailbox.len() == 1 => {
+                mailboxes.push((mailbox[0].clone(), false))
+            }
+            ("fileinto", ["copy"], [mailbox]) if mailbox.len() == 1 => {
+                mailboxes.push((mailbox[0].clone(), true))
+            }
+            ("addflag" | "removeflag", [], [flags]) => {
+                let flags: Vec<String> = flags
+                    .iter()
+                    .flat_map(|f| f.split_whitespace())
+                    .map(str::to_string)
+                    .collect();
+                actions.push(if command.name == "addflag" {
+                    RuleAction::AddFlags(flags)
+                } else {
+                    RuleAction::RemoveFlags(flags)
+                });
+            }
+            ("addflag" | "removeflag", [], [_, _]) => issues.push(SieveIssue::new(
+                command.line,
+                "flag variables are not supported",
+            )),
+            ("if", _, _) => issues.push(SieveIssue::new(
+                command.line,
+                "nested 'if' is not supported",
+            )),
+            (name, _, _) => issues.push(SieveIssue::new(
+                command.line,
+                format!("action '{}' is not supported", name),
+            )),
+        }
+        if command.block.is_some() && command.name != "if" {
+            issues.push(SieveIssue::new(
+                command.line,
+                format!("'{}' does not take a block", command.name),
+            ));
+        }
+    }
+    if !issues.is_empty() {
+        return Err(issues);
+    }
+
+    let leaves_inbox = !keep && (discard || mailboxes.iter().any(|(_, copy)| !copy));
+    let mut seen = BTreeSet::new();
+    mailboxes.retain(|(mailbox, _)| seen.insert(mailbox.clone()));
+    let last = mailboxes.len().checked_sub(1);
+    for (index, (mailbox, _)) in mailboxes.into_iter().enumerate() {
+        actions.push(if leaves_inbox && Some(index) == last {
+            RuleAction::Move(mailbox)
+        } else {
+            RuleAction::Copy(mailbox)
+        });
+    }
+    if leaves_inbox && last.is_none() {
+        actions.push(RuleAction::Delete);
+    }
+
+    Ok((actions, stop))
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn quote_list(values: &[String]) -> String {
+    match values {
+        [value] => quote(value),
+        values => format!(
+            "[{}]",
+            values
+                .iter()
+                .map(|v| quote(v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Match type tag and quoted key
+fn export_match(
+    value: &TextMatch,
+    required: &mut BTreeSet<&'static str>,
+) -> (&'static str, String) {
+    let (tag, key) = match value {
+        TextMatch::Is(key) => (":is", key.as_str()),
+        TextMatch::Contains(key) => (":contains", key.as_str()),
+        TextMatch::Matches(key) => (":matches", key.as_str()),
+        TextMatch::Regex(key) => {
+            required.insert("regex");
+            (":regex", key.as_str())
+        }
+    };
+    (tag, quote(key))
+}
+
+fn export_condition(
+    condition: &RuleCondition,
+    required: &mut BTreeSet<&'static str>,
+) -> Result<String, String> {
+    Ok(match condition {
+        RuleCondition::From(value) => {
+            let (tag, key) = export_match(value, required);
+            format!("address {} \"from\" {}", tag, key)
+        }
+        RuleCondition::Subject(value) => {
+            let (tag, key) = export_match(value, required);
+            format!("header {} \"subject\" {}", tag, key)
+        }
+        RuleCondition::Header { name, value } => {
+            let (tag, key) = export_match(value, required);
+            format!("header {} {} {}", tag, quote(name), key)
+        }
+        RuleCondition::Body(value) => {
+            required.insert("body");
+            let (tag, key) = export_match(value, required);
+            format!("body :text {} {}", tag, key)
+        }
+        RuleCondition::AllOf(conditions) if conditions.is_empty() => "true".to_string(),
+        RuleCondition::AnyOf(conditions) if conditions.is_empty() => "false".to_string(),
+        RuleCondition::AllOf(conditions) | RuleCondition::AnyOf(conditions) => {
+            let name = if matches!(condition, RuleCondition::AllOf(_)) {
+                "allof"
+            } else {
+                "anyof"
+            };
+            let tests = conditions
+                .iter()
+                .map(|c| export_condition(c, required))
+                .collect::<Result<Vec<_>, _>>()?;
+            format!("{}({})", name, tests.join(", "))
+        }
+        RuleCondition::Not(inner) => format!("not {}", export_condition(inner, required)?),
+        RuleCondition::Score(_) => return Err("score conditions".to_string()),
+        RuleCondition::Category(_) => return Err("category conditions".to_string()),
+        RuleCondition::HasMfaCode(_) => return Err("MFA code conditions".to_string()),
+    })
+}
+
+fn export_action(action: &RuleAction, required: &mut BTreeSet<&'static str>) -> String {
+    let seen = || vec!["\\Seen".to_string()];
+    let (command, flags) = match action {
+        RuleAction::MarkRead => ("addflag", seen()),
+        RuleAction::MarkUnread => ("removeflag", seen()),
+        RuleAction::AddFlags(flags) => ("addflag", flags.clone()),
+        RuleAction::RemoveFlags(flags) => ("removeflag", flags.clone()),
+        _ => ("", Vec::new()),
+    };
+    if !command.is_empty() {
+        required.insert("imap4flags");
+        return format!("{} {};", command, quote_list(&flags));
+    }
+
+    match action {
+        // On Gmail a label is a mailbox the message is copied to
+        RuleAction::Label(mailbox) | RuleAction::Copy(mailbox) => {
+            required.insert("fileinto");
+            required.insert("copy");
+            format!("fileinto :copy {};", quote(mailbox))
+        }
+        RuleAction::Move(mailbox) => {
+            required.insert("fileinto");
+            format!("fileinto {};", quote(mailbox))
+        }
+        RuleAction::Delete => "discard;".to_string(),
+        RuleAction::MarkRead
+        | RuleAction::MarkUnread
+        | RuleAction::AddFlags(_)
+        | RuleAction::RemoveFlags(_) => unreachable!("flag actions are handled above"),
+    }
+}
+
+/// Render rules as a Sieve script. Disabled rules and rules using conditions
+/// Sieve cannot express are listed in comments at the top.
+pub fn export(rules: &[Rule]) -> String {
+    let mut required = BTreeSet::new();
+    let mut skipped = Vec::new();
+    let mut blocks = Vec::new();
+
+    for rule in rules {
+        if !rule.enabled {
+            skipped.push(format!("# Not exported: '{}' is disabled", rule.name));
+            continue;
+        }
+        let mut rule_required = BTreeSet::new();
+        let test = match rule.conditions.as_slice() {
+            [] => Ok("true".to_string()),
+            [condition] => export_condition(condition, &mut rule_required),
+            conditions => export_condition(
+                &RuleCondition::AllOf(conditions.to_vec()),
+                &mut rule_required,
+            ),
+        };
+        let test = match test {
+            Ok(test) => test,
+            Err(what) => {
+                skipped.push(format!(
+                    "# Not exported: '{}' uses {}, which Sieve cannot express",
+                    rule.name, what
+                ));
+                continue;
+            }
+        };
+
+        let mut block = format!("# rule:[{}]\nif {} {{\n", rule.name, test);
+        for action in &rule.actions {
+            block.push_str(&format!(
+                "    {}\n",
+                export_action(action, &mut rule_required)
+            ));
+        }
+        if rule.stop {
+            block.push_str("    stop;\n");
+        }
+        block.push_str("}\n");
+        blocks.push(block);
+        required.extend(rule_required);
+    }
+
+    let mut script = String::new();
+    for line in skipped {
+        script.push_str(&line);
+        script.push('\n');
+    }
+    if !required.is_empty() {
+        let required: Vec<String> = required.into_iter().map(str::to_string).collect();
+        script.push_str(&format!("require {};\n", quote_list(&required)));
+    }
+    for block in blocks {
+        script.push('\n');
+        script.push_str(&block);
+    }
+    script
+}
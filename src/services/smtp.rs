@@ -0,0 +1,198 @@
+use crate::config::SmtpConfig;
+use crate::errors::ApiError;
+use chrono::Utc;
+use native_tls::TlsConnector;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// A plain-text message
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Minimal SMTP client (EHLO, AUTH PLAIN, one recipient) for sending the
+/// occasional message, such as a `mailto:` unsubscribe request
+pub struct SmtpClient {
+    config: SmtpConfig,
+    username: String,
+    password: String,
+}
+
+impl SmtpClient {
+    pub fn new(config: SmtpConfig, username: String, password: String) -> Self {
+        Self {
+            config,
+            username,
+            password,
+        }
+    }
+
+    /// Send `message` from the account's address
+    pub async fn send(&self, message: OutgoingMessage) -> Result<(), ApiError> {
+        // The recipient ends up in `RCPT TO:<...>` and the `To:` header
+        if !is_valid_address(&message.to) {
+            return Err(ApiError::ValidationError(format!(
+                "Invalid recipient address '{}'",
+                message.to.escape_debug()
+            )));
+        }
+        let config = self.config.clone();
+        let username = self.username.clone();
+        let password = self.password.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let stream = TcpStream::connect((config.host.as_str(), config.port)).map_err(|e| {
+                ApiError::ConnectionError(format!(
+                    "Failed to connect to {}:{}: {}",
+                    config.host, config.port, e
+                ))
+            })?;
+            let timeout = Some(Duration::from_secs(config.timeout_secs));
+            stream
+                .set_read_timeout(timeout)
+                .and_then(|_| stream.set_write_timeout(timeout))
+                .map_err(|e| ApiError::ConnectionError(e.to_string()))?;
+
+            if config.tls {
+                let connector = TlsConnector::new()
+                    .map_err(|e| ApiError::ConnectionError(format!("TLS error: {}", e)))?;
+                let stream = connector
+                    .connect(&config.host, stream)
+                    .map_err(|e| ApiError::ConnectionError(format!("TLS error: {}", e)))?;
+                send_message(stream, &username, &password, &message)
+            } else {
+                send_message(stream, &username, &password, &message)
+            }
+        })
+        .await
+        .map_err(|e| ApiError::InternalError(format!("SMTP task failed: {}", e)))?
+    }
+}
+
+/// Whether `address` is a plain `local@domain` addr-spec (RFC 5322 dot-atom
+/// on both sides, no quoted strings, comments or display names)
+pub fn is_valid_address(address: &str) -> bool {
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    let atext = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c);
+    let label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+
+    address.len() <= 254
+        && (1..=64).contains(&local.len())
+        && local
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(atext))
+        && domain.contains('.')
+        && domain.split('.').all(label)
+}
+
+fn send_message<S: Read + Write>(
+    stream: S,
+    username: &str,
+    password: &str,
+    message: &OutgoingMessage,
+) -> Result<(), ApiError> {
+    let mut connection = Connection {
+        stream: BufReader::new(stream),
+    };
+    let domain = username.rsplit('@').next().unwrap_or("localhost");
+
+    connection.expect(220)?;
+    connection.command(&format!("EHLO {}", domain), 250)?;
+    if !password.is_empty() {
+        let credentials = format!("\0{}\0{}", username, password);
+        connection.command(
+            &format!(
+                "AUTH PLAIN {}",
+                data_encoding::BASE64.encode(credentials.as_bytes())
+            ),
+            235,
+        )?;
+    }
+    connection.command(&format!("MAIL FROM:<{}>", username), 250)?;
+    connection.command(&format!("RCPT TO:<{}>", message.to), 250)?;
+    connection.command("DATA", 354)?;
+    connection.command(&format_message(username, domain, message), 250)?;
+    // The message is accepted; a failed QUIT does not matter
+    let _ = connection.command("QUIT", 221);
+
+    Ok(())
+}
+
+/// Headers and dot-stuffed body, ending with the `.` line
+fn format_message(from: &str, domain: &str, message: &OutgoingMessage) -> String {
+    let mut data = format!(
+        "From: <{}>\r\nTo: <{}>\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@{}>\r\n\
+         MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        message.to,
+        message.subject.replace(['\r', '\n'], " "),
+        Utc::now().to_rfc2822(),
+        uuid::Uuid::new_v4(),
+        domain
+    );
+    for line in message.body.lines() {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push('.');
+    data
+}
+
+struct Connection<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Connection<S> {
+    fn command(&mut self, line: &str, expected: u16) -> Result<(), ApiError> {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(|e| ApiError::ConnectionError(format!("SMTP write failed: {}", e)))?;
+        self.expect(expected)
+    }
+
+    /// Read a (possibly multi-line) reply and check its code
+    fn expect(&mut self, expected: u16) -> Result<(), ApiError> {
+        loop {
+            let mut line = String::new();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .map_err(|e| ApiError::ConnectionError(format!("SMTP read failed: {}", e)))?;
+            if read == 0 {
+                return Err(ApiError::ConnectionError(
+                    "SMTP server closed the connection".to_string(),
+                ));
+            }
+
+            let line = line.trim_end();
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            if code != Some(expected) {
+                return Err(ApiError::ConnectionError(format!(
+                    "Unexpected SMTP reply: {}",
+                    line
+                )));
+            }
+            // `250-` continues a multi-line reply, `250 ` ends it
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
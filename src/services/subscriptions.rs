@@ -0,0 +1,478 @@
+use crate::config::SubscriptionsConfig;
+use crate::errors::ApiError;
+use crate::handlers::emails::SharedEmailService;
+use crate::models::{EmailCategory, EmailSummary};
+use crate::services::json_store::JsonStore;
+use crate::services::smtp::{is_valid_address, OutgoingMessage, SmtpClient};
+use chrono::{DateTime, Utc};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Body of an RFC 8058 one-click unsubscribe request
+pub const ONE_CLICK_BODY: &str = "List-Unsubscribe=One-Click";
+
+/// A sender of newsletters or mailing-list mail
+#[derive(Debug, Clone, Serialize)]
+pub struct Subscription {
+    /// Derived from the sender address, stable across listings
+    pub id: String,
+    pub sender: String,
+    pub sender_email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list_id: Option<String>,
+    /// Messages from the sender among the scanned emails
+    pub count: usize,
+    pub unread: usize,
+    /// Share of the messages that were read
+    pub open_rate: f64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// `List-Unsubscribe` URIs of the latest message that has them
+    pub unsubscribe_links: Vec<String>,
+    /// The sender supports RFC 8058 one-click unsubscribe
+    pub one_click: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsubscribed: Option<UnsubscribeRecord>,
+}
+
+impl Subscription {
+    /// How to unsubscribe: one-click when offered, otherwise by mail
+    pub fn method(&self, allow_http: bool) -> Result<UnsubscribeMethod, ApiError> {
+        let http_link = self.unsubscribe_links.iter().find_map(|link| {
+            let url = url::Url::parse(link).ok()?;
+            (url.scheme() == "https" || (allow_http && url.scheme() == "http")).then_some(url)
+        });
+        if let (true, Some(url)) = (self.one_click, &http_link) {
+            return Ok(UnsubscribeMethod::OneClick {
+                url: url.to_string(),
+            });
+        }
+
+        if let Some(method) = self
+            .unsubscribe_links
+            .iter()
+            .find_map(|link| parse_mailto(link))
+        {
+            return Ok(method);
+        }
+
+        Err(ApiError::ValidationError(match http_link {
+            Some(url) => format!(
+                "'{}' has no one-click or mailto unsubscribe; open {}",
+                self.sender_email, url
+            ),
+            None => format!("'{}' has no unsubscribe link", self.sender_email),
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum UnsubscribeMethod {
+    /// RFC 8058 POST to the https URL
+    OneClick { url: String },
+    /// Message to the `mailto:` address
+    Mailto {
+        address: String,
+        subject: String,
+        body: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsubscribeStatus {
+    Succeeded,
+    Failed,
+}
+
+/// The latest unsubscribe attempt for a subscription
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnsubscribeRecord {
+    pub subscription_id: String,
+    pub sender_email: String,
+    #[serde(flatten)]
+    pub method: UnsubscribeMethod,
+    pub status: UnsubscribeStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Aggregates newsletter senders from recent mail and unsubscribes from them
+pub struct SubscriptionManager {
+    email_service: SharedEmailService,
+    smtp: SmtpClient,
+    client: reqwest::Client,
+    config: SubscriptionsConfig,
+    /// Subscriptions of the latest aggregation, by id
+    latest: RwLock<HashMap<String, Subscription>>,
+    records: RwLock<Vec<UnsubscribeRecord>>,
+    store: JsonStore<Vec<UnsubscribeRecord>>,
+}
+
+impl SubscriptionManager {
+    pub fn new(
+        email_service: SharedEmailService,
+        smtp: SmtpClient,
+        config: SubscriptionsConfig,
+        data_dir: &Path,
+    ) -> Result<Self, ApiError> {
+        let store = JsonStore::new(data_dir.join("subscriptions.json"));
+        // RFC 8058: no cookies or credentials, just the POST. The URL comes
+        // from the email, so redirects are not followed and, unless allowed,
+        // hosts only resolve to public addresses.
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy();
+        if !config.allow_private_hosts {
+            builder = builder.dns_resolver(Arc::new(PublicResolver));
+        }
+        let client = builder
+            .build()
+            .map_err(|e| ApiError::InternalError(format!("HTTP client error: {}", e)))?;
+
+        Ok(Self {
+            email_service,
+            smtp,
+            client,
+            config,
+            latest: RwLock::new(HashMap::new()),
+            records: RwLock::new(store.load()?),
+            store,
+        })
+    }
+
+    /// Subscriptions among the recent emails
+    pub async fn list(&self) -> Result<Vec<Subscription>, ApiError> {
+        let emails = {
+            let service = self.email_service.lock().await;
+            service.get_recent_emails(self.config.scan_limit).await?
+        };
+        Ok(self.update(&emails).await)
+    }
+
+    /// Aggregate `emails` into subscriptions, replacing the previous ones
+    pub async fn update(&self, emails: &[EmailSummary]) -> Vec<Subscription> {
+        let mut subscriptions = aggregate(emails);
+        {
+            let records = self.records.read().await;
+            for subscription in &mut subscriptions {
+                subscription.unsubscribed = records
+                    .iter()
+                    .find(|record| record.subscription_id == subscription.id)
+                    .cloned();
+            }
+        }
+
+        *self.latest.write().await = subscriptions
+            .iter()
+            .map(|subscription| (subscription.id.clone(), subscription.clone()))
+            .collect();
+        subscriptions
+    }
+
+    /// A subscription from the latest aggregation, aggregating again if needed
+    pub async fn get(&self, id: &str) -> Result<Subscription, ApiError> {
+        if let Some(subscription) = self.latest.read().await.get(id) {
+            return Ok(subscription.clone());
+        }
+        self.list()
+            .await?
+            .into_iter()
+            .find(|subscription| subscription.id == id)
+            .ok_or_else(|| ApiError::NotFound(format!("Subscription {} not found", id)))
+    }
+
+    /// How `id` would be unsubscribed from
+    pub async fn method(&self, id: &str) -> Result<UnsubscribeMethod, ApiError> {
+        self.get(id).await?.method(self.config.allow_http)
+    }
+
+    /// Unsubscribe with the sender's preferred method. The attempt is recorded
+    /// whether or not it succeeds.
+    pub async fn unsubscribe(&self, id: &str) -> Result<UnsubscribeRecord, ApiError> {
+        let subscription = self.get(id).await?;
+        let method = subscription.method(self.config.allow_http)?;
+
+        let (status_code, result) = match &method {
+            UnsubscribeMethod::OneClick { url } => self.one_click(url).await,
+            UnsubscribeMethod::Mailto {
+                address,
+                subject,
+                body,
+            } => {
+                let message = OutgoingMessage {
+                    to: address.clone(),
+                    subject: subject.clone(),
+                    body: body.clone(),
+                };
+                let result = self.smtp.send(message).await.map_err(|e| match e {
+                    ApiError::ConnectionError(message) | ApiError::InternalError(message) => {
+                        message
+                    }
+                    e => e.to_string(),
+                });
+                (None, result)
+            }
+        };
+
+        let record = UnsubscribeRecord {
+            subscription_id: subscription.id.clone(),
+            sender_email: subscription.sender_email.clone(),
+            method,
+            status: if result.is_ok() {
+                UnsubscribeStatus::Succeeded
+            } else {
+                UnsubscribeStatus::Failed
+            },
+            status_code,
+            error: result.err(),
+            attempted_at: Utc::now(),
+        };
+
+        {
+            let mut records = self.records.write().await;
+            records.retain(|existing| existing.subscription_id != record.subscription_id);
+            records.push(record.clone());
+            self.store.save(&records)?;
+        }
+        if let Some(subscription) = self.latest.write().await.get_mut(id) {
+            subscription.unsubscribed = Some(record.clone());
+        }
+
+        Ok(record)
+    }
+
+    pub async fn records(&self) -> Vec<UnsubscribeRecord> {
+        self.records.read().await.clone()
+    }
+
+    async fn one_click(&self, url: &str) -> (Option<u16>, Result<(), String>) {
+        // IP literals are not looked up, so the resolver does not see them
+        if !self.config.allow_private_hosts {
+            let ip = match url::Url::parse(url)
+                .ok()
+                .and_then(|url| url.host().map(|h| h.to_owned()))
+            {
+                Some(url::Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+                Some(url::Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+                _ => None,
+            };
+            if let Some(ip) = ip.filter(|ip| !is_public(*ip)) {
+                return (
+                    None,
+                    Err(format!("Refusing to contact non-public address {}", ip)),
+                );
+            }
+        }
+
+        let response = self
+            .client
+            .post(url)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .body(ONE_CLICK_BODY)
+            .send()
+            .await;
+
+        match response {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), Ok(()))
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Err(format!(
+                    "Unsubscribe endpoint responded with {}",
+                    response.status()
+                )),
+            ),
+            Err(e) => (None, Err(format!("Unsubscribe request failed: {}", e))),
+        }
+    }
+}
+
+/// Resolves hosts to their public addresses only, so a one-click URL cannot
+/// reach the local network (even through DNS rebinding, as the connection
+/// uses the addresses checked here)
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} does not resolve to a public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is reachable on the internet, as opposed to loopback,
+/// private, link-local and other reserved ranges
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_multicast())
+            }
+        },
+    }
+}
+
+/// Group newsletter and mailing-list senders, most prolific first. A sender
+/// counts when any of its messages has a `List-Unsubscribe` or `List-Id`
+/// header or is categorized as a newsletter.
+pub fn aggregate(emails: &[EmailSummary]) -> Vec<Subscription> {
+    let mut by_sender: HashMap<String, Vec<&EmailSummary>> = HashMap::new();
+    for email in emails {
+        by_sender
+            .entry(email.sender_email.to_lowercase())
+            .or_default()
+            .push(email);
+    }
+
+    let mut subscriptions: Vec<Subscription> = by_sender
+        .into_iter()
+        .filter(|(_, emails)| emails.iter().any(|email| is_list_mail(email)))
+        .map(|(sender_email, mut emails)| {
+            emails.sort_by_key(|email| std::cmp::Reverse(email.date));
+            let latest = emails[0];
+            let read = emails.iter().filter(|email| email.is_read).count();
+            let unsubscribe_source = emails
+                .iter()
+                .find(|email| header(email, "list-unsubscribe").is_some());
+
+            Subscription {
+                id: subscription_id(&sender_email),
+                sender: latest.sender.clone(),
+                list_id: emails
+                    .iter()
+                    .find_map(|email| header(email, "list-id"))
+                    .map(str::to_string),
+                count: emails.len(),
+                unread: emails.len() - read,
+                open_rate: read as f64 / emails.len() as f64,
+                first_seen: emails[emails.len() - 1].date,
+                last_seen: latest.date,
+                unsubscribe_links: unsubscribe_source
+                    .and_then(|email| header(email, "list-unsubscribe"))
+                    .map(parse_list_unsubscribe)
+                    .unwrap_or_default(),
+                one_click: unsubscribe_source
+                    .and_then(|email| header(email, "list-unsubscribe-post"))
+                    .is_some_and(|value| value.trim().eq_ignore_ascii_case(ONE_CLICK_BODY)),
+                unsubscribed: None,
+                sender_email,
+            }
+        })
+        .collect();
+
+    subscriptions.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then(b.last_seen.cmp(&a.last_seen))
+            .then(a.sender_email.cmp(&b.sender_email))
+    });
+    subscriptions
+}
+
+/// URIs of a `List-Unsubscribe` header: `<mailto:...>, <https://...>`
+pub fn parse_list_unsubscribe(value: &str) -> Vec<String> {
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(uri, _)| uri.split_whitespace().collect::<String>())
+        .filter(|uri| !uri.is_empty())
+        .collect()
+}
+
+/// Address, subject and body of a `mailto:` URI (RFC 6068)
+pub fn parse_mailto(uri: &str) -> Option<UnsubscribeMethod> {
+    let url = url::Url::parse(uri).ok()?;
+    if url.scheme() != "mailto" {
+        return None;
+    }
+    // The address comes from the sender and ends up in SMTP commands
+    let address = percent_decode(url.path());
+    if !is_valid_address(&address) {
+        return None;
+    }
+
+    let mut subject = "Unsubscribe".to_string();
+    let mut body = "Unsubscribe".to_string();
+    for (name, value) in url.query_pairs() {
+        match name.to_ascii_lowercase().as_str() {
+            "subject" => subject = value.into_owned(),
+            "body" => body = value.into_owned(),
+            _ => {}
+        }
+    }
+
+    Some(UnsubscribeMethod::Mailto {
+        address,
+        subject,
+        body,
+    })
+}
+
+fn percent_decode(value: &str) -> String {
+    url::form_urlencoded::parse(format!("a={}", value.replace('+', "%2B")).as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default()
+}
+
+fn subscription_id(sender_email: &str) -> String {
+    hex::encode(&Sha256::digest(sender_email.as_bytes())[..8])
+}
+
+fn is_list_mail(email: &EmailSummary) -> bool {
+    email.category == EmailCategory::Newsletter
+        || header(email, "list-unsubscribe").is_some()
+        || header(email, "list-id").is_some()
+}
+
+fn header<'a>(email: &'a EmailSummary, name: &str) -> Option<&'a str> {
+    email
+        .headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
@@ -0,0 +1,224 @@
+use crate::errors::ApiError;
+use crate::middleware::auth::{hash_token, AuthContext, Scope};
+use crate::services::json_store::JsonStore;
+use chrono::{DateTime, Duration, Utc};
+use ipnet::IpNet;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+use subtle::ConstantTimeEq;
+use tokio::sync::RwLock;
+
+/// Prefix of minted tokens, so they can be told apart from config tokens
+pub const TOKEN_PREFIX: &str = "emt_";
+
+/// `last_used_at` is only written to disk when it moved by at least this much
+const LAST_USED_PERSIST_SECS: i64 = 60;
+
+/// A token minted through the API. Only the SHA-256 hash of the token is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    id: String,
+    description: Option<String>,
+    scopes: Vec<Scope>,
+    allowed_cidrs: Vec<IpNet>,
+    /// Hex-encoded SHA-256 hash of the token
+    sha256: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl StoredToken {
+    fn info(&self) -> TokenInfo {
+        TokenInfo {
+            id: self.id.clone(),
+            description: self.description.clone(),
+            scopes: self.scopes.clone(),
+            allowed_cidrs: self.allowed_cidrs.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            last_used_at: self.last_used_at,
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn allows(&self, ip: Option<IpAddr>) -> bool {
+        self.allowed_cidrs.is_empty()
+            || ip.is_some_and(|ip| self.allowed_cidrs.iter().any(|cidr| cidr.contains(&ip)))
+    }
+}
+
+/// A minted token without its hash
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub description: Option<String>,
+    pub scopes: Vec<Scope>,
+    pub allowed_cidrs: Vec<IpNet>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A newly minted token. The token itself is only returned here.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: TokenInfo,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub description: Option<String>,
+    pub scopes: Vec<Scope>,
+    /// Seconds until the token expires (default: never)
+    pub expires_in_secs: Option<u64>,
+    /// Source addresses the token is accepted from (default: any)
+    #[serde(default)]
+    pub allowed_cidrs: Vec<IpNet>,
+}
+
+/// API tokens minted at runtime, persisted in the data directory
+pub struct TokenStore {
+    tokens: RwLock<Vec<StoredToken>>,
+    store: JsonStore<Vec<StoredToken>>,
+}
+
+impl TokenStore {
+    pub fn new(data_dir: &Path) -> Result<Self, ApiError> {
+        let store = JsonStore::new(data_dir.join("api_tokens.json"));
+
+        Ok(Self {
+            tokens: RwLock::new(store.load()?),
+            store,
+        })
+    }
+
+    pub async fn create(&self, request: CreateTokenRequest) -> Result<CreatedToken, ApiError> {
+        if request.scopes.is_empty() {
+            return Err(ApiError::ValidationError(
+                "A token needs at least one scope".to_string(),
+            ));
+        }
+        let expires_at = match request.expires_in_secs {
+            Some(secs) => {
+                let lifetime = i64::try_from(secs)
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .and_then(Duration::try_seconds)
+                    .ok_or_else(|| {
+                        ApiError::ValidationError(
+                            "expires_in_secs must be a positive number of seconds".to_string(),
+                        )
+                    })?;
+                Some(Utc::now() + lifetime)
+            }
+            None => None,
+        };
+
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect();
+        let token = format!("{}{}", TOKEN_PREFIX, secret);
+
+        let stored = StoredToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            description: request.description,
+            scopes: request.scopes,
+            allowed_cidrs: request.allowed_cidrs,
+            sha256: hex::encode(hash_token(&token)),
+            created_at: Utc::now(),
+            expires_at,
+            last_used_at: None,
+        };
+
+        let mut tokens = self.tokens.write().await;
+        tokens.push(stored.clone());
+        self.store.save(&tokens)?;
+
+        tracing::info!("Minted API token {}", stored.id);
+
+        Ok(CreatedToken {
+            token,
+            info: stored.info(),
+        })
+    }
+
+    pub async fn list(&self) -> Vec<TokenInfo> {
+        self.tokens
+            .read()
+            .await
+            .iter()
+            .map(StoredToken::info)
+            .collect()
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<(), ApiError> {
+        let mut tokens = self.tokens.write().await;
+        let before = tokens.len();
+        tokens.retain(|t| t.id != id);
+
+        if tokens.len() == before {
+            return Err(ApiError::NotFound(format!("Token {}", id)));
+        }
+
+        tracing::info!("Revoked API token {}", id);
+        self.store.save(&tokens)
+    }
+
+    /// The minted token with this hash, if it has not expired and is allowed
+    /// from `ip`. Records the use in `last_used_at`.
+    pub async fn authenticate(&self, hash: &[u8; 32], ip: Option<IpAddr>) -> Option<AuthContext> {
+        let now = Utc::now();
+        let mut tokens = self.tokens.write().await;
+
+        // Compare against every token so the time taken does not depend on
+        // which one matched
+        let mut found = None;
+        for (index, token) in tokens.iter().enumerate() {
+            let stored = hex::decode(&token.sha256).unwrap_or_default();
+            if bool::from(stored.ct_eq(hash)) {
+                found = Some(index);
+            }
+        }
+        let token = &mut tokens[found?];
+
+        if token.is_expired(now) {
+            tracing::warn!("Rejected expired API token {}", token.id);
+            return None;
+        }
+        if !token.allows(ip) {
+            tracing::warn!(
+                "Rejected API token {} from {}",
+                token.id,
+                ip.map(|ip| ip.to_string())
+                    .unwrap_or_else(|| "unknown address".to_string())
+            );
+            return None;
+        }
+
+        let persist = token
+            .last_used_at
+            .is_none_or(|last| (now - last).num_seconds() >= LAST_USED_PERSIST_SECS);
+        token.last_used_at = Some(now);
+        let context = AuthContext {
+            token_name: token.id.clone(),
+            scopes: token.scopes.clone(),
+        };
+
+        if persist {
+            if let Err(e) = self.store.save(&tokens) {
+                tracing::error!("Failed to record API token use: {}", e);
+            }
+        }
+        Some(context)
+    }
+}
@@ -0,0 +1,315 @@
+use crate::errors::ApiError;
+use crate::services::mfa_registry::{fold, MfaRegistry};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// Images larger than this are not scanned for QR codes
+const MAX_QR_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// Shortest accepted secret, in bytes (RFC 4226 requires at least 128 bits,
+/// but 80-bit secrets are common)
+const MIN_SECRET_BYTES: usize = 10;
+
+/// Words that mark an email as an authenticator app enrolment
+const SETUP_KEYWORDS: &[&str] = &[
+    "authenticator",
+    "two-factor",
+    "two factor",
+    "2-step",
+    "2fa",
+    "totp",
+    "setup key",
+    "secret key",
+    "qr code",
+    "chave secreta",
+    "autenticador",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TotpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// A TOTP secret and its parameters (RFC 6238)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TotpSecret {
+    /// Service the secret is for: the issuer, or the service the email was
+    /// detected as coming from
+    pub service: String,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+    /// Base32, uppercase, without padding
+    pub secret: String,
+    pub algorithm: TotpAlgorithm,
+    pub digits: u32,
+    pub period: u64,
+}
+
+impl TotpSecret {
+    /// A secret with the default parameters (SHA1, 6 digits, 30 seconds)
+    pub fn new(service: impl Into<String>, secret: &str) -> Result<Self, ApiError> {
+        Ok(Self {
+            service: service.into(),
+            issuer: None,
+            account: None,
+            secret: normalize_secret(secret)?,
+            algorithm: TotpAlgorithm::Sha1,
+            digits: 6,
+            period: 30,
+        })
+    }
+
+    /// Parse an `otpauth://totp/Issuer:account?secret=...&issuer=...` URI
+    pub fn from_uri(uri: &str) -> Result<Self, ApiError> {
+        let invalid =
+            |reason: &str| ApiError::ValidationError(format!("Invalid otpauth URI: {}", reason));
+
+        let url = url::Url::parse(uri).map_err(|e| invalid(&e.to_string()))?;
+        if url.scheme() != "otpauth" {
+            return Err(invalid("not an otpauth URI"));
+        }
+        if !url
+            .host_str()
+            .unwrap_or_default()
+            .eq_ignore_ascii_case("totp")
+        {
+            return Err(invalid("only TOTP is supported"));
+        }
+
+        let label = url
+            .path_segments()
+            .and_then(|mut segments| segments.next())
+            .map(|label| {
+                url::form_urlencoded::parse(format!("l={}", label).as_bytes())
+                    .next()
+                    .map(|(_, v)| v.into_owned())
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default();
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim()),
+            None => (None, label.trim()),
+        };
+
+        let mut secret = None;
+        let mut issuer = None;
+        let mut algorithm = TotpAlgorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+        for (key, value) in url.query_pairs() {
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(normalize_secret(&value)?),
+                "issuer" => issuer = Some(value.trim().to_string()),
+                "algorithm" => {
+                    algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => TotpAlgorithm::Sha1,
+                        "SHA256" => TotpAlgorithm::Sha256,
+                        "SHA512" => TotpAlgorithm::Sha512,
+                        _ => return Err(invalid("unsupported algorithm")),
+                    }
+                }
+                "digits" => {
+                    digits = value
+                        .parse()
+                        .ok()
+                        .filter(|d| (6..=8).contains(d))
+                        .ok_or_else(|| invalid("digits must be 6 to 8"))?
+                }
+                "period" => {
+                    period = value
+                        .parse()
+                        .ok()
+                        .filter(|p| *p > 0)
+                        .ok_or_else(|| invalid("invalid period"))?
+                }
+                _ => {}
+            }
+        }
+
+        let issuer = issuer.filter(|i| !i.is_empty()).or(label_issuer);
+        Ok(Self {
+            service: issuer.clone().unwrap_or_else(|| account.to_string()),
+            issuer,
+            account: Some(account.to_string()).filter(|a| !a.is_empty()),
+            secret: secret.ok_or_else(|| invalid("missing secret"))?,
+            algorithm,
+            digits,
+            period,
+        })
+    }
+
+    /// The code for the time step containing `unix_time`
+    pub fn code_at(&self, unix_time: u64) -> Result<String, ApiError> {
+        let key = BASE32_NOPAD
+            .decode(self.secret.as_bytes())
+            .map_err(|e| ApiError::InternalError(format!("Invalid TOTP secret: {}", e)))?;
+        let counter = (unix_time / self.period.max(1)).to_be_bytes();
+
+        let digest = match self.algorithm {
+            TotpAlgorithm::Sha1 => hmac_digest::<Hmac<sha1::Sha1>>(&key, &counter),
+            TotpAlgorithm::Sha256 => hmac_digest::<Hmac<sha2::Sha256>>(&key, &counter),
+            TotpAlgorithm::Sha512 => hmac_digest::<Hmac<sha2::Sha512>>(&key, &counter),
+        };
+
+        // Dynamic truncation (RFC 4226 section 5.3)
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        let code = binary as u64 % 10u64.pow(self.digits);
+
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+
+    /// Seconds until the code for `unix_time` stops being valid
+    pub fn remaining_secs(&self, unix_time: u64) -> u64 {
+        let period = self.period.max(1);
+        period - unix_time % period
+    }
+}
+
+fn hmac_digest<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Uppercase a base32 secret and drop its spaces, dashes and padding
+fn normalize_secret(secret: &str) -> Result<String, ApiError> {
+    let normalized: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    match BASE32_NOPAD.decode(normalized.as_bytes()) {
+        Ok(key) if key.len() >= MIN_SECRET_BYTES => Ok(normalized),
+        Ok(_) => Err(ApiError::ValidationError(
+            "TOTP secret is too short".to_string(),
+        )),
+        Err(e) => Err(ApiError::ValidationError(format!(
+            "TOTP secret is not valid base32: {}",
+            e
+        ))),
+    }
+}
+
+fn otpauth_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"(?i)otpauth://totp/[^\s"'<>]+"#).unwrap())
+}
+
+fn setup_key_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        // "Setup key: JBSW Y3DP EHPK 3PXP", "secret key is jbswy3dpehpk3pxp"
+        Regex::new(
+            r"(?i)(?:key|secret|chave|code)(?:\s+is)?\s*[:\-]?\s*((?:[a-z2-7]{4}[ -]?){3,}[a-z2-7]{0,12}=*)(?:[^a-z0-9]|$)",
+        )
+        .unwrap()
+    })
+}
+
+/// TOTP secrets sent during an authenticator enrolment: `otpauth://` URIs in
+/// the text or HTML body, base32 setup keys next to a "key" label, and QR
+/// codes in image attachments. Setup keys and QR codes are only looked for in
+/// emails that read like an enrolment.
+pub fn detect_secrets(
+    subject: &str,
+    sender: &str,
+    text: &str,
+    html: Option<&str>,
+    images: &[Vec<u8>],
+) -> Vec<TotpSecret> {
+    let mut secrets: Vec<TotpSecret> = Vec::new();
+    let mut push = |secret: TotpSecret| {
+        if !secrets.iter().any(|s| s.secret == secret.secret) {
+            secrets.push(secret);
+        }
+    };
+
+    let html = html.map(|h| h.replace("&amp;", "&"));
+    for body in std::iter::once(text).chain(html.as_deref()) {
+        for uri in otpauth_regex().find_iter(body) {
+            match TotpSecret::from_uri(uri.as_str()) {
+                Ok(secret) => push(secret),
+                Err(e) => tracing::debug!("Ignoring otpauth URI in email from {}: {}", sender, e),
+            }
+        }
+    }
+
+    let context = fold(&format!("{}\n{}", subject, text));
+    if !SETUP_KEYWORDS
+        .iter()
+        .any(|keyword| context.contains(keyword))
+    {
+        return secrets;
+    }
+
+    for image in images
+        .iter()
+        .filter(|image| image.len() <= MAX_QR_IMAGE_BYTES)
+    {
+        for content in decode_qr_codes(image) {
+            if let Ok(secret) = TotpSecret::from_uri(&content) {
+                push(secret);
+            }
+        }
+    }
+
+    let service = MfaRegistry::global()
+        .detect_service(Some(sender), Some(subject))
+        .map(String::from)
+        .unwrap_or_else(|| sender_domain(sender));
+    for captures in setup_key_regex().captures_iter(text) {
+        // Runs of ordinary words are valid base32 too
+        let key = &captures[1];
+        let looks_random = key.chars().any(|c| ('2'..='7').contains(&c))
+            || !key.chars().any(|c| c.is_ascii_lowercase());
+        if !looks_random {
+            continue;
+        }
+        if let Ok(secret) = TotpSecret::new(service.clone(), key) {
+            push(secret);
+        }
+    }
+
+    secrets
+}
+
+/// Contents of every QR code found in an image
+pub fn decode_qr_codes(image: &[u8]) -> Vec<String> {
+    let image = match image::load_from_memory(image) {
+        Ok(image) => image.into_luma8(),
+        Err(e) => {
+            tracing::debug!("Skipping undecodable image: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+    prepared
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| grid.decode().ok().map(|(_, content)| content))
+        .collect()
+}
+
+fn sender_domain(sender: &str) -> String {
+    sender
+        .rsplit('@')
+        .next()
+        .unwrap_or(sender)
+        .trim_end_matches('>')
+        .to_string()
+}
@@ -0,0 +1,319 @@
+use crate::errors::ApiError;
+use crate::services::json_store::JsonStore;
+use crate::services::totp::TotpSecret;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::sync::RwLock;
+
+/// A vault entry. Only the secret and its parameters are encrypted; the
+/// service and account are kept in the clear so entries can be listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SealedSecret {
+    service: String,
+    account: Option<String>,
+    /// Email the secret was found in
+    email_id: Option<String>,
+    added_at: DateTime<Utc>,
+    /// Hex-encoded AES-256-GCM nonce and ciphertext of the `TotpSecret` JSON
+    nonce: String,
+    ciphertext: String,
+}
+
+/// A vault entry without its secret
+#[derive(Debug, Clone, Serialize)]
+pub struct TotpEntry {
+    pub service: String,
+    pub account: Option<String>,
+    pub email_id: Option<String>,
+    pub added_at: DateTime<Utc>,
+}
+
+/// The current code of a stored secret
+#[derive(Debug, Clone, Serialize)]
+pub struct TotpCode {
+    pub service: String,
+    pub account: Option<String>,
+    pub code: String,
+    pub digits: u32,
+    pub period: u64,
+    /// Seconds until the code changes
+    pub expires_in: u64,
+}
+
+/// TOTP secrets found in enrolment emails, encrypted at rest
+pub struct TotpVault {
+    entries: RwLock<Vec<SealedSecret>>,
+    store: JsonStore<Vec<SealedSecret>>,
+    cipher: Aes256Gcm,
+}
+
+impl TotpVault {
+    /// Open the vault in `data_dir`. The encryption key is derived from
+    /// `vault_key` with Argon2id and a random salt kept in `totp_vault.salt`;
+    /// when `vault_key` is empty a random key is generated once and kept in
+    /// `totp_vault.key` next to the vault.
+    pub fn new(data_dir: &Path, vault_key: &str) -> Result<Self, ApiError> {
+        let store = JsonStore::new(data_dir.join("totp_vault.json"));
+        let mut entries: Vec<SealedSecret> = store.load()?;
+
+        let (key, legacy_key) = if vault_key.is_empty() {
+            (load_or_create_key(&data_dir.join("totp_vault.key"))?, None)
+        } else {
+            let salt = load_or_create_salt(&data_dir.join("totp_vault.salt"))?;
+            let mut key = [0u8; 32];
+            Argon2::default()
+                .hash_password_into(vault_key.as_bytes(), &salt, &mut key)
+                .map_err(|e| ApiError::InternalError(format!("Key derivation failed: {}", e)))?;
+            // Earlier versions used the bare SHA-256 of the passphrase
+            let legacy_key = Sha256::digest(vault_key.as_bytes()).to_vec();
+            (key.to_vec(), Some(legacy_key))
+        };
+        let mut vault = Self {
+            entries: RwLock::new(Vec::new()),
+            store,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        };
+
+        if let Some(legacy_key) = legacy_key {
+            let legacy = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&legacy_key));
+            let mut resealed = 0;
+            for entry in entries.iter_mut() {
+                if vault.open(entry).is_ok() {
+                    continue;
+                }
+                if let Ok(secret) = open_with(&legacy, entry) {
+                    *entry = SealedSecret {
+                        added_at: entry.added_at,
+                        ..vault.seal(&secret, entry.email_id.as_deref())?
+                    };
+                    resealed += 1;
+                }
+            }
+            if resealed > 0 {
+                vault.store.save(&entries)?;
+                tracing::info!(
+                    "Re-encrypted {} TOTP secrets with the derived key",
+                    resealed
+                );
+            }
+        }
+
+        *vault.entries.get_mut() = entries;
+        Ok(vault)
+    }
+
+    /// Store secrets. A different secret already stored for the same service
+    /// and account is only replaced with `overwrite`; otherwise nothing is
+    /// stored. Returns the number of new or changed secrets.
+    pub async fn add(
+        &self,
+        secrets: &[TotpSecret],
+        email_id: Option<&str>,
+        overwrite: bool,
+    ) -> Result<usize, ApiError> {
+        let mut entries = self.entries.write().await;
+        let position = |entries: &[SealedSecret], secret: &TotpSecret| {
+            entries.iter().position(|entry| {
+                entry.service.eq_ignore_ascii_case(&secret.service)
+                    && entry.account == secret.account
+            })
+        };
+
+        if !overwrite {
+            let conflict = secrets.iter().find(|secret| {
+                position(&entries, secret)
+                    .is_some_and(|index| self.open(&entries[index]).ok().as_ref() != Some(*secret))
+            });
+            if let Some(secret) = conflict {
+                return Err(ApiError::ValidationError(format!(
+                    "A different TOTP secret is already stored for {} ({}); \
+                     use overwrite=true to replace it",
+                    secret.service,
+                    secret.account.as_deref().unwrap_or("no account")
+                )));
+            }
+        }
+
+        let mut added = 0;
+        for secret in secrets {
+            if let Some(index) = position(&entries, secret) {
+                if self.open(&entries[index]).ok().as_ref() == Some(secret) {
+                    continue;
+                }
+                entries.remove(index);
+            }
+
+            entries.push(self.seal(secret, email_id)?);
+            added += 1;
+            tracing::info!(
+                "Stored TOTP secret for {} ({})",
+                secret.service,
+                secret.account.as_deref().unwrap_or("no account")
+            );
+        }
+
+        if added > 0 {
+            self.store.save(&entries)?;
+        }
+        Ok(added)
+    }
+
+    /// Stored secrets, without the secrets themselves
+    pub async fn list(&self) -> Vec<TotpEntry> {
+        self.entries
+            .read()
+            .await
+            .iter()
+            .map(|entry| TotpEntry {
+                service: entry.service.clone(),
+                account: entry.account.clone(),
+                email_id: entry.email_id.clone(),
+                added_at: entry.added_at,
+            })
+            .collect()
+    }
+
+    /// The current code for `service` (case-insensitive), using the most
+    /// recently added secret when there are several accounts and none is given
+    pub async fn code(
+        &self,
+        service: &str,
+        account: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<TotpCode, ApiError> {
+        let entries = self.entries.read().await;
+        let entry = entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.service.eq_ignore_ascii_case(service))
+            .find(|entry| account.is_none() || entry.account.as_deref() == account)
+            .ok_or_else(|| {
+                ApiError::NotFound(format!("No TOTP secret stored for service '{}'", service))
+            })?;
+
+        let secret = self.open(entry)?;
+        let unix_time = now.timestamp().max(0) as u64;
+        Ok(TotpCode {
+            service: secret.service.clone(),
+            account: secret.account.clone(),
+            code: secret.code_at(unix_time)?,
+            digits: secret.digits,
+            period: secret.period,
+            expires_in: secret.remaining_secs(unix_time),
+        })
+    }
+
+    fn seal(&self, secret: &TotpSecret, email_id: Option<&str>) -> Result<SealedSecret, ApiError> {
+        let plaintext = serde_json::to_vec(secret)
+            .map_err(|e| ApiError::InternalError(format!("Failed to serialize: {}", e)))?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| ApiError::InternalError("Failed to encrypt TOTP secret".to_string()))?;
+
+        Ok(SealedSecret {
+            service: secret.service.clone(),
+            account: secret.account.clone(),
+            email_id: email_id.map(String::from),
+            added_at: Utc::now(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    fn open(&self, entry: &SealedSecret) -> Result<TotpSecret, ApiError> {
+        open_with(&self.cipher, entry)
+    }
+}
+
+fn open_with(cipher: &Aes256Gcm, entry: &SealedSecret) -> Result<TotpSecret, ApiError> {
+    let corrupt = || {
+        ApiError::InternalError(format!(
+            "Failed to decrypt TOTP secret for '{}' (wrong vault key?)",
+            entry.service
+        ))
+    };
+
+    let nonce = hex::decode(&entry.nonce).map_err(|_| corrupt())?;
+    if nonce.len() != 12 {
+        return Err(corrupt());
+    }
+    let ciphertext = hex::decode(&entry.ciphertext).map_err(|_| corrupt())?;
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| corrupt())?;
+
+    serde_json::from_slice(&plaintext).map_err(|_| corrupt())
+}
+
+/// The Argon2 salt of the vault key, generated on first use
+fn load_or_create_salt(path: &Path) -> Result<Vec<u8>, ApiError> {
+    if path.exists() {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            ApiError::InternalError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        return hex::decode(contents.trim())
+            .ok()
+            .filter(|salt| salt.len() >= 16)
+            .ok_or_else(|| ApiError::InternalError(format!("Invalid salt in {}", path.display())));
+    }
+
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    create_parent(path)?;
+    write_private(path, &hex::encode(salt))?;
+    Ok(salt.to_vec())
+}
+
+fn load_or_create_key(path: &Path) -> Result<Vec<u8>, ApiError> {
+    if path.exists() {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            ApiError::InternalError(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        return hex::decode(contents.trim())
+            .ok()
+            .filter(|key| key.len() == 32)
+            .ok_or_else(|| ApiError::InternalError(format!("Invalid key in {}", path.display())));
+    }
+
+    let key = Aes256Gcm::generate_key(&mut OsRng).to_vec();
+    create_parent(path)?;
+    write_private(path, &hex::encode(&key))?;
+    tracing::info!("Generated TOTP vault key in {}", path.display());
+    Ok(key)
+}
+
+fn create_parent(path: &Path) -> Result<(), ApiError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            ApiError::InternalError(format!(
+                "Failed to create directory {}: {}",
+                parent.display(),
+                e
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+/// Write a file readable only by its owner
+fn write_private(path: &Path, contents: &str) -> Result<(), ApiError> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| ApiError::InternalError(format!("Failed to write {}: {}", path.display(), e)))
+}
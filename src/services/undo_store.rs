@@ -0,0 +1,86 @@
+use crate::errors::ApiError;
+use crate::services::imap_service::TrashedEmail;
+use crate::services::json_store::JsonStore;
+use chrono::{DateTime, Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::sync::Mutex;
+
+/// Messages moved to the trash by one delete, restorable until `expires_at`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoEntry {
+    pub token: String,
+    pub emails: Vec<TrashedEmail>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Undo tokens of recent deletes, persisted in the data directory
+pub struct UndoStore {
+    entries: Mutex<Vec<UndoEntry>>,
+    store: JsonStore<Vec<UndoEntry>>,
+    window: Duration,
+}
+
+impl UndoStore {
+    pub fn new(data_dir: &Path, window_secs: u64) -> Result<Self, ApiError> {
+        let store = JsonStore::new(data_dir.join("undo.json"));
+        let window = i64::try_from(window_secs)
+            .ok()
+            .and_then(Duration::try_seconds)
+            .ok_or_else(|| {
+                ApiError::ValidationError(format!("Invalid undo window {}s", window_secs))
+            })?;
+
+        Ok(Self {
+            entries: Mutex::new(store.load()?),
+            store,
+            window,
+        })
+    }
+
+    /// Record trashed messages, returning the entry with its undo token
+    pub async fn register(&self, emails: Vec<TrashedEmail>) -> Result<UndoEntry, ApiError> {
+        let now = Utc::now();
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let entry = UndoEntry {
+            token,
+            emails,
+            created_at: now,
+            expires_at: now + self.window,
+        };
+
+        let mut entries = self.entries.lock().await;
+        entries.retain(|entry| entry.expires_at > now);
+        entries.push(entry.clone());
+        self.store.save(&entries)?;
+        Ok(entry)
+    }
+
+    /// Remove and return the entry of a token that has not expired
+    pub async fn take(&self, token: &str) -> Result<UndoEntry, ApiError> {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().await;
+        let position = entries
+            .iter()
+            .position(|entry| entry.token == token && entry.expires_at > now)
+            .ok_or_else(|| ApiError::NotFound(format!("Undo token {} (or it expired)", token)))?;
+        let entry = entries.remove(position);
+        entries.retain(|entry| entry.expires_at > now);
+        self.store.save(&entries)?;
+        Ok(entry)
+    }
+
+    /// Put back the messages of an undo that could not be restored, so the
+    /// token can be retried
+    pub async fn reinstate(&self, entry: UndoEntry) -> Result<(), ApiError> {
+        let mut entries = self.entries.lock().await;
+        entries.push(entry);
+        self.store.save(&entries)
+    }
+}
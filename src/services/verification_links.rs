@@ -0,0 +1,219 @@
+use crate::services::html_text::decode_entities;
+use regex::Regex;
+use std::sync::OnceLock;
+use url::Url;
+
+/// A candidate sign-in / verification / password-reset link
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerificationLink {
+    pub url: String,
+    /// Anchor text for HTML links, surrounding text for plain-text links
+    pub label: String,
+    pub score: i32,
+}
+
+/// Phrases that mark a link as the call to action of a verification email
+const ANCHOR_KEYWORDS: &[&str] = &[
+    // English
+    "sign in",
+    "sign-in",
+    "log in",
+    "login",
+    "verify",
+    "confirm",
+    "activate",
+    "reset password",
+    "reset your password",
+    "magic link",
+    "continue",
+    // Portuguese
+    "confirmar",
+    "verificar",
+    "entrar",
+    "acessar",
+    "ativar",
+    "redefinir",
+    // Spanish
+    "iniciar sesión",
+    "restablecer",
+    "activar",
+];
+
+/// URL path / query fragments typical of verification links
+const URL_KEYWORDS: &[&str] = &[
+    "verify",
+    "verification",
+    "confirm",
+    "magic",
+    "login",
+    "signin",
+    "sign-in",
+    "sign_in",
+    "auth",
+    "token",
+    "reset",
+    "activate",
+    "validate",
+    "otp",
+];
+
+/// Links that are never the call to action
+const IGNORED_KEYWORDS: &[&str] = &[
+    "unsubscribe",
+    "privacy",
+    "terms",
+    "preferences",
+    "support",
+    "help",
+    "descadastr",
+    "cancelar inscri",
+];
+
+const IMAGE_EXTENSIONS: &[&str] = &[".png", ".jpg", ".jpeg", ".gif", ".svg", ".webp"];
+
+/// Query parameters that tracking redirects use to carry the target URL
+const REDIRECT_PARAMS: &[&str] = &[
+    "url",
+    "u",
+    "q",
+    "target",
+    "redirect",
+    "redirect_url",
+    "dest",
+    "destination",
+    "link",
+    "to",
+];
+
+const MIN_LINK_SCORE: i32 = 2;
+const MAX_REDIRECT_DEPTH: usize = 3;
+
+fn anchor_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r#"(?is)<a\s[^>]*?href\s*=\s*["']([^"']+)["'][^>]*>(.*?)</a>"#).unwrap()
+    })
+}
+
+fn url_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"https?://[^\s<>"'\])]+"#).unwrap())
+}
+
+fn tag_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?s)<[^>]*>").unwrap())
+}
+
+/// Replace every URL in `text` with a space, so codes are not picked out of links
+pub fn strip_urls(text: &str) -> String {
+    url_regex().replace_all(text, " ").into_owned()
+}
+
+/// Find the most likely verification link in the plain-text and HTML bodies
+pub fn find_verification_link(text: Option<&str>, html: Option<&str>) -> Option<VerificationLink> {
+    let mut candidates = Vec::new();
+
+    if let Some(html) = html {
+        for captures in anchor_regex().captures_iter(html) {
+            let href = decode_entities(captures[1].trim());
+            let label = decode_entities(tag_regex().replace_all(&captures[2], " ").trim());
+            if let Some(link) = score_link(&href, &label, 3) {
+                candidates.push(link);
+            }
+        }
+    }
+
+    if let Some(text) = text {
+        for m in url_regex().find_iter(text) {
+            let raw = m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?']);
+            // The line leading up to the URL usually says what it is for
+            let before = &text[..m.start()];
+            let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            let label: String = before[line_start..]
+                .chars()
+                .rev()
+                .take(80)
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .collect();
+            if let Some(link) = score_link(raw, label.trim(), 2) {
+                candidates.push(link);
+            }
+        }
+    }
+
+    // Highest score wins; on ties keep document order (HTML first)
+    candidates
+        .into_iter()
+        .enumerate()
+        .max_by_key(|(index, link)| (link.score, std::cmp::Reverse(*index)))
+        .map(|(_, link)| link)
+}
+
+fn score_link(href: &str, label: &str, label_weight: i32) -> Option<VerificationLink> {
+    let url = unwrap_redirects(Url::parse(href).ok()?);
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    let location = format!("{}{}", url.host_str().unwrap_or_default(), url.path()).to_lowercase();
+    let label_lower = label.to_lowercase();
+
+    if IGNORED_KEYWORDS
+        .iter()
+        .any(|k| location.contains(k) || label_lower.contains(k))
+    {
+        return None;
+    }
+    if IMAGE_EXTENSIONS.iter().any(|ext| url.path().ends_with(ext)) {
+        return None;
+    }
+
+    let mut score = 0;
+    if ANCHOR_KEYWORDS.iter().any(|k| label_lower.contains(k)) {
+        score += label_weight;
+    }
+
+    let path_and_query =
+        format!("{}?{}", url.path(), url.query().unwrap_or_default()).to_lowercase();
+    if URL_KEYWORDS.iter().any(|k| path_and_query.contains(k)) {
+        score += 2;
+    }
+
+    if score < MIN_LINK_SCORE {
+        return None;
+    }
+
+    Some(VerificationLink {
+        url: url.to_string(),
+        label: label.to_string(),
+        score,
+    })
+}
+
+/// Follow tracking redirects that carry the real target in a query parameter.
+/// URLs that already look like verification links (e.g. OAuth flows with a
+/// `redirect` parameter) are left alone.
+pub fn unwrap_redirects(mut url: Url) -> Url {
+    for _ in 0..MAX_REDIRECT_DEPTH {
+        let path = url.path().to_lowercase();
+        if URL_KEYWORDS.iter().any(|k| path.contains(k)) {
+            break;
+        }
+
+        let target = url
+            .query_pairs()
+            .find(|(key, _)| REDIRECT_PARAMS.contains(&key.to_lowercase().as_str()))
+            .and_then(|(_, value)| Url::parse(&value).ok())
+            .filter(|target| target.scheme() == "http" || target.scheme() == "https");
+
+        match target {
+            Some(target) => url = target,
+            None => break,
+        }
+    }
+
+    url
+}
@@ -0,0 +1,500 @@
+use crate::config::WebhooksConfig;
+use crate::errors::ApiError;
+use crate::services::event_bus::{EventBus, EventFilter, EventType, MailboxEvent};
+use crate::services::json_store::JsonStore;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<EventType>,
+    #[serde(default)]
+    pub filter: EventFilter,
+    pub description: Option<String>,
+    /// HMAC-SHA256 signing secret. Only returned when the subscription is created.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookSubscription {
+    pub fn matches(&self, event: &MailboxEvent) -> bool {
+        self.events.contains(&event.payload.event_type()) && self.filter.matches(event)
+    }
+
+    /// Copy without the signing secret, for listings
+    pub fn redacted(&self) -> Self {
+        Self {
+            secret: String::new(),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    /// Defaults to every event type
+    #[serde(default)]
+    pub events: Vec<EventType>,
+    #[serde(default)]
+    pub filter: EventFilter,
+    pub description: Option<String>,
+    /// Generated when omitted
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryRecord {
+    pub id: String,
+    pub subscription_id: String,
+    pub event_id: u64,
+    pub event_type: EventType,
+    pub url: String,
+    /// The event sent. Kept in memory only, since it can hold email bodies
+    /// and MFA codes; a delivery can be replayed until the server restarts.
+    #[serde(skip)]
+    pub payload: serde_json::Value,
+    /// Hex SHA-256 of the request body
+    #[serde(default)]
+    pub payload_sha256: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Set when this delivery is a manual replay of another one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_of: Option<String>,
+}
+
+/// Compute the `X-Webhook-Signature` value: HMAC-SHA256 over `"{timestamp}.{body}"`
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Registry of webhook subscriptions that forwards bus events to them
+pub struct WebhookManager {
+    subscriptions: RwLock<Vec<WebhookSubscription>>,
+    deliveries: RwLock<VecDeque<DeliveryRecord>>,
+    subscriptions_store: JsonStore<Vec<WebhookSubscription>>,
+    deliveries_store: Arc<JsonStore<VecDeque<DeliveryRecord>>>,
+    /// Serializes delivery log writes so an older snapshot never overwrites a newer one
+    deliveries_write: Mutex<()>,
+    client: reqwest::Client,
+    config: WebhooksConfig,
+}
+
+impl WebhookManager {
+    pub fn new(config: WebhooksConfig, data_dir: &Path) -> Result<Self, ApiError> {
+        let subscriptions_store = JsonStore::new(data_dir.join("webhooks.json"));
+        let deliveries_store = Arc::new(JsonStore::new(data_dir.join("webhook_deliveries.json")));
+
+        // Following a redirect would turn the signed POST into a GET to
+        // whatever host the endpoint names
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| ApiError::InternalError(format!("HTTP client error: {}", e)))?;
+
+        Ok(Self {
+            subscriptions: RwLock::new(subscriptions_store.load()?),
+            deliveries: RwLock::new(deliveries_store.load()?),
+            subscriptions_store,
+            deliveries_store,
+            deliveries_write: Mutex::new(()),
+            client,
+            config,
+        })
+    }
+
+    pub async fn create_subscription(
+        &self,
+        request: CreateWebhookRequest,
+    ) -> Result<WebhookSubscription, ApiError> {
+        let url = reqwest::Url::parse(&request.url)
+            .map_err(|e| ApiError::ValidationError(format!("Invalid webhook URL: {}", e)))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(ApiError::ValidationError(
+                "Webhook URL must use http or https".to_string(),
+            ));
+        }
+
+        let events = if request.events.is_empty() {
+            vec![
+                EventType::NewEmail,
+                EventType::FlagsChanged,
+                EventType::Deleted,
+                EventType::MfaCodeDetected,
+            ]
+        } else {
+            request.events
+        };
+
+        let secret = match request.secret {
+            Some(secret) if !secret.is_empty() => secret,
+            _ => rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect(),
+        };
+
+        let subscription = WebhookSubscription {
+            id: uuid::Uuid::new_v4().to_string(),
+            url: url.to_string(),
+            events,
+            filter: request.filter,
+            description: request.description,
+            secret,
+            created_at: Utc::now(),
+        };
+
+        let mut subscriptions = self.subscriptions.write().await;
+        subscriptions.push(subscription.clone());
+        self.subscriptions_store.save(&subscriptions)?;
+
+        tracing::info!(
+            "Registered webhook {} -> {}",
+            subscription.id,
+            subscription.url
+        );
+
+        Ok(subscription)
+    }
+
+    pub async fn list_subscriptions(&self) -> Vec<WebhookSubscription> {
+        self.subscriptions
+            .read()
+            .await
+            .iter()
+            .map(WebhookSubscription::redacted)
+            .collect()
+    }
+
+    pub async fn delete_subscription(&self, id: &str) -> Result<(), ApiError> {
+        let mut subscriptions = self.subscriptions.write().await;
+        let before = subscriptions.len();
+        subscriptions.retain(|s| s.id != id);
+
+        if subscriptions.len() == before {
+            return Err(ApiError::NotFound(format!("Webhook {}", id)));
+        }
+
+        self.subscriptions_store.save(&subscriptions)
+    }
+
+    /// Most recent deliveries first, optionally for a single subscription
+    pub async fn list_deliveries(
+        &self,
+        subscription_id: Option<&str>,
+        limit: usize,
+    ) -> Vec<DeliveryRecord> {
+        self.deliveries
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|d| subscription_id.is_none_or(|id| d.subscription_id == id))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn get_delivery(&self, delivery_id: &str) -> Option<DeliveryRecord> {
+        self.deliveries
+            .read()
+            .await
+            .iter()
+            .find(|d| d.id == delivery_id)
+            .cloned()
+    }
+
+    /// Send a recorded delivery's payload again as a new delivery.
+    /// The replay runs in the background; the returned record is still pending.
+    pub async fn replay_delivery(
+        self: &Arc<Self>,
+        delivery_id: &str,
+    ) -> Result<DeliveryRecord, ApiError> {
+        let original = self
+            .get_delivery(delivery_id)
+            .await
+            .ok_or_else(|| ApiError::NotFound(format!("Delivery {}", delivery_id)))?;
+        if original.payload.is_null() {
+            return Err(ApiError::ValidationError(format!(
+                "The payload of delivery {} is no longer available",
+                delivery_id
+            )));
+        }
+
+        let subscription = self
+            .find_subscription(&original.subscription_id)
+            .await
+            .ok_or_else(|| ApiError::NotFound(format!("Webhook {}", original.subscription_id)))?;
+
+        let record = DeliveryRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            created_at: Utc::now(),
+            url: subscription.url.clone(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            last_status_code: None,
+            last_error: None,
+            completed_at: None,
+            replay_of: Some(original.id.clone()),
+            ..original
+        };
+
+        self.record_delivery(record.clone()).await?;
+
+        let manager = Arc::clone(self);
+        let delivery_id = record.id.clone();
+        tokio::spawn(async move { manager.deliver(subscription, delivery_id).await });
+
+        Ok(record)
+    }
+
+    /// Forward bus events to matching subscriptions until the bus closes
+    pub fn spawn(self: Arc<Self>, event_bus: &EventBus) -> JoinHandle<()> {
+        let mut receiver = event_bus.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        self.dispatch(&event).await;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Webhook dispatcher lagged, skipped {} events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+
+    /// Create a delivery for every subscription matching the event and send them
+    pub async fn dispatch(self: &Arc<Self>, event: &MailboxEvent) -> Vec<JoinHandle<()>> {
+        let matching: Vec<WebhookSubscription> = self
+            .subscriptions
+            .read()
+            .await
+            .iter()
+            .filter(|s| s.matches(event))
+            .cloned()
+            .collect();
+
+        let mut handles = Vec::new();
+        if matching.is_empty() {
+            return handles;
+        }
+
+        let payload = match serde_json::to_value(event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!("Failed to serialize event #{}: {}", event.id, e);
+                return handles;
+            }
+        };
+        let payload_sha256 = hex::encode(Sha256::digest(payload.to_string().as_bytes()));
+
+        for subscription in matching {
+            let record = DeliveryRecord {
+                id: uuid::Uuid::new_v4().to_string(),
+                subscription_id: subscription.id.clone(),
+                event_id: event.id,
+                event_type: event.payload.event_type(),
+                url: subscription.url.clone(),
+                payload: payload.clone(),
+                payload_sha256: payload_sha256.clone(),
+                status: DeliveryStatus::Pending,
+                attempts: 0,
+                last_status_code: None,
+                last_error: None,
+                created_at: Utc::now(),
+                completed_at: None,
+                replay_of: None,
+            };
+
+            if let Err(e) = self.record_delivery(record.clone()).await {
+                tracing::error!("Failed to record webhook delivery: {}", e);
+            }
+
+            let manager = Arc::clone(self);
+            handles.push(tokio::spawn(async move {
+                manager.deliver(subscription, record.id).await
+            }));
+        }
+
+        handles
+    }
+
+    async fn find_subscription(&self, id: &str) -> Option<WebhookSubscription> {
+        self.subscriptions
+            .read()
+            .await
+            .iter()
+            .find(|s| s.id == id)
+            .cloned()
+    }
+
+    async fn record_delivery(&self, record: DeliveryRecord) -> Result<(), ApiError> {
+        {
+            let mut deliveries = self.deliveries.write().await;
+            while deliveries.len() >= self.config.delivery_log_capacity.max(1) {
+                deliveries.pop_front();
+            }
+            deliveries.push_back(record);
+        }
+        self.persist_deliveries().await
+    }
+
+    async fn update_delivery(&self, delivery_id: &str, update: impl FnOnce(&mut DeliveryRecord)) {
+        {
+            let mut deliveries = self.deliveries.write().await;
+            if let Some(record) = deliveries.iter_mut().find(|d| d.id == delivery_id) {
+                update(record);
+            }
+        }
+        if let Err(e) = self.persist_deliveries().await {
+            tracing::error!("Failed to persist webhook delivery log: {}", e);
+        }
+    }
+
+    /// Write a snapshot of the delivery log off the async runtime
+    async fn persist_deliveries(&self) -> Result<(), ApiError> {
+        let _write = self.deliveries_write.lock().await;
+        let snapshot = self.deliveries.read().await.clone();
+        let store = Arc::clone(&self.deliveries_store);
+
+        tokio::task::spawn_blocking(move || store.save(&snapshot))
+            .await
+            .map_err(|e| ApiError::InternalError(format!("Delivery log write failed: {}", e)))?
+    }
+
+    /// POST the payload, retrying with exponential backoff until it succeeds
+    /// or the attempts run out
+    async fn deliver(&self, subscription: WebhookSubscription, delivery_id: String) {
+        let Some(record) = self.get_delivery(&delivery_id).await else {
+            return;
+        };
+
+        let body = match serde_json::to_vec(&record.payload) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to serialize webhook payload: {}", e);
+                return;
+            }
+        };
+
+        let max_attempts = self.config.max_attempts.max(1);
+        let mut backoff = Duration::from_millis(self.config.initial_backoff_ms);
+
+        for attempt in 1..=max_attempts {
+            let timestamp = Utc::now().timestamp();
+            let signature = sign_payload(&subscription.secret, timestamp, &body);
+
+            let result = self
+                .client
+                .post(&subscription.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, signature)
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(EVENT_HEADER, record.event_type.as_str())
+                .header(DELIVERY_HEADER, &delivery_id)
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (status_code, error) = match result {
+                Ok(response) if response.status().is_success() => {
+                    let code = response.status().as_u16();
+                    self.update_delivery(&delivery_id, |d| {
+                        d.attempts = attempt;
+                        d.status = DeliveryStatus::Succeeded;
+                        d.last_status_code = Some(code);
+                        d.last_error = None;
+                        d.completed_at = Some(Utc::now());
+                    })
+                    .await;
+                    tracing::info!(
+                        "Delivered webhook {} to {} (attempt {})",
+                        delivery_id,
+                        subscription.url,
+                        attempt
+                    );
+                    return;
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    format!("Endpoint responded with {}", response.status()),
+                ),
+                Err(e) => (None, format!("Request failed: {}", e)),
+            };
+
+            let exhausted = attempt == max_attempts;
+            self.update_delivery(&delivery_id, |d| {
+                d.attempts = attempt;
+                d.last_status_code = status_code;
+                d.last_error = Some(error.clone());
+                if exhausted {
+                    d.status = DeliveryStatus::Failed;
+                    d.completed_at = Some(Utc::now());
+                }
+            })
+            .await;
+
+            if exhausted {
+                tracing::error!(
+                    "Webhook {} to {} failed after {} attempts: {}",
+                    delivery_id,
+                    subscription.url,
+                    attempt,
+                    error
+                );
+                return;
+            }
+
+            tracing::warn!(
+                "Webhook {} attempt {} failed: {}. Retrying in {:?}",
+                delivery_id,
+                attempt,
+                error,
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
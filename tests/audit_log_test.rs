@@ -0,0 +1,116 @@
+mod common;
+
+use actix_web::{web, App};
+use chrono::Utc;
+use common::TempDir;
+use email_manager::handlers::admin as admin_handlers;
+use email_manager::services::audit_log::{
+    AuditAction, AuditActor, AuditLog, AuditMessage, AuditOutcome, AuditQuery, AuditRecord,
+};
+use std::sync::Arc;
+
+fn actor(token: &str) -> AuditActor {
+    AuditActor {
+        token: Some(token.to_string()),
+        client_ip: Some("10.0.0.7".to_string()),
+    }
+}
+
+#[actix_rt::test]
+async fn test_records_are_appended_and_queried() {
+    let data_dir = TempDir::new();
+    let audit_log = AuditLog::new(&data_dir).unwrap();
+
+    audit_log
+        .record(
+            AuditRecord::new(actor("reader"), AuditAction::MarkRead)
+                .with_messages(vec![AuditMessage::new("1", None, true)]),
+        )
+        .await;
+    let since = Utc::now();
+    audit_log
+        .record(
+            AuditRecord::new(actor("cleanup-bot"), AuditAction::BulkDelete).with_messages(vec![
+                AuditMessage::new("1", None, true),
+                AuditMessage::new("2", None, false),
+            ]),
+        )
+        .await;
+
+    let lines = std::fs::read_to_string(data_dir.join("audit.jsonl")).unwrap();
+    assert_eq!(lines.lines().count(), 2);
+
+    // Who deleted email 1?
+    let records = audit_log
+        .query(&AuditQuery {
+            action: Some(AuditAction::BulkDelete),
+            email_id: Some("1".to_string()),
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].actor.token.as_deref(), Some("cleanup-bot"));
+    assert_eq!(records[0].actor.client_ip.as_deref(), Some("10.0.0.7"));
+    assert_eq!(records[0].outcome, AuditOutcome::PartiallyFailed);
+
+    let records = audit_log
+        .query(&AuditQuery {
+            since: Some(since),
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+
+    // Reopening the log keeps earlier records, newest first
+    let reopened = AuditLog::new(&data_dir).unwrap();
+    let records = reopened
+        .query(&AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(records[0].action, AuditAction::BulkDelete);
+    assert_eq!(records[1].action, AuditAction::MarkRead);
+}
+
+#[actix_rt::test]
+async fn test_audit_endpoint() {
+    let data_dir = TempDir::new();
+    let audit_log = Arc::new(AuditLog::new(&data_dir).unwrap());
+    audit_log
+        .record(
+            AuditRecord::new(actor("admin"), AuditAction::Delete)
+                .with_messages(vec![AuditMessage::new("42", None, true)]),
+        )
+        .await;
+    audit_log
+        .record(AuditRecord::new(actor("admin"), AuditAction::TokenCreate).with_target("t1"))
+        .await;
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(audit_log))
+            .route("/admin/audit", web::get().to(admin_handlers::get_audit_log)),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/admin/audit?action=delete&since=0")
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["records"][0]["messages"][0]["email_id"], "42");
+    assert_eq!(body["records"][0]["token"], "admin");
+    assert_eq!(body["records"][0]["outcome"], "succeeded");
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/admin/audit?since=yesterday")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
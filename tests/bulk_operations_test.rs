@@ -0,0 +1,143 @@
+mod common;
+
+use actix_web::{web, App};
+use chrono::NaiveDate;
+use common::TempDir;
+use email_manager::config::JobsConfig;
+use email_manager::handlers::emails as email_handlers;
+use email_manager::middleware::auth::{ApiToken, ApiTokenAuth, RequireScope, Scope};
+use email_manager::models::{BulkAction, BulkRequest, BulkSelector};
+use email_manager::services::audit_log::AuditLog;
+use email_manager::services::imap_service::{
+    date_range_query, imap_search_query, sequence_set, ImapService,
+};
+use email_manager::services::job_queue::JobQueue;
+use email_manager::services::undo_store::UndoStore;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[test]
+fn test_sequence_set() {
+    assert_eq!(sequence_set(&[7, 1, 2, 3, 9, 10, 3]), "1:3,7,9:10");
+    assert_eq!(sequence_set(&[42]), "42");
+    assert_eq!(sequence_set(&[]), "");
+}
+
+#[test]
+fn test_selector_criteria() {
+    assert_eq!(imap_search_query("from:alice"), "FROM \"alice\"");
+    assert_eq!(imap_search_query("invoice"), "TEXT \"invoice\"");
+
+    let date = |d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok();
+    assert_eq!(
+        date_range_query(date("2024-01-05"), date("2024-02-01")).unwrap(),
+        "SINCE 05-Jan-2024 BEFORE 01-Feb-2024"
+    );
+    assert_eq!(
+        date_range_query(None, date("2024-02-01")).unwrap(),
+        "BEFORE 01-Feb-2024"
+    );
+    assert!(date_range_query(None, None).is_err());
+    assert!(date_range_query(date("2024-02-01"), date("2024-01-05")).is_err());
+}
+
+#[test]
+fn test_bulk_request_format() {
+    let request: BulkRequest = serde_json::from_value(serde_json::json!({
+        "action": "move",
+        "folder": "Archive",
+        "selector": {"date_range": {"before": "2024-01-01"}}
+    }))
+    .unwrap();
+    assert_eq!(request.action, BulkAction::Move);
+    assert!(matches!(
+        request.selector,
+        BulkSelector::DateRange {
+            since: None,
+            before: Some(_)
+        }
+    ));
+    assert!(!request.dry_run);
+    assert_eq!(request.limit, 500);
+
+    let request: BulkRequest = serde_json::from_value(serde_json::json!({
+        "action": "mark_read",
+        "selector": {"query": "from:alerts@example.com"},
+        "dry_run": true
+    }))
+    .unwrap();
+    assert!(matches!(request.selector, BulkSelector::Query(_)));
+    assert!(request.dry_run);
+}
+
+#[actix_rt::test]
+async fn test_bulk_endpoint_validation() {
+    let data_dir = TempDir::new();
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+
+    let job_queue = Arc::new(
+        JobQueue::new(
+            email_service.clone(),
+            Arc::new(UndoStore::new(&data_dir, 600).unwrap()),
+            Arc::new(AuditLog::new(&data_dir).unwrap()),
+            JobsConfig::default(),
+            &data_dir,
+        )
+        .unwrap(),
+    );
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(job_queue))
+            .wrap(ApiTokenAuth::with_tokens(vec![
+                ApiToken::new("reader", "read-token", vec![Scope::EmailsRead]),
+                ApiToken::new(
+                    "writer",
+                    "write-token",
+                    vec![Scope::EmailsRead, Scope::EmailsWrite, Scope::EmailsDelete],
+                ),
+            ]))
+            .route(
+                "/emails/bulk",
+                web::post()
+                    .to(email_handlers::bulk_operation)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            ),
+    )
+    .await;
+
+    let call = |token: &str, body: serde_json::Value| {
+        actix_web::test::TestRequest::post()
+            .uri("/emails/bulk")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(body)
+            .to_request()
+    };
+
+    // Changing messages needs more than the read scope of the route
+    let body = serde_json::json!({"action": "flag", "selector": {"ids": ["1"]}});
+    let resp = actix_web::test::call_service(&app, call("read-token", body)).await;
+    assert_eq!(resp.status(), 403);
+
+    let body =
+        serde_json::json!({"action": "delete", "selector": {"ids": ["1"]}, "permanent": true});
+    let resp = actix_web::test::call_service(&app, call("write-token", body)).await;
+    assert_eq!(resp.status(), 403);
+
+    let body = serde_json::json!({"action": "move", "selector": {"ids": ["1"]}});
+    let resp = actix_web::test::call_service(&app, call("write-token", body)).await;
+    assert_eq!(resp.status(), 400);
+
+    let body = serde_json::json!({"action": "flag", "selector": {"ids": []}});
+    let resp = actix_web::test::call_service(&app, call("write-token", body)).await;
+    assert_eq!(resp.status(), 400);
+
+    let body =
+        serde_json::json!({"action": "mark_read", "selector": {"date_range": {}}, "dry_run": true});
+    let resp = actix_web::test::call_service(&app, call("read-token", body)).await;
+    assert_eq!(resp.status(), 400);
+}
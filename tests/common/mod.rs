@@ -0,0 +1,25 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A fresh data directory under the system temp dir, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("email-manager-test-{}", uuid::Uuid::new_v4())))
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
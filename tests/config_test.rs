@@ -0,0 +1,31 @@
+use email_manager::config::{CleanupAction, Settings};
+
+// The only test in this binary, so the environment is not shared
+#[test]
+fn test_settings_from_env() {
+    std::env::set_var("GMAIL_EMAIL", "me@gmail.com");
+    std::env::set_var("APP_SERVER__PORT", "9090");
+    std::env::set_var("APP_AUTH__INSECURE_DEV", "true");
+    std::env::set_var("APP_TRASH__FOLDER", "Bin");
+    std::env::set_var("APP_MFA__REGISTRY_PATH", "/etc/mfa.toml");
+    std::env::set_var("APP_MFA__CLEANUP__ENABLED", "true");
+    std::env::set_var("APP_MFA__CLEANUP__ACTION", "move");
+    std::env::set_var("APP_TOTP__VAULT_KEY", "passphrase");
+
+    let settings = Settings::from_env().unwrap();
+    assert_eq!(settings.server.host, "127.0.0.1");
+    assert_eq!(settings.server.port, 9090);
+    assert_eq!(settings.email.email_address, "me@gmail.com");
+    assert!(settings.auth.insecure_dev);
+    assert_eq!(settings.trash.folder, "Bin");
+    assert_eq!(settings.mfa.registry_path, "/etc/mfa.toml");
+    assert!(settings.mfa.cleanup.enabled);
+    assert_eq!(settings.mfa.cleanup.action, CleanupAction::Move);
+    // Unset keys keep their defaults
+    assert_eq!(settings.mfa.cleanup.scan_limit, 50);
+    assert_eq!(settings.totp.vault_key, "passphrase");
+
+    // An invalid value is an error rather than the defaults
+    std::env::set_var("APP_SERVER__PORT", "not-a-port");
+    assert!(Settings::from_env().is_err());
+}
@@ -0,0 +1,148 @@
+use actix_web::{body::MessageBody, web, App};
+use chrono::Utc;
+use email_manager::handlers::events as event_handlers;
+use email_manager::models::{EmailCategory, EmailSummary};
+use email_manager::services::event_bus::{EventBus, EventFilter, EventPayload};
+use email_manager::services::mfa_extractor::MfaExtractor;
+use std::sync::Arc;
+
+fn sample_email(id: &str, score: u8, category: EmailCategory) -> EmailSummary {
+    EmailSummary {
+        id: id.to_string(),
+        uid: None,
+        subject: "Quarterly report".to_string(),
+        sender: "Alice".to_string(),
+        sender_email: "alice@work.com".to_string(),
+        date: Utc::now(),
+        snippet: "Please find attached".to_string(),
+        body: None,
+        html_body: None,
+        is_read: false,
+        flags: Vec::new(),
+        labels: vec!["INBOX".to_string()],
+        importance_score: score,
+        category,
+        totp_secrets: Vec::new(),
+        headers: Vec::new(),
+    }
+}
+
+#[test]
+fn test_event_log_is_bounded_and_resumable() {
+    let bus = EventBus::new(3);
+
+    for i in 1..=5 {
+        bus.publish(EventPayload::Deleted {
+            email_id: i.to_string(),
+        });
+    }
+
+    assert_eq!(bus.latest_id(), 5);
+
+    // Only the last three events are retained
+    let all: Vec<u64> = bus.events_since(0).iter().map(|e| e.id).collect();
+    assert_eq!(all, vec![3, 4, 5]);
+
+    let missed: Vec<u64> = bus.events_since(4).iter().map(|e| e.id).collect();
+    assert_eq!(missed, vec![5]);
+}
+
+#[test]
+fn test_event_filter_matches() {
+    let bus = EventBus::new(10);
+    let important = bus.publish(EventPayload::NewEmail(sample_email(
+        "1",
+        3,
+        EmailCategory::Personal,
+    )));
+    let newsletter = bus.publish(EventPayload::NewEmail(sample_email(
+        "2",
+        1,
+        EmailCategory::Newsletter,
+    )));
+    let deleted = bus.publish(EventPayload::Deleted {
+        email_id: "2".to_string(),
+    });
+
+    let filter = EventFilter {
+        min_score: Some(2),
+        ..Default::default()
+    };
+    assert!(filter.matches(&important));
+    assert!(!filter.matches(&newsletter));
+    assert!(filter.matches(&deleted), "id-only events always pass");
+
+    let filter = EventFilter {
+        category: Some(EmailCategory::Newsletter),
+        ..Default::default()
+    };
+    assert!(!filter.matches(&important));
+    assert!(filter.matches(&newsletter));
+}
+
+#[test]
+fn test_event_filter_by_mfa_service() {
+    let bus = EventBus::new(10);
+    let codes = MfaExtractor::extract_codes(
+        "42",
+        Some("Sign in to GitHub"),
+        Some("noreply@github.com"),
+        Some("Your verification code is 123456"),
+        Utc::now(),
+    );
+    let event = bus.publish(EventPayload::MfaCodeDetected(codes[0].clone()));
+
+    let github = EventFilter {
+        service: Some("github".to_string()),
+        ..Default::default()
+    };
+    let google = EventFilter {
+        service: Some("google".to_string()),
+        ..Default::default()
+    };
+    assert!(github.matches(&event));
+    assert!(!google.matches(&event));
+}
+
+#[actix_rt::test]
+async fn test_sse_stream_replays_from_last_event_id() {
+    let bus = Arc::new(EventBus::new(10));
+    bus.publish(EventPayload::Deleted {
+        email_id: "1".to_string(),
+    });
+    bus.publish(EventPayload::NewEmail(sample_email(
+        "2",
+        2,
+        EmailCategory::Personal,
+    )));
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(bus.clone()))
+            .route("/events", web::get().to(event_handlers::stream_events)),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/events")
+        .insert_header(("Last-Event-ID", "1"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+
+    let body = resp.into_body();
+    let mut body = std::pin::pin!(body);
+    let chunk = futures::future::poll_fn(|cx| body.as_mut().poll_next(cx))
+        .await
+        .expect("stream should yield the missed event")
+        .unwrap();
+    let text = String::from_utf8(chunk.to_vec()).unwrap();
+
+    assert!(text.starts_with("id: 2\nevent: new_email\n"), "{}", text);
+    assert!(text.contains("\"id\":\"2\""));
+}
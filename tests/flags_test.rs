@@ -0,0 +1,115 @@
+mod common;
+
+use actix_web::{web, App};
+use common::TempDir;
+use email_manager::handlers::emails as email_handlers;
+use email_manager::services::audit_log::{AuditAction, AuditLog, AuditOutcome, AuditQuery};
+use email_manager::services::imap_service::{
+    imap_search_query, normalize_flags, unsupported_flags, ImapService,
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+#[test]
+fn test_normalize_flags() {
+    assert_eq!(
+        normalize_flags(&strings(&["\\flagged", "$Todo", "$Processed"])).unwrap(),
+        strings(&["\\Flagged", "$Todo", "$Processed"])
+    );
+    // \Deleted goes through the delete endpoints, \Recent cannot be set
+    assert!(normalize_flags(&strings(&["\\Deleted"])).is_err());
+    assert!(normalize_flags(&strings(&["\\Recent"])).is_err());
+    assert!(normalize_flags(&strings(&["two words"])).is_err());
+    assert!(normalize_flags(&strings(&["$Todo)"])).is_err());
+    assert!(normalize_flags(&strings(&[""])).is_err());
+}
+
+#[test]
+fn test_unsupported_flags() {
+    let flags = strings(&["\\Flagged", "$Todo"]);
+
+    let permanent = strings(&["\\Seen", "\\Flagged", "\\*"]);
+    assert!(unsupported_flags(&flags, &permanent).is_empty());
+
+    let permanent = strings(&["\\Seen", "\\Flagged", "$todo"]);
+    assert!(unsupported_flags(&flags, &permanent).is_empty());
+
+    let permanent = strings(&["\\Seen"]);
+    assert_eq!(
+        unsupported_flags(&flags, &permanent),
+        vec!["\\Flagged", "$Todo"]
+    );
+
+    // Without PERMANENTFLAGS every flag is permanent
+    assert!(unsupported_flags(&flags, &[]).is_empty());
+}
+
+#[test]
+fn test_flag_search_criteria() {
+    assert_eq!(imap_search_query("is:flagged"), "FLAGGED");
+    assert_eq!(
+        imap_search_query("from:alice keyword:$Todo -keyword:$Processed"),
+        "FROM \"alice\" KEYWORD $Todo UNKEYWORD $Processed"
+    );
+    assert_eq!(
+        imap_search_query("quarterly report is:unread"),
+        "TEXT \"quarterly report\" UNSEEN"
+    );
+    // Queries without flag words are unchanged
+    assert_eq!(
+        imap_search_query("subject:Hello World"),
+        "SUBJECT \"Hello World\""
+    );
+}
+
+#[actix_rt::test]
+async fn test_flags_endpoint_rejects_invalid_flags() {
+    let data_dir = TempDir::new();
+    let audit_log = Arc::new(AuditLog::new(&data_dir).unwrap());
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(audit_log.clone()))
+            .route(
+                "/emails/{id}/flags",
+                web::post().to(email_handlers::modify_flags),
+            ),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/42/flags")
+        .set_json(serde_json::json!({}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/42/flags")
+        .set_json(serde_json::json!({"add": ["$Todo", "\\Deleted"]}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let records = audit_log
+        .query(&AuditQuery {
+            action: Some(AuditAction::Flags),
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(records
+        .iter()
+        .all(|record| record.outcome == AuditOutcome::Failed));
+}
@@ -5,15 +5,21 @@ async fn test_email_parsing() {
     // Test email model parsing and serialization
     let email = EmailSummary {
         id: "test-id".to_string(),
+        uid: None,
         subject: "Test Subject".to_string(),
         sender: "Test Sender".to_string(),
         sender_email: "sender@example.com".to_string(),
         snippet: "This is a test email snippet".to_string(),
         body: Some("This is the full body of the test email".to_string()),
+        html_body: None,
         date: chrono::Utc::now(),
         labels: vec!["INBOX".to_string()],
         is_read: false,
+        flags: Vec::new(),
         importance_score: 5,
+        category: Default::default(),
+        totp_secrets: Vec::new(),
+        headers: Vec::new(),
     };
 
     // Test serialization
@@ -0,0 +1,97 @@
+use email_manager::services::html_text::html_to_text;
+use email_manager::services::imap_service::message_bodies;
+
+#[test]
+fn test_html_to_text_skips_style_script_and_hidden_elements() {
+    let html = r#"<html><head><title>Code</title><style>.code { color: red; }</style></head>
+        <body>
+            <div style="display: none; max-height: 0px">Preheader 999999</div>
+            <script>var code = "111111";</script>
+            <p>Hello&nbsp;there,</p>
+            <p>Your code is <span aria-hidden="true">000000</span>below.</p>
+        </body></html>"#;
+
+    let rendered = html_to_text(html);
+
+    assert_eq!(rendered.text, "Hello there,\nYour code is below.");
+    assert!(rendered.emphasized.is_empty());
+}
+
+#[test]
+fn test_html_to_text_collects_emphasized_text() {
+    let html = r#"<table><tr>
+        <td>Use this code:</td>
+        <td>482 913</td>
+        </tr></table>
+        <p><strong>Do not share it</strong></p>
+        <div style="font-size: 32px; letter-spacing: 4px">XK7P2Q</div>"#;
+
+    let rendered = html_to_text(html);
+
+    assert!(rendered.emphasized.contains(&"482 913".to_string()));
+    assert!(rendered.emphasized.contains(&"Do not share it".to_string()));
+    assert!(rendered.emphasized.contains(&"XK7P2Q".to_string()));
+    assert!(rendered.text.contains("Use this code: 482 913"));
+}
+
+#[test]
+fn test_html_to_text_reads_style_attribute_by_name() {
+    let html = r#"<p class="stylebox" data-style="display:none">Visible text</p>
+        <p><span data-style="font-size: 40px">Plain</span> words</p>
+        <p data-style="font-size: 40px" style='display: none'>Hidden text</p>"#;
+
+    let rendered = html_to_text(html);
+
+    assert_eq!(rendered.text, "Visible text\nPlain words");
+    assert!(rendered.emphasized.is_empty());
+}
+
+#[test]
+fn test_html_to_text_decodes_entities_once() {
+    let rendered =
+        html_to_text("<p>Write &amp;lt;b&amp;gt; for &lt;b&gt; &#38;amp; caf&eacute;&#x21;</p>");
+
+    assert_eq!(rendered.text, "Write &lt;b&gt; for <b> &amp; café!");
+}
+
+#[test]
+fn test_message_bodies_reads_multipart_alternative() {
+    let raw = concat!(
+        "From: GitHub <noreply@github.com>\r\n",
+        "Subject: Your code\r\n",
+        "Content-Type: multipart/alternative; boundary=\"b1\"\r\n",
+        "\r\n",
+        "--b1\r\n",
+        "Content-Type: text/plain; charset=utf-8\r\n",
+        "\r\n",
+        "Your code is 123456\r\n",
+        "--b1\r\n",
+        "Content-Type: text/html; charset=utf-8\r\n",
+        "\r\n",
+        "<p>Your code is <b>123456</b></p>\r\n",
+        "--b1--\r\n",
+    );
+    let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+
+    let bodies = message_bodies(&parsed);
+
+    assert_eq!(bodies.text.trim(), "Your code is 123456");
+    assert!(bodies.html.unwrap().contains("<b>123456</b>"));
+}
+
+#[test]
+fn test_message_bodies_renders_html_only_email() {
+    let raw = concat!(
+        "From: Example <security@example.com>\r\n",
+        "Subject: Verify\r\n",
+        "Content-Type: text/html; charset=utf-8\r\n",
+        "\r\n",
+        "<html><head><style>p{}</style></head><body><p>Your verification code</p><h2>654321</h2></body></html>\r\n",
+    );
+    let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+
+    let bodies = message_bodies(&parsed);
+
+    assert_eq!(bodies.text, "Your verification code\n654321");
+    assert!(bodies.html.is_some());
+}
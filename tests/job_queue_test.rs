@@ -0,0 +1,160 @@
+mod common;
+
+use actix_web::{web, App};
+use common::TempDir;
+use email_manager::config::JobsConfig;
+use email_manager::handlers::emails as email_handlers;
+use email_manager::handlers::jobs as job_handlers;
+use email_manager::middleware::auth::{ApiToken, ApiTokenAuth, RequireScope, Scope};
+use email_manager::services::audit_log::AuditLog;
+use email_manager::services::imap_service::ImapService;
+use email_manager::services::job_queue::{JobQueue, JobStatus};
+use email_manager::services::undo_store::UndoStore;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn email_service() -> Arc<Mutex<ImapService>> {
+    Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )))
+}
+
+fn job_queue(data_dir: &Path) -> Arc<JobQueue> {
+    Arc::new(
+        JobQueue::new(
+            email_service(),
+            Arc::new(UndoStore::new(data_dir, 600).unwrap()),
+            Arc::new(AuditLog::new(data_dir).unwrap()),
+            JobsConfig::default(),
+            data_dir,
+        )
+        .unwrap(),
+    )
+}
+
+#[actix_rt::test]
+async fn test_bulk_request_is_queued_and_cancelled() {
+    let data_dir = TempDir::new();
+    // No worker is spawned, so the job stays queued until it is cancelled
+    let job_queue = job_queue(&data_dir);
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service()))
+            .app_data(web::Data::new(job_queue.clone()))
+            .wrap(ApiTokenAuth::with_tokens(vec![ApiToken::new(
+                "writer",
+                "write-token",
+                vec![Scope::EmailsRead, Scope::EmailsWrite],
+            )]))
+            .route(
+                "/emails/bulk",
+                web::post()
+                    .to(email_handlers::bulk_operation)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/jobs",
+                web::get()
+                    .to(job_handlers::list_jobs)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/jobs/{id}",
+                web::get()
+                    .to(job_handlers::get_job)
+                    .wrap(RequireScope::new(Scope::EmailsRead)),
+            )
+            .route(
+                "/jobs/{id}",
+                web::delete()
+                    .to(job_handlers::cancel_job)
+                    .wrap(RequireScope::new(Scope::EmailsWrite)),
+            ),
+    )
+    .await;
+
+    let call = |req: actix_web::test::TestRequest| {
+        req.insert_header(("Authorization", "Bearer write-token"))
+            .to_request()
+    };
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/bulk")
+        .set_json(serde_json::json!({
+            "action": "mark_read",
+            "selector": {"query": "from:newsletter@example.com"},
+            "limit": 10000
+        }));
+    let resp = actix_web::test::call_service(&app, call(req)).await;
+    assert_eq!(resp.status(), 202);
+    let job: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    let id = job["id"].as_str().unwrap().to_string();
+    assert_eq!(job["status"], "queued");
+    assert_eq!(job["action"], "mark_read");
+    assert!(job["total"].is_null());
+
+    let uri = format!("/jobs/{}", id);
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(
+        &app,
+        call(actix_web::test::TestRequest::get().uri(&uri)),
+    )
+    .await;
+    assert_eq!(body["processed"], 0);
+
+    let resp =
+        actix_web::test::call_service(&app, call(actix_web::test::TestRequest::delete().uri(&uri)))
+            .await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["status"], "cancelled");
+    assert!(body["finished_at"].is_string());
+
+    // Finished jobs cannot be cancelled again
+    let resp =
+        actix_web::test::call_service(&app, call(actix_web::test::TestRequest::delete().uri(&uri)))
+            .await;
+    assert_eq!(resp.status(), 400);
+
+    let resp = actix_web::test::call_service(
+        &app,
+        call(actix_web::test::TestRequest::get().uri("/jobs/unknown")),
+    )
+    .await;
+    assert_eq!(resp.status(), 404);
+
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(
+        &app,
+        call(actix_web::test::TestRequest::get().uri("/jobs")),
+    )
+    .await;
+    assert_eq!(body["count"], 1);
+}
+
+#[actix_rt::test]
+async fn test_jobs_survive_restart() {
+    let data_dir = TempDir::new();
+    let queue = job_queue(&data_dir);
+    let request = serde_json::from_value(serde_json::json!({
+        "action": "move",
+        "folder": "Archive",
+        "selector": {"date_range": {"before": "2024-01-01"}}
+    }))
+    .unwrap();
+    let job = queue.enqueue(request, Default::default()).await.unwrap();
+
+    let reloaded = job_queue(&data_dir);
+    let restored = reloaded.get(&job.id).await.unwrap();
+    assert_eq!(restored.status, JobStatus::Queued);
+    assert_eq!(restored.created_at, job.created_at);
+
+    // Requests that could never run are rejected up front
+    let request = serde_json::from_value(serde_json::json!({
+        "action": "label",
+        "selector": {"ids": ["1"]}
+    }))
+    .unwrap();
+    assert!(reloaded.enqueue(request, Default::default()).await.is_err());
+}
@@ -0,0 +1,146 @@
+use actix_web::{web, App, HttpResponse};
+use email_manager::config::JwtConfig;
+use email_manager::middleware::auth::{ApiTokenAuth, RequireScope, Scope};
+use email_manager::middleware::jwt::JwtValidator;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use std::sync::Arc;
+
+const ACCOUNT: &str = "me@example.com";
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn config() -> JwtConfig {
+    JwtConfig {
+        jwks_path: Some(format!("{}/jwt_jwks.json", FIXTURES)),
+        issuer: Some("https://sso.example.com".to_string()),
+        audience: Some("email-manager".to_string()),
+        ..JwtConfig::default()
+    }
+}
+
+/// A JWT signed with the fixture key, valid for an hour unless `claims`
+/// overrides `exp`
+fn sign(kid: Option<&str>, claims: serde_json::Value) -> String {
+    let mut payload = serde_json::json!({
+        "sub": "svc-otp",
+        "iss": "https://sso.example.com",
+        "aud": "email-manager",
+        "exp": chrono::Utc::now().timestamp() + 3600,
+    });
+    for (key, value) in claims.as_object().unwrap() {
+        payload[key] = value.clone();
+    }
+
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = kid.map(String::from);
+    let pem = std::fs::read(format!("{}/jwt_ec_private.pem", FIXTURES)).unwrap();
+    encode(&header, &payload, &EncodingKey::from_ec_pem(&pem).unwrap()).unwrap()
+}
+
+#[test]
+fn test_claims_map_to_scopes() {
+    let validator = JwtValidator::new(&config(), ACCOUNT).unwrap();
+
+    let context = validator
+        .validate(&sign(
+            Some("test-key"),
+            serde_json::json!({"scope": "openid mfa:read emails:read"}),
+        ))
+        .unwrap();
+    assert_eq!(context.token_name, "svc-otp");
+    assert_eq!(context.scopes, vec![Scope::MfaRead, Scope::EmailsRead]);
+
+    // Scopes as an array, and an accounts claim listing this mailbox
+    let context = validator
+        .validate(&sign(
+            None,
+            serde_json::json!({"scope": ["admin"], "accounts": ["ME@example.com"]}),
+        ))
+        .unwrap();
+    assert!(context.has_scope(Scope::EmailsDelete));
+}
+
+#[test]
+fn test_rejected_tokens() {
+    let validator = JwtValidator::new(&config(), ACCOUNT).unwrap();
+    let rejected = |claims: serde_json::Value| validator.validate(&sign(None, claims)).is_err();
+
+    assert!(rejected(
+        serde_json::json!({"accounts": ["other@example.com"]})
+    ));
+    assert!(rejected(serde_json::json!({"exp": 1_000_000_000})));
+    assert!(rejected(
+        serde_json::json!({"iss": "https://evil.example.com"})
+    ));
+    assert!(rejected(serde_json::json!({"aud": "another-api"})));
+    assert!(validator
+        .validate(&sign(Some("unknown-key"), serde_json::json!({})))
+        .is_err());
+
+    // HS256 tokens are refused even when signed with the public key
+    let public_pem = std::fs::read(format!("{}/jwt_ec_public.pem", FIXTURES)).unwrap();
+    let forged = encode(
+        &Header::new(Algorithm::HS256),
+        &serde_json::json!({"sub": "x", "exp": chrono::Utc::now().timestamp() + 60}),
+        &EncodingKey::from_secret(&public_pem),
+    )
+    .unwrap();
+    assert!(validator.validate(&forged).is_err());
+}
+
+#[test]
+fn test_pem_public_key() {
+    let config = JwtConfig {
+        jwks_path: None,
+        public_key_paths: vec![format!("{}/jwt_ec_public.pem", FIXTURES)],
+        ..config()
+    };
+    let validator = JwtValidator::new(&config, ACCOUNT).unwrap();
+    assert!(validator
+        .validate(&sign(None, serde_json::json!({})))
+        .is_ok());
+
+    assert!(JwtValidator::new(&JwtConfig::default(), ACCOUNT).is_err());
+    // Keys alone are not enough: tokens for other applications must be refused
+    let config = JwtConfig {
+        audience: None,
+        ..config
+    };
+    assert!(JwtValidator::new(&config, ACCOUNT).is_err());
+}
+
+#[actix_rt::test]
+async fn test_jwt_bearer_authentication() {
+    let validator = Arc::new(JwtValidator::new(&config(), ACCOUNT).unwrap());
+    let app = actix_web::test::init_service(
+        App::new()
+            .wrap(ApiTokenAuth::with_tokens(Vec::new()).with_jwt(validator))
+            .route(
+                "/mfa/codes",
+                web::get()
+                    .to(HttpResponse::Ok)
+                    .wrap(RequireScope::new(Scope::MfaRead)),
+            ),
+    )
+    .await;
+
+    let call = |token: String| {
+        actix_web::test::TestRequest::get()
+            .uri("/mfa/codes")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    let token = sign(Some("test-key"), serde_json::json!({"scope": "mfa:read"}));
+    let resp = actix_web::test::call_service(&app, call(token)).await;
+    assert_eq!(resp.status(), 200);
+
+    let token = sign(
+        Some("test-key"),
+        serde_json::json!({"scope": "emails:read"}),
+    );
+    let resp = actix_web::test::call_service(&app, call(token)).await;
+    assert_eq!(resp.status(), 403);
+
+    let resp = actix_web::test::call_service(&app, call("not-a-jwt".to_string())).await;
+    assert_eq!(resp.status(), 401);
+}
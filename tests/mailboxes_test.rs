@@ -0,0 +1,105 @@
+mod common;
+
+use actix_web::{web, App};
+use common::TempDir;
+use email_manager::handlers::emails as email_handlers;
+use email_manager::handlers::mailboxes as mailbox_handlers;
+use email_manager::services::audit_log::{AuditAction, AuditLog, AuditOutcome, AuditQuery};
+use email_manager::services::imap_service::{gmail_labels, validate_mailbox_name, ImapService};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[test]
+fn test_gmail_labels() {
+    let labels =
+        |labels: &[&str]| gmail_labels(&labels.iter().map(|l| l.to_string()).collect::<Vec<_>>());
+
+    assert_eq!(
+        labels(&["\\Important", "Receipts", "Work/Q1 \"plan\""]).unwrap(),
+        "\\Important \"Receipts\" \"Work/Q1 \\\"plan\\\"\""
+    );
+    // A backslash not followed by a system label name is quoted
+    assert_eq!(labels(&["\\ odd"]).unwrap(), "\"\\\\ odd\"");
+    assert!(labels(&[""]).is_err());
+    assert!(labels(&["a\r\nb"]).is_err());
+}
+
+#[test]
+fn test_mailbox_names() {
+    assert!(validate_mailbox_name("[Gmail]/All Mail").is_ok());
+    assert!(validate_mailbox_name(" ").is_err());
+    assert!(validate_mailbox_name("a\nb").is_err());
+}
+
+#[actix_rt::test]
+async fn test_endpoints_reject_invalid_requests() {
+    let data_dir = TempDir::new();
+    let audit_log = Arc::new(AuditLog::new(&data_dir).unwrap());
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(audit_log.clone()))
+            .route(
+                "/emails/{id}/move",
+                web::post().to(email_handlers::move_email),
+            )
+            .route(
+                "/emails/{id}/labels",
+                web::post().to(email_handlers::modify_labels),
+            )
+            .route(
+                "/mailboxes/{name:.*}",
+                web::patch().to(mailbox_handlers::rename_mailbox),
+            )
+            .route(
+                "/mailboxes/{name:.*}",
+                web::delete().to(mailbox_handlers::delete_mailbox),
+            ),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/42/move")
+        .set_json(serde_json::json!({"mailbox": ""}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/42/labels")
+        .set_json(serde_json::json!({}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    // The inbox cannot be renamed or deleted
+    let req = actix_web::test::TestRequest::patch()
+        .uri("/mailboxes/Inbox")
+        .set_json(serde_json::json!({"name": "Old"}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri("/mailboxes/INBOX")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let records = audit_log
+        .query(&AuditQuery {
+            action: Some(AuditAction::MailboxDelete),
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].target.as_deref(), Some("INBOX"));
+    assert_eq!(records[0].outcome, AuditOutcome::Failed);
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::TempDir;
+use email_manager::config::{CleanupAction, MfaCleanupConfig};
+use email_manager::models::{EmailCategory, EmailSummary};
+use email_manager::services::audit_log::{AuditAction, AuditActor, AuditLog, AuditQuery};
+use email_manager::services::imap_service::ImapService;
+use email_manager::services::mfa_cleanup::{CleanupOutcome, CleanupReason, MfaCleanup};
+use email_manager::services::mfa_extractor::MfaExtractor;
+use email_manager::services::mfa_store::MfaStore;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn verification_email(id: &str, minutes_ago: i64, category: EmailCategory) -> EmailSummary {
+    EmailSummary {
+        id: id.to_string(),
+        uid: None,
+        subject: "Your verification code".to_string(),
+        sender: "GitHub".to_string(),
+        sender_email: "noreply@github.com".to_string(),
+        date: Utc::now() - Duration::minutes(minutes_ago),
+        snippet: "Your verification code is 123456".to_string(),
+        body: Some("Your verification code is 123456".to_string()),
+        html_body: None,
+        is_read: false,
+        flags: Vec::new(),
+        labels: vec!["INBOX".to_string()],
+        importance_score: 2,
+        category,
+        totp_secrets: Vec::new(),
+        headers: Vec::new(),
+    }
+}
+
+fn cleanup_job(data_dir: &Path, mfa_store: Arc<MfaStore>, action: CleanupAction) -> MfaCleanup {
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+    let config = MfaCleanupConfig {
+        retention_minutes: 30,
+        action,
+        ..MfaCleanupConfig::default()
+    };
+    let audit_log = Arc::new(AuditLog::new(data_dir).unwrap());
+    MfaCleanup::new(email_service, mfa_store, audit_log, config, data_dir).unwrap()
+}
+
+#[actix_rt::test]
+async fn test_plan_selects_old_and_consumed_verification_emails() {
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    let cleanup = cleanup_job(&data_dir, mfa_store.clone(), CleanupAction::Delete);
+
+    let consumed = verification_email("3", 1, EmailCategory::Verification);
+    mfa_store
+        .record(MfaExtractor::extract_from_email(&consumed))
+        .await
+        .unwrap();
+    mfa_store.consume("3", None).await.unwrap();
+
+    let emails = vec![
+        verification_email("1", 45, EmailCategory::Verification),
+        verification_email("2", 5, EmailCategory::Verification),
+        consumed,
+        verification_email("4", 45, EmailCategory::Personal),
+    ];
+
+    let planned = cleanup.plan(&emails, Utc::now()).await;
+    let selected: Vec<_> = planned
+        .iter()
+        .map(|p| (p.email.id.as_str(), p.reason))
+        .collect();
+
+    assert_eq!(
+        selected,
+        vec![
+            ("1", CleanupReason::Retention),
+            ("3", CleanupReason::Consumed)
+        ]
+    );
+}
+
+#[actix_rt::test]
+async fn test_consumed_code_of_a_reused_id_does_not_count() {
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    let cleanup = cleanup_job(&data_dir, mfa_store.clone(), CleanupAction::Delete);
+
+    let earlier = verification_email("3", 20, EmailCategory::Verification);
+    mfa_store
+        .record(MfaExtractor::extract_from_email(&earlier))
+        .await
+        .unwrap();
+    mfa_store.consume("3", None).await.unwrap();
+
+    // The earlier message is gone and a new one now has sequence number 3
+    let mut newer = verification_email("3", 1, EmailCategory::Verification);
+    newer.sender_email = "noreply@example.com".to_string();
+
+    assert!(cleanup.plan(&[newer], Utc::now()).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_plan_skips_link_only_emails() {
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    let cleanup = cleanup_job(&data_dir, mfa_store, CleanupAction::Delete);
+
+    let mut email = verification_email("1", 45, EmailCategory::Verification);
+    email.subject = "Verify your email address".to_string();
+    email.body = Some(
+        "Please verify your email address: https://example.com/verify?token=abcdef".to_string(),
+    );
+    email.snippet = email.body.clone().unwrap();
+
+    assert!(cleanup.plan(&[email], Utc::now()).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_mark_read_skips_read_emails() {
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    let cleanup = cleanup_job(&data_dir, mfa_store, CleanupAction::MarkRead);
+
+    let mut email = verification_email("1", 45, EmailCategory::Verification);
+    email.is_read = true;
+
+    assert!(cleanup.plan(&[email], Utc::now()).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_dry_run_is_audited_once() {
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    let cleanup = cleanup_job(&data_dir, mfa_store.clone(), CleanupAction::Move);
+
+    let mut emails = vec![
+        verification_email("1", 45, EmailCategory::Verification),
+        verification_email("2", 60, EmailCategory::Verification),
+    ];
+    emails[0].uid = Some(101);
+    emails[1].uid = Some(102);
+
+    let planned = cleanup.plan(&emails, Utc::now()).await;
+    let records = cleanup
+        .execute(planned, true, AuditActor::default())
+        .await
+        .unwrap();
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1].email_id, "2");
+    assert_eq!(records[1].uid, Some(102));
+    assert!(records.iter().all(|r| r.dry_run
+        && r.outcome == CleanupOutcome::Skipped
+        && r.target_folder.as_deref() == Some("MFA")));
+
+    // The next run does not report the same emails again
+    let planned = cleanup.plan(&emails, Utc::now()).await;
+    assert!(cleanup
+        .execute(planned, true, AuditActor::default())
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Nor after they were renumbered, since records are matched by UID
+    emails[1].id = "1".to_string();
+    let planned = cleanup.plan(&emails[1..], Utc::now()).await;
+    assert!(cleanup
+        .execute(planned, true, AuditActor::default())
+        .await
+        .unwrap()
+        .is_empty());
+
+    // The audit log survives a restart
+    let reloaded = cleanup_job(&data_dir, mfa_store, CleanupAction::Move);
+    assert_eq!(reloaded.audit_log(10).await.len(), 2);
+
+    // Dry runs change nothing and stay out of the shared audit log
+    let audit_log = AuditLog::new(&data_dir).unwrap();
+    let audited = audit_log
+        .query(&AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert!(audited.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_cleanup_needs_a_uid_to_act() {
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    let cleanup = cleanup_job(&data_dir, mfa_store, CleanupAction::Delete);
+
+    // Without a UID nothing is sent to the server and the failure is audited
+    let emails = vec![verification_email("1", 45, EmailCategory::Verification)];
+    let planned = cleanup.plan(&emails, Utc::now()).await;
+    let records = cleanup
+        .execute(planned, false, AuditActor::default())
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].outcome, CleanupOutcome::Failed);
+    assert!(records[0].error.as_deref().unwrap().contains("UID"));
+
+    // The attempt is in the shared audit log too
+    let audit_log = AuditLog::new(&data_dir).unwrap();
+    let audited = audit_log
+        .query(&AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(audited.len(), 1);
+    assert_eq!(audited[0].action, AuditAction::MfaCleanupDelete);
+    assert_eq!(audited[0].messages[0].email_id, "1");
+    assert!(!audited[0].messages[0].succeeded);
+}
@@ -188,3 +188,385 @@ fn test_multiple_services_detection() {
         );
     }
 }
+
+#[test]
+fn test_extract_magic_link_from_text() {
+    let body = "Hi,\n\nClick the link below to sign in to Slack:\nhttps://slack.com/z-app-login?token=abc123XYZ\n\nThis link expires in 24 hours.\n\nUnsubscribe: https://slack.com/unsubscribe?u=1";
+    let codes = MfaExtractor::extract_codes(
+        "test-id",
+        Some("Your Slack sign-in link"),
+        Some("no-reply@slack.com"),
+        Some(body),
+        Utc::now(),
+    );
+
+    assert_eq!(codes.len(), 1);
+    assert_eq!(
+        codes[0].code,
+        "https://slack.com/z-app-login?token=abc123XYZ"
+    );
+    assert_eq!(codes[0].service, Some("Slack".to_string()));
+    assert!(matches!(codes[0].code_type, CodeType::Url));
+}
+
+#[test]
+fn test_extract_link_ranked_by_anchor_text_in_html() {
+    let html = r#"<html><body>
+        <a href="https://www.exemplo.com.br/">Exemplo</a>
+        <p>Confirme seu e-mail para continuar.</p>
+        <a href="https://www.exemplo.com.br/conta/abc?k=1&amp;e=2" class="btn">Confirmar e-mail</a>
+        <a href="https://www.exemplo.com.br/privacidade/privacy">Privacidade</a>
+    </body></html>"#;
+    let codes = MfaExtractor::extract_codes_with_html(
+        "test-id",
+        Some("Confirme seu cadastro"),
+        Some("contato@exemplo.com.br"),
+        None,
+        Some(html),
+        Utc::now(),
+    );
+
+    assert_eq!(codes.len(), 1);
+    assert_eq!(
+        codes[0].code,
+        "https://www.exemplo.com.br/conta/abc?k=1&e=2"
+    );
+    assert!(matches!(codes[0].code_type, CodeType::Url));
+}
+
+#[test]
+fn test_extract_link_unwraps_tracking_redirect() {
+    let html = r#"<a href="https://click.mailer.example.com/ls/click?upn=xyz&amp;url=https%3A%2F%2Fapp.example.com%2Fverify-email%3Ftoken%3Dabc">Verify email</a>"#;
+    let codes = MfaExtractor::extract_codes_with_html(
+        "test-id",
+        Some("Verify your email address"),
+        Some("hello@example.com"),
+        Some("Please verify your email address."),
+        Some(html),
+        Utc::now(),
+    );
+
+    let link = codes
+        .iter()
+        .find(|c| matches!(c.code_type, CodeType::Url))
+        .expect("verification link");
+    assert_eq!(link.code, "https://app.example.com/verify-email?token=abc");
+}
+
+#[test]
+fn test_code_is_not_taken_from_url_digits() {
+    let body = "Your verification code is 482913.\nOr reset your password here: https://example.com/reset?id=777777";
+    let codes = MfaExtractor::extract_codes(
+        "test-id",
+        Some("Password reset"),
+        Some("security@example.com"),
+        Some(body),
+        Utc::now(),
+    );
+
+    assert_eq!(codes.len(), 2);
+    assert_eq!(codes[0].code, "482913");
+    assert_eq!(codes[1].code, "https://example.com/reset?id=777777");
+}
+
+#[test]
+fn test_no_link_from_unrelated_email() {
+    let html = r#"<a href="https://blog.example.com/posts/42">Read more</a>
+        <a href="https://blog.example.com/unsubscribe">Unsubscribe</a>"#;
+    let codes = MfaExtractor::extract_codes_with_html(
+        "test-id",
+        Some("This week on our blog"),
+        Some("news@example.com"),
+        Some("Here are this week's posts."),
+        Some(html),
+        Utc::now(),
+    );
+
+    assert!(codes.is_empty());
+}
+
+#[test]
+fn test_no_link_from_newsletter_login_button() {
+    let html = r#"<p>Our spring collection is here.</p>
+        <a href="https://shop.example.com/login?next=/spring">Log in</a>"#;
+    let codes = MfaExtractor::extract_codes_with_html(
+        "test-id",
+        Some("New arrivals this week"),
+        Some("news@shop.example.com"),
+        None,
+        Some(html),
+        Utc::now(),
+    );
+
+    assert!(codes.is_empty());
+}
+
+#[test]
+fn test_emphasized_html_code_wins_over_other_numbers() {
+    let text = "Your sign-in request from 2024 device 1234. Use the code below.";
+    let html = r#"<html><head><style>.x{font-size:40px}</style></head><body>
+        <p>Sign-in request from device 1234.</p>
+        <p>Use the verification code below:</p>
+        <table><tr><td style="font-size:28px;letter-spacing:6px">847 209</td></tr></table>
+        </body></html>"#;
+    let codes = MfaExtractor::extract_codes_with_html(
+        "test-id",
+        Some("Your verification code"),
+        Some("noreply@github.com"),
+        Some(text),
+        Some(html),
+        Utc::now(),
+    );
+
+    assert_eq!(codes.len(), 1);
+    assert_eq!(codes[0].code, "847209");
+    assert_eq!(codes[0].service, Some("GitHub".to_string()));
+}
+
+#[test]
+fn test_extract_code_from_html_only_email_ignores_hidden_content() {
+    let html = r#"<div style="display:none">Preview 111111</div>
+        <p>Seu código de verificação é <strong>592013</strong></p>"#;
+    let codes = MfaExtractor::extract_codes_with_html(
+        "test-id",
+        Some("Código de verificação"),
+        Some("noreply@example.com.br"),
+        None,
+        Some(html),
+        Utc::now(),
+    );
+
+    assert_eq!(codes.len(), 1);
+    assert_eq!(codes[0].code, "592013");
+}
+
+#[test]
+fn test_year_is_ranked_below_code() {
+    let body = "© 2024 Acme Inc. Your code 4821 expires in 10 minutes";
+    let codes = MfaExtractor::extract_codes(
+        "test-id",
+        Some("Sign-in attempt"),
+        Some("security@acme.com"),
+        Some(body),
+        Utc::now(),
+    );
+
+    assert_eq!(codes.len(), 1);
+    assert_eq!(codes[0].code, "4821");
+    let year = codes[0]
+        .alternatives
+        .iter()
+        .find(|c| c.code == "2024")
+        .expect("year kept as an alternative");
+    assert!(year.confidence < codes[0].confidence);
+}
+
+#[test]
+fn test_phone_number_is_not_the_code() {
+    let body = "If this wasn't you, call 555-123-4567.\nYour verification code is 918273.";
+    let codes = MfaExtractor::extract_codes(
+        "test-id",
+        Some("Verify your account"),
+        Some("noreply@bank.example"),
+        Some(body),
+        Utc::now(),
+    );
+
+    assert_eq!(codes[0].code, "918273");
+    for alternative in &codes[0].alternatives {
+        assert!(alternative.confidence <= codes[0].confidence);
+    }
+}
+
+#[test]
+fn test_confidence_is_ranked_and_filtered() {
+    let body = "Your verification code is 246810. Reference 135790.";
+    let codes = MfaExtractor::extract_codes(
+        "test-id",
+        Some("246810 is your code"),
+        Some("noreply@example.com"),
+        Some(body),
+        Utc::now(),
+    );
+
+    let best = &codes[0];
+    assert_eq!(best.code, "246810");
+    assert!(best.confidence > 0.8 && best.confidence <= 1.0);
+
+    let alternative = &best.alternatives[0];
+    assert_eq!(alternative.code, "135790");
+    assert_eq!(alternative.pattern, "standalone_6_digits");
+    assert!(!alternative.in_subject);
+    assert!(alternative.confidence < best.confidence);
+
+    let filtered = best.clone().with_min_confidence(0.5).unwrap();
+    assert!(filtered.alternatives.is_empty());
+    assert!(best.clone().with_min_confidence(1.1).is_none());
+}
+
+fn extract_one(
+    subject: &str,
+    sender: &str,
+    body: &str,
+) -> email_manager::services::mfa_extractor::MfaCode {
+    let codes = MfaExtractor::extract_codes(
+        "test-id",
+        Some(subject),
+        Some(sender),
+        Some(body),
+        Utc::now(),
+    );
+    assert_eq!(codes.len(), 1, "expected one code in {:?}", body);
+    codes.into_iter().next().unwrap()
+}
+
+#[test]
+fn test_extract_spanish_code() {
+    let code = extract_one(
+        "Tu código de seguridad",
+        "no-reply@banco.es",
+        "Hola, tu código de verificación es 482913. Gracias por usar nuestro servicio.",
+    );
+
+    assert_eq!(code.code, "482913");
+    assert_eq!(code.language.as_deref(), Some("es"));
+}
+
+#[test]
+fn test_extract_spanish_code_without_accents() {
+    let code = extract_one(
+        "Tu codigo",
+        "no-reply@banco.es",
+        "Hola, tu codigo de verificacion es 665544. Gracias.",
+    );
+
+    assert_eq!(code.code, "665544");
+    assert_eq!(code.language.as_deref(), Some("es"));
+}
+
+#[test]
+fn test_extract_french_code() {
+    let code = extract_one(
+        "Votre code de vérification",
+        "securite@banque.fr",
+        "Bonjour, votre code de vérification est : 731905. Merci de ne pas le partager.",
+    );
+
+    assert_eq!(code.code, "731905");
+    assert_eq!(code.language.as_deref(), Some("fr"));
+}
+
+#[test]
+fn test_extract_german_code() {
+    let code = extract_one(
+        "Ihr Bestätigungscode",
+        "sicherheit@bank.de",
+        "Hallo, Ihr Bestätigungscode lautet: 550214. Bitte geben Sie ihn in der App ein.",
+    );
+
+    assert_eq!(code.code, "550214");
+    assert_eq!(code.language.as_deref(), Some("de"));
+}
+
+#[test]
+fn test_extract_italian_code() {
+    let code = extract_one(
+        "Il tuo codice di verifica",
+        "sicurezza@banca.it",
+        "Ciao, il tuo codice di verifica è 918273. Grazie per non condividerlo.",
+    );
+
+    assert_eq!(code.code, "918273");
+    assert_eq!(code.language.as_deref(), Some("it"));
+}
+
+#[test]
+fn test_extract_japanese_code_with_full_width_digits() {
+    let code = extract_one(
+        "【重要】認証コードのお知らせ",
+        "info@example.jp",
+        "認証コード：１２３４５６\nこのコードは10分間有効です。",
+    );
+
+    assert_eq!(code.code, "123456");
+    assert_eq!(code.language.as_deref(), Some("ja"));
+    assert_eq!(
+        code.email_subject.as_deref(),
+        Some("【重要】認証コードのお知らせ")
+    );
+}
+
+#[test]
+fn test_extract_google_voice_sms_with_prefixed_code() {
+    let code = extract_one(
+        "New text message from (555) 123-4567",
+        "15559876543.15551234567.abcd@txt.voice.google.com",
+        "G-482913 is your Google verification code.\n\n\
+         To respond to this text message, reply to this email or visit Google Voice.\n\
+         YOUR ACCOUNT HELP CENTER HELP FORUM",
+    );
+
+    assert_eq!(code.code, "482913");
+    assert_eq!(code.service.as_deref(), Some("Google"));
+    assert_eq!(code.forwarder.as_deref(), Some("Google Voice"));
+}
+
+#[test]
+fn test_extract_carrier_gateway_sms_service_from_text() {
+    let code = extract_one(
+        "",
+        "5551234567@vtext.com",
+        "Your Uber code is 1234. Reply STOP to unsubscribe.",
+    );
+
+    assert_eq!(code.code, "1234");
+    assert_eq!(code.service.as_deref(), Some("Uber"));
+    assert_eq!(code.forwarder.as_deref(), Some("Carrier SMS gateway"));
+}
+
+#[test]
+fn test_extract_spoken_digits_from_voicemail_transcript() {
+    let code = extract_one(
+        "New voicemail from (555) 010-9999",
+        "voice-noreply@google.com",
+        "Hello, your verification code is four eight one, five two six. \
+         Again, your verification code is four eight one five two six. Goodbye.\n\n\
+         Play message\nYOUR ACCOUNT HELP CENTER",
+    );
+
+    assert_eq!(code.code, "481526");
+    assert_eq!(code.service, None);
+    assert_eq!(code.forwarder.as_deref(), Some("Google Voice"));
+}
+
+#[test]
+fn test_extract_spoken_digits_in_french() {
+    let code = extract_one(
+        "Votre code",
+        "securite@banque.fr",
+        "Bonjour, votre code de vérification est un deux trois quatre cinq six. Merci.",
+    );
+
+    assert_eq!(code.code, "123456");
+    assert_eq!(code.forwarder, None);
+}
+
+#[test]
+fn test_emphasized_candidate_has_its_rendered_position() {
+    let html = "<p>Your verification code is 246810.</p><p>Backup: <strong>135 790</strong></p>";
+    let codes = MfaExtractor::extract_codes_with_html(
+        "test-id",
+        Some("246810 is your code"),
+        Some("noreply@example.com"),
+        None,
+        Some(html),
+        Utc::now(),
+    );
+
+    // Both score the same, so the one that comes first wins
+    assert_eq!(codes[0].code, "246810");
+    let emphasized = &codes[0].alternatives[0];
+    assert_eq!(emphasized.code, "135790");
+    assert_eq!(emphasized.pattern, "html_emphasis");
+    assert!(emphasized.position.is_some_and(|position| position > 26));
+}
@@ -0,0 +1,236 @@
+use actix_web::{web, App};
+use email_manager::handlers::admin as admin_handlers;
+use email_manager::services::mfa_extractor::CodeType;
+use email_manager::services::mfa_registry::MfaRegistry;
+
+const CUSTOM_REGISTRY: &str = r#"
+verification_keywords = ["code"]
+exclusion_keywords = ["invoice"]
+
+[[patterns]]
+name = "code_is"
+regex = 'code\s+is\s+(\d{6})'
+
+[[patterns]]
+name = "six_digits"
+regex = '\b(\d{6})\b'
+
+[[services]]
+name = "Acme Bank"
+senders = ["acmebank.com"]
+patterns = [{ name = "acme_ref", regex = 'ref\s+([A-Z]{2})-(\d{4})', code_type = "alphanumeric", case_sensitive = true }]
+
+[[services]]
+name = "Portal"
+senders = [".gov"]
+subjects = ["portal"]
+"#;
+
+#[test]
+fn test_builtin_registry_detects_services() {
+    let registry = MfaRegistry::builtin();
+
+    assert_eq!(
+        registry.detect_service(Some("noreply@github.com"), None),
+        Some("GitHub")
+    );
+    assert_eq!(
+        registry.detect_service(
+            Some("noreply@sistemas.gov.br"),
+            Some("Central de Segurança")
+        ),
+        Some("Central de Segurança")
+    );
+    assert_eq!(
+        registry.detect_service(Some("noreply@sistemas.gov.br"), Some("Código")),
+        Some("Brazilian Gov")
+    );
+    assert_eq!(
+        registry.detect_service(Some("friend@example.com"), None),
+        None
+    );
+    assert_eq!(registry.detect_service(None, Some("GitHub")), None);
+}
+
+#[test]
+fn test_generic_patterns_keep_priority_order() {
+    let registry = MfaRegistry::from_toml(CUSTOM_REGISTRY).unwrap();
+
+    let found = registry
+        .find_code(None, "Order 111111. Your code is 222222")
+        .unwrap();
+    assert_eq!(found.code, "222222");
+    assert_eq!(found.pattern, "code_is");
+
+    let found = registry.find_code(None, "Use 333333 to sign in").unwrap();
+    assert_eq!(found.pattern, "six_digits");
+}
+
+#[test]
+fn test_service_patterns_take_priority() {
+    let registry = MfaRegistry::from_toml(CUSTOM_REGISTRY).unwrap();
+    let text = "Your code is 123456, ref AB-9876";
+
+    let service = registry.detect_service(Some("alerts@acmebank.com"), None);
+    assert_eq!(service, Some("Acme Bank"));
+
+    let found = registry.find_code(service, text).unwrap();
+    assert_eq!(found.code, "AB9876");
+    assert_eq!(found.code_type, CodeType::Alphanumeric);
+    assert_eq!(found.pattern, "acme_ref");
+
+    // Other senders only get the generic patterns
+    assert_eq!(registry.find_code(None, text).unwrap().code, "123456");
+}
+
+#[test]
+fn test_service_requires_sender_and_subject() {
+    let registry = MfaRegistry::from_toml(CUSTOM_REGISTRY).unwrap();
+
+    assert_eq!(
+        registry.detect_service(Some("no-reply@city.gov"), Some("Portal sign-in")),
+        Some("Portal")
+    );
+    assert_eq!(
+        registry.detect_service(Some("no-reply@city.gov"), Some("Newsletter")),
+        None
+    );
+}
+
+#[test]
+fn test_keywords_come_from_registry() {
+    let registry = MfaRegistry::from_toml(CUSTOM_REGISTRY).unwrap();
+
+    assert!(registry.has_verification_context("your code", None));
+    assert!(!registry.has_verification_context("your otp", None));
+    assert!(registry.is_excluded("invoice #42", None));
+    assert!(!registry.is_excluded("your order", None));
+}
+
+#[test]
+fn test_invalid_pattern_is_rejected() {
+    let result = MfaRegistry::from_toml(
+        r#"
+[[patterns]]
+name = "broken"
+regex = 'code (\d{6}'
+"#,
+    );
+
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("broken"), "{}", error);
+}
+
+#[actix_rt::test]
+async fn test_admin_endpoint_tests_pattern() {
+    let app = actix_web::test::init_service(App::new().route(
+        "/admin/mfa/test-pattern",
+        web::post().to(admin_handlers::test_mfa_pattern),
+    ))
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/admin/mfa/test-pattern")
+        .set_json(serde_json::json!({
+            "pattern": r"ref\s+(\d{3})-(\d{3})",
+            "subject": "Your GitHub code",
+            "sender": "noreply@github.com",
+            "body": "Your verification code is 482913 (ref 123-456)"
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["matched"], true);
+    assert_eq!(body["code"], "123456");
+    assert_eq!(body["service"], "GitHub");
+    assert_eq!(body["extracted"][0]["code"], "482913");
+}
+
+#[actix_rt::test]
+async fn test_admin_endpoint_rejects_invalid_pattern() {
+    let app = actix_web::test::init_service(App::new().route(
+        "/admin/mfa/test-pattern",
+        web::post().to(admin_handlers::test_mfa_pattern),
+    ))
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/admin/mfa/test-pattern")
+        .set_json(serde_json::json!({ "pattern": "(unclosed", "body": "code 123456" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 400);
+}
+
+#[test]
+fn test_language_detection_and_accent_folding() {
+    let registry = MfaRegistry::builtin();
+
+    assert_eq!(
+        registry.detect_language("Your code is 123456. Please enter it to sign in."),
+        Some("en")
+    );
+    assert_eq!(
+        registry.detect_language("Olá, utilize o código 123456 para acessar sua conta. Você"),
+        Some("pt")
+    );
+    assert_eq!(registry.detect_language("確認コード 123456"), Some("ja"));
+    assert_eq!(registry.detect_language("123456"), None);
+
+    assert!(registry.has_verification_context("CODIGO DE VERIFICACION", Some("es")));
+    assert!(!registry.has_verification_context("codice di verifica", Some("es")));
+    assert!(registry.has_verification_context("codice di verifica", None));
+
+    // Patterns tagged with another language are not tried
+    let found = registry.find_candidates(None, Some("de"), "Código: 123456");
+    assert!(!found.is_empty());
+    assert!(found.iter().all(|m| !m.pattern.starts_with("pt_")));
+    let found = registry.find_candidates(None, Some("pt"), "Código: 123456");
+    assert_eq!(found[0].pattern, "pt_code");
+}
+
+#[test]
+fn test_forwarded_sms_service_and_spoken_digits() {
+    let registry = MfaRegistry::builtin();
+
+    assert_eq!(
+        registry.detect_forwarder(Some("5551234567@tmomail.net"), None),
+        Some("Carrier SMS gateway")
+    );
+    assert_eq!(
+        registry.detect_forwarder(Some("noreply@google.com"), None),
+        None
+    );
+
+    assert_eq!(
+        registry.forwarded_text(
+            "Google Voice",
+            "Code 1234\nTo respond to this text message..."
+        ),
+        "Code 1234\n"
+    );
+    assert_eq!(
+        registry.detect_service_in_text("Use G-123456 to sign in"),
+        Some("Google")
+    );
+    assert_eq!(
+        registry.detect_service_in_text("Your Steam Guard code is 12345, not PayPal"),
+        Some("Steam")
+    );
+    assert_eq!(
+        registry.detect_service_in_text("Steamboat code 12345"),
+        None
+    );
+
+    assert_eq!(
+        registry.replace_spoken_digits("Code: drei, vier, fünf, sechs."),
+        "Code: 3456."
+    );
+    assert_eq!(
+        registry.replace_spoken_digits("one or two things"),
+        "one or two things"
+    );
+}
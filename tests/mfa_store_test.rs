@@ -0,0 +1,166 @@
+mod common;
+
+use actix_web::{web, App};
+use chrono::Utc;
+use common::TempDir;
+use email_manager::handlers::emails as email_handlers;
+use email_manager::services::audit_log::AuditLog;
+use email_manager::services::imap_service::ImapService;
+use email_manager::services::mfa_extractor::MfaExtractor;
+use email_manager::services::mfa_store::MfaStore;
+use email_manager::services::undo_store::UndoStore;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[test]
+fn test_parse_expiry() {
+    let cases = [
+        ("This code expires in 10 minutes.", 600),
+        ("It is valid for the next 15 minutes", 900),
+        ("O código é válido por 5 minutos.", 300),
+        ("Este código expira en 2 horas", 7200),
+        ("Expira em 30 segundos", 30),
+    ];
+    for (text, seconds) in cases {
+        assert_eq!(
+            MfaExtractor::parse_expiry(text).map(|d| d.num_seconds()),
+            Some(seconds),
+            "{}",
+            text
+        );
+    }
+    assert!(MfaExtractor::parse_expiry("Your code is 123456").is_none());
+}
+
+#[test]
+fn test_expiry_falls_back_to_service_ttl() {
+    let date = Utc::now();
+
+    let codes = MfaExtractor::extract_codes(
+        "1",
+        Some("Sign in"),
+        Some("noreply@github.com"),
+        Some("Your verification code is 123456. It expires in 3 minutes."),
+        date,
+    );
+    assert_eq!(
+        codes[0].expires_at,
+        Some(date + chrono::Duration::minutes(3))
+    );
+
+    // Default TTL from the registry
+    let codes = MfaExtractor::extract_codes(
+        "2",
+        Some("Sign in"),
+        Some("noreply@github.com"),
+        Some("Your verification code is 123456."),
+        date,
+    );
+    assert_eq!(
+        codes[0].expires_at,
+        Some(date + chrono::Duration::minutes(10))
+    );
+
+    // Service TTL from the registry
+    let codes = MfaExtractor::extract_codes(
+        "3",
+        Some("Código de acesso"),
+        Some("noreply@sistemas.gov.br"),
+        Some("Seu código de validação: 275992"),
+        date,
+    );
+    assert_eq!(
+        codes[0].expires_at,
+        Some(date + chrono::Duration::minutes(5))
+    );
+}
+
+#[actix_rt::test]
+async fn test_consumed_state_is_kept_and_persisted() {
+    let data_dir = TempDir::new();
+    let store = MfaStore::new(&data_dir).unwrap();
+
+    let codes = MfaExtractor::extract_codes(
+        "42",
+        Some("Your code"),
+        Some("noreply@github.com"),
+        Some("Your verification code is 654321."),
+        Utc::now(),
+    );
+    let recorded = store.record(codes.clone()).await.unwrap();
+    assert!(!recorded[0].is_consumed());
+    assert!(!recorded[0].expired);
+
+    store.consume("42", None).await.unwrap();
+
+    // Seeing the same code again does not reset its state
+    let recorded = store.record(codes).await.unwrap();
+    assert!(recorded[0].is_consumed());
+
+    let reloaded = MfaStore::new(&data_dir).unwrap();
+    assert!(reloaded.get("42").await[0].is_consumed());
+
+    assert!(store.consume("43", None).await.is_err());
+}
+
+#[actix_rt::test]
+async fn test_expired_codes_are_flagged() {
+    let data_dir = TempDir::new();
+    let store = MfaStore::new(&data_dir).unwrap();
+    let codes = MfaExtractor::extract_codes(
+        "7",
+        Some("Your code"),
+        Some("noreply@github.com"),
+        Some("Your verification code is 111222. It expires in 5 minutes."),
+        Utc::now() - chrono::Duration::minutes(6),
+    );
+
+    let recorded = store.record(codes).await.unwrap();
+    assert!(recorded[0].expired);
+}
+
+#[actix_rt::test]
+async fn test_consume_endpoint() {
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    let codes = MfaExtractor::extract_codes(
+        "42",
+        Some("Your code"),
+        Some("noreply@github.com"),
+        Some("Your verification code is 654321."),
+        Utc::now(),
+    );
+    mfa_store.record(codes).await.unwrap();
+    let audit_log = Arc::new(AuditLog::new(&data_dir).unwrap());
+    let undo_store = Arc::new(UndoStore::new(&data_dir, 600).unwrap());
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(mfa_store.clone()))
+            .app_data(web::Data::new(audit_log))
+            .app_data(web::Data::new(undo_store))
+            .route(
+                "/mfa/codes/{email_id}/consume",
+                web::post().to(email_handlers::consume_mfa_code),
+            ),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/mfa/codes/42/consume")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["consumed"][0]["code"], "654321");
+    assert!(body["consumed"][0]["consumed_at"].is_string());
+    assert_eq!(body["deleted"], false);
+
+    assert!(mfa_store.get("42").await[0].is_consumed());
+}
@@ -0,0 +1,271 @@
+mod common;
+
+use actix_web::{test, web, App};
+use chrono::Utc;
+use common::TempDir;
+use email_manager::handlers::emails as email_handlers;
+use email_manager::models::{EmailCategory, EmailSummary};
+use email_manager::services::event_bus::{EventBus, EventPayload};
+use email_manager::services::imap_service::ImapService;
+use email_manager::services::mfa_extractor::MfaExtractor;
+use email_manager::services::mfa_store::MfaStore;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+fn test_app_data() -> (
+    Arc<Mutex<ImapService>>,
+    Arc<MfaStore>,
+    Arc<EventBus>,
+    TempDir,
+) {
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+    let data_dir = TempDir::new();
+    let mfa_store = Arc::new(MfaStore::new(&data_dir).unwrap());
+    (
+        email_service,
+        mfa_store,
+        Arc::new(EventBus::new(10)),
+        data_dir,
+    )
+}
+
+/// Poll answering with `emails` after `delay`, instead of going to the inbox
+fn stub_poll(emails: Vec<EmailSummary>, delay: Duration) -> email_handlers::MfaWaitPoll {
+    Arc::new(move || {
+        let emails = emails.clone();
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            Ok(emails)
+        })
+    })
+}
+
+fn github_email() -> EmailSummary {
+    EmailSummary {
+        id: "97".to_string(),
+        uid: None,
+        subject: "[GitHub] Please verify your device".to_string(),
+        sender: "GitHub".to_string(),
+        sender_email: "noreply@github.com".to_string(),
+        date: Utc::now(),
+        snippet: String::new(),
+        body: Some("Your GitHub authentication code is 314159.".to_string()),
+        html_body: None,
+        is_read: false,
+        flags: Vec::new(),
+        labels: vec!["INBOX".to_string()],
+        importance_score: 3,
+        category: EmailCategory::Verification,
+        totp_secrets: Vec::new(),
+        headers: Vec::new(),
+    }
+}
+
+#[actix_rt::test]
+async fn test_wait_returns_code_from_event() {
+    let (email_service, mfa_store, event_bus, _data_dir) = test_app_data();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(mfa_store))
+            .app_data(web::Data::new(event_bus.clone()))
+            .app_data(web::Data::new(stub_poll(Vec::new(), Duration::ZERO)))
+            .route(
+                "/mfa/wait",
+                web::get().to(email_handlers::wait_for_mfa_code),
+            ),
+    )
+    .await;
+
+    // Publish a GitHub code shortly after the request starts waiting
+    let publisher = event_bus.clone();
+    actix_rt::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let codes = MfaExtractor::extract_codes(
+            "99",
+            Some("[GitHub] Please verify your device"),
+            Some("noreply@github.com"),
+            Some("Your GitHub authentication code is 456789."),
+            Utc::now(),
+        );
+        publisher.publish(EventPayload::MfaCodeDetected(codes[0].clone()));
+    });
+
+    let since = (Utc::now() - chrono::Duration::seconds(60)).timestamp();
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/mfa/wait?service=github&timeout=10&since={}",
+            since
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "456789");
+    assert_eq!(body["service"], "GitHub");
+}
+
+#[actix_rt::test]
+async fn test_wait_times_out_with_408() {
+    let (email_service, mfa_store, event_bus, _data_dir) = test_app_data();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(mfa_store))
+            .app_data(web::Data::new(event_bus.clone()))
+            .app_data(web::Data::new(stub_poll(Vec::new(), Duration::ZERO)))
+            .route(
+                "/mfa/wait",
+                web::get().to(email_handlers::wait_for_mfa_code),
+            ),
+    )
+    .await;
+
+    // A code for a different service must not end the wait
+    let codes = MfaExtractor::extract_codes(
+        "98",
+        Some("Sign in to Google"),
+        Some("noreply@google.com"),
+        Some("Your Google verification code is 123456."),
+        Utc::now(),
+    );
+    let publisher = event_bus.clone();
+    actix_rt::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        publisher.publish(EventPayload::MfaCodeDetected(codes[0].clone()));
+    });
+
+    let req = test::TestRequest::get()
+        .uri("/mfa/wait?service=github&timeout=1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 408);
+}
+
+#[actix_rt::test]
+async fn test_wait_rejects_invalid_since() {
+    let (email_service, mfa_store, event_bus, _data_dir) = test_app_data();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(mfa_store))
+            .app_data(web::Data::new(event_bus))
+            .app_data(web::Data::new(stub_poll(Vec::new(), Duration::ZERO)))
+            .route(
+                "/mfa/wait",
+                web::get().to(email_handlers::wait_for_mfa_code),
+            ),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/mfa/wait?since=yesterday")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_rt::test]
+async fn test_wait_returns_code_from_poll() {
+    let (email_service, mfa_store, event_bus, _data_dir) = test_app_data();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(mfa_store))
+            .app_data(web::Data::new(event_bus))
+            .app_data(web::Data::new(stub_poll(
+                vec![github_email()],
+                Duration::ZERO,
+            )))
+            .route(
+                "/mfa/wait",
+                web::get().to(email_handlers::wait_for_mfa_code),
+            ),
+    )
+    .await;
+
+    let since = (Utc::now() - chrono::Duration::seconds(60)).timestamp();
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/mfa/wait?service=github&timeout=5&since={}",
+            since
+        ))
+        .to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["code"], "314159");
+}
+
+#[actix_rt::test]
+async fn test_slow_poll_does_not_overrun_timeout() {
+    let (email_service, mfa_store, event_bus, _data_dir) = test_app_data();
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(mfa_store))
+            .app_data(web::Data::new(event_bus))
+            .app_data(web::Data::new(stub_poll(
+                vec![github_email()],
+                Duration::from_secs(30),
+            )))
+            .route(
+                "/mfa/wait",
+                web::get().to(email_handlers::wait_for_mfa_code),
+            ),
+    )
+    .await;
+
+    let started = std::time::Instant::now();
+    let since = (Utc::now() - chrono::Duration::seconds(60)).timestamp();
+    let req = test::TestRequest::get()
+        .uri(&format!("/mfa/wait?timeout=1&since={}", since))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 408);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[actix_rt::test]
+async fn test_wait_for_new_codes_does_not_poll_the_inbox() {
+    let (email_service, mfa_store, event_bus, _data_dir) = test_app_data();
+    let polls = Arc::new(AtomicUsize::new(0));
+    let counter = polls.clone();
+    let poll: email_handlers::MfaWaitPoll = Arc::new(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async { Ok(vec![github_email()]) })
+    });
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(mfa_store))
+            .app_data(web::Data::new(event_bus))
+            .app_data(web::Data::new(poll))
+            .route(
+                "/mfa/wait",
+                web::get().to(email_handlers::wait_for_mfa_code),
+            ),
+    )
+    .await;
+
+    // Without an earlier `since` only the watcher's events are waited for
+    let req = test::TestRequest::get()
+        .uri("/mfa/wait?service=github&timeout=1")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 408);
+    assert_eq!(polls.load(Ordering::SeqCst), 0);
+}
@@ -1,19 +1,25 @@
 use chrono::Utc;
-use email_manager::models::{EmailSummary, ImportanceScore};
+use email_manager::models::{EmailCategory, EmailSummary, ImportanceScore};
 
 #[test]
 fn test_email_summary_creation() {
     let email = EmailSummary {
         id: "test123".to_string(),
+        uid: None,
         subject: "Test Subject".to_string(),
         sender: "John Doe".to_string(),
         sender_email: "john@example.com".to_string(),
         date: Utc::now(),
         snippet: "This is a test...".to_string(),
         body: None,
+        html_body: None,
         is_read: false,
+        flags: Vec::new(),
         labels: vec!["INBOX".to_string()],
         importance_score: 2,
+        category: EmailCategory::Personal,
+        totp_secrets: Vec::new(),
+        headers: Vec::new(),
     };
 
     assert_eq!(email.importance_score, 2);
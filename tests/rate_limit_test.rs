@@ -0,0 +1,56 @@
+use actix_web::{web, App, HttpResponse};
+use email_manager::config::{RateLimitConfig, RateLimitQuota};
+use email_manager::middleware::auth::ApiTokenAuth;
+use email_manager::middleware::rate_limit::{RateLimit, RateLimiter};
+use std::sync::Arc;
+
+#[actix_rt::test]
+async fn test_rate_limited_requests_get_429() {
+    let limiter = Arc::new(
+        RateLimiter::new(RateLimitConfig {
+            default_quota: RateLimitQuota {
+                burst: 20,
+                per_minute: 60,
+            },
+            ..RateLimitConfig::default()
+        })
+        .unwrap(),
+    );
+    let app = actix_web::test::init_service(
+        App::new()
+            .wrap(RateLimit::new(limiter))
+            .wrap(ApiTokenAuth::new("token".to_string()))
+            .route("/health", web::get().to(HttpResponse::Ok))
+            .route("/emails/recent", web::get().to(HttpResponse::Ok))
+            .route("/emails/bulk-delete", web::post().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/emails/recent")
+        .insert_header(("Authorization", "Bearer token"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("ratelimit-limit").unwrap(), "20");
+    assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "19");
+
+    // A bulk delete costs more than what is left
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/bulk-delete")
+        .insert_header(("Authorization", "Bearer token"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 429);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "1");
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["error"]["code"], "RATE_LIMIT_ERROR");
+
+    // Health checks are not limited
+    let req = actix_web::test::TestRequest::get()
+        .uri("/health")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("ratelimit-limit").is_none());
+}
@@ -0,0 +1,302 @@
+mod common;
+
+use actix_web::{web, App};
+use common::TempDir;
+use email_manager::errors::ApiError;
+use email_manager::handlers::rules as rule_handlers;
+use email_manager::models::EmailCategory;
+use email_manager::services::audit_log::{AuditAction, AuditLog, AuditQuery};
+use email_manager::services::imap_service::ImapService;
+use email_manager::services::rules::{
+    glob_matches, RuleAction, RuleCondition, RuleRequest, RulesEngine, SampleMessage, ScoreMatch,
+    TextMatch,
+};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn rules_engine(data_dir: &Path) -> Arc<RulesEngine> {
+    Arc::new(
+        RulesEngine::new(
+            Arc::new(Mutex::new(ImapService::new(
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ))),
+            Arc::new(AuditLog::new(data_dir).unwrap()),
+            data_dir,
+        )
+        .unwrap(),
+    )
+}
+
+fn newsletter_rule() -> RuleRequest {
+    serde_json::from_value(serde_json::json!({
+        "name": "Newsletters",
+        "conditions": [
+            {"from": {"matches": "*@newsletter.com"}},
+            {"score": {"eq": 1}}
+        ],
+        "actions": ["mark_read", {"move": "Newsletters"}]
+    }))
+    .unwrap()
+}
+
+fn sample(sender_email: &str, importance_score: u8) -> SampleMessage {
+    SampleMessage {
+        sender_email: sender_email.to_string(),
+        importance_score,
+        ..SampleMessage::default()
+    }
+}
+
+#[test]
+fn test_glob_matches() {
+    assert!(glob_matches("*@newsletter.com", "News@Newsletter.com"));
+    assert!(glob_matches("a?c*", "abcdef"));
+    assert!(glob_matches("*", ""));
+    assert!(glob_matches("*b*b", "abab"));
+    assert!(!glob_matches("*@newsletter.com", "me@newsletter.com.evil"));
+    assert!(!glob_matches("a?c", "ac"));
+}
+
+#[test]
+fn test_rule_format() {
+    let request = newsletter_rule();
+    assert!(request.enabled);
+    assert_eq!(
+        request.conditions[1],
+        RuleCondition::Score(ScoreMatch::Eq(1))
+    );
+    assert_eq!(
+        request.actions,
+        vec![
+            RuleAction::MarkRead,
+            RuleAction::Move("Newsletters".to_string())
+        ]
+    );
+
+    let condition: RuleCondition = serde_json::from_value(serde_json::json!({
+        "header": {"name": "List-Id", "contains": "weekly"}
+    }))
+    .unwrap();
+    assert_eq!(
+        condition,
+        RuleCondition::Header {
+            name: "List-Id".to_string(),
+            value: TextMatch::Contains("weekly".to_string()),
+        }
+    );
+
+    let mut email = sample("news@newsletter.com", 1).into_email();
+    assert!(!condition.matches(&email));
+    email.headers = vec![("list-id".to_string(), "Weekly <weekly.example>".to_string())];
+    assert!(condition.matches(&email));
+
+    let condition: RuleCondition = serde_json::from_value(serde_json::json!({
+        "not": {"any_of": [{"category": "newsletter"}, {"subject": {"regex": "^\\[ci\\]"}}]}
+    }))
+    .unwrap();
+    assert!(condition.matches(&email));
+    email.category = EmailCategory::Newsletter;
+    assert!(!condition.matches(&email));
+}
+
+#[test]
+fn test_rule_validation() {
+    assert!(newsletter_rule().validate().is_ok());
+
+    let invalid = [
+        serde_json::json!({"name": "", "actions": ["mark_read"]}),
+        serde_json::json!({"name": "No actions", "actions": []}),
+        // Nothing can follow a move
+        serde_json::json!({"name": "Order", "actions": [{"move": "A"}, "mark_read"]}),
+        serde_json::json!({"name": "Flags", "actions": [{"add_flags": ["\\Deleted"]}]}),
+        serde_json::json!({
+            "name": "Regex",
+            "conditions": [{"all_of": [{"body": {"regex": "("}}]}],
+            "actions": ["delete"]
+        }),
+    ];
+    for request in invalid {
+        let request: RuleRequest = serde_json::from_value(request).unwrap();
+        assert!(request.validate().is_err(), "{:?}", request);
+    }
+}
+
+#[actix_rt::test]
+async fn test_matching_rules_in_order() {
+    let data_dir = TempDir::new();
+    let engine = rules_engine(&data_dir);
+
+    let tag = serde_json::from_value(serde_json::json!({
+        "name": "Tag low scores",
+        "conditions": [{"score": {"at_most": 1}}],
+        "actions": [{"add_flags": ["$Todo"]}]
+    }))
+    .unwrap();
+    engine.create(tag).await.unwrap();
+    let newsletters = engine.create(newsletter_rule()).await.unwrap();
+    let catch_all = serde_json::from_value(serde_json::json!({
+        "name": "Everything",
+        "actions": [{"label": "Seen by rules"}]
+    }))
+    .unwrap();
+    engine.create(catch_all).await.unwrap();
+
+    // The move ends processing
+    let matched = engine
+        .matching(&sample("a@newsletter.com", 1).into_email())
+        .await;
+    let names: Vec<_> = matched.iter().map(|rule| rule.name.as_str()).collect();
+    assert_eq!(names, vec!["Tag low scores", "Newsletters"]);
+
+    let matched = engine
+        .matching(&sample("a@newsletter.com", 3).into_email())
+        .await;
+    let names: Vec<_> = matched.iter().map(|rule| rule.name.as_str()).collect();
+    assert_eq!(names, vec!["Everything"]);
+
+    // Disabled rules are skipped
+    let mut disabled = newsletter_rule();
+    disabled.enabled = false;
+    engine.update(&newsletters.id, disabled).await.unwrap();
+    let matched = engine
+        .matching(&sample("a@newsletter.com", 1).into_email())
+        .await;
+    assert_eq!(matched.len(), 2);
+
+    // Rules survive a restart, in order
+    let reloaded = rules_engine(&data_dir);
+    let rules = reloaded.list().await;
+    assert_eq!(rules.len(), 3);
+    assert_eq!(rules[1].id, newsletters.id);
+    assert!(!rules[1].enabled);
+    assert_eq!(rules[1].hits, 0);
+}
+
+#[actix_rt::test]
+async fn test_rules_need_a_uid_to_act() {
+    let data_dir = TempDir::new();
+    let engine = rules_engine(&data_dir);
+
+    let rule = serde_json::from_value(serde_json::json!({
+        "name": "Archive",
+        "conditions": [{"from": {"regex": "^a@news"}}],
+        "actions": [{"move": "Archive"}]
+    }))
+    .unwrap();
+    engine.create(rule).await.unwrap();
+
+    // Sequence numbers shift as messages move, so without a UID the rule
+    // fails before reaching the server
+    let email = sample("a@newsletter.com", 1).into_email();
+    assert!(email.uid.is_none());
+    let result = engine.apply(&email).await;
+    assert!(
+        matches!(result, Err(ApiError::ValidationError(_))),
+        "{:?}",
+        result
+    );
+    assert_eq!(engine.list().await[0].hits, 1);
+}
+
+#[actix_rt::test]
+async fn test_rule_endpoints() {
+    let data_dir = TempDir::new();
+    let engine = rules_engine(&data_dir);
+    let audit_log = Arc::new(AuditLog::new(&data_dir).unwrap());
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine))
+            .app_data(web::Data::new(audit_log.clone()))
+            .app_data(web::Data::new(email_service))
+            .route("/rules", web::get().to(rule_handlers::list_rules))
+            .route("/rules", web::post().to(rule_handlers::create_rule))
+            .route("/rules/test", web::post().to(rule_handlers::test_rules))
+            .route("/rules/{id}", web::get().to(rule_handlers::get_rule))
+            .route("/rules/{id}", web::put().to(rule_handlers::update_rule))
+            .route("/rules/{id}", web::delete().to(rule_handlers::delete_rule)),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/rules")
+        .set_json(serde_json::json!({
+            "name": "2FA",
+            "conditions": [{"has_mfa_code": true}],
+            "actions": [{"label": "2FA"}]
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let rule: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    let uri = format!("/rules/{}", rule["id"].as_str().unwrap());
+    assert_eq!(rule["hits"], 0);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/rules/test")
+        .set_json(serde_json::json!({
+            "message": {
+                "sender_email": "noreply@github.com",
+                "subject": "Your verification code",
+                "body": "Your verification code is 482913"
+            }
+        }))
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(body["matches"][0]["name"], "2FA");
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/rules/test")
+        .set_json(serde_json::json!({"message": {"subject": "Lunch?"}}))
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["count"], 0);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/rules/test")
+        .set_json(serde_json::json!({}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = actix_web::test::TestRequest::put()
+        .uri(&uri)
+        .set_json(serde_json::json!({"name": "Broken", "actions": [{"move": ""}]}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&uri)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+
+    let req = actix_web::test::TestRequest::get().uri(&uri).to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+
+    let records = audit_log
+        .query(&AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    let actions: Vec<_> = records.iter().map(|record| record.action).collect();
+    assert_eq!(
+        actions,
+        vec![
+            AuditAction::RuleDelete,
+            AuditAction::RuleUpdate,
+            AuditAction::RuleCreate
+        ]
+    );
+}
@@ -1,3 +1,4 @@
+use email_manager::models::EmailCategory;
 use email_manager::services::scoring::EmailScorer;
 
 #[test]
@@ -41,3 +42,21 @@ fn test_score_regular_email() {
     let score = scorer.calculate_score("friend@gmail.com", "Hey, how are you?", &["INBOX"]);
     assert_eq!(score, 2);
 }
+
+#[test]
+fn test_categorize_emails() {
+    let scorer = EmailScorer::new();
+
+    assert_eq!(
+        scorer.categorize("newsletter@company.com", "This week in tech"),
+        EmailCategory::Newsletter
+    );
+    assert_eq!(
+        scorer.categorize("no-reply@bank.com", "Your statement is ready"),
+        EmailCategory::Notification
+    );
+    assert_eq!(
+        scorer.categorize("friend@gmail.com", "Dinner tonight?"),
+        EmailCategory::Personal
+    );
+}
@@ -0,0 +1,245 @@
+mod common;
+
+use actix_web::{web, App};
+use common::TempDir;
+use email_manager::handlers::rules as rule_handlers;
+use email_manager::services::audit_log::AuditLog;
+use email_manager::services::imap_service::ImapService;
+use email_manager::services::rules::{
+    RuleAction, RuleCondition, RuleRequest, RulesEngine, TextMatch,
+};
+use email_manager::services::sieve;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn rules_engine(data_dir: &Path) -> Arc<RulesEngine> {
+    Arc::new(
+        RulesEngine::new(
+            Arc::new(Mutex::new(ImapService::new(
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ))),
+            Arc::new(AuditLog::new(data_dir).unwrap()),
+            data_dir,
+        )
+        .unwrap(),
+    )
+}
+
+const SCRIPT: &str = r#"require ["fileinto", "copy", "imap4flags", "body", "envelope"];
+
+# rule:[Newsletters]
+if address :domain :is "from" "newsletter.com" {
+    fileinto "Newsletters";
+    addflag "\\Seen";
+}
+
+# rule:[Lists]
+if header :contains "List-Id" ["dev.example.org", "ops.example.org"] {
+    fileinto :copy "Lists";
+    stop;
+} elsif anyof (body :contains "unsubscribe", header :matches "subject" "*digest*") {
+    fileinto "Digests";
+} else {
+    keep;
+}
+
+if size :over 1M {
+    discard;
+}
+if header :is "subject" "forward me" {
+    redirect "someone@example.com";
+}
+/* Known spammer */
+if envelope :is "from" "spam@example.com" { discard; }
+"#;
+
+fn text(value: &str) -> String {
+    value.to_string()
+}
+
+#[test]
+fn test_import_script() {
+    let import = sieve::import(SCRIPT).unwrap();
+    let names: Vec<_> = import.rules.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "Newsletters",
+            "Lists",
+            "Lists (elsif on line 13)",
+            "Known spammer"
+        ]
+    );
+
+    // Flags are set before the message leaves the inbox
+    let newsletters = &import.rules[0];
+    assert_eq!(
+        newsletters.conditions,
+        vec![RuleCondition::From(TextMatch::Matches(text(
+            "*@newsletter.com"
+        )))]
+    );
+    assert_eq!(
+        newsletters.actions,
+        vec![
+            RuleAction::AddFlags(vec![text("\\Seen")]),
+            RuleAction::Move(text("Newsletters"))
+        ]
+    );
+
+    let lists = &import.rules[1];
+    assert_eq!(lists.actions, vec![RuleAction::Copy(text("Lists"))]);
+    assert!(lists.stop);
+
+    // An elsif branch only applies when the if test failed
+    let digests = &import.rules[2];
+    assert_eq!(digests.conditions.len(), 2);
+    assert_eq!(
+        digests.conditions[0],
+        RuleCondition::Not(Box::new(lists.conditions[0].clone()))
+    );
+    assert_eq!(digests.actions, vec![RuleAction::Move(text("Digests"))]);
+
+    assert_eq!(import.rules[3].actions, vec![RuleAction::Delete]);
+
+    let unsupported: Vec<_> = import.unsupported.iter().map(|issue| issue.line).collect();
+    assert_eq!(unsupported, vec![19, 23]);
+    assert!(import.unsupported[0].message.contains("size"));
+    assert!(import.unsupported[1].message.contains("redirect"));
+}
+
+#[test]
+fn test_import_errors_and_strings() {
+    let error = sieve::import("if header :is \"subject\" \"x\" {\n  fileinto \"A\";\n")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("line 2"), "{}", error);
+
+    let error = sieve::import("require \"fileinto\";\nfileinto \"A\nB;")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("line 2"), "{}", error);
+
+    let import =
+        sieve::import("if body :contains text:\nhello\n..dot\n.\n{ discard; }\nstop;\n").unwrap();
+    assert_eq!(
+        import.rules[0].conditions,
+        vec![RuleCondition::Body(TextMatch::Contains(text(
+            "hello\n.dot\n"
+        )))]
+    );
+    // A top-level stop becomes a rule without conditions
+    assert!(import.rules[1].conditions.is_empty());
+    assert!(import.rules[1].stop);
+
+    let import = sieve::import("require [\"vacation\"];").unwrap();
+    assert_eq!(import.unsupported[0].line, 1);
+}
+
+#[test]
+fn test_import_limits_nesting() {
+    // Deep nesting is a syntax error rather than a stack overflow
+    let deep = format!("if {}true {{ discard; }}", "not ".repeat(10_000));
+    let error = sieve::import(&deep).unwrap_err().to_string();
+    assert!(error.contains("nested"), "{}", error);
+    let deep = format!("{}discard;{}", "if true {".repeat(100), "}".repeat(100));
+    let error = sieve::import(&deep).unwrap_err().to_string();
+    assert!(error.contains("nested"), "{}", error);
+
+    let shallow = format!("if {}true {{ discard; }}", "not ".repeat(8));
+    assert!(sieve::import(&shallow).is_ok());
+
+    // Rules created through the API are held to a depth rules.json can load
+    let mut condition = serde_json::json!({"has_mfa_code": true});
+    for _ in 0..40 {
+        condition = serde_json::json!({"not": condition});
+    }
+    let request: RuleRequest = serde_json::from_value(serde_json::json!({
+        "name": "Deep",
+        "conditions": [condition],
+        "actions": ["mark_read"]
+    }))
+    .unwrap();
+    let error = request.validate().unwrap_err().to_string();
+    assert!(error.contains("nested"), "{}", error);
+}
+
+#[actix_rt::test]
+async fn test_export_round_trip() {
+    let data_dir = TempDir::new();
+    let engine = rules_engine(&data_dir);
+    for request in sieve::import(SCRIPT).unwrap().rules {
+        engine.create(request).await.unwrap();
+    }
+    let scored = serde_json::from_value(serde_json::json!({
+        "name": "Low scores",
+        "conditions": [{"score": {"at_most": 1}}],
+        "actions": ["mark_read"]
+    }))
+    .unwrap();
+    engine.create(scored).await.unwrap();
+
+    let rules = engine.list().await;
+    let script = sieve::export(&rules);
+    assert!(script.contains("# Not exported: 'Low scores' uses score conditions"));
+    assert!(script.contains("require [\"body\", \"copy\", \"fileinto\", \"imap4flags\"];"));
+
+    let reimported = sieve::import(&script).unwrap();
+    assert!(reimported.unsupported.is_empty());
+    assert_eq!(reimported.rules.len(), 4);
+    for (rule, request) in rules.iter().zip(&reimported.rules) {
+        assert_eq!(rule.name, request.name);
+        assert_eq!(rule.conditions, request.conditions);
+        assert_eq!(rule.actions, request.actions);
+        assert_eq!(rule.stop, request.stop);
+    }
+}
+
+#[actix_rt::test]
+async fn test_import_endpoint() {
+    let data_dir = TempDir::new();
+    let engine = rules_engine(&data_dir);
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(engine.clone()))
+            .app_data(web::Data::new(Arc::new(AuditLog::new(&data_dir).unwrap())))
+            .route("/rules/import", web::post().to(rule_handlers::import_sieve))
+            .route("/rules/export", web::get().to(rule_handlers::export_sieve)),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/rules/import?dry_run=true")
+        .insert_header(("Content-Type", "application/sieve"))
+        .set_payload(SCRIPT)
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["count"], 4);
+    assert_eq!(body["unsupported"][0]["line"], 19);
+    assert!(engine.list().await.is_empty());
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/rules/import")
+        .set_payload(SCRIPT)
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["count"], 4);
+    assert_eq!(engine.list().await.len(), 4);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/rules/import")
+        .set_payload("if true {")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/rules/export")
+        .to_request();
+    let body = actix_web::test::call_and_read_body(&app, req).await;
+    let script = String::from_utf8(body.to_vec()).unwrap();
+    assert!(script.contains("fileinto \"Newsletters\";"));
+}
@@ -0,0 +1,525 @@
+mod common;
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use chrono::{Duration, Utc};
+use common::TempDir;
+use email_manager::config::{SmtpConfig, SubscriptionsConfig};
+use email_manager::errors::ApiError;
+use email_manager::handlers::subscriptions as subscription_handlers;
+use email_manager::models::{EmailCategory, EmailSummary};
+use email_manager::services::audit_log::{AuditAction, AuditLog, AuditOutcome, AuditQuery};
+use email_manager::services::imap_service::ImapService;
+use email_manager::services::smtp::{OutgoingMessage, SmtpClient};
+use email_manager::services::subscriptions::{
+    aggregate, is_public, parse_list_unsubscribe, parse_mailto, SubscriptionManager,
+    UnsubscribeMethod, UnsubscribeStatus, ONE_CLICK_BODY,
+};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn email(id: &str, sender_email: &str, days_ago: i64, is_read: bool) -> EmailSummary {
+    EmailSummary {
+        id: id.to_string(),
+        uid: None,
+        subject: "This week".to_string(),
+        sender: "Weekly News".to_string(),
+        sender_email: sender_email.to_string(),
+        date: Utc::now() - Duration::days(days_ago),
+        snippet: String::new(),
+        body: None,
+        html_body: None,
+        is_read,
+        flags: Vec::new(),
+        labels: vec!["INBOX".to_string()],
+        importance_score: 1,
+        category: EmailCategory::Personal,
+        totp_secrets: Vec::new(),
+        headers: Vec::new(),
+    }
+}
+
+fn list_email(
+    id: &str,
+    sender_email: &str,
+    list_unsubscribe: &str,
+    one_click: bool,
+) -> EmailSummary {
+    let mut email = email(id, sender_email, 0, false);
+    email.headers = vec![("List-Unsubscribe".to_string(), list_unsubscribe.to_string())];
+    if one_click {
+        email.headers.push((
+            "List-Unsubscribe-Post".to_string(),
+            ONE_CLICK_BODY.to_string(),
+        ));
+    }
+    email
+}
+
+fn manager(data_dir: &Path, smtp: SmtpConfig) -> Arc<SubscriptionManager> {
+    Arc::new(
+        SubscriptionManager::new(
+            Arc::new(Mutex::new(ImapService::new(
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ))),
+            SmtpClient::new(
+                smtp,
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ),
+            SubscriptionsConfig {
+                allow_http: true,
+                // The HTTP stub listens on 127.0.0.1
+                allow_private_hosts: true,
+                timeout_secs: 5,
+                ..SubscriptionsConfig::default()
+            },
+            data_dir,
+        )
+        .unwrap(),
+    )
+}
+
+#[test]
+fn test_parse_unsubscribe_headers() {
+    assert_eq!(
+        parse_list_unsubscribe(
+            "<mailto:leave@list.example.org?subject=unsubscribe>,\r\n <https://list.example.org/u?id=4 2>"
+        ),
+        vec![
+            "mailto:leave@list.example.org?subject=unsubscribe".to_string(),
+            "https://list.example.org/u?id=42".to_string()
+        ]
+    );
+    assert!(parse_list_unsubscribe("https://no-brackets.example").is_empty());
+
+    assert_eq!(
+        parse_mailto("mailto:leave%2Bnews@list.example.org?Subject=Stop%20it"),
+        Some(UnsubscribeMethod::Mailto {
+            address: "leave+news@list.example.org".to_string(),
+            subject: "Stop it".to_string(),
+            body: "Unsubscribe".to_string(),
+        })
+    );
+    assert_eq!(parse_mailto("https://list.example.org/u"), None);
+    assert_eq!(parse_mailto("mailto:?subject=x"), None);
+}
+
+#[test]
+fn test_aggregate_subscriptions() {
+    let mut newsletter = email("4", "digest@shop.com", 3, false);
+    newsletter.category = EmailCategory::Newsletter;
+    let mut old = list_email("1", "News@Weekly.com", "<https://weekly.com/old>", false);
+    old.date = Utc::now() - Duration::days(10);
+    old.is_read = true;
+
+    let subscriptions = aggregate(&[
+        old,
+        email("2", "news@weekly.com", 5, true),
+        list_email("3", "news@weekly.com", "<https://weekly.com/u>", true),
+        newsletter,
+        // Senders without list headers are not subscriptions
+        email("5", "alice@work.com", 1, false),
+    ]);
+
+    assert_eq!(subscriptions.len(), 2);
+    let weekly = &subscriptions[0];
+    assert_eq!(weekly.sender_email, "news@weekly.com");
+    assert_eq!(weekly.count, 3);
+    assert_eq!(weekly.unread, 1);
+    assert!((weekly.open_rate - 2.0 / 3.0).abs() < 1e-9);
+    assert!(weekly.first_seen < weekly.last_seen);
+    // Links come from the latest message that has them
+    assert_eq!(weekly.unsubscribe_links, vec!["https://weekly.com/u"]);
+    assert!(weekly.one_click);
+    assert_eq!(
+        weekly.method(false).unwrap(),
+        UnsubscribeMethod::OneClick {
+            url: "https://weekly.com/u".to_string()
+        }
+    );
+
+    let shop = &subscriptions[1];
+    assert_eq!(shop.count, 1);
+    assert!(shop.unsubscribe_links.is_empty());
+    assert!(shop.method(true).is_err());
+
+    // The same sender always gets the same id
+    let again = aggregate(&[list_email("9", "NEWS@weekly.com", "<https://x>", false)]);
+    assert_eq!(again[0].id, weekly.id);
+}
+
+/// Status the stub answers with
+static STUB_STATUS: AtomicU16 = AtomicU16::new(200);
+
+async fn one_click_stub(req: HttpRequest, body: String) -> HttpResponse {
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    if content_type != "application/x-www-form-urlencoded" || body != ONE_CLICK_BODY {
+        return HttpResponse::BadRequest().finish();
+    }
+    HttpResponse::build(
+        actix_web::http::StatusCode::from_u16(STUB_STATUS.load(Ordering::SeqCst)).unwrap(),
+    )
+    .finish()
+}
+
+/// Start a local HTTP server accepting one-click unsubscribe requests
+fn start_http_stub() -> String {
+    let server =
+        HttpServer::new(|| App::new().route("/unsubscribe", web::post().to(one_click_stub)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+
+    let addr = server.addrs()[0];
+    actix_rt::spawn(server.run());
+    format!("http://{}/unsubscribe?list=weekly", addr)
+}
+
+#[actix_rt::test]
+async fn test_one_click_unsubscribe() {
+    let data_dir = TempDir::new();
+    let subscriptions = manager(&data_dir, SmtpConfig::default());
+    let audit_log = Arc::new(AuditLog::new(&data_dir).unwrap());
+    let url = start_http_stub();
+
+    let listed = subscriptions
+        .update(&[
+            list_email("1", "news@weekly.com", &format!("<{}>", url), true),
+            list_email("2", "deals@shop.com", &format!("<{}>", url), true),
+        ])
+        .await;
+    let weekly = listed
+        .iter()
+        .find(|s| s.sender_email == "news@weekly.com")
+        .unwrap();
+    let shop = listed
+        .iter()
+        .find(|s| s.sender_email == "deals@shop.com")
+        .unwrap();
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(subscriptions.clone()))
+            .app_data(web::Data::new(audit_log.clone()))
+            .route(
+                "/subscriptions/{id}/unsubscribe",
+                web::post().to(subscription_handlers::unsubscribe),
+            ),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/subscriptions/{}/unsubscribe", weekly.id))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let record: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(record["method"], "one_click");
+    assert_eq!(record["status"], "succeeded");
+    assert_eq!(record["status_code"], 200);
+
+    // A rejected request is recorded and reported as a bad gateway
+    STUB_STATUS.store(500, Ordering::SeqCst);
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/subscriptions/{}/unsubscribe", shop.id))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 502);
+    let record: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(record["status"], "failed");
+    assert_eq!(record["status_code"], 500);
+
+    // Attempts survive a restart and show up in the next listing
+    let reloaded = manager(&data_dir, SmtpConfig::default());
+    assert_eq!(reloaded.records().await.len(), 2);
+    let listed = reloaded
+        .update(&[list_email(
+            "3",
+            "news@weekly.com",
+            &format!("<{}>", url),
+            true,
+        )])
+        .await;
+    let unsubscribed = listed[0].unsubscribed.as_ref().unwrap();
+    assert_eq!(unsubscribed.status, UnsubscribeStatus::Succeeded);
+
+    let records = audit_log
+        .query(&AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    let outcomes: Vec<_> = records
+        .iter()
+        .map(|record| (record.action, record.outcome))
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            (AuditAction::Unsubscribe, AuditOutcome::Failed),
+            (AuditAction::Unsubscribe, AuditOutcome::Succeeded)
+        ]
+    );
+}
+
+#[actix_rt::test]
+async fn test_https_required_for_one_click() {
+    let data_dir = TempDir::new();
+    let subscriptions = Arc::new(
+        SubscriptionManager::new(
+            Arc::new(Mutex::new(ImapService::new(
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ))),
+            SmtpClient::new(
+                SmtpConfig::default(),
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ),
+            SubscriptionsConfig::default(),
+            &data_dir,
+        )
+        .unwrap(),
+    );
+
+    let listed = subscriptions
+        .update(&[list_email(
+            "1",
+            "news@weekly.com",
+            "<http://weekly.com/u>",
+            true,
+        )])
+        .await;
+    let error = subscriptions.unsubscribe(&listed[0].id).await.unwrap_err();
+    assert!(
+        error.to_string().contains("no unsubscribe link"),
+        "{}",
+        error
+    );
+    assert!(subscriptions.records().await.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_one_click_refuses_private_hosts() {
+    let data_dir = TempDir::new();
+    let subscriptions = Arc::new(
+        SubscriptionManager::new(
+            Arc::new(Mutex::new(ImapService::new(
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ))),
+            SmtpClient::new(
+                SmtpConfig::default(),
+                "test@gmail.com".to_string(),
+                "test-password".to_string(),
+            ),
+            SubscriptionsConfig {
+                allow_http: true,
+                ..SubscriptionsConfig::default()
+            },
+            &data_dir,
+        )
+        .unwrap(),
+    );
+    let url = start_http_stub();
+
+    let listed = subscriptions
+        .update(&[
+            list_email("1", "news@weekly.com", &format!("<{}>", url), true),
+            list_email(
+                "2",
+                "deals@shop.com",
+                &format!("<{}>", url.replace("127.0.0.1", "localhost")),
+                true,
+            ),
+        ])
+        .await;
+    // Neither the IP literal nor a name resolving to loopback reaches the stub
+    for subscription in &listed {
+        let record = subscriptions.unsubscribe(&subscription.id).await.unwrap();
+        assert_eq!(record.status, UnsubscribeStatus::Failed);
+        assert_eq!(record.status_code, None);
+    }
+
+    assert!(is_public("93.184.216.34".parse().unwrap()));
+    for ip in [
+        "10.0.0.1",
+        "169.254.169.254",
+        "::1",
+        "fd00::1",
+        "::ffff:192.168.1.1",
+    ] {
+        assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+    }
+}
+
+/// Accept one SMTP session on a local port and return what was sent
+fn start_smtp_stub() -> (u16, std::thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut lines = Vec::new();
+        let mut in_data = false;
+
+        writer.write_all(b"220 stub ESMTP\r\n").unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            lines.push(line.clone());
+
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                b"250 queued\r\n"
+            } else if line.starts_with("EHLO") {
+                b"250-stub\r\n250 AUTH PLAIN\r\n"
+            } else if line.starts_with("AUTH") {
+                b"235 ok\r\n"
+            } else if line == "DATA" {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line == "QUIT" {
+                writer.write_all(b"221 bye\r\n").unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            writer.write_all(reply).unwrap();
+        }
+        lines
+    });
+
+    (port, handle)
+}
+
+#[actix_rt::test]
+async fn test_mailto_unsubscribe() {
+    let data_dir = TempDir::new();
+    let (port, stub) = start_smtp_stub();
+    let subscriptions = manager(
+        &data_dir,
+        SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            tls: false,
+            timeout_secs: 5,
+        },
+    );
+
+    let listed = subscriptions
+        .update(&[list_email(
+            "1",
+            "news@weekly.com",
+            "<mailto:leave@weekly.com?subject=unsubscribe%20me&body=.bye>, <https://weekly.com/u>",
+            false,
+        )])
+        .await;
+    let id = listed[0].id.clone();
+
+    // Sending mail needs the send scope
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(subscriptions.clone()))
+            .app_data(web::Data::new(Arc::new(AuditLog::new(&data_dir).unwrap())))
+            .route(
+                "/subscriptions/{id}/unsubscribe",
+                web::post().to(subscription_handlers::unsubscribe),
+            ),
+    )
+    .await;
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/subscriptions/{}/unsubscribe", id))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+
+    let record = subscriptions.unsubscribe(&id).await.unwrap();
+    assert_eq!(record.status, UnsubscribeStatus::Succeeded, "{:?}", record);
+
+    let lines = stub.join().unwrap();
+    assert!(lines.contains(&"MAIL FROM:<test@gmail.com>".to_string()));
+    assert!(lines.contains(&"RCPT TO:<leave@weekly.com>".to_string()));
+    assert!(lines.contains(&"Subject: unsubscribe me".to_string()));
+    // Lines starting with a dot are escaped
+    assert!(lines.contains(&"..bye".to_string()));
+    let auth = lines
+        .iter()
+        .find(|line| line.starts_with("AUTH PLAIN "))
+        .unwrap();
+    assert_eq!(
+        data_encoding::BASE64
+            .decode(auth.trim_start_matches("AUTH PLAIN ").as_bytes())
+            .unwrap(),
+        b"\0test@gmail.com\0test-password"
+    );
+}
+
+#[actix_rt::test]
+async fn test_hostile_mailto_is_not_followed() {
+    let hostile = "mailto:x@evil.com%3E%0D%0ARCPT%20TO:%3Cvictim@example.com?subject=hi";
+    assert_eq!(parse_mailto(hostile), None);
+    for address in [
+        "a b@example.com",
+        "a@example.com,b@example.com",
+        "<a@example.com>",
+    ] {
+        assert_eq!(
+            parse_mailto(&format!("mailto:{}", address)),
+            None,
+            "{}",
+            address
+        );
+    }
+
+    let data_dir = TempDir::new();
+    let subscriptions = manager(&data_dir, SmtpConfig::default());
+    let listed = subscriptions
+        .update(&[list_email(
+            "1",
+            "news@weekly.com",
+            &format!("<{}>", hostile),
+            false,
+        )])
+        .await;
+    let error = subscriptions.unsubscribe(&listed[0].id).await.unwrap_err();
+    assert!(
+        error.to_string().contains("no unsubscribe link"),
+        "{}",
+        error
+    );
+
+    // The client refuses such a recipient too, before connecting
+    let smtp = SmtpClient::new(
+        SmtpConfig::default(),
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    );
+    let error = smtp
+        .send(OutgoingMessage {
+            to: "x@evil.com>\r\nRCPT TO:<victim@example.com".to_string(),
+            subject: "hi".to_string(),
+            body: "hi".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ApiError::ValidationError(_)), "{}", error);
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use actix_web::{web, App, HttpResponse};
+use common::TempDir;
+use email_manager::handlers::tokens as token_handlers;
+use email_manager::middleware::auth::{ApiTokenAuth, RequireScope, Scope};
+use email_manager::services::audit_log::{AuditAction, AuditLog, AuditQuery};
+use email_manager::services::token_store::{CreateTokenRequest, TokenStore};
+use std::sync::Arc;
+
+fn request(scopes: Vec<Scope>, allowed_cidrs: &[&str]) -> CreateTokenRequest {
+    CreateTokenRequest {
+        description: Some("test".to_string()),
+        scopes,
+        expires_in_secs: None,
+        allowed_cidrs: allowed_cidrs.iter().map(|c| c.parse().unwrap()).collect(),
+    }
+}
+
+async fn ok() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[actix_rt::test]
+async fn test_minted_token_lifecycle() {
+    let data_dir = TempDir::new();
+    let token_store = Arc::new(TokenStore::new(&data_dir).unwrap());
+    let audit_log = Arc::new(AuditLog::new(&data_dir).unwrap());
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(token_store.clone()))
+            .app_data(web::Data::new(audit_log.clone()))
+            .wrap(
+                ApiTokenAuth::new("admin-token".to_string()).with_token_store(token_store.clone()),
+            )
+            .route(
+                "/admin/tokens",
+                web::post()
+                    .to(token_handlers::create_token)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/tokens",
+                web::get()
+                    .to(token_handlers::list_tokens)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/admin/tokens/{id}",
+                web::delete()
+                    .to(token_handlers::revoke_token)
+                    .wrap(RequireScope::new(Scope::Admin)),
+            )
+            .route(
+                "/mfa/codes",
+                web::get().to(ok).wrap(RequireScope::new(Scope::MfaRead)),
+            ),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/admin/tokens")
+        .insert_header(("Authorization", "Bearer admin-token"))
+        .set_json(serde_json::json!({"scopes": ["mfa:read"], "expires_in_secs": 3600}))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let created: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    let token = created["token"].as_str().unwrap().to_string();
+    let id = created["id"].as_str().unwrap().to_string();
+    assert!(created["expires_at"].is_string());
+
+    let call = |uri: &str, token: &str| {
+        actix_web::test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&app, call("/mfa/codes", &token)).await;
+    assert_eq!(resp.status(), 200);
+    // The minted token only has the scopes it was given
+    let resp = actix_web::test::call_service(&app, call("/admin/tokens", &token)).await;
+    assert_eq!(resp.status(), 403);
+
+    let body: serde_json::Value =
+        actix_web::test::call_and_read_body_json(&app, call("/admin/tokens", "admin-token")).await;
+    assert_eq!(body["count"], 1);
+    assert!(body["tokens"][0]["last_used_at"].is_string());
+    assert!(body["tokens"][0].get("token").is_none());
+    assert!(body["tokens"][0].get("sha256").is_none());
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/admin/tokens/{}", id))
+        .insert_header(("Authorization", "Bearer admin-token"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+
+    let resp = actix_web::test::call_service(&app, call("/mfa/codes", &token)).await;
+    assert_eq!(resp.status(), 401);
+
+    let records = audit_log
+        .query(&AuditQuery {
+            limit: 10,
+            ..AuditQuery::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].action, AuditAction::TokenRevoke);
+    assert_eq!(records[0].target.as_deref(), Some(id.as_str()));
+    assert_eq!(records[0].actor.token.as_deref(), Some("default"));
+}
+
+#[actix_rt::test]
+async fn test_token_restrictions() {
+    let data_dir = TempDir::new();
+    let token_store = TokenStore::new(&data_dir).unwrap();
+    let restricted = token_store
+        .create(request(vec![Scope::EmailsRead], &["10.0.0.0/8"]))
+        .await
+        .unwrap();
+    let hash = email_manager::middleware::auth::hash_token(&restricted.token);
+
+    assert!(token_store
+        .authenticate(&hash, Some("10.1.2.3".parse().unwrap()))
+        .await
+        .is_some());
+    assert!(token_store
+        .authenticate(&hash, Some("192.168.1.1".parse().unwrap()))
+        .await
+        .is_none());
+    assert!(token_store.authenticate(&hash, None).await.is_none());
+
+    assert!(token_store.create(request(vec![], &[])).await.is_err());
+
+    // Tokens are persisted, and rejected once expired
+    let path = data_dir.join("api_tokens.json");
+    let mut stored: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    stored[0]["expires_at"] = serde_json::json!("2020-01-01T00:00:00Z");
+    std::fs::write(&path, stored.to_string()).unwrap();
+
+    let reloaded = TokenStore::new(&data_dir).unwrap();
+    assert_eq!(reloaded.list().await.len(), 1);
+    assert!(reloaded
+        .authenticate(&hash, Some("10.1.2.3".parse().unwrap()))
+        .await
+        .is_none());
+}
@@ -0,0 +1,258 @@
+mod common;
+
+use actix_web::{web, App};
+use chrono::{TimeZone, Utc};
+use common::TempDir;
+use email_manager::handlers::totp as totp_handlers;
+use email_manager::services::totp::{self, TotpAlgorithm, TotpSecret};
+use email_manager::services::totp_vault::TotpVault;
+use std::sync::Arc;
+
+/// RFC 6238 test secret ("12345678901234567890")
+const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+/// PNG of a QR code holding `content`
+fn qr_png(content: &str) -> Vec<u8> {
+    let code = qrcode::QrCode::new(content).unwrap();
+    let width = code.width() as u32;
+    let colors = code.to_colors();
+    let (scale, quiet) = (8, 4);
+    let size = (width + 2 * quiet) * scale;
+
+    let image = image::GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / scale, y / scale);
+        let dark = x >= quiet
+            && y >= quiet
+            && x < width + quiet
+            && y < width + quiet
+            && colors[((y - quiet) * width + (x - quiet)) as usize] == qrcode::Color::Dark;
+        image::Luma([if dark { 0 } else { 255 }])
+    });
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    png
+}
+
+#[test]
+fn test_rfc6238_code() {
+    let secret = TotpSecret {
+        digits: 8,
+        ..TotpSecret::new("Test", RFC_SECRET).unwrap()
+    };
+
+    assert_eq!(secret.code_at(59).unwrap(), "94287082");
+    assert_eq!(secret.code_at(1111111109).unwrap(), "07081804");
+    assert_eq!(secret.remaining_secs(59), 1);
+}
+
+#[test]
+fn test_parse_otpauth_uri() {
+    let secret = TotpSecret::from_uri(
+        "otpauth://totp/ACME%20Co:alice@example.com?secret=jbsw-y3dp-ehpk-3pxp&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+    )
+    .unwrap();
+
+    assert_eq!(secret.service, "ACME Co");
+    assert_eq!(secret.issuer.as_deref(), Some("ACME Co"));
+    assert_eq!(secret.account.as_deref(), Some("alice@example.com"));
+    assert_eq!(secret.secret, "JBSWY3DPEHPK3PXP");
+    assert_eq!(secret.algorithm, TotpAlgorithm::Sha256);
+    assert_eq!((secret.digits, secret.period), (8, 60));
+
+    assert!(TotpSecret::from_uri("otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP").is_err());
+    assert!(TotpSecret::from_uri("otpauth://totp/x?secret=not-base32!").is_err());
+}
+
+#[test]
+fn test_detect_secrets_in_enrolment_email() {
+    let text = "Set up two-factor authentication.\n\
+                Scan the QR code with your authenticator app, or enter this setup key: \
+                jbsw y3dp ehpk 3pxp\n\
+                Or open otpauth://totp/Example:bob?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Example";
+    let secrets = totp::detect_secrets("Enable 2FA", "security@example.org", text, None, &[]);
+
+    assert_eq!(secrets.len(), 2);
+    assert_eq!(secrets[0].service, "Example");
+    assert_eq!(secrets[0].account.as_deref(), Some("bob"));
+    assert_eq!(secrets[1].secret, "JBSWY3DPEHPK3PXP");
+    assert_eq!(secrets[1].service, "example.org");
+
+    // Setup keys are only looked for in enrolment emails, and runs of
+    // ordinary words are not keys
+    let secrets = totp::detect_secrets(
+        "Your order",
+        "shop@example.org",
+        "Promo code: JBSW Y3DP EHPK 3PXP",
+        None,
+        &[],
+    );
+    assert!(secrets.is_empty());
+    let secrets = totp::detect_secrets(
+        "Two-factor authentication",
+        "security@example.org",
+        "Your secret key is your only backup, keep this safe",
+        None,
+        &[],
+    );
+    assert!(secrets.is_empty());
+}
+
+#[test]
+fn test_detect_secret_in_qr_attachment() {
+    let png = qr_png("otpauth://totp/GitHub:octocat?secret=JBSWY3DPEHPK3PXP&issuer=GitHub");
+    let secrets = totp::detect_secrets(
+        "Two-factor authentication setup",
+        "noreply@github.com",
+        "Scan the attached QR code with your authenticator app.",
+        None,
+        &[b"not an image".to_vec(), png],
+    );
+
+    assert_eq!(secrets.len(), 1);
+    assert_eq!(secrets[0].service, "GitHub");
+    assert_eq!(secrets[0].account.as_deref(), Some("octocat"));
+}
+
+#[actix_rt::test]
+async fn test_vault_encrypts_and_persists_secrets() {
+    let data_dir = TempDir::new();
+    let secrets = [TotpSecret::new("GitHub", RFC_SECRET).unwrap()];
+    let time = Utc.timestamp_opt(59, 0).unwrap();
+
+    let vault = TotpVault::new(&data_dir, "").unwrap();
+    assert_eq!(vault.add(&secrets, Some("7"), false).await.unwrap(), 1);
+    assert_eq!(vault.add(&secrets, Some("7"), false).await.unwrap(), 0);
+
+    let stored = std::fs::read_to_string(data_dir.join("totp_vault.json")).unwrap();
+    assert!(!stored.contains(RFC_SECRET));
+
+    // Reopened with the generated key file
+    let vault = TotpVault::new(&data_dir, "").unwrap();
+    let code = vault.code("github", None, time).await.unwrap();
+    assert_eq!(code.code, "287082");
+    assert_eq!(code.expires_in, 1);
+    assert_eq!(vault.list().await[0].email_id.as_deref(), Some("7"));
+
+    let other_key = TotpVault::new(&data_dir, "another passphrase").unwrap();
+    assert!(other_key.code("GitHub", None, time).await.is_err());
+}
+
+#[actix_rt::test]
+async fn test_vault_keeps_existing_secret_without_overwrite() {
+    let data_dir = TempDir::new();
+    let vault = TotpVault::new(&data_dir, "").unwrap();
+    let time = Utc.timestamp_opt(59, 0).unwrap();
+    vault
+        .add(
+            &[TotpSecret::new("GitHub", RFC_SECRET).unwrap()],
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+
+    // Someone mails a different secret for the same service
+    let other = [TotpSecret::new("GitHub", "JBSWY3DPEHPK3PXP").unwrap()];
+    assert!(vault.add(&other, Some("9"), false).await.is_err());
+    assert_eq!(
+        vault.code("GitHub", None, time).await.unwrap().code,
+        "287082"
+    );
+
+    assert_eq!(vault.add(&other, Some("9"), true).await.unwrap(), 1);
+    assert_ne!(
+        vault.code("GitHub", None, time).await.unwrap().code,
+        "287082"
+    );
+    assert_eq!(vault.list().await.len(), 1);
+}
+
+#[actix_rt::test]
+async fn test_vault_passphrase_key_is_salted_and_upgraded() {
+    use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+    use aes_gcm::{Aes256Gcm, Key};
+    use sha2::{Digest, Sha256};
+
+    let data_dir = TempDir::new();
+    let time = Utc.timestamp_opt(59, 0).unwrap();
+
+    // A secret sealed by an earlier version, keyed by SHA-256(passphrase)
+    let legacy_key = Sha256::digest(b"test passphrase");
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&legacy_key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let plaintext = serde_json::to_vec(&TotpSecret::new("GitHub", RFC_SECRET).unwrap()).unwrap();
+    let ciphertext = cipher.encrypt(&nonce, plaintext.as_slice()).unwrap();
+    let legacy = serde_json::json!([{
+        "service": "GitHub",
+        "account": null,
+        "email_id": "7",
+        "added_at": "2024-01-01T00:00:00Z",
+        "nonce": hex::encode(nonce),
+        "ciphertext": hex::encode(&ciphertext),
+    }]);
+    std::fs::create_dir_all(&*data_dir).unwrap();
+    std::fs::write(data_dir.join("totp_vault.json"), legacy.to_string()).unwrap();
+
+    let vault = TotpVault::new(&data_dir, "test passphrase").unwrap();
+    assert_eq!(
+        vault.code("GitHub", None, time).await.unwrap().code,
+        "287082"
+    );
+    assert!(data_dir.join("totp_vault.salt").exists());
+
+    // Re-encrypted with the salted key
+    let stored = std::fs::read_to_string(data_dir.join("totp_vault.json")).unwrap();
+    assert!(!stored.contains(&hex::encode(&ciphertext)));
+    let reopened = TotpVault::new(&data_dir, "test passphrase").unwrap();
+    assert_eq!(
+        reopened.code("GitHub", None, time).await.unwrap().code,
+        "287082"
+    );
+    assert_eq!(reopened.list().await[0].email_id.as_deref(), Some("7"));
+}
+
+#[actix_rt::test]
+async fn test_totp_endpoint() {
+    let data_dir = TempDir::new();
+    let vault = Arc::new(TotpVault::new(&data_dir, "test passphrase").unwrap());
+    vault
+        .add(&[TotpSecret::new("Okta", RFC_SECRET).unwrap()], None, false)
+        .await
+        .unwrap();
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(vault))
+            .route("/totp", web::get().to(totp_handlers::list_totp_secrets))
+            .route(
+                "/totp/{service}",
+                web::get().to(totp_handlers::get_totp_code),
+            ),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/totp/okta")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+    assert_eq!(body["service"], "Okta");
+    assert_eq!(body["code"].as_str().unwrap().len(), 6);
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/totp")
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["count"], 1);
+    assert!(body["secrets"][0].get("secret").is_none());
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/totp/unknown")
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use actix_web::{web, App};
+use common::TempDir;
+use email_manager::handlers::emails as email_handlers;
+use email_manager::middleware::auth::{ApiToken, ApiTokenAuth, RequireScope, Scope};
+use email_manager::services::audit_log::AuditLog;
+use email_manager::services::imap_service::{quoted, ImapService, TrashedEmail};
+use email_manager::services::undo_store::UndoStore;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn trashed(email_id: &str) -> TrashedEmail {
+    TrashedEmail {
+        email_id: email_id.to_string(),
+        message_id: Some(format!("<{}@example.com>", email_id)),
+        source_folder: "INBOX".to_string(),
+        trash_folder: "[Gmail]/Trash".to_string(),
+    }
+}
+
+#[test]
+fn test_message_ids_are_quoted_for_search() {
+    // The restore search quotes the trashed message's Message-ID
+    assert_eq!(quoted("<a@example.com>"), "\"<a@example.com>\"");
+    assert_eq!(
+        quoted("<a\\\" OR ALL \"@x>"),
+        "\"<a\\\\\\\" OR ALL \\\"@x>\""
+    );
+    assert_eq!(quoted("<a\r\nb@x>"), "\"<ab@x>\"");
+}
+
+#[actix_rt::test]
+async fn test_undo_tokens_are_single_use() {
+    let data_dir = TempDir::new();
+    let undo_store = UndoStore::new(&data_dir, 600).unwrap();
+
+    let entry = undo_store
+        .register(vec![trashed("1"), trashed("2")])
+        .await
+        .unwrap();
+    assert_eq!(entry.token.len(), 32);
+    assert!(entry.expires_at > entry.created_at);
+
+    // Entries survive a restart
+    let reloaded = UndoStore::new(&data_dir, 600).unwrap();
+    let taken = reloaded.take(&entry.token).await.unwrap();
+    assert_eq!(taken.emails.len(), 2);
+    assert_eq!(
+        taken.emails[1].message_id.as_deref(),
+        Some("<2@example.com>")
+    );
+    assert!(reloaded.take(&entry.token).await.is_err());
+
+    // A token whose restore failed can be retried
+    reloaded.reinstate(taken).await.unwrap();
+    assert!(reloaded.take(&entry.token).await.is_ok());
+}
+
+#[actix_rt::test]
+async fn test_undo_tokens_expire() {
+    let data_dir = TempDir::new();
+    let undo_store = UndoStore::new(&data_dir, 0).unwrap();
+    let entry = undo_store.register(vec![trashed("1")]).await.unwrap();
+
+    assert!(undo_store.take(&entry.token).await.is_err());
+}
+
+#[actix_rt::test]
+async fn test_permanent_delete_requires_admin() {
+    let data_dir = TempDir::new();
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(Arc::new(
+                UndoStore::new(&data_dir, 600).unwrap(),
+            )))
+            .app_data(web::Data::new(Arc::new(AuditLog::new(&data_dir).unwrap())))
+            .wrap(ApiTokenAuth::with_tokens(vec![ApiToken::new(
+                "deleter",
+                "delete-token",
+                vec![Scope::EmailsDelete],
+            )]))
+            .route(
+                "/emails/undo/{token}",
+                web::post()
+                    .to(email_handlers::undo_delete)
+                    .wrap(RequireScope::new(Scope::EmailsDelete)),
+            )
+            .route(
+                "/emails/{id}",
+                web::delete()
+                    .to(email_handlers::delete_email)
+                    .wrap(RequireScope::new(Scope::EmailsDelete)),
+            ),
+    )
+    .await;
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri("/emails/42?permanent=true")
+        .insert_header(("Authorization", "Bearer delete-token"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 403);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/undo/unknown")
+        .insert_header(("Authorization", "Bearer delete-token"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[actix_rt::test]
+async fn test_bulk_delete_reports_unknown_ids_as_failed() {
+    let data_dir = TempDir::new();
+    let email_service = Arc::new(Mutex::new(ImapService::new(
+        "test@gmail.com".to_string(),
+        "test-password".to_string(),
+    )));
+
+    let app = actix_web::test::init_service(
+        App::new()
+            .app_data(web::Data::new(email_service))
+            .app_data(web::Data::new(Arc::new(
+                UndoStore::new(&data_dir, 600).unwrap(),
+            )))
+            .app_data(web::Data::new(Arc::new(AuditLog::new(&data_dir).unwrap())))
+            .route(
+                "/emails/bulk-delete",
+                web::post().to(email_handlers::bulk_delete),
+            ),
+    )
+    .await;
+
+    // Ids that are not sequence numbers never reach the server
+    let req = actix_web::test::TestRequest::post()
+        .uri("/emails/bulk-delete")
+        .set_json(serde_json::json!({ "ids": ["abc", "-1"] }))
+        .to_request();
+    let body: serde_json::Value = actix_web::test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(body["deleted"], 0);
+    assert_eq!(body["failed_ids"], serde_json::json!(["abc", "-1"]));
+    assert!(body.get("undo_token").is_none());
+}
@@ -0,0 +1,253 @@
+mod common;
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use common::TempDir;
+use email_manager::config::WebhooksConfig;
+use email_manager::services::event_bus::{EventBus, EventFilter, EventPayload, EventType};
+use email_manager::services::webhooks::{
+    sign_payload, CreateWebhookRequest, DeliveryStatus, WebhookManager, SIGNATURE_HEADER,
+    TIMESTAMP_HEADER,
+};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default)]
+struct StubState {
+    /// Number of requests to fail with 500 before succeeding
+    failures_left: AtomicUsize,
+    received: Mutex<Vec<(String, String, web::Bytes)>>,
+}
+
+async fn stub_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<Arc<StubState>>,
+) -> HttpResponse {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    state
+        .received
+        .lock()
+        .unwrap()
+        .push((header(SIGNATURE_HEADER), header(TIMESTAMP_HEADER), body));
+
+    if state.failures_left.load(Ordering::SeqCst) > 0 {
+        state.failures_left.fetch_sub(1, Ordering::SeqCst);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// Start a local HTTP server that records webhook requests
+fn start_stub(state: Arc<StubState>) -> String {
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(state.clone()))
+            .route("/hook", web::post().to(stub_handler))
+            .route(
+                "/moved",
+                web::post().to(|| async {
+                    HttpResponse::Found()
+                        .insert_header(("Location", "/hook"))
+                        .finish()
+                }),
+            )
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+
+    let addr = server.addrs()[0];
+    actix_rt::spawn(server.run());
+    format!("http://{}/hook", addr)
+}
+
+fn test_config() -> WebhooksConfig {
+    WebhooksConfig {
+        max_attempts: 3,
+        initial_backoff_ms: 10,
+        timeout_secs: 5,
+        delivery_log_capacity: 10,
+    }
+}
+
+#[test]
+fn test_signature_is_hmac_of_timestamp_and_body() {
+    let signature = sign_payload("secret", 1700000000, b"{\"id\":1}");
+
+    assert!(signature.starts_with("sha256="));
+    assert_eq!(signature.len(), "sha256=".len() + 64);
+    assert_eq!(signature, sign_payload("secret", 1700000000, b"{\"id\":1}"));
+    assert_ne!(signature, sign_payload("other", 1700000000, b"{\"id\":1}"));
+    assert_ne!(signature, sign_payload("secret", 1700000001, b"{\"id\":1}"));
+}
+
+#[actix_rt::test]
+async fn test_webhook_delivery_is_signed_and_retried() {
+    let state = Arc::new(StubState::default());
+    state.failures_left.store(1, Ordering::SeqCst);
+    let url = start_stub(state.clone());
+
+    let data_dir = TempDir::new();
+    let manager = Arc::new(WebhookManager::new(test_config(), &data_dir).unwrap());
+    let subscription = manager
+        .create_subscription(CreateWebhookRequest {
+            url,
+            events: vec![EventType::Deleted],
+            filter: EventFilter::default(),
+            description: Some("test".to_string()),
+            secret: Some("shh".to_string()),
+        })
+        .await
+        .unwrap();
+
+    let bus = EventBus::new(10);
+    let event = bus.publish(EventPayload::Deleted {
+        email_id: "7".to_string(),
+    });
+
+    for handle in manager.dispatch(&event).await {
+        handle.await.unwrap();
+    }
+
+    let received = state.received.lock().unwrap().clone();
+    assert_eq!(received.len(), 2, "first attempt fails, second succeeds");
+
+    let (signature, timestamp, body) = &received[1];
+    let timestamp: i64 = timestamp.parse().unwrap();
+    assert_eq!(signature, &sign_payload("shh", timestamp, body));
+
+    let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(payload["type"], "deleted");
+    assert_eq!(payload["data"]["email_id"], "7");
+
+    let deliveries = manager.list_deliveries(Some(&subscription.id), 10).await;
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].status, DeliveryStatus::Succeeded);
+    assert_eq!(deliveries[0].attempts, 2);
+    assert_eq!(
+        deliveries[0].payload_sha256,
+        hex::encode(Sha256::digest(body))
+    );
+
+    // Only a digest of the payload is written to disk
+    let log = std::fs::read_to_string(data_dir.join("webhook_deliveries.json")).unwrap();
+    assert!(log.contains(&deliveries[0].payload_sha256));
+    assert!(!log.contains("\"payload\""));
+
+    // So it can no longer be replayed after a restart
+    let reloaded = Arc::new(WebhookManager::new(test_config(), &data_dir).unwrap());
+    assert!(reloaded.get_delivery(&deliveries[0].id).await.is_some());
+    assert!(reloaded.replay_delivery(&deliveries[0].id).await.is_err());
+}
+
+#[actix_rt::test]
+async fn test_non_matching_events_are_not_delivered() {
+    let state = Arc::new(StubState::default());
+    let url = start_stub(state.clone());
+
+    let data_dir = TempDir::new();
+    let manager = Arc::new(WebhookManager::new(test_config(), &data_dir).unwrap());
+    manager
+        .create_subscription(CreateWebhookRequest {
+            url,
+            events: vec![EventType::MfaCodeDetected],
+            filter: EventFilter::default(),
+            description: None,
+            secret: None,
+        })
+        .await
+        .unwrap();
+
+    let bus = EventBus::new(10);
+    let event = bus.publish(EventPayload::Deleted {
+        email_id: "7".to_string(),
+    });
+
+    assert!(manager.dispatch(&event).await.is_empty());
+    assert!(manager.list_deliveries(None, 10).await.is_empty());
+}
+
+#[actix_rt::test]
+async fn test_redirects_are_not_followed() {
+    let state = Arc::new(StubState::default());
+    let url = start_stub(state.clone()).replace("/hook", "/moved");
+
+    let data_dir = TempDir::new();
+    let manager = Arc::new(WebhookManager::new(test_config(), &data_dir).unwrap());
+    manager
+        .create_subscription(CreateWebhookRequest {
+            url,
+            events: vec![],
+            filter: EventFilter::default(),
+            description: None,
+            secret: None,
+        })
+        .await
+        .unwrap();
+
+    let bus = EventBus::new(10);
+    let event = bus.publish(EventPayload::Deleted {
+        email_id: "7".to_string(),
+    });
+    for handle in manager.dispatch(&event).await {
+        handle.await.unwrap();
+    }
+
+    let delivery = manager.list_deliveries(None, 10).await.remove(0);
+    assert_eq!(delivery.status, DeliveryStatus::Failed);
+    assert_eq!(delivery.last_status_code, Some(302));
+    assert!(state.received.lock().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn test_failed_delivery_can_be_replayed() {
+    let state = Arc::new(StubState::default());
+    state.failures_left.store(3, Ordering::SeqCst);
+    let url = start_stub(state.clone());
+
+    let data_dir = TempDir::new();
+    let manager = Arc::new(WebhookManager::new(test_config(), &data_dir).unwrap());
+    manager
+        .create_subscription(CreateWebhookRequest {
+            url,
+            events: vec![],
+            filter: EventFilter::default(),
+            description: None,
+            secret: None,
+        })
+        .await
+        .unwrap();
+
+    let bus = EventBus::new(10);
+    let event = bus.publish(EventPayload::Deleted {
+        email_id: "7".to_string(),
+    });
+    for handle in manager.dispatch(&event).await {
+        handle.await.unwrap();
+    }
+
+    let failed = manager.list_deliveries(None, 10).await.remove(0);
+    assert_eq!(failed.status, DeliveryStatus::Failed);
+    assert_eq!(failed.attempts, 3);
+
+    let replay = manager.replay_delivery(&failed.id).await.unwrap();
+    assert_eq!(replay.replay_of.as_deref(), Some(failed.id.as_str()));
+
+    let mut status = DeliveryStatus::Pending;
+    for _ in 0..50 {
+        status = manager.get_delivery(&replay.id).await.unwrap().status;
+        if status != DeliveryStatus::Pending {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, DeliveryStatus::Succeeded);
+}